@@ -1,47 +1,444 @@
-use std::path::Path;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::SystemTime;
 
 use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use indicatif::{HumanBytes, HumanCount};
+use regex::RegexBuilder;
+use serde::Serialize;
+use tempfile::NamedTempFile;
 
-use crate::cli::Args;
-use crate::ctx::Context as AppContext;
-use crate::db::BackupManifest;
-use crate::utils;
+use crate::bucket::BucketStrategy;
+use crate::cli::{
+    Args, BackupArgs, Command, FileSort, ListFormat, OutputFormat, ProgressMode, StoreCommand,
+};
+use crate::crypto::{self, BackupKeys, EncryptedManifest};
+use crate::ctx::{
+    can_hard_link, BlobProblemKind, ConflictPolicy, ContentSearchOptions, Context as AppContext,
+    DateLayout, ExtractOptions, FreeSpaceGuard, IndexCache, ProgressEvent, ReportRecorder,
+    TimestampAnomalyKind,
+};
+use crate::db::{self, BackupManifest, ManifestFile, ManifestFileType};
+use crate::domains::DomainListing;
+use crate::extract_manifest::{ExtractManifestWriter, ManifestEntry};
+use crate::fs_index::{FileSystemIndex, SubtreeSize};
+use crate::staging::StagingDir;
+use crate::store::ObjectStore;
+use crate::summary::{self, RunStatus, RunSummary};
+use crate::utils::mode::format_mode;
+use crate::utils::protection_class::ProtectionClass;
+use crate::utils::string_pool::StringPool;
+use crate::utils::timestamp::{format_timestamp, unix_timestamp};
+use crate::volume::{self, Adaptation, VolumeKind};
+use crate::{checkpoint, domains, info, notes, utils};
 
 mod progress_bar {
+    use std::env;
+    use std::io::{self, IsTerminal, Write};
     use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::sync::Mutex;
     use std::thread::{Builder as ThreadBuilder, JoinHandle};
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
 
-    use indicatif::{ProgressBar, ProgressStyle};
+    use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressStyle};
+    use serde::Serialize;
 
+    use crate::cli::ProgressMode;
     use crate::ctx::ProgressEvent;
+    use crate::utils::logger;
 
+    /// Resolution of the bar when it spans several weighted phases.
+    const WEIGHTED_BAR_LENGTH: u64 = 1000;
+
+    /// Minimum interval between the JSON lines of the same frequent phase.
+    const JSON_INTERVAL: Duration = Duration::from_millis(250);
+
+    /// Shares of the bar taken by the indexing and extracting phases, so
+    /// that it advances continuously across both instead of restarting.
+    #[derive(Clone, Copy)]
+    pub struct PhaseWeights {
+        pub indexing: u64,
+        pub extracting: u64,
+    }
+
+    impl PhaseWeights {
+        /// Position of the bar given the progress within a phase, which
+        /// starts after `preceding` weight and spans `weight`.
+        fn position(&self, preceding: u64, weight: u64, done: u64, total: u64) -> u64 {
+            let (done, total) = if total == 0 {
+                (1, 1)
+            } else {
+                (done.min(total), total)
+            };
+            let sum = self.indexing + self.extracting;
+            (preceding * total + weight * done) * WEIGHTED_BAR_LENGTH / (sum * total)
+        }
+    }
+
+    impl Default for PhaseWeights {
+        fn default() -> Self {
+            // Indexing only touches memory, extraction does the file I/O.
+            Self {
+                indexing: 1,
+                extracting: 9,
+            }
+        }
+    }
+
+    /// Where an extraction of several domains is at, the bar then counts
+    /// the files of all of them.
+    #[derive(Clone, Copy)]
+    struct DomainProgress {
+        index: usize,
+        total: usize,
+        files_before: usize,
+        files_total: usize,
+    }
+
+    /// Rate of the bytes extracted since the extraction started.
+    #[derive(Default)]
+    struct Throughput {
+        started_at: Option<Instant>,
+    }
+
+    impl Throughput {
+        /// Describes the bytes done with the rate and the time left, once
+        /// there is a rate to tell.
+        fn describe(&mut self, bytes_done: u64, bytes_total: u64) -> String {
+            let started_at = *self.started_at.get_or_insert_with(Instant::now);
+            let done = format!("{}/{}", HumanBytes(bytes_done), HumanBytes(bytes_total));
+            let elapsed = started_at.elapsed().as_secs_f64();
+            if bytes_done == 0 || elapsed < 1.0 {
+                return done;
+            }
+            let rate = bytes_done as f64 / elapsed;
+            let left = bytes_total.saturating_sub(bytes_done) as f64 / rate;
+            format!(
+                "{done}, {}/s, ETA {}",
+                HumanBytes(rate as u64),
+                HumanDuration(Duration::from_secs_f64(left))
+            )
+        }
+    }
+
+    /// A line of the JSON progress stream.
+    #[derive(Serialize, Default)]
+    #[serde(rename_all = "camelCase")]
+    struct JsonEvent<'a> {
+        phase: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        current: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        total: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        bytes_done: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        bytes_total: Option<u64>,
+        /// Relative path of the file the event is about, or of the last
+        /// written one.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<&'a str>,
+        /// `succeeded` or `failed`, only in the final `finished` line.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        status: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<&'a str>,
+    }
+
+    /// Writes the events as JSON lines to stderr, for other programs to
+    /// follow the progress.
+    #[derive(Default)]
+    struct JsonStream {
+        state: Mutex<JsonState>,
+    }
+
+    #[derive(Default)]
+    struct JsonState {
+        last_phase: &'static str,
+        last_written_at: Option<Instant>,
+        last_path: Option<String>,
+    }
+
+    impl JsonState {
+        /// Whether a line of a frequent phase is due, the first and last
+        /// ones of the phase always are.
+        fn is_due(&mut self, phase: &'static str, current: usize, total: usize) -> bool {
+            let due = phase != self.last_phase
+                || current >= total
+                || self
+                    .last_written_at
+                    .is_none_or(|written_at| written_at.elapsed() >= JSON_INTERVAL);
+            if due {
+                self.last_phase = phase;
+                self.last_written_at = Some(Instant::now());
+            }
+            due
+        }
+    }
+
+    impl JsonStream {
+        fn send(&self, event: &ProgressEvent) {
+            if let Some(line) = self.line(event) {
+                write_line(&line);
+            }
+        }
+
+        fn finish(&self, error: Option<&str>) {
+            if let Some(line) = Self::finish_line(error) {
+                write_line(&line);
+            }
+        }
+
+        /// The line of the event, if it's due.
+        fn line(&self, event: &ProgressEvent) -> Option<String> {
+            let mut state = self.state.lock().unwrap();
+            let json_event = match event {
+                ProgressEvent::Querying => JsonEvent {
+                    phase: "querying",
+                    ..Default::default()
+                },
+                ProgressEvent::Indexing { indexed, total } => {
+                    if !state.is_due("indexing", *indexed, *total) {
+                        return None;
+                    }
+                    JsonEvent {
+                        phase: "indexing",
+                        current: Some(*indexed as u64),
+                        total: Some(*total as u64),
+                        ..Default::default()
+                    }
+                }
+                ProgressEvent::Extracting {
+                    extracted,
+                    total,
+                    bytes_done,
+                    bytes_total,
+                    ..
+                } => {
+                    if !state.is_due("extracting", *extracted, *total) {
+                        return None;
+                    }
+                    JsonEvent {
+                        phase: "extracting",
+                        current: Some(*extracted as u64),
+                        total: Some(*total as u64),
+                        bytes_done: Some(*bytes_done),
+                        bytes_total: Some(*bytes_total),
+                        path: state.last_path.as_deref(),
+                        ..Default::default()
+                    }
+                }
+                ProgressEvent::Packing { packed, total } => {
+                    if !state.is_due("packing", *packed, *total) {
+                        return None;
+                    }
+                    JsonEvent {
+                        phase: "packing",
+                        current: Some(*packed as u64),
+                        total: Some(*total as u64),
+                        ..Default::default()
+                    }
+                }
+                ProgressEvent::Written { relative_path, .. } => {
+                    state.last_path = Some(relative_path.clone());
+                    return None;
+                }
+                ProgressEvent::Domain { index, total, .. } => JsonEvent {
+                    phase: "domain",
+                    current: Some(*index as u64 + 1),
+                    total: Some(*total as u64),
+                    ..Default::default()
+                },
+                ProgressEvent::Skipped {
+                    relative_path,
+                    reason,
+                } => JsonEvent {
+                    phase: "skipped",
+                    path: Some(relative_path),
+                    reason: Some(reason),
+                    ..Default::default()
+                },
+                ProgressEvent::Failed {
+                    relative_path,
+                    error,
+                } => JsonEvent {
+                    phase: "failed",
+                    path: Some(relative_path),
+                    error: Some(error),
+                    ..Default::default()
+                },
+                ProgressEvent::Placeholder { relative_path } => JsonEvent {
+                    phase: "placeholder",
+                    path: Some(relative_path),
+                    ..Default::default()
+                },
+                ProgressEvent::WaitingForSpace {
+                    available,
+                    required,
+                } => JsonEvent {
+                    phase: "waitingForSpace",
+                    bytes_done: Some(*available),
+                    bytes_total: Some(*required),
+                    ..Default::default()
+                },
+                ProgressEvent::Verifying => JsonEvent {
+                    phase: "verifying",
+                    ..Default::default()
+                },
+                ProgressEvent::Kept { .. }
+                | ProgressEvent::Offloaded { .. }
+                | ProgressEvent::Deduplicated { .. }
+                | ProgressEvent::Normalized { .. }
+                | ProgressEvent::Compared { .. }
+                | ProgressEvent::Filtered { .. }
+                | ProgressEvent::Planned { .. } => return None,
+            };
+            serde_json::to_string(&json_event).ok()
+        }
+
+        /// The final line, telling how the work ended.
+        fn finish_line(error: Option<&str>) -> Option<String> {
+            let json_event = JsonEvent {
+                phase: "finished",
+                status: Some(if error.is_some() {
+                    "failed"
+                } else {
+                    "succeeded"
+                }),
+                error,
+                ..Default::default()
+            };
+            serde_json::to_string(&json_event).ok()
+        }
+    }
+
+    fn write_line(line: &str) {
+        // Written at once, stderr isn't buffered.
+        let _ = writeln!(io::stderr(), "{line}");
+    }
+
+    /// Sends the events to the UI thread, or writes them as JSON lines, or
+    /// drops them if the progress is hidden.
     pub struct ControlPort {
-        tx: Sender<Option<ProgressEvent>>,
+        tx: Option<Sender<Option<ProgressEvent>>>,
         join_handle: Option<JoinHandle<()>>,
+        json: Option<JsonStream>,
     }
 
     impl ControlPort {
         pub fn send(&self, event: ProgressEvent) {
-            self.tx.send(Some(event)).unwrap();
+            if let Some(json) = &self.json {
+                json.send(&event);
+            }
+            if let Some(tx) = &self.tx {
+                tx.send(Some(event)).unwrap();
+            }
+        }
+
+        /// Disposes the progress, reporting how the work ended in the JSON
+        /// stream.
+        pub fn finish(self, error: Option<String>) {
+            if let Some(json) = &self.json {
+                json.finish(error.as_deref());
+            }
         }
     }
 
     impl Drop for ControlPort {
         fn drop(&mut self) {
-            self.tx.send(None).unwrap();
+            let Some(tx) = &self.tx else {
+                return;
+            };
+            tx.send(None).unwrap();
             self.join_handle.take().unwrap().join().unwrap();
         }
     }
 
-    fn thread_main(rx: Receiver<Option<ProgressEvent>>) {
-        let spinner_style = ProgressStyle::with_template("{spinner} [{bar:20.white}] {msg}")
-            .unwrap()
-            .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
-            .progress_chars("=> ");
+    /// How the progress is shown.
+    #[derive(Clone, Copy, Debug)]
+    pub struct Appearance {
+        pub charset: Charset,
+        pub mode: ProgressMode,
+    }
+
+    impl Appearance {
+        pub fn detect(mode: ProgressMode, force_ascii: bool) -> Self {
+            Self {
+                charset: Charset::detect(force_ascii),
+                mode,
+            }
+        }
+    }
+
+    /// Characters the progress bar is drawn with.
+    #[derive(PartialEq, Eq, Clone, Copy, Debug)]
+    pub enum Charset {
+        Unicode,
+        /// Plain ASCII for terminals that would garble the Braille spinner.
+        Ascii,
+    }
+
+    impl Charset {
+        pub fn detect(force_ascii: bool) -> Self {
+            if force_ascii || !supports_unicode(|key| env::var(key).ok(), cfg!(windows)) {
+                Self::Ascii
+            } else {
+                Self::Unicode
+            }
+        }
 
+        fn style(self) -> ProgressStyle {
+            let tick_chars = match self {
+                Self::Unicode => "⠁⠂⠄⡀⢀⠠⠐⠈ ",
+                Self::Ascii => "|/-\\ ",
+            };
+            ProgressStyle::with_template("{spinner} [{bar:20.white}] {percent:>3}% {msg}")
+                .unwrap()
+                .tick_chars(tick_chars)
+                .progress_chars("=> ")
+        }
+    }
+
+    /// Guesses whether the terminal renders Unicode from the environment.
+    fn supports_unicode<E>(env: E, windows: bool) -> bool
+    where
+        E: Fn(&str) -> Option<String>,
+    {
+        if windows {
+            // The legacy console uses an OEM code page, modern terminals
+            // identify themselves.
+            return env("WT_SESSION").is_some()
+                || env("TERM_PROGRAM").is_some_and(|p| p == "vscode");
+        }
+
+        if env("TERM").is_some_and(|term| term == "linux" || term == "dumb") {
+            return false;
+        }
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .into_iter()
+            .filter_map(&env)
+            .find(|value| !value.is_empty());
+        locale.is_some_and(|locale| {
+            let locale = locale.to_ascii_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        })
+    }
+
+    fn thread_main(rx: Receiver<Option<ProgressEvent>>, weights: PhaseWeights, charset: Charset) {
         let progress_bar = ProgressBar::new(100);
-        progress_bar.set_style(spinner_style);
+        progress_bar.set_style(charset.style());
+        logger::set_progress_bar(Some(progress_bar.clone()));
+        let mut domain = None;
+        let mut throughput = Throughput::default();
 
         loop {
             let Ok(event) = rx.recv_timeout(Duration::from_millis(200)) else {
@@ -56,84 +453,2351 @@ mod progress_bar {
                 break;
             };
 
-            update_progress_bar(&progress_bar, event);
+            if let ProgressEvent::Domain {
+                index,
+                total,
+                files_before,
+                files_total,
+            } = event
+            {
+                domain = Some(DomainProgress {
+                    index,
+                    total,
+                    files_before,
+                    files_total,
+                });
+                continue;
+            }
+            update_progress_bar(&progress_bar, &weights, domain, &mut throughput, event);
         }
 
+        logger::set_progress_bar(None);
         progress_bar.finish_and_clear();
     }
 
-    fn update_progress_bar(progress_bar: &ProgressBar, event: ProgressEvent) {
+    fn update_progress_bar(
+        progress_bar: &ProgressBar,
+        weights: &PhaseWeights,
+        domain: Option<DomainProgress>,
+        throughput: &mut Throughput,
+        event: ProgressEvent,
+    ) {
+        let set_message = |message: String| match domain {
+            Some(domain) => progress_bar.set_message(format!(
+                "[domain {}/{}] {message}",
+                domain.index + 1,
+                domain.total
+            )),
+            None => progress_bar.set_message(message),
+        };
         match event {
             ProgressEvent::Querying => {
-                progress_bar.set_message("Querying database...");
+                *throughput = Throughput::default();
+                set_message("Querying database...".to_owned());
             }
             ProgressEvent::Indexing { indexed, total } => {
-                progress_bar
-                    .set_message(format!("Creating file system index... ({indexed}/{total})"));
-                progress_bar.set_length(total as u64);
-                progress_bar.set_position(indexed as u64);
+                set_message(format!("Creating file system index... ({indexed}/{total})"));
+                if let Some(domain) = domain {
+                    // The bar counts the files of all the domains instead.
+                    progress_bar.set_length(domain.files_total as u64);
+                    progress_bar.set_position(domain.files_before as u64);
+                    return;
+                }
+                progress_bar.set_length(WEIGHTED_BAR_LENGTH);
+                progress_bar.set_position(weights.position(
+                    0,
+                    weights.indexing,
+                    indexed as u64,
+                    total as u64,
+                ));
             }
-            ProgressEvent::Extracting { extracted, total } => {
-                progress_bar.set_message(format!("Extracting files... ({extracted}/{total})"));
+            ProgressEvent::Extracting {
+                extracted,
+                total,
+                bytes_done,
+                bytes_total,
+                ..
+            } => {
+                let bytes = throughput.describe(bytes_done, bytes_total);
+                if let Some(domain) = domain {
+                    let extracted = domain.files_before + extracted;
+                    let files_total = domain.files_total;
+                    set_message(format!(
+                        "Extracting files... ({extracted}/{files_total}, {bytes})"
+                    ));
+                    progress_bar.set_length(files_total as u64);
+                    progress_bar.set_position(extracted as u64);
+                    return;
+                }
+                set_message(format!(
+                    "Extracting files... ({extracted}/{total}, {bytes})"
+                ));
+                progress_bar.set_length(WEIGHTED_BAR_LENGTH);
+                // By bytes, so that a large file weighs more than many tiny
+                // ones, unless no size is known.
+                let (done, total) = if bytes_total > 0 {
+                    (bytes_done, bytes_total)
+                } else {
+                    (extracted as u64, total as u64)
+                };
+                progress_bar.set_position(weights.position(
+                    weights.indexing,
+                    weights.extracting,
+                    done,
+                    total,
+                ));
+            }
+            ProgressEvent::WaitingForSpace {
+                available,
+                required,
+            } => {
+                set_message(format!(
+                    "Waiting for free space... ({} available, {} required)",
+                    HumanBytes(available),
+                    HumanBytes(required)
+                ));
+            }
+            ProgressEvent::Packing { packed, total } => {
+                progress_bar.set_message(format!("Packing files... ({packed}/{total})"));
                 progress_bar.set_length(total as u64);
-                progress_bar.set_position(extracted as u64);
+                progress_bar.set_position(packed as u64);
+            }
+            ProgressEvent::Verifying => {
+                progress_bar.set_message("Verifying archive...");
             }
+            ProgressEvent::Skipped { .. }
+            | ProgressEvent::Normalized { .. }
+            | ProgressEvent::Placeholder { .. }
+            | ProgressEvent::Offloaded { .. }
+            | ProgressEvent::Kept { .. }
+            | ProgressEvent::Deduplicated { .. }
+            | ProgressEvent::Written { .. }
+            | ProgressEvent::Failed { .. }
+            | ProgressEvent::Compared { .. }
+            | ProgressEvent::Filtered { .. }
+            | ProgressEvent::Domain { .. }
+            | ProgressEvent::Planned { .. } => {}
         }
     }
 
-    pub fn make() -> ControlPort {
+    pub fn make(weights: PhaseWeights, appearance: Appearance) -> ControlPort {
+        match appearance.mode {
+            ProgressMode::Bar if io::stderr().is_terminal() => {}
+            ProgressMode::Json => {
+                return ControlPort {
+                    tx: None,
+                    join_handle: None,
+                    json: Some(JsonStream::default()),
+                };
+            }
+            // The bar would only litter the output of cron jobs and pipes.
+            ProgressMode::Bar | ProgressMode::None => {
+                return ControlPort {
+                    tx: None,
+                    join_handle: None,
+                    json: None,
+                };
+            }
+        }
+
+        let charset = appearance.charset;
         let (tx, rx) = channel();
 
         let join_handle = ThreadBuilder::new()
             .name("ProgressUIThread".to_owned())
-            .spawn(move || thread_main(rx))
+            .spawn(move || thread_main(rx, weights, charset))
             .unwrap();
 
         ControlPort {
-            tx,
+            tx: Some(tx),
             join_handle: Some(join_handle),
+            json: None,
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::collections::HashMap;
+        use std::time::Instant;
+
+        use super::{supports_unicode, JsonStream, JSON_INTERVAL};
+        use crate::ctx::ProgressEvent;
+
+        fn extracting(extracted: usize, total: usize) -> ProgressEvent {
+            ProgressEvent::Extracting {
+                extracted,
+                total,
+                bytes_written: 0,
+                bytes_done: extracted as u64 * 10,
+                bytes_total: total as u64 * 10,
+            }
+        }
+
+        fn supports_unicode_with(vars: &[(&str, &str)], windows: bool) -> bool {
+            let vars: HashMap<_, _> = vars.iter().copied().collect();
+            supports_unicode(|key| vars.get(key).map(|v| v.to_string()), windows)
+        }
+
+        #[test]
+        fn it_detects_unicode_support() {
+            assert!(supports_unicode_with(&[("LANG", "en_US.UTF-8")], false));
+            assert!(supports_unicode_with(
+                &[("LC_ALL", ""), ("LANG", "zh_CN.utf8")],
+                false
+            ));
+            assert!(!supports_unicode_with(&[("LC_ALL", "C")], false));
+            assert!(!supports_unicode_with(&[], false));
+            assert!(!supports_unicode_with(
+                &[("TERM", "linux"), ("LANG", "en_US.UTF-8")],
+                false
+            ));
+
+            assert!(supports_unicode_with(&[("WT_SESSION", "1")], true));
+            assert!(!supports_unicode_with(&[("LANG", "en_US.UTF-8")], true));
+        }
+
+        #[test]
+        fn it_throttles_the_json_lines() {
+            let stream = JsonStream::default();
+            let indexing = |indexed| ProgressEvent::Indexing { indexed, total: 3 };
+
+            // The first and last lines of a phase are always written.
+            assert!(stream.line(&indexing(1)).is_some());
+            assert!(stream.line(&indexing(2)).is_none());
+            assert!(stream.line(&indexing(3)).is_some());
+            assert!(stream.line(&extracting(1, 3)).is_some());
+            assert!(stream.line(&extracting(2, 3)).is_none());
+
+            // The lines in between are written once the interval passed.
+            stream.state.lock().unwrap().last_written_at =
+                Instant::now().checked_sub(JSON_INTERVAL);
+            assert!(stream.line(&extracting(2, 3)).is_some());
+            assert!(stream.line(&extracting(3, 3)).is_some());
+        }
+
+        #[test]
+        fn it_writes_json_lines() {
+            let stream = JsonStream::default();
+            assert_eq!(
+                stream.line(&ProgressEvent::Querying).as_deref(),
+                Some(r#"{"phase":"querying"}"#)
+            );
+            assert_eq!(
+                stream
+                    .line(&ProgressEvent::Written {
+                        relative_path: "a/b".to_string(),
+                        file_id: "00".to_string(),
+                        path: "out/a/b".into(),
+                        size: 10,
+                    })
+                    .as_deref(),
+                None
+            );
+            assert_eq!(
+                stream.line(&extracting(1, 2)).as_deref(),
+                Some(
+                    r#"{"phase":"extracting","current":1,"total":2,"bytesDone":10,"bytesTotal":20,"path":"a/b"}"#
+                )
+            );
+            assert_eq!(
+                stream
+                    .line(&ProgressEvent::Skipped {
+                        relative_path: "c".to_string(),
+                        reason: "exists",
+                    })
+                    .as_deref(),
+                Some(r#"{"phase":"skipped","path":"c","reason":"exists"}"#)
+            );
+            assert_eq!(
+                stream
+                    .line(&ProgressEvent::Kept {
+                        relative_path: "c".to_string(),
+                    })
+                    .as_deref(),
+                None
+            );
+
+            assert_eq!(
+                JsonStream::finish_line(None).as_deref(),
+                Some(r#"{"phase":"finished","status":"succeeded"}"#)
+            );
+            assert_eq!(
+                JsonStream::finish_line(Some("oops")).as_deref(),
+                Some(r#"{"phase":"finished","status":"failed","error":"oops"}"#)
+            );
         }
     }
 }
 
 pub fn run(args: Args) -> Result<()> {
-    let backup_dir = args.backup_dir;
+    let Some(backup) = args.command.backup() else {
+        return run_without_backup(&args.command);
+    };
+    let encrypted_manifest = crypto::read_encrypted_manifest(&backup.backup_dir)?;
+    let (backup_keys, decrypted_manifest) = match &encrypted_manifest {
+        Some(_) if backup.index_cache.is_some() => {
+            return Err(anyhow!(
+                "`--index-cache` can't be used with encrypted backups, \
+                 the cache would keep their manifest decrypted"
+            ));
+        }
+        Some(encrypted_manifest) => {
+            let (backup_keys, decrypted_manifest) = unlock_backup(backup, encrypted_manifest)?;
+            (Some(backup_keys), Some(decrypted_manifest))
+        }
+        None => (None, None),
+    };
+    let mut manifest = match &decrypted_manifest {
+        Some(decrypted_manifest) => BackupManifest::open(decrypted_manifest.path()),
+        None => BackupManifest::open_backup(&backup.backup_dir),
+    }
+    .context("failed to open the manifest database")?;
+    let progress_mode = if args.no_progress || (args.quiet && args.progress == ProgressMode::Bar) {
+        ProgressMode::None
+    } else {
+        args.progress
+    };
+    let appearance = progress_bar::Appearance::detect(progress_mode, args.ascii_progress);
+
+    match &args.command {
+        Command::ListDomains {
+            with_paths,
+            sizes,
+            format,
+            ..
+        } => {
+            let context = open_context(backup, &mut manifest, backup_keys.as_ref(), false);
+
+            let timer = utils::PerfTimer::new();
+            let domain_sizes: Option<HashMap<_, _>> = if *sizes {
+                let domain_sizes = context
+                    .list_domain_sizes()
+                    .context("failed to measure domains")?;
+                Some(domain_sizes.into_iter().collect())
+            } else {
+                None
+            };
+            let size_of = |domain: &str| {
+                let domain_sizes = domain_sizes.as_ref()?;
+                Some(domain_sizes.get(domain).copied().unwrap_or_default())
+            };
 
-    let manifest_path = backup_dir.join("Manifest.db");
-    let mut manifest =
-        BackupManifest::open(manifest_path).context("failed to open the manifest database")?;
+            if *format == OutputFormat::Json {
+                let mut listings: Vec<_> = context
+                    .list_domain_file_counts()
+                    .context("failed to list domains")?
+                    .into_iter()
+                    .map(|(name, file_count)| DomainListing {
+                        root: domains::device_root(&name).filter(|_| *with_paths),
+                        size: size_of(&name),
+                        name,
+                        file_count,
+                    })
+                    .collect();
+                // The largest first with `--sizes`, in the order of the names
+                // otherwise.
+                listings.sort_by_key(|listing| Reverse(listing.size));
+                let mut stdout = io::stdout().lock();
+                serde_json::to_writer_pretty(&mut stdout, &listings)
+                    .context("failed to write domains")?;
+                writeln!(stdout).context("failed to write domains")?;
+                return Ok(());
+            }
 
-    let context = AppContext::new(&backup_dir, &mut manifest, args.copy);
-    if args.list_domains {
-        let timer = utils::PerfTimer::new();
-        let domains = context.list_domains().context("failed to list domains")?;
-        timer.finish();
+            let mut domains = context.list_domains().context("failed to list domains")?;
+            timer.finish();
+            domains.sort_by_key(|domain| Reverse(size_of(domain)));
 
-        for domain in domains {
-            println!("{domain}");
+            for domain in domains {
+                let mut columns = vec![domain.clone()];
+                if let Some(size) = size_of(&domain) {
+                    columns.push(HumanBytes(size).to_string());
+                }
+                if let Some(root) = domains::device_root(&domain).filter(|_| *with_paths) {
+                    columns.push(root);
+                }
+                println!("{}", columns.join("\t"));
+            }
         }
-    } else {
-        let timer = utils::PerfTimer::new();
-        let pb_port = progress_bar::make();
-        context
-            .extract_file(
-                args.domain.as_ref().expect("domain should not be empty"),
-                args.out_dir
+        Command::ListFiles {
+            domain,
+            show_ids,
+            regex,
+            protection_class,
+            under,
+            max_depth,
+            format,
+            sort,
+            reverse,
+            limit,
+            ..
+        } => {
+            let context = open_context(backup, &mut manifest, backup_keys.as_ref(), false);
+
+            let under = under.as_deref().unwrap_or(Path::new(""));
+            let max_depth = max_depth.map(NonZeroUsize::get);
+            let is_listed = |file: &ManifestFile| {
+                (protection_class.is_empty()
+                    || protection_class.contains(&ProtectionClass::of(file)))
+                    && regex
+                        .as_ref()
+                        .is_none_or(|regex| regex.is_match(&file.relative_path))
+            };
+
+            let timer = utils::PerfTimer::new();
+            if *format == ListFormat::Ndjson && sort.is_none() {
+                // Written as the rows are read, so that the first entries
+                // come out at once on huge domains.
+                let mut stdout = io::BufWriter::new(io::stdout().lock());
+                let (mut row_count, mut listed_count) = (0, 0);
+                let res = context.for_each_file(domain, |file| {
+                    row_count += 1;
+                    let Ok(path) = Path::new(&file.relative_path).strip_prefix(under) else {
+                        return Ok(());
+                    };
+                    if !is_listed(&file)
+                        || max_depth.is_some_and(|max_depth| path.components().count() > max_depth)
+                        || limit.is_some_and(|limit| listed_count >= limit)
+                    {
+                        return Ok(());
+                    }
+                    listed_count += 1;
+                    write_file_listing(&mut stdout, &file)
+                });
+                let res = res.and_then(|()| Ok(stdout.flush()?));
+                timer.finish();
+                match res {
+                    // The reader has seen enough, e.g. `head`.
+                    Err(err) if is_broken_pipe(&err) => return Ok(()),
+                    res => res.context("failed to list files")?,
+                }
+                if row_count == 0 {
+                    return Err(anyhow!("domain not found: {domain}"));
+                }
+                if listed_count == 0 && under != Path::new("") {
+                    return Err(anyhow!(
+                        "no entry found under `{}`",
+                        under.to_string_lossy()
+                    ));
+                }
+                return Ok(());
+            }
+
+            let mut files = context
+                .query_files(domain)
+                .context("failed to query files")?;
+            timer.finish();
+            if files.is_empty() {
+                return Err(anyhow!("domain not found: {domain}"));
+            }
+            if *format == ListFormat::Ndjson
+                && limit.is_none()
+                && files.len() > LARGE_DOMAIN_ENTRY_COUNT
+            {
+                warn!(
+                    "sorting holds all the {} entries of the domain before listing them, \
+                     pass `--limit` to list the first ones only",
+                    files.len()
+                );
+            }
+            sort_files(&mut files, sort.unwrap_or(FileSort::Path), *reverse);
+
+            // Depths are counted below `--under`, the entries past the
+            // maximum depth are summarized under their ancestor at that
+            // depth.
+            let mut listed_files = vec![];
+            let mut collapsed: HashMap<PathBuf, SubtreeSize> = HashMap::new();
+            for file in &files {
+                if !is_listed(file) {
+                    continue;
+                }
+                let Ok(path) = Path::new(&file.relative_path).strip_prefix(under) else {
+                    continue;
+                };
+                let ancestor = max_depth
+                    .filter(|max_depth| path.components().count() > *max_depth)
+                    .map(|max_depth| under.join(path.iter().take(max_depth).collect::<PathBuf>()));
+                if let Some(ancestor) = &ancestor {
+                    let subtree = collapsed.entry(ancestor.clone()).or_default();
+                    match file.file_type {
+                        ManifestFileType::File => {
+                            subtree.files += 1;
+                            subtree.bytes += file.size.unwrap_or_default();
+                        }
+                        ManifestFileType::Directory => subtree.dirs += 1,
+                        ManifestFileType::SymbolicLink => subtree.links += 1,
+                    }
+                }
+                listed_files.push((file, ancestor));
+            }
+            if listed_files.is_empty() && under != Path::new("") {
+                return Err(anyhow!(
+                    "no entry found under `{}`",
+                    under.to_string_lossy()
+                ));
+            }
+
+            if *format == ListFormat::Ndjson {
+                let mut files = listed_files
+                    .into_iter()
+                    .filter(|(_, ancestor)| ancestor.is_none())
+                    .take(limit.unwrap_or(usize::MAX));
+                let mut stdout = io::BufWriter::new(io::stdout().lock());
+                let res = files
+                    .try_for_each(|(file, _)| write_file_listing(&mut stdout, file))
+                    .and_then(|()| Ok(stdout.flush()?));
+                return match res {
+                    Err(err) if is_broken_pipe(&err) => Ok(()),
+                    res => res.context("failed to list files"),
+                };
+            }
+
+            let mut print_collapsed = |ancestor: &Path| {
+                if let Some(subtree) = collapsed.remove(ancestor) {
+                    println!(
+                        "{}\t{}/ {}",
+                        ellipsis(appearance.charset),
+                        ancestor.to_string_lossy(),
+                        collapsed_entries(&subtree)
+                    );
+                }
+            };
+            let mut listed_count = 0;
+            for (file, ancestor) in listed_files {
+                if let Some(ancestor) = ancestor {
+                    // The ancestor may have no row of its own, summarize
+                    // its entries at the first one left out then.
+                    print_collapsed(&ancestor);
+                    continue;
+                }
+                if limit.is_some_and(|limit| listed_count >= limit) {
+                    break;
+                }
+                listed_count += 1;
+                let file_type = file_type_name(file.file_type);
+                if *show_ids {
+                    println!("{file_type}\t{}\t{}", file.file_id, file.relative_path);
+                } else {
+                    println!("{file_type}\t{}", file.relative_path);
+                }
+                print_collapsed(Path::new(&file.relative_path));
+            }
+        }
+        Command::Tree {
+            domain,
+            under,
+            max_depth,
+            ..
+        } => {
+            let context = open_context(backup, &mut manifest, backup_keys.as_ref(), false);
+
+            let timer = utils::PerfTimer::new();
+            let files = context
+                .query_files(domain)
+                .context("failed to query files")?;
+            if files.is_empty() {
+                return Err(anyhow!("domain not found: {domain}"));
+            }
+            let sizes: HashMap<_, _> = files
+                .iter()
+                .map(|file| (file.file_id.clone(), file.size.unwrap_or_default()))
+                .collect();
+            let under = under.as_deref().unwrap_or(Path::new(""));
+            let string_pool = StringPool::new();
+            let mut index = FileSystemIndex::new(&string_pool);
+            // Links are added last, as they can't take the place of a file.
+            let (files, others): (Vec<_>, Vec<_>) = files
+                .into_iter()
+                .partition(|f| f.file_type == ManifestFileType::File);
+            for file in files {
+                let Ok(path) = Path::new(&file.relative_path).strip_prefix(under) else {
+                    continue;
+                };
+                if let Err(err) = index.add_file(path, file.file_id.clone()) {
+                    warn!("skipped `{}`: {err}", file.relative_path);
+                }
+            }
+            for file in others {
+                let Some(target) = &file.target else {
+                    continue;
+                };
+                if file.file_type != ManifestFileType::SymbolicLink {
+                    continue;
+                }
+                let Ok(path) = Path::new(&file.relative_path).strip_prefix(under) else {
+                    continue;
+                };
+                if let Err(err) = index.add_symlink(path, target.clone()) {
+                    warn!("skipped `{}`: {err}", file.relative_path);
+                }
+            }
+            timer.finish();
+
+            let root = if under == Path::new("") {
+                domain.clone()
+            } else {
+                format!("{domain}/{}", under.to_string_lossy())
+            };
+            print_tree(
+                &index,
+                &root,
+                max_depth.map(NonZeroUsize::get),
+                |file_id| sizes.get(file_id).copied().unwrap_or_default(),
+                appearance.charset,
+            );
+        }
+        Command::Extract {
+            domain,
+            strict,
+            keep_going,
+            out_dir,
+            copy,
+            hardlink,
+            hardlink_or_copy,
+            dedupe,
+            atomic,
+            overwrite_dir,
+            strip_prefix,
+            prefix_mismatch,
+            ignore_case,
+            on_conflict,
+            skip_existing,
+            overwrite,
+            fail_on_existing,
+            by_date,
+            date_field,
+            date_format,
+            min_free_space,
+            on_low_space,
+            force,
+            sidecars_only,
+            no_index,
+            buffer_size,
+            no_reflink,
+            jobs,
+            count,
+            summary_json,
+            write_manifest,
+            metadata,
+            archive,
+            notes_bundle,
+            decode_notes,
+            checkpoint_dbs,
+            store,
+            priority_glob,
+            portable_names,
+            no_adapt,
+            since_backup,
+            write_deleted,
+            protection_class,
+            placeholders,
+            preserve_timestamps,
+            preserve_permissions,
+            no_preserve_metadata,
+            paths,
+            include,
+            exclude,
+            regex,
+            newer_than,
+            older_than,
+            dry_run,
+            ..
+        } => {
+            // Several domains are extracted into subdirectories named after
+            // them, given none or a pattern.
+            // `--all-domains` leaves the domain out too.
+            let domains = match domain.as_deref() {
+                None => Some(manifest.query_domains().context("failed to list domains")?),
+                Some(pattern) if domains::is_pattern(pattern) => {
+                    let single_domain_flag = [
+                        ("--count", *count),
+                        ("--sidecars-only", *sidecars_only),
+                        ("--summary-json", summary_json.is_some()),
+                        ("--write-manifest", write_manifest.is_some()),
+                        ("--metadata", metadata.is_some()),
+                        ("--archive", archive.is_some()),
+                        ("--notes-bundle", *notes_bundle),
+                        ("--write-deleted", *write_deleted),
+                        ("--path", !paths.is_empty()),
+                        ("--dry-run", *dry_run),
+                        ("--atomic", *atomic),
+                    ]
+                    .into_iter()
+                    .find_map(|(flag, set)| set.then_some(flag));
+                    if let Some(flag) = single_domain_flag {
+                        return Err(anyhow!("`{flag}` can't be used with a domain pattern"));
+                    }
+                    let all = manifest.query_domains().context("failed to list domains")?;
+                    Some(domains::expand_pattern(pattern, &all)?)
+                }
+                Some(_) => None,
+            };
+            let domain = domain.as_deref().filter(|_| domains.is_none());
+            if out_dir.as_deref() == Some(Path::new("-")) {
+                let Some(domain) = domain else {
+                    return Err(anyhow!(
+                        "extracting to stdout (`-o -`) needs a single domain, not a pattern"
+                    ));
+                };
+                let [relative_path] = paths.as_slice() else {
+                    return Err(anyhow!(
+                        "extracting to stdout (`-o -`) needs exactly one `--path`"
+                    ));
+                };
+                let context = open_context(backup, &mut manifest, backup_keys.as_ref(), false);
+                return cat_file(&context, domain, &relative_path.to_string_lossy());
+            }
+
+            let out_dir = out_dir
+                .as_deref()
+                .map(|out_dir| resolve_out_dir(out_dir, &backup.backup_dir, domain))
+                .transpose()?;
+
+            let mut hard_links = *hardlink || *hardlink_or_copy;
+            if let Some(out_dir) = out_dir.as_deref().filter(|_| hard_links && !*sidecars_only) {
+                if !can_hard_link(&backup.backup_dir, out_dir) {
+                    if !*hardlink_or_copy {
+                        return Err(anyhow!(
+                            "can't create hard links to the backup in {}, it must be on the \
+                             same volume (pass `--hardlink-or-copy` to copy the files instead)",
+                            out_dir.to_string_lossy()
+                        ));
+                    }
+                    warn!(
+                        "can't create hard links to the backup in {}, copying the files",
+                        out_dir.to_string_lossy()
+                    );
+                    hard_links = false;
+                }
+            }
+
+            let mut adaptations = vec![];
+            if let Some(out_dir) = out_dir.as_deref().filter(|_| !*sidecars_only) {
+                let volume = volume::detect(out_dir).unwrap_or_else(|err| {
+                    warn!("{err:#}, extracting without adapting to the file system");
+                    VolumeKind::Other
+                });
+                for adaptation in volume.adaptations() {
+                    if no_adapt.contains(adaptation) {
+                        continue;
+                    }
+                    let notice = match adaptation {
+                        Adaptation::ForceCopy if *copy || store.is_some() || hard_links => continue,
+                        Adaptation::ForceCopy => "copying the files as it has no symbolic links",
+                        Adaptation::SizeCheck => "checking the files against its 4 GB size limit",
+                        Adaptation::PortableNames if *portable_names => continue,
+                        Adaptation::PortableNames => {
+                            "replacing the characters it rejects in file names"
+                        }
+                    };
+                    warn!("destination is on {}, {notice}", volume.name());
+                    adaptations.push(*adaptation);
+                }
+            }
+
+            let copy = *copy
+                || (*hardlink_or_copy && !hard_links)
+                || adaptations.contains(&Adaptation::ForceCopy);
+            let context = open_context(backup, &mut manifest, backup_keys.as_ref(), copy);
+            let date_layout = if *by_date {
+                let format = time::format_description::parse_owned::<2>(date_format)
+                    .context("invalid date format")?;
+                Some(DateLayout {
+                    field: *date_field,
+                    format,
+                })
+            } else {
+                None
+            };
+            let options = ExtractOptions {
+                strip_prefix: strip_prefix.clone(),
+                prefix_mismatch: *prefix_mismatch,
+                ignore_case: *ignore_case,
+                keep_going: *keep_going,
+                strict: *strict,
+                force: *force,
+                hard_links,
+                dedupe: *dedupe,
+                on_conflict: if *skip_existing {
+                    ConflictPolicy::Skip
+                } else if *overwrite {
+                    ConflictPolicy::Overwrite
+                } else if *fail_on_existing {
+                    ConflictPolicy::Error
+                } else {
+                    *on_conflict
+                },
+                date_layout,
+                free_space_guard: min_free_space.map(|min_free_space| FreeSpaceGuard {
+                    min_free_space,
+                    policy: *on_low_space,
+                }),
+                no_index: *no_index,
+                copy_buffer_size: *buffer_size as usize,
+                reflink: !*no_reflink,
+                include: if *notes_bundle {
+                    Some(build_glob_set(notes::NOTE_STORE_FILES, false)?)
+                } else if include.is_empty() {
+                    None
+                } else {
+                    Some(build_glob_set(include, *ignore_case)?)
+                },
+                exclude: if exclude.is_empty() {
+                    None
+                } else {
+                    Some(build_glob_set(exclude, *ignore_case)?)
+                },
+                regex: match regex {
+                    Some(regex) if *ignore_case => Some(
+                        RegexBuilder::new(regex.as_str())
+                            .case_insensitive(true)
+                            .build()?,
+                    ),
+                    regex => regex.clone(),
+                },
+                modified_after: *newer_than,
+                modified_before: *older_than,
+                priority: if priority_glob.is_empty() {
+                    None
+                } else {
+                    Some(build_glob_set(priority_glob, false)?)
+                },
+                store: store
+                    .as_deref()
+                    .map(ObjectStore::open)
+                    .transpose()
+                    .context("failed to open the store")?,
+                portable_names: *portable_names || adaptations.contains(&Adaptation::PortableNames),
+                max_file_size: adaptations
+                    .contains(&Adaptation::SizeCheck)
+                    .then_some(volume::FAT32_MAX_FILE_SIZE),
+                since_backup: since_backup
                     .as_ref()
-                    .map(|p| p as &Path)
-                    .expect("out_dir should not be empty"),
-                |event| {
+                    .map(|dir| open_older_manifest(dir))
+                    .transpose()
+                    .context("failed to open the manifest of the older backup")?,
+                protection_classes: protection_class.clone(),
+                placeholders: *placeholders,
+                preserve_timestamps: *preserve_timestamps && !*no_preserve_metadata,
+                preserve_permissions: *preserve_permissions,
+                paths: paths.clone(),
+                dry_run: *dry_run,
+                jobs: jobs.unwrap_or_else(|| {
+                    thread::available_parallelism().unwrap_or(NonZeroUsize::MIN)
+                }),
+            };
+
+            if let Some(domains) = &domains {
+                let out_dir = out_dir.expect("out_dir is required");
+                let domains: Vec<_> = domains
+                    .iter()
+                    .map(|domain| {
+                        (
+                            domain.clone(),
+                            PathBuf::from(domains::path_component(domain)),
+                        )
+                    })
+                    .collect();
+                extract_domains(&context, &domains, &out_dir, &options, *strict, appearance)?;
+                if *checkpoint_dbs {
+                    let checkpointed_count = checkpoint::checkpoint_databases(&out_dir)
+                        .context("failed to checkpoint databases")?;
+                    info!("checkpointed {checkpointed_count} database(s)");
+                }
+                return Ok(());
+            }
+            let domain = domain.expect("domain is checked above");
+
+            if *count {
+                let size = context
+                    .measure_extraction(domain, &options)
+                    .context("failed to count files")?;
+                println!("{} file(s), {}", size.files, HumanBytes(size.bytes));
+                return Ok(());
+            }
+
+            if let Some(archive) = archive {
+                let timer = utils::PerfTimer::new();
+                let pb_port = progress_bar::make(Default::default(), appearance);
+                let res = context.extract_to_archive(domain, archive, &options, |event| {
+                    pb_port.send(event);
+                });
+                pb_port.finish(res.as_ref().err().map(|err| format!("{err:#}")));
+                let report = res.context("failed to extract files into the archive")?;
+
+                for (reason, paths) in &report.skipped {
+                    warn!("{} file(s) skipped: {reason}", paths.len());
+                }
+                for (path, error) in &report.failed {
+                    error!("failed to extract `{path}`: {error}");
+                }
+                println!(
+                    "archived {} file(s), {}",
+                    report.files_extracted,
+                    HumanBytes(report.bytes_written)
+                );
+                timer.finish();
+                if !report.failed.is_empty() {
+                    return Err(anyhow!("{} file(s) failed to extract", report.failed.len()));
+                }
+                return Ok(());
+            }
+
+            let out_dir = out_dir.expect("out_dir is required");
+
+            if *dry_run {
+                let action = if options.store.is_some() {
+                    "store"
+                } else if copy {
+                    "copy"
+                } else if options.hard_links {
+                    "hard link"
+                } else {
+                    "link"
+                };
+                let (mut files, mut bytes) = (0, 0);
+                let report = context
+                    .extract_file(domain, &out_dir, &options, |event| {
+                        if let ProgressEvent::Planned { path, size } = event {
+                            println!("{action}\t{}", out_dir.join(path).to_string_lossy());
+                            files += 1;
+                            bytes += size;
+                        }
+                    })
+                    .context("failed to plan the extraction")?;
+                for (reason, paths) in &report.skipped {
+                    for path in paths {
+                        warn!("would skip `{path}`: {reason}");
+                    }
+                }
+                println!("{files} file(s), {} would be extracted", HumanBytes(bytes));
+                return Ok(());
+            }
+
+            if *sidecars_only {
+                let timer = utils::PerfTimer::new();
+                let pb_port = progress_bar::make(
+                    progress_bar::PhaseWeights {
+                        indexing: 0,
+                        extracting: 1,
+                    },
+                    appearance,
+                );
+                let res = context.write_sidecars(domain, &out_dir, |event| {
                     pb_port.send(event);
+                });
+                pb_port.finish(res.as_ref().err().map(|err| format!("{err:#}")));
+                res.context("failed to write sidecars")?;
+                timer.finish();
+                return Ok(());
+            }
+
+            // The files are written into the staging directory, which
+            // becomes `out_dir` once the extraction succeeds.
+            let staging = atomic
+                .then(|| StagingDir::new(&out_dir, *overwrite_dir))
+                .transpose()?;
+            let extract_dir = staging.as_ref().map_or(&*out_dir, StagingDir::path);
+
+            let recorder = Arc::new(Mutex::new(ReportRecorder::default()));
+            if summary_json.is_some() || staging.is_some() {
+                let recorder = recorder.clone();
+                let domain = domain.to_owned();
+                let adaptations = adaptations.clone();
+                let summary_path = summary_json.clone();
+                let staging_path = staging.as_ref().map(|s| s.path().to_owned());
+                let res = ctrlc::set_handler(move || {
+                    // Exiting skips the drop of the staging directory.
+                    if let Some(staging_path) = &staging_path {
+                        let _ = fs::remove_dir_all(staging_path);
+                    }
+                    let report = recorder.lock().unwrap().report();
+                    let mut summary = RunSummary::new(&domain, RunStatus::Cancelled, &report, None);
+                    summary.adaptations = adaptations.clone();
+                    if let Some(summary_path) = &summary_path {
+                        if let Err(err) = summary::write_summary(summary_path, &summary) {
+                            eprintln!("{err:?}");
+                        }
+                    }
+                    std::process::exit(summary.exit_code);
+                });
+                match res {
+                    // Only the first extraction of the process sets it, the
+                    // later ones only happen in the tests.
+                    Err(ctrlc::Error::MultipleHandlers) => {
+                        debug!("the interrupt handler is already set")
+                    }
+                    res => res.context("failed to set the interrupt handler")?,
+                }
+            }
+
+            let mut manifest_writer = write_manifest
+                .as_deref()
+                .map(|path| ExtractManifestWriter::create(path, domain))
+                .transpose()?;
+
+            // The file IDs of the extracted files and their paths, for
+            // `--metadata`.
+            let mut extracted = HashMap::new();
+
+            let timer = utils::PerfTimer::new();
+            let pb_port = progress_bar::make(Default::default(), appearance);
+            let res = context.extract_file(domain, extract_dir, &options, |event| {
+                recorder.lock().unwrap().record(&event);
+                if let ProgressEvent::Written {
+                    relative_path,
+                    file_id,
+                    path,
+                    size,
+                } = &event
+                {
+                    let path = path.strip_prefix(extract_dir).unwrap_or(path);
+                    if let Some(manifest_writer) = &mut manifest_writer {
+                        manifest_writer.add(&ManifestEntry {
+                            path: &path.to_string_lossy(),
+                            relative_path,
+                            file_id,
+                            size: *size,
+                        });
+                    }
+                    if metadata.is_some() {
+                        extracted.insert(file_id.clone(), path.to_string_lossy().into_owned());
+                    }
+                }
+                pb_port.send(event);
+            });
+            // Failed files with `--keep-going` discard the staged ones too.
+            let (res, discarded) = match (res, staging) {
+                (Ok(report), Some(staging)) if report.failed.is_empty() => {
+                    let res = staging
+                        .commit()
+                        .map(|()| report)
+                        .context("failed to move the extracted files into place");
+                    let discarded = res.is_err();
+                    (res, discarded)
+                }
+                (res, staging) => (res, staging.is_some()),
+            };
+
+            // Dispose the progress bar first to prevent it from being
+            // clobbered by the timer message.
+            pb_port.finish(match &res {
+                Ok(report) if !report.failed.is_empty() => {
+                    Some(format!("{} file(s) failed to extract", report.failed.len()))
+                }
+                Ok(_) => None,
+                Err(err) => Some(format!("{err:#}")),
+            });
+
+            let is_empty = matches!(&res, Ok(report) if report.files_total == 0);
+            let filtered = res.as_ref().ok().and_then(|report| report.filtered);
+            if is_empty && filtered.is_some() {
+                warn!("no files of domain `{domain}` matched the filters");
+            } else if is_empty && since_backup.is_some() {
+                warn!("no files of domain `{domain}` changed since the older backup");
+            } else if is_empty {
+                warn!(
+                    "domain `{domain}` contains no extractable files \
+                     (only directories, symlinks or metadata)"
+                );
+            }
+
+            if let Some(summary_path) = summary_json {
+                let mut summary = match &res {
+                    Ok(report) if is_empty => {
+                        RunSummary::new(domain, RunStatus::Empty, report, None)
+                    }
+                    Ok(report) if !report.failed.is_empty() => RunSummary::new(
+                        domain,
+                        RunStatus::Failed,
+                        report,
+                        Some(format!("{} file(s) failed to extract", report.failed.len())),
+                    ),
+                    Ok(report) => RunSummary::new(domain, RunStatus::Succeeded, report, None),
+                    Err(err) => {
+                        // The partial report of the events seen before the
+                        // failure.
+                        let report = recorder.lock().unwrap().report();
+                        RunSummary::new(
+                            domain,
+                            RunStatus::Failed,
+                            &report,
+                            Some(format!("{err:#}")),
+                        )
+                    }
+                };
+                summary.adaptations = adaptations;
+                summary::write_summary(summary_path, &summary)?;
+            }
+            // Nothing is left to describe once the staged files are gone.
+            if discarded {
+                warn!(
+                    "discarded the staged files, {} was left as it was",
+                    out_dir.to_string_lossy()
+                );
+                if let Some(manifest_path) = write_manifest.as_deref() {
+                    drop(manifest_writer.take());
+                    let _ = fs::remove_file(manifest_path);
+                }
+            }
+            if let Some(manifest_writer) = manifest_writer {
+                manifest_writer.finish()?;
+            }
+            if let Some(format) = metadata.filter(|_| !discarded) {
+                let metadata_path = out_dir.join(format.file_name());
+                let entry_count = context
+                    .write_metadata(domain, &extracted, &metadata_path, format)
+                    .context("failed to write metadata")?;
+                info!(
+                    "wrote the metadata of {entry_count} file(s) to {}",
+                    metadata_path.to_string_lossy()
+                );
+            }
+            let report = res.context("failed to extract files")?;
+
+            // With `--summary-json -`, stdout only carries the JSON.
+            let json_stdout = summary_json.as_deref() == Some(Path::new("-"));
+            let print_result = |line: String| {
+                if json_stdout {
+                    eprintln!("{line}");
+                } else {
+                    println!("{line}");
+                }
+            };
+
+            if let Some((matched, total)) = report.filtered {
+                print_result(format!("{matched} of {total} file(s) matched the filters"));
+            }
+            if !report.kept.is_empty() {
+                print_result(format!("kept {} existing file(s)", report.kept.len()));
+            }
+            if report.deduplicated > 0 {
+                print_result(format!(
+                    "hard linked {} duplicate file(s), saved {}",
+                    report.deduplicated,
+                    HumanBytes(report.bytes_saved)
+                ));
+            }
+            for (reason, paths) in &report.skipped {
+                warn!("{} file(s) skipped: {reason}", paths.len());
+            }
+            let failed_count = report.failed.len();
+            if failed_count > 0 {
+                for (path, error) in &report.failed {
+                    error!("failed to extract `{path}`: {error}");
+                }
+                if discarded {
+                    print_result(format!(
+                        "discarded {} extracted file(s), {failed_count} failed",
+                        report.files_extracted
+                    ));
+                } else {
+                    print_result(format!(
+                        "extracted {} file(s), {failed_count} failed",
+                        report.files_extracted
+                    ));
+                }
+            }
+            timer.finish();
+            if discarded {
+                return Err(anyhow!("{failed_count} file(s) failed to extract"));
+            }
+
+            if *write_deleted {
+                let deleted_path = out_dir.join("deleted.txt");
+                let deleted = report.changes.map(|c| c.deleted).unwrap_or_default();
+                let mut content = deleted.join("\n");
+                if !content.is_empty() {
+                    content.push('\n');
+                }
+                // An extracted file or symlink of the same name is never
+                // followed or overwritten.
+                fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&deleted_path)
+                    .and_then(|mut file| file.write_all(content.as_bytes()))
+                    .with_context(|| {
+                        format!("failed to write file: {}", deleted_path.to_string_lossy())
+                    })?;
+            }
+
+            if *checkpoint_dbs {
+                let checkpointed_count = checkpoint::checkpoint_databases(&out_dir)
+                    .context("failed to checkpoint databases")?;
+                info!("checkpointed {checkpointed_count} database(s)");
+            }
+
+            if *notes_bundle {
+                let db_path = out_dir.join(notes::NOTE_STORE_NAME);
+                if !db_path.exists() {
+                    return Err(anyhow!(
+                        "`{}` not found in domain `{domain}`",
+                        notes::NOTE_STORE_NAME
+                    ));
+                }
+                if *decode_notes {
+                    let decoded_count = notes::decode_notes(&db_path, &out_dir.join("notes"))
+                        .context("failed to decode notes")?;
+                    info!("decoded {decoded_count} note(s)");
+                }
+            }
+            if failed_count > 0 {
+                return Err(anyhow!("{failed_count} file(s) failed to extract"));
+            }
+        }
+        Command::Stat {
+            domain,
+            relative_path,
+            raw_plist,
+            ..
+        } => {
+            let context = open_context(backup, &mut manifest, backup_keys.as_ref(), false);
+            let file = context
+                .query_file(domain, relative_path)
+                .context("failed to query file")?;
+
+            if *raw_plist {
+                let file_plist = context
+                    .query_file_plist(&file.file_id)
+                    .context("failed to query file metadata")?;
+                file_plist
+                    .to_writer_xml(io::stdout())
+                    .context("failed to write plist")?;
+                println!();
+                return Ok(());
+            }
+
+            print_file_stat(&context, domain, &file);
+        }
+        Command::Cat {
+            domain,
+            relative_path,
+            ..
+        } => {
+            let context = open_context(backup, &mut manifest, backup_keys.as_ref(), false);
+            cat_file(&context, domain, relative_path)?;
+        }
+        Command::ShowBlob {
+            domain,
+            relative_path,
+            all_matches,
+            ignore_case,
+            ..
+        } => {
+            let context = open_context(backup, &mut manifest, backup_keys.as_ref(), false);
+            let files = if *all_matches {
+                let matcher = GlobBuilder::new(relative_path)
+                    .case_insensitive(*ignore_case)
+                    .build()
+                    .context("invalid glob pattern")?
+                    .compile_matcher();
+                let files: Vec<_> = context
+                    .query_files(domain)
+                    .context("failed to query files")?
+                    .into_iter()
+                    .filter(|f| f.file_type == ManifestFileType::File)
+                    .filter(|f| matcher.is_match(&f.relative_path))
+                    .collect();
+                if files.is_empty() {
+                    return Err(anyhow!("no file matches `{relative_path}`"));
+                }
+                files
+            } else {
+                let file = context
+                    .query_file(domain, relative_path)
+                    .context("failed to query file")?;
+                if file.file_type != ManifestFileType::File {
+                    return Err(anyhow!("not a regular file: {relative_path}"));
+                }
+                vec![file]
+            };
+
+            let mut missing_count = 0;
+            for file in files {
+                let blob_path = context.original_file_path(&file.file_id);
+                match fs::canonicalize(&blob_path) {
+                    Ok(blob_path) => println!("{}", blob_path.to_string_lossy()),
+                    Err(_) => {
+                        error!(
+                            "blob of `{}` is missing: {}",
+                            file.relative_path,
+                            blob_path.to_string_lossy()
+                        );
+                        missing_count += 1;
+                    }
+                }
+            }
+            if missing_count > 0 {
+                return Err(anyhow!("{missing_count} blob(s) are missing"));
+            }
+        }
+        Command::Whois {
+            file_ids, metadata, ..
+        } => {
+            let context = open_context(backup, &mut manifest, backup_keys.as_ref(), false);
+
+            let mut file_ids = file_ids.clone();
+            if let Some(pos) = file_ids.iter().position(|id| id == "-") {
+                file_ids.remove(pos);
+                for line in io::stdin().lines() {
+                    let line = line.context("failed to read stdin")?;
+                    if let Some(file_id) = file_id_of_line(&line) {
+                        file_ids.push(file_id.to_owned());
+                    }
+                }
+            }
+
+            let mut unknown_count = 0;
+            for file_id in &file_ids {
+                let found = context
+                    .query_file_by_id(file_id)
+                    .with_context(|| format!("failed to look up `{file_id}`"))?;
+                match found {
+                    Some((domain, file)) if *metadata => {
+                        print_file_stat(&context, &domain, &file);
+                        println!();
+                    }
+                    // The full ID, given a prefix.
+                    Some((domain, file)) => println!(
+                        "{}\t{domain}\t{}\t{:?}\t{}",
+                        file.file_id,
+                        file.relative_path,
+                        file.file_type,
+                        file.size.map_or_else(|| "-".to_owned(), |s| s.to_string())
+                    ),
+                    None => {
+                        println!("{file_id}\tnot in manifest");
+                        unknown_count += 1;
+                    }
+                }
+            }
+            if unknown_count > 0 {
+                return Err(anyhow!(
+                    "{unknown_count} file ID(s) are not in the manifest"
+                ));
+            }
+        }
+        Command::ExtractApp {
+            bundle_id,
+            out_dir,
+            copy,
+            strict,
+            ..
+        } => {
+            let all = manifest.query_domains().context("failed to list domains")?;
+            let app_domains = domains::app_domains(bundle_id, &all)?;
+            let context = open_context(backup, &mut manifest, backup_keys.as_ref(), *copy);
+            extract_domains(
+                &context,
+                &app_domains,
+                out_dir,
+                &ExtractOptions {
+                    strict: *strict,
+                    ..Default::default()
                 },
-            )
-            .context("failed to extract files")?;
+                *strict,
+                appearance,
+            )?;
+        }
+        Command::ExtractId {
+            file_id,
+            out_path,
+            copy,
+            raw,
+            ..
+        } => {
+            let context = open_context(backup, &mut manifest, backup_keys.as_ref(), *copy);
+            let blob = context
+                .extract_by_id(file_id, out_path, *raw)
+                .context("failed to extract blob")?;
+            match &blob.entry {
+                Some((domain, file)) => println!(
+                    "{domain}\t{}\t-> {}",
+                    file.relative_path,
+                    blob.path.to_string_lossy()
+                ),
+                None => println!(
+                    "{file_id}\tnot in manifest\t-> {}",
+                    blob.path.to_string_lossy()
+                ),
+            }
+        }
+        Command::Search {
+            name,
+            ignore_case,
+            content,
+            domain,
+            under,
+            max_size,
+            binary,
+            jobs,
+            ..
+        } => {
+            let context = open_context(backup, &mut manifest, backup_keys.as_ref(), false);
+            if let Some(name) = name {
+                let matches = context
+                    .search_paths(name, *ignore_case)
+                    .context("failed to search files")?;
+                for m in &matches {
+                    println!("{}\t{}", m.domain, m.relative_path);
+                }
+                // Stdout only lists the matches, so that it can be piped.
+                eprintln!("{} match(es)", matches.len());
+                return Ok(());
+            }
+
+            let content = content
+                .as_ref()
+                .expect("`--content` is required without `--name`");
+            let domain = domain.as_deref().expect("`--content` requires a domain");
+            let options = ContentSearchOptions {
+                under: under.as_deref(),
+                max_size: *max_size,
+                binary: *binary,
+                jobs: jobs.unwrap_or_else(|| {
+                    thread::available_parallelism().unwrap_or(NonZeroUsize::MIN)
+                }),
+            };
+
+            let stats = context
+                .search_content(domain, content, &options, |m| {
+                    println!("{}:{}: {}", m.relative_path, m.offset, m.context);
+                })
+                .context("failed to search files")?;
+            eprintln!(
+                "{} of {} searched file(s) matched, skipped {} too large, {} binary, {} missing",
+                stats.matched,
+                stats.searched,
+                stats.skipped_too_large,
+                stats.skipped_binary,
+                stats.skipped_missing
+            );
+        }
+        Command::CheckTimestamps { domain, .. } => {
+            let context = open_context(backup, &mut manifest, backup_keys.as_ref(), false);
+
+            let info = info::read_info(&backup.backup_dir).context("failed to read backup info")?;
+            let reference = info.last_backup_date.unwrap_or_else(|| {
+                warn!("backup date is unknown, comparing against the current time");
+                unix_timestamp(SystemTime::now())
+            });
+            let domains = match domain {
+                Some(domain) => vec![domain.clone()],
+                None => context.list_domains().context("failed to list domains")?,
+            };
+
+            let timer = utils::PerfTimer::new();
+            let anomalies = context
+                .scan_timestamps(&domains, reference)
+                .context("failed to scan timestamps")?;
+            timer.finish();
+
+            for anomaly in &anomalies {
+                let reason = match anomaly.kind {
+                    TimestampAnomalyKind::InFuture => "later than the backup date",
+                    TimestampAnomalyKind::TooOld => "implausibly old",
+                };
+                println!(
+                    "{}\t{}\t{} {} ({reason})",
+                    anomaly.domain,
+                    anomaly.relative_path,
+                    anomaly.field,
+                    format_timestamp(anomaly.timestamp)
+                );
+            }
+            println!("{} suspicious timestamp(s) found", anomalies.len());
+        }
+        Command::Verify {
+            domain, check_ids, ..
+        } => {
+            let context = open_context(backup, &mut manifest, backup_keys.as_ref(), false);
+            let domains = match domain {
+                Some(domain) => vec![domain.clone()],
+                None => context.list_domains().context("failed to list domains")?,
+            };
+
+            let timer = utils::PerfTimer::new();
+            let verification = context
+                .verify_blobs(&domains, *check_ids)
+                .context("failed to verify blobs")?;
+            timer.finish();
+
+            for problem in &verification.problems {
+                let reason = match problem.kind {
+                    BlobProblemKind::Missing => "missing".to_owned(),
+                    BlobProblemKind::SizeMismatch { expected, actual } => {
+                        format!("size mismatch, expected {expected} bytes, found {actual}")
+                    }
+                    BlobProblemKind::Misnamed => "file ID doesn't match the path".to_owned(),
+                };
+                println!(
+                    "{}\t{}\t{}\t{reason}",
+                    problem.domain, problem.relative_path, problem.file_id
+                );
+            }
+            println!("{} file(s) checked", verification.files_checked);
+            if !verification.problems.is_empty() {
+                return Err(anyhow!(
+                    "{} problem(s) found in the backup",
+                    verification.problems.len()
+                ));
+            }
+        }
+        Command::Pack {
+            out_path, domains, ..
+        } => {
+            let context = open_context(backup, &mut manifest, backup_keys.as_ref(), false);
+
+            let timer = utils::PerfTimer::new();
+            let pb_port = progress_bar::make(Default::default(), appearance);
+            let res = context.pack(out_path, domains, |event| {
+                pb_port.send(event);
+            });
+            pb_port.finish(res.as_ref().err().map(|err| format!("{err:#}")));
+            res.context("failed to pack the backup")?;
+
+            timer.finish();
+        }
+        Command::Stats {
+            domain,
+            by_protection_class,
+            protection_class,
+            ..
+        } => {
+            let context = open_context(backup, &mut manifest, backup_keys.as_ref(), false);
+            let domains = match domain {
+                Some(domain) => vec![domain.clone()],
+                None => context.list_domains().context("failed to list domains")?,
+            };
+
+            let timer = utils::PerfTimer::new();
+            let counts = context
+                .count_by_protection_class(&domains, protection_class)
+                .context("failed to count files")?;
+            timer.finish();
+
+            if *by_protection_class {
+                for (class, size) in &counts {
+                    println!(
+                        "{class}\t{} file(s), {}",
+                        size.files,
+                        HumanBytes(size.bytes)
+                    );
+                }
+            }
+            let files: usize = counts.values().map(|size| size.files).sum();
+            let bytes: u64 = counts.values().map(|size| size.bytes).sum();
+            println!("total\t{files} file(s), {}", HumanBytes(bytes));
+        }
+        Command::Backups { .. } | Command::Info { .. } | Command::Store { .. } => {
+            unreachable!("command doesn't need a backup")
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the index like the `tree` tool does, down to `max_depth`
+/// levels.
+fn print_tree<S>(
+    index: &FileSystemIndex,
+    root: &str,
+    max_depth: Option<usize>,
+    size_of: S,
+    charset: progress_bar::Charset,
+) where
+    S: Fn(&str) -> u64,
+{
+    let (branch, last_branch, pipe) = match charset {
+        progress_bar::Charset::Unicode => ("├── ", "└── ", "│   "),
+        progress_bar::Charset::Ascii => ("|-- ", "`-- ", "|   "),
+    };
+    let indent = |ancestors_last: &[bool]| -> String {
+        ancestors_last
+            .iter()
+            .map(|is_last| if *is_last { "    " } else { pipe })
+            .collect()
+    };
+
+    println!("{root}");
+    let mut dir_count = 0;
+    // Whether each ancestor of the current entry is the last of its
+    // directory, which decides whether its line continues.
+    let mut ancestors_last = vec![];
+    index.walk_tree(max_depth, size_of, |entry| {
+        ancestors_last.truncate(entry.depth);
+        let mut line = indent(&ancestors_last);
+        line.push_str(if entry.is_last { last_branch } else { branch });
+        line.push_str(entry.name);
+        if let Some(target) = entry.target {
+            line.push_str(&format!(" -> {target}"));
+        }
+        if let Some(subtree) = &entry.subtree {
+            dir_count += 1;
+            line.push_str(&format!("/ ({} file(s))", subtree.files));
+        }
+        println!("{line}");
+        ancestors_last.push(entry.is_last);
+
+        if let Some(subtree) = entry.subtree.filter(|_| entry.collapsed) {
+            dir_count += subtree.dirs;
+            println!(
+                "{}{last_branch}{} {}",
+                indent(&ancestors_last),
+                ellipsis(charset),
+                collapsed_entries(&subtree)
+            );
+        }
+    });
+    println!("\n{dir_count} dir(s), {} file(s)", index.file_count());
+}
+
+/// An entry as listed by `list-files --format ndjson`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FileListing<'a> {
+    path: &'a str,
+    #[serde(rename = "type")]
+    file_type: &'static str,
+    size: Option<u64>,
+    /// Modification time in seconds since the Unix epoch.
+    mtime: Option<i64>,
+    #[serde(rename = "fileID")]
+    file_id: &'a str,
+}
+
+impl<'a> FileListing<'a> {
+    fn new(file: &'a ManifestFile) -> Self {
+        Self {
+            path: &file.relative_path,
+            file_type: file_type_name(file.file_type),
+            size: file.size,
+            mtime: file.last_modified,
+            file_id: &file.file_id,
+        }
+    }
+}
+
+/// Entries of a domain above which sorting its `ndjson` listing is worth
+/// a warning, as they are all held in memory.
+const LARGE_DOMAIN_ENTRY_COUNT: usize = 100_000;
+
+fn write_file_listing<W>(writer: &mut W, file: &ManifestFile) -> Result<()>
+where
+    W: Write,
+{
+    let line = serde_json::to_string(&FileListing::new(file))?;
+    writeln!(writer, "{line}")?;
+    Ok(())
+}
+
+/// Sorts the files by `sort` then by path.
+fn sort_files(files: &mut [ManifestFile], sort: FileSort, reverse: bool) {
+    let file_name = |file: &ManifestFile| {
+        let (_, name) = file
+            .relative_path
+            .rsplit_once('/')
+            .unwrap_or(("", &file.relative_path));
+        name.to_owned()
+    };
+    files.sort_by(|a, b| {
+        let order = match sort {
+            FileSort::Name => file_name(a).cmp(&file_name(b)),
+            FileSort::Size => a.size.cmp(&b.size),
+            FileSort::Mtime => a.last_modified.cmp(&b.last_modified),
+            FileSort::Path => a.relative_path.cmp(&b.relative_path),
+        };
+        let order = if reverse { order.reverse() } else { order };
+        order.then_with(|| a.relative_path.cmp(&b.relative_path))
+    });
+}
+
+fn file_type_name(file_type: ManifestFileType) -> &'static str {
+    match file_type {
+        ManifestFileType::File => "file",
+        ManifestFileType::Directory => "dir",
+        ManifestFileType::SymbolicLink => "link",
+    }
+}
+
+fn is_broken_pipe(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<io::Error>()
+        .is_some_and(|err| err.kind() == io::ErrorKind::BrokenPipe)
+}
+
+fn ellipsis(charset: progress_bar::Charset) -> &'static str {
+    match charset {
+        progress_bar::Charset::Unicode => "…",
+        progress_bar::Charset::Ascii => "...",
+    }
+}
+
+/// Summarizes the entries left out of a listing, e.g.
+/// `(1,234 more entries, 456.00 MiB)`.
+fn collapsed_entries(subtree: &SubtreeSize) -> String {
+    let entries = subtree.entries();
+    format!(
+        "({} more {}, {})",
+        HumanCount(entries as u64),
+        if entries == 1 { "entry" } else { "entries" },
+        HumanBytes(subtree.bytes)
+    )
+}
+
+fn print_file_stat(context: &AppContext, domain: &str, file: &ManifestFile) {
+    for (name, value) in file_stat_fields(context, domain, file) {
+        println!("{:>10}: {value}", console::style(name).bold());
+    }
+}
+
+/// Names and values of everything known about the file, as `stat` prints
+/// them.
+fn file_stat_fields(
+    context: &AppContext,
+    domain: &str,
+    file: &ManifestFile,
+) -> Vec<(&'static str, String)> {
+    fn or_dash<T: ToString>(value: Option<T>) -> String {
+        value.map_or_else(|| "-".to_owned(), |v| v.to_string())
+    }
+
+    let blob_path = context.original_file_path(&file.file_id);
+    let blob_state = match fs::metadata(&blob_path) {
+        Ok(metadata) => format!("present ({} bytes)", metadata.len()),
+        Err(_) => "missing".to_owned(),
+    };
 
-        // Dispose the progress bar first to prevent it from being
-        // clobbered by the timer message.
-        drop(pb_port);
+    vec![
+        ("Domain", domain.to_owned()),
+        ("Path", file.relative_path.clone()),
+        ("File ID", file.file_id.clone()),
+        ("Type", format!("{:?}", file.file_type)),
+        ("Size", or_dash(file.size)),
+        (
+            "Mode",
+            or_dash(file.mode.map(|m| format!("{m:o} ({})", format_mode(m)))),
+        ),
+        ("User ID", or_dash(file.user_id)),
+        ("Group ID", or_dash(file.group_id)),
+        ("Inode", or_dash(file.inode)),
+        (
+            "Protection",
+            or_dash(file.protection_class.map(|c| ProtectionClass(Some(c)))),
+        ),
+        (
+            "Modified",
+            or_dash(file.last_modified.map(format_timestamp)),
+        ),
+        (
+            "Changed",
+            or_dash(file.last_status_change.map(format_timestamp)),
+        ),
+        ("Birth", or_dash(file.birth.map(format_timestamp))),
+        (
+            "Digest",
+            or_dash(
+                file.digest
+                    .as_ref()
+                    .map(|d| d.iter().map(|b| format!("{b:02x}")).collect::<String>()),
+            ),
+        ),
+        ("Target", or_dash(file.target.as_ref())),
+        ("Blob", blob_path.to_string_lossy().into_owned()),
+        ("Blob state", blob_state),
+    ]
+}
+
+/// Reads a file ID from a line given to `whois` on stdin, which may be the
+/// path to the blob too, e.g. from `find`.
+fn file_id_of_line(line: &str) -> Option<&str> {
+    let file_id = line.trim().rsplit(['/', '\\']).next().unwrap_or_default();
+    (!file_id.is_empty()).then_some(file_id)
+}
+
+/// Runs the commands that don't open a single backup (listing backups,
+/// reading backup info and managing stores).
+fn run_without_backup(command: &Command) -> Result<()> {
+    match command {
+        Command::Backups { backups_dir } => {
+            let backups_dir = match backups_dir {
+                Some(backups_dir) => backups_dir.clone(),
+                None => info::default_backups_dir().ok_or_else(|| {
+                    anyhow!("no backup directory found at the default location, pass one")
+                })?,
+            };
+            let backups = info::list_backups(&backups_dir).context("failed to list backups")?;
+            if backups.is_empty() {
+                return Err(anyhow!(
+                    "no backup found in {}",
+                    backups_dir.to_string_lossy()
+                ));
+            }
+
+            let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_owned());
+            for (idx, backup) in backups.iter().enumerate() {
+                let info = &backup.info;
+                println!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    idx + 1,
+                    or_dash(info.device_name.clone()),
+                    or_dash(info.product_version.as_ref().map(|v| format!("iOS {v}"))),
+                    or_dash(info.last_backup_date.map(format_timestamp)),
+                    backup.path.to_string_lossy()
+                );
+            }
+        }
+        Command::Info { backup_dir } => {
+            let info = info::read_info(backup_dir).context("failed to read backup info")?;
+            let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_owned());
+            let yes_no = |value: Option<bool>| {
+                or_dash(value.map(|v| if v { "yes" } else { "no" }.to_owned()))
+            };
+            let fields = [
+                ("Device", or_dash(info.device_name.clone())),
+                ("Model", or_dash(info.product_type.clone())),
+                ("iOS", or_dash(info.product_version.clone())),
+                ("Serial", or_dash(info.serial_number.clone())),
+                (
+                    "Backed up",
+                    or_dash(info.last_backup_date.map(format_timestamp)),
+                ),
+                ("Encrypted", yes_no(info.is_encrypted)),
+                ("Complete", yes_no(info.is_complete)),
+            ];
+            for (name, value) in fields {
+                println!("{:>9}: {value}", console::style(name).bold());
+            }
+        }
+        Command::Store {
+            command: StoreCommand::Verify { store_dir },
+        } => {
+            let store =
+                ObjectStore::open_read_only(store_dir).context("failed to open the store")?;
+            let timer = utils::PerfTimer::new();
+            let stats = store.verify().context("failed to verify the store")?;
+            timer.finish();
+
+            for object_path in &stats.corrupted {
+                println!("corrupted: {}", object_path.to_string_lossy());
+            }
+            if !stats.corrupted.is_empty() {
+                return Err(anyhow!(
+                    "{} of {} object(s) are corrupted",
+                    stats.corrupted.len(),
+                    stats.verified
+                ));
+            }
+            println!("{} object(s) verified", stats.verified);
+        }
+        _ => unreachable!("command needs a backup"),
+    }
+    Ok(())
+}
+
+fn open_context<'a>(
+    backup: &'a BackupArgs,
+    manifest: &'a mut BackupManifest,
+    backup_keys: Option<&BackupKeys>,
+    copy: bool,
+) -> AppContext<'a, 'a> {
+    let index_cache = backup.index_cache.as_ref().map(|dir| IndexCache {
+        dir: dir.clone(),
+        refresh: backup.refresh_index,
+    });
+    let bucket = backup.bucket.unwrap_or(if manifest.is_legacy() {
+        BucketStrategy::None
+    } else {
+        BucketStrategy::default()
+    });
+    AppContext::new(&backup.backup_dir, manifest, copy, bucket)
+        .with_index_cache(index_cache)
+        .with_backup_keys(backup_keys.cloned())
+}
 
-        timer.finish();
+/// Opens the manifest of the older backup of an incremental extraction.
+fn open_older_manifest(backup_dir: &Path) -> Result<BackupManifest> {
+    if crypto::read_encrypted_manifest(backup_dir)?.is_some() {
+        return Err(anyhow!(
+            "the older backup is encrypted, which is not supported yet"
+        ));
     }
+    BackupManifest::open_backup(backup_dir)
+}
 
+/// Unlocks an encrypted backup and decrypts its manifest database into a
+/// temporary file, which must outlive the opened manifest.
+fn unlock_backup(
+    backup: &BackupArgs,
+    encrypted_manifest: &EncryptedManifest,
+) -> Result<(BackupKeys, NamedTempFile)> {
+    let password = match &backup.password {
+        Some(password) => password.clone(),
+        None if io::stdin().is_terminal() => rpassword::prompt_password("Backup password: ")
+            .context("failed to read the backup password")?,
+        None => {
+            return Err(anyhow!(
+                "this backup is encrypted; pass its password with `--password`, \
+                 or create an unencrypted backup"
+            ))
+        }
+    };
+    let backup_keys = encrypted_manifest.unlock(&password)?;
+
+    let decrypted_manifest =
+        NamedTempFile::new().context("failed to create the decrypted manifest database")?;
+    encrypted_manifest
+        .decrypt_manifest_db(
+            &backup_keys,
+            &backup.backup_dir.join(db::MANIFEST_DB_NAME),
+            decrypted_manifest.path(),
+        )
+        .context("failed to decrypt the manifest database")?;
+    Ok((backup_keys, decrypted_manifest))
+}
+
+/// Writes the content of a file to stdout.
+fn cat_file(context: &AppContext, domain: &str, relative_path: &str) -> Result<()> {
+    let mut file = context
+        .open_file(domain, relative_path)
+        .context("failed to open file")?;
+    let copied = io::copy(&mut file, &mut io::stdout().lock()).context("failed to write file")?;
+    if let Some(size) = file.metadata().size.filter(|size| *size != copied) {
+        warn!("content is {copied} bytes, but the manifest records {size} bytes");
+    }
     Ok(())
 }
+
+fn build_glob_set<S: AsRef<str>>(patterns: &[S], case_insensitive: bool) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let pattern = pattern.as_ref();
+        builder.add(
+            GlobBuilder::new(pattern)
+                .case_insensitive(case_insensitive)
+                .build()
+                .with_context(|| format!("invalid glob pattern: {pattern}"))?,
+        );
+    }
+    builder.build().context("invalid glob patterns")
+}
+
+/// Extracts each domain into its subdirectory of `out_dir`.
+///
+/// A domain that fails to extract is reported and skipped, unless `strict`.
+fn extract_domains(
+    context: &AppContext,
+    domains: &[(String, PathBuf)],
+    out_dir: &Path,
+    options: &ExtractOptions,
+    strict: bool,
+    appearance: progress_bar::Appearance,
+) -> Result<()> {
+    // Counted upfront, so that the progress doesn't restart per domain. A
+    // domain that can't be measured fails again when it's extracted.
+    let file_counts: Vec<_> = domains
+        .iter()
+        .map(|(domain, _)| {
+            context
+                .measure_extraction(domain, options)
+                .map_or(0, |size| size.files)
+        })
+        .collect();
+    let files_total = file_counts.iter().sum();
+
+    let timer = utils::PerfTimer::new();
+    let pb_port = progress_bar::make(Default::default(), appearance);
+    let mut files_before = 0;
+    let mut files_extracted = 0;
+    let mut failures = vec![];
+    let mut failed_files = vec![];
+    let mut skipped_counts = BTreeMap::new();
+    for (index, (domain, subdir)) in domains.iter().enumerate() {
+        pb_port.send(ProgressEvent::Domain {
+            index,
+            total: domains.len(),
+            files_before,
+            files_total,
+        });
+        files_before += file_counts[index];
+
+        // Each domain gets its own index, which is dropped before the next.
+        let res = context.extract_file(domain, &out_dir.join(subdir), options, |event| {
+            pb_port.send(event)
+        });
+        match res {
+            Ok(report) => {
+                files_extracted += report.files_extracted;
+                for (reason, paths) in &report.skipped {
+                    *skipped_counts.entry(*reason).or_insert(0) += paths.len();
+                }
+                failed_files.extend(report.failed.into_iter().map(|failed| (domain, failed)));
+            }
+            Err(err) if !strict => failures.push((domain, err)),
+            Err(err) => {
+                let err = err.context(format!("failed to extract domain `{domain}`"));
+                pb_port.finish(Some(format!("{err:#}")));
+                return Err(err);
+            }
+        }
+    }
+    let error = if !failures.is_empty() {
+        Some(anyhow!(
+            "{} of {} domain(s) failed to extract",
+            failures.len(),
+            domains.len()
+        ))
+    } else if !failed_files.is_empty() {
+        Some(anyhow!("{} file(s) failed to extract", failed_files.len()))
+    } else {
+        None
+    };
+    pb_port.finish(error.as_ref().map(|err| err.to_string()));
+
+    for (domain, err) in &failures {
+        error!("failed to extract domain `{domain}`: {err:#}");
+    }
+    for (domain, (path, error)) in &failed_files {
+        error!("failed to extract `{path}` of domain `{domain}`: {error}");
+    }
+    for (reason, count) in &skipped_counts {
+        warn!("{count} file(s) skipped: {reason}");
+    }
+    print!(
+        "extracted {files_extracted} file(s) from {} domain(s)",
+        domains.len() - failures.len()
+    );
+    if failed_files.is_empty() {
+        println!();
+    } else {
+        println!(", {} file(s) failed", failed_files.len());
+    }
+    timer.finish();
+
+    match error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Expands the `{device}`, `{date}`, `{ios}` and `{domain}` tokens in the
+/// destination path, using the information from `Info.plist`.
+fn resolve_out_dir(out_dir: &Path, backup_dir: &Path, domain: Option<&str>) -> Result<PathBuf> {
+    let Some(template) = out_dir.to_str().filter(|s| s.contains('{')) else {
+        return Ok(out_dir.to_owned());
+    };
+    let info = info::read_info(backup_dir).context("failed to read backup info")?;
+
+    let mut resolved = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            return Err(anyhow!("unclosed token in destination path: {template}"));
+        };
+        let token = &rest[start + 1..start + len];
+        let value = match token {
+            "device" => info.device_name.clone(),
+            "ios" => info.product_version.clone(),
+            "date" => info
+                .last_backup_date
+                .map(|d| format_timestamp(d)[0..10].to_owned()),
+            "domain" => match domain {
+                Some(domain) => Some(domain.to_owned()),
+                None => {
+                    return Err(anyhow!(
+                        "{{domain}} can't be used when extracting several domains"
+                    ))
+                }
+            },
+            _ => return Err(anyhow!("unknown token in destination path: {{{token}}}")),
+        };
+        let Some(value) = value else {
+            return Err(anyhow!("`Info.plist` has no value for {{{token}}}"));
+        };
+
+        resolved.push_str(&rest[..start]);
+        // Values go into a single path component.
+        resolved.push_str(&domains::path_component(&value));
+        rest = &rest[start + len + 1..];
+    }
+    resolved.push_str(rest);
+
+    Ok(PathBuf::from(resolved))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, SystemTime};
+
+    use clap::Parser;
+    use plist::{Dictionary, Value};
+
+    use super::{file_id_of_line, file_stat_fields, resolve_out_dir, run, sort_files};
+    use crate::cli::{Args, FileSort};
+    use crate::crypto::test_support::{keybag, wrap_key, PASSWORD};
+    use crate::utils::test_fixture::FixtureBackup;
+
+    #[test]
+    fn it_discards_failed_atomic_extractions() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Media/a.txt", b"hello");
+        let file_id = fixture.add_file("HomeDomain", "Media/b.txt", b"gone");
+        std::fs::remove_file(fixture.path().join(&file_id[0..2]).join(&file_id)).unwrap();
+
+        let backup_dir = fixture.path().to_str().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let out_dir = dir.path().join("out");
+        let manifest_path = dir.path().join("out.json");
+        let args = Args::try_parse_from([
+            "ibackupextractor",
+            "extract",
+            backup_dir,
+            "HomeDomain",
+            "-o",
+            out_dir.to_str().unwrap(),
+            "-c",
+            "--atomic",
+            "--keep-going",
+            "--metadata",
+            "json",
+            "--write-manifest",
+            manifest_path.to_str().unwrap(),
+        ])
+        .unwrap();
+        let err = run(args).unwrap_err();
+        assert_eq!(err.to_string(), "1 file(s) failed to extract");
+        assert!(!out_dir.exists());
+        assert!(!manifest_path.exists());
+    }
+
+    #[test]
+    fn it_sorts_files_with_ties_broken_by_path() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "b/x", b"abc");
+        fixture.add_file("HomeDomain", "a/y", b"a");
+        fixture.add_file("HomeDomain", "a/x", b"abc");
+        fixture.add_dir("HomeDomain", "a");
+        let mut files = fixture.manifest().query_files("HomeDomain").unwrap();
+
+        let mut sorted = |sort, reverse| {
+            sort_files(&mut files, sort, reverse);
+            files
+                .iter()
+                .map(|file| file.relative_path.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sorted(FileSort::Path, false), ["a", "a/x", "a/y", "b/x"]);
+        assert_eq!(sorted(FileSort::Path, true), ["b/x", "a/y", "a/x", "a"]);
+        assert_eq!(sorted(FileSort::Name, false), ["a", "a/x", "b/x", "a/y"]);
+        assert_eq!(sorted(FileSort::Size, false), ["a", "a/y", "a/x", "b/x"]);
+        assert_eq!(sorted(FileSort::Size, true), ["a/x", "b/x", "a/y", "a"]);
+        assert_eq!(sorted(FileSort::Mtime, true), ["a", "a/x", "a/y", "b/x"]);
+    }
+
+    #[test]
+    fn it_describes_files() {
+        let fixture = FixtureBackup::new();
+        let file_id = fixture.add_file("HomeDomain", "Media/a.txt", b"hello");
+        fixture.add_dir("HomeDomain", "Media");
+        let blob_path = fixture.path().join(&file_id[0..2]).join(&file_id);
+
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+        let file = context.query_file("HomeDomain", "Media/a.txt").unwrap();
+        let fields = file_stat_fields(&context, "HomeDomain", &file);
+        let field = |name| {
+            fields
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, value)| value.as_str())
+                .unwrap()
+        };
+        assert_eq!(field("File ID"), file_id);
+        assert_eq!(field("Type"), "File");
+        assert_eq!(field("Size"), "5");
+        assert_eq!(field("Mode"), "100644 (-rw-r--r--)");
+        assert_eq!(
+            field("Protection"),
+            "3 (NSFileProtectionCompleteUntilFirstUserAuthentication)"
+        );
+        assert_eq!(field("Modified"), "2022-04-15T05:20:00Z");
+        assert_eq!(field("Target"), "-");
+        assert_eq!(field("Blob"), blob_path.to_string_lossy());
+        assert_eq!(field("Blob state"), "present (5 bytes)");
+
+        std::fs::remove_file(&blob_path).unwrap();
+        let dir = context.query_file("HomeDomain", "Media").unwrap();
+        let fields = file_stat_fields(&context, "HomeDomain", &file);
+        assert!(fields.contains(&("Blob state", "missing".to_owned())));
+        let fields = file_stat_fields(&context, "HomeDomain", &dir);
+        assert!(fields.contains(&("Mode", "40755 (drwxr-xr-x)".to_owned())));
+    }
+
+    #[test]
+    fn it_resolves_destination_tokens() {
+        let backup_dir = tempfile::tempdir().unwrap();
+        let resolve =
+            |out_dir: &str, domain| resolve_out_dir(Path::new(out_dir), backup_dir.path(), domain);
+        assert!(resolve("out/{device}", None).is_err());
+
+        let mut info = Dictionary::new();
+        info.insert("Device Name".to_owned(), "Bob's iPhone/2".into());
+        info.insert("Product Version".to_owned(), "17.1".into());
+        let date = SystemTime::UNIX_EPOCH + Duration::from_secs(1_650_000_000);
+        info.insert("Last Backup Date".to_owned(), Value::Date(date.into()));
+        plist::to_file_xml(backup_dir.path().join("Info.plist"), &info).unwrap();
+
+        assert_eq!(
+            resolve("out/{device}-{date}/{ios}/{domain}", Some("HomeDomain")).unwrap(),
+            PathBuf::from("out/Bob's iPhone_2-2022-04-15/17.1/HomeDomain")
+        );
+        assert_eq!(
+            resolve("out/plain", None).unwrap(),
+            PathBuf::from("out/plain")
+        );
+        for template in ["out/{domain}", "out/{udid}", "out/{device"] {
+            assert!(resolve(template, None).is_err(), "{template}");
+        }
+
+        info.remove("Product Version");
+        plist::to_file_xml(backup_dir.path().join("Info.plist"), &info).unwrap();
+        let err = resolve("out/{ios}", None).unwrap_err();
+        assert!(err.to_string().contains("no value for {ios}"));
+    }
+
+    #[test]
+    fn it_reports_unknown_file_ids() {
+        let fixture = FixtureBackup::new();
+        let file_id = fixture.add_file("HomeDomain", "Media/a.txt", b"hello");
+
+        let backup_dir = fixture.path().to_str().unwrap();
+        let unknown_id = "ff".repeat(20);
+        let whois = |file_ids: &[&str]| {
+            let command = ["ibackupextractor", "whois", backup_dir];
+            run(Args::try_parse_from(command.iter().chain(file_ids)).unwrap())
+        };
+        whois(&[&file_id, &file_id[0..8]]).unwrap();
+        let err = whois(&[&file_id, &unknown_id, "00ff"]).unwrap_err();
+        assert_eq!(err.to_string(), "2 file ID(s) are not in the manifest");
+
+        assert_eq!(file_id_of_line(&format!(" {file_id}\n")), Some(&*file_id));
+        assert_eq!(
+            file_id_of_line(&format!("./{}/{file_id}", &file_id[0..2])),
+            Some(&*file_id)
+        );
+        assert_eq!(file_id_of_line("  "), None);
+        assert_eq!(file_id_of_line("dir/"), None);
+    }
+
+    #[test]
+    fn it_reports_empty_domains() {
+        let fixture = FixtureBackup::new();
+        fixture.add_dir("HomeDomain", "Library");
+        fixture.add_dir("HomeDomain", "Library/Preferences");
+        fixture.add_file("MediaDomain", "Media/a.txt", b"a");
+
+        let backup_dir = fixture.path().to_str().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let summary_path = dir.path().join("summary.json");
+        let summary_path = summary_path.to_str().unwrap();
+        let status = |domain| {
+            let out_dir = dir.path().join(domain);
+            let command = [
+                "ibackupextractor",
+                "extract",
+                backup_dir,
+                domain,
+                "-o",
+                out_dir.to_str().unwrap(),
+                "--summary-json",
+                summary_path,
+            ];
+            run(Args::try_parse_from(command).unwrap()).unwrap();
+            let summary: serde_json::Value =
+                serde_json::from_slice(&std::fs::read(summary_path).unwrap()).unwrap();
+            summary["status"].as_str().unwrap().to_owned()
+        };
+        assert_eq!(status("HomeDomain"), "empty");
+        assert_eq!(status("MediaDomain"), "succeeded");
+    }
+
+    #[test]
+    fn it_refuses_to_cache_encrypted_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manifest_plist = plist::Dictionary::new();
+        manifest_plist.insert("IsEncrypted".to_owned(), true.into());
+        manifest_plist.insert("BackupKeyBag".to_owned(), plist::Value::Data(keybag(3)));
+        manifest_plist.insert(
+            "ManifestKey".to_owned(),
+            plist::Value::Data(wrap_key(3, &[9; 32])),
+        );
+        plist::to_file_xml(dir.path().join("Manifest.plist"), &manifest_plist).unwrap();
+
+        let backup_dir = dir.path().to_str().unwrap();
+        let cache_dir = dir.path().join("cache");
+        let args = Args::try_parse_from([
+            "ibackupextractor",
+            "list-domains",
+            backup_dir,
+            "--index-cache",
+            cache_dir.to_str().unwrap(),
+            "--password",
+            PASSWORD,
+        ])
+        .unwrap();
+        let err = run(args).unwrap_err();
+        assert!(err.to_string().contains("--index-cache"), "{err}");
+        assert!(!cache_dir.exists());
+    }
+
+    #[test]
+    fn it_filters_regardless_of_case() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Media/a.txt", b"a");
+        fixture.add_file("HomeDomain", "Media/b.jpg", b"b");
+
+        let backup_dir = fixture.path().to_str().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        for (name, filter) in [
+            ("include", &["--include", "MEDIA/*.TXT"][..]),
+            ("exclude", &["--exclude", "*.JPG"]),
+            ("regex", &["--regex", "^media/.*\\.TXT$"]),
+        ] {
+            let out_dir = dir.path().join(name);
+            let args = Args::try_parse_from(
+                [
+                    "ibackupextractor",
+                    "extract",
+                    backup_dir,
+                    "HomeDomain",
+                    "-o",
+                    out_dir.to_str().unwrap(),
+                    "--ignore-case",
+                ]
+                .iter()
+                .chain(filter),
+            )
+            .unwrap();
+            run(args).unwrap();
+            assert!(out_dir.join("Media/a.txt").exists(), "{name}");
+            assert!(!out_dir.join("Media/b.jpg").exists(), "{name}");
+        }
+    }
+
+    #[test]
+    fn it_needs_a_domain_to_extract_to_stdout() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Media/a.txt", b"hello");
+
+        let backup_dir = fixture.path().to_str().unwrap();
+        for command in [
+            &["extract", backup_dir, "-o", "-"][..],
+            &["extract", backup_dir, "--all-domains", "-o", "-"],
+            &["extract", backup_dir, "Home*", "-o", "-"],
+        ] {
+            let args = Args::try_parse_from(["ibackupextractor"].iter().chain(command)).unwrap();
+            assert!(run(args).is_err());
+        }
+    }
+}
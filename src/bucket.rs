@@ -0,0 +1,63 @@
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+
+/// Strategy for locating the blob of a file inside the backup archive.
+#[derive(ValueEnum, PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum BucketStrategy {
+    /// Blobs are stored directly in the backup directory.
+    None,
+    /// Blobs are grouped by the first two characters of their file ID,
+    /// which is the layout used by modern backups.
+    #[default]
+    #[value(name = "first-2-chars")]
+    FirstTwoChars,
+    /// Each blob lives in a directory named after its full file ID.
+    Full,
+}
+
+impl BucketStrategy {
+    /// Returns the name of the bucket directory for the given file ID,
+    /// or `None` if the blob is not bucketed.
+    pub fn bucket<'a>(&self, file_id: &'a str) -> Option<&'a str> {
+        match self {
+            Self::None => None,
+            Self::FirstTwoChars => Some(file_id.get(0..2).unwrap_or(file_id)),
+            Self::Full => Some(file_id),
+        }
+    }
+
+    pub fn blob_path(&self, backup_dir: &Path, file_id: &str) -> PathBuf {
+        match self.bucket(file_id) {
+            Some(bucket) => backup_dir.join(bucket).join(file_id),
+            None => backup_dir.join(file_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::BucketStrategy;
+
+    const FILE_ID: &str = "3d0d7e5fb2ce288813306e4d4636395e047a3d28";
+
+    #[test]
+    fn it_resolves_blob_paths() {
+        let backup_dir = Path::new("/backup");
+
+        assert_eq!(
+            BucketStrategy::None.blob_path(backup_dir, FILE_ID),
+            backup_dir.join(FILE_ID)
+        );
+        assert_eq!(
+            BucketStrategy::FirstTwoChars.blob_path(backup_dir, FILE_ID),
+            backup_dir.join("3d").join(FILE_ID)
+        );
+        assert_eq!(
+            BucketStrategy::Full.blob_path(backup_dir, FILE_ID),
+            backup_dir.join(FILE_ID).join(FILE_ID)
+        );
+    }
+}
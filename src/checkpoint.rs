@@ -0,0 +1,116 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use rusqlite::Connection as SqliteConnection;
+
+const WAL_SUFFIX: &str = "-wal";
+const SHM_SUFFIX: &str = "-shm";
+
+/// Folds the WAL files of the SQLite databases under `dir` into the main
+/// database files and removes the companions, returns the number of
+/// checkpointed databases.
+///
+/// Databases that fail to checkpoint are left untouched with a warning.
+pub fn checkpoint_databases(dir: &Path) -> Result<usize> {
+    let mut wal_paths = vec![];
+    find_wal_files(dir, &mut wal_paths)?;
+
+    let mut checkpointed_count = 0;
+    for wal_path in wal_paths {
+        let wal_path_str = wal_path.to_string_lossy();
+        let db_path = PathBuf::from(&wal_path_str[..wal_path_str.len() - WAL_SUFFIX.len()]);
+        if !db_path.is_file() {
+            continue;
+        }
+
+        match checkpoint_database(&db_path) {
+            Ok(()) => {
+                debug!("checkpointed {}", db_path.to_string_lossy());
+                checkpointed_count += 1;
+            }
+            Err(err) => warn!(
+                "failed to checkpoint {}: {err:#}",
+                db_path.to_string_lossy()
+            ),
+        }
+    }
+    Ok(checkpointed_count)
+}
+
+fn checkpoint_database(db_path: &Path) -> Result<()> {
+    let db_conn = SqliteConnection::open(db_path)?;
+    db_conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |r| {
+        let busy: i64 = r.get(0)?;
+        Ok(busy)
+    })?;
+    // Leaving the WAL mode removes the companion files when closed.
+    db_conn.query_row("PRAGMA journal_mode = DELETE", [], |_| Ok(()))?;
+    db_conn.close().map_err(|(_, err)| err)?;
+
+    for suffix in [WAL_SUFFIX, SHM_SUFFIX] {
+        let mut companion_path = db_path.as_os_str().to_owned();
+        companion_path.push(suffix);
+        let companion_path = PathBuf::from(companion_path);
+        if companion_path.exists() {
+            fs::remove_file(&companion_path).with_context(|| {
+                format!("failed to remove: {}", companion_path.to_string_lossy())
+            })?;
+        }
+    }
+    Ok(())
+}
+
+fn find_wal_files(dir: &Path, wal_paths: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = fs::read_dir(dir)
+        .with_context(|| format!("failed to read directory: {}", dir.to_string_lossy()))?;
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+        if file_type.is_dir() {
+            find_wal_files(&path, wal_paths)?;
+        } else if file_type.is_file() && path.to_string_lossy().ends_with(WAL_SUFFIX) {
+            wal_paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use rusqlite::Connection as SqliteConnection;
+
+    use super::checkpoint_databases;
+
+    #[test]
+    fn it_checkpoints_copied_databases() {
+        let live_dir = tempfile::tempdir().unwrap();
+        let live_db = SqliteConnection::open(live_dir.path().join("a.sqlite")).unwrap();
+        live_db
+            .execute_batch(
+                "PRAGMA journal_mode = WAL; PRAGMA wal_autocheckpoint = 0; \
+                 CREATE TABLE t (v INTEGER); INSERT INTO t VALUES (42);",
+            )
+            .unwrap();
+
+        // Copy the database while it's open, like a backup would.
+        let out_dir = tempfile::tempdir().unwrap();
+        let nested_dir = out_dir.path().join("Library");
+        fs::create_dir(&nested_dir).unwrap();
+        for file_name in ["a.sqlite", "a.sqlite-wal", "a.sqlite-shm"] {
+            fs::copy(live_dir.path().join(file_name), nested_dir.join(file_name)).unwrap();
+        }
+        drop(live_db);
+
+        assert_eq!(checkpoint_databases(out_dir.path()).unwrap(), 1);
+        assert!(!nested_dir.join("a.sqlite-wal").exists());
+        assert!(!nested_dir.join("a.sqlite-shm").exists());
+
+        let db = SqliteConnection::open(nested_dir.join("a.sqlite")).unwrap();
+        let v: i64 = db.query_row("SELECT v FROM t", [], |r| r.get(0)).unwrap();
+        assert_eq!(v, 42);
+    }
+}
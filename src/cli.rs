@@ -1,30 +1,943 @@
+use std::env;
+use std::ffi::OsString;
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
+use regex::bytes::Regex;
+
+use crate::bucket::BucketStrategy;
+use crate::ctx::{
+    ConflictPolicy, LowSpacePolicy, MetadataFormat, PrefixMismatchPolicy, TimestampField,
+};
+use crate::utils::protection_class::{parse_protection_class, ProtectionClass};
+use crate::utils::size::parse_size;
+use crate::utils::timestamp::parse_date;
+use crate::volume::Adaptation;
 
 #[derive(Parser, Debug)]
 #[command(version, about)]
 pub struct Args {
+    /// Print more details, repeat for per-file logs (`-vv`).
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Only print errors, besides the output of the command.
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Also write the debug logs to this file, whatever the verbosity.
+    #[arg(long, value_name = "PATH", global = true)]
+    pub log_file: Option<PathBuf>,
+
+    /// Draw the progress bar with ASCII characters only.
+    #[arg(long, global = true)]
+    pub ascii_progress: bool,
+
+    /// Don't show the progress bar, which is also hidden when stderr is
+    /// not a terminal, same as `--progress none`.
+    #[arg(long, global = true, conflicts_with = "progress")]
+    pub no_progress: bool,
+
+    /// How to report the progress.
+    #[arg(long, value_enum, default_value_t, global = true)]
+    pub progress: ProgressMode,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+// Parsed once per run, the size of `Extract` doesn't matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// List the backups of Finder and iTunes, with the names of their
+    /// devices and when they were made, the most recent first.
+    Backups {
+        /// Directory containing the backups, defaults to where Finder and
+        /// iTunes keep them on macOS and Windows.
+        backups_dir: Option<PathBuf>,
+    },
+
+    /// Show the device and state of a backup, read from its plists, without
+    /// opening the manifest.
+    Info {
+        /// Path of the backup archive.
+        backup_dir: PathBuf,
+    },
+
+    /// List all the domains.
+    ListDomains {
+        #[command(flatten)]
+        backup: BackupArgs,
+
+        /// Show the known on-device root of each domain.
+        #[arg(long)]
+        with_paths: bool,
+
+        /// Show the total size of the regular files of each domain, as
+        /// recorded in the manifest, and sort the domains by it.
+        #[arg(long)]
+        sizes: bool,
+
+        /// Output format, `json` also counts the entries of each domain.
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+
+    /// List the entries of a domain, sorted by path, without extracting.
+    ListFiles {
+        #[command(flatten)]
+        backup: BackupArgs,
+
+        /// Domain of the entries to list.
+        #[arg(short, long)]
+        domain: String,
+
+        /// Also print the file ID of each entry.
+        #[arg(long)]
+        show_ids: bool,
+
+        /// Only list the entries whose relative paths match this regular
+        /// expression.
+        #[arg(long, value_name = "PATTERN")]
+        regex: Option<regex::Regex>,
+
+        /// Only list the files of this data protection class, can be
+        /// repeated.
+        #[arg(long, value_name = "CLASS", value_parser = parse_protection_class)]
+        protection_class: Vec<ProtectionClass>,
+
+        /// Only list the entries under this relative path.
+        #[arg(long, value_name = "PATH")]
+        under: Option<PathBuf>,
+
+        /// List the entries this many levels deep at most, below `--under`
+        /// if given, and summarize the rest of each deeper directory in a
+        /// line. The deeper entries are just left out in `ndjson` format.
+        #[arg(long, value_name = "N")]
+        max_depth: Option<NonZeroUsize>,
+
+        /// Output format, `ndjson` writes the entries as the manifest is
+        /// read, in no particular order, unless `--sort` is given.
+        #[arg(long, value_enum, default_value_t)]
+        format: ListFormat,
+
+        /// Order of the entries, by path if not given. Ties are broken by
+        /// path, and all the entries are read before any is listed.
+        #[arg(long, value_enum)]
+        sort: Option<FileSort>,
+
+        /// List the entries in the reverse order of `--sort`.
+        #[arg(long, requires = "sort")]
+        reverse: bool,
+
+        /// List this many entries at most.
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+    },
+
+    /// Show the files of a domain as a tree, with the number of files in
+    /// each directory.
+    Tree {
+        #[command(flatten)]
+        backup: BackupArgs,
+
+        /// Domain of the files to show.
+        #[arg(short, long)]
+        domain: String,
+
+        /// Only show the entries under this relative path.
+        #[arg(long, value_name = "PATH")]
+        under: Option<PathBuf>,
+
+        /// Show this many levels of the tree at most, below `--under` if
+        /// given, and summarize the rest of each deeper directory in a
+        /// line.
+        #[arg(long, value_name = "N")]
+        max_depth: Option<NonZeroUsize>,
+    },
+
+    /// Extract the files of a domain.
+    Extract {
+        #[command(flatten)]
+        backup: BackupArgs,
+
+        /// Domain of the files to extract, or a glob pattern like
+        /// `*com.foo.bar*` to extract the matching domains into their own
+        /// subdirectories. All the domains are extracted if omitted.
+        domain: Option<String>,
+
+        /// Extract every domain, each into a subdirectory named after it,
+        /// which is also what omitting the domain does.
+        #[arg(long, conflicts_with = "domain")]
+        all_domains: bool,
+
+        /// Fail on the files with malformed IDs instead of skipping them,
+        /// and stop at the first domain that fails to extract when
+        /// extracting several, instead of going on with the others.
+        #[arg(long)]
+        strict: bool,
+
+        /// Go on past the files that fail to be written, list them at the
+        /// end and only fail then.
+        #[arg(long)]
+        keep_going: bool,
+
+        /// Path of the destination directory for extracted files, may
+        /// contain `{device}`, `{date}`, `{ios}` and `{domain}` tokens, or
+        /// `-` to write the single file selected by `--path` to stdout.
+        #[arg(short, required_unless_present_any = ["count", "archive"])]
+        out_dir: Option<PathBuf>,
+
+        /// Copy the files instead of creating symbolic links.
+        #[arg(short)]
+        copy: bool,
+
+        /// Create hard links to the blobs instead of symbolic links, the
+        /// destination must be on the volume of the backup. Editing the
+        /// extracted files edits the backup.
+        #[arg(long, conflicts_with_all = ["copy", "store", "archive"])]
+        hardlink: bool,
+
+        /// Same as `--hardlink`, but copy the files if the destination
+        /// can't have hard links to the backup.
+        #[arg(long, conflicts_with_all = ["copy", "store", "archive", "hardlink"])]
+        hardlink_or_copy: bool,
+
+        /// Hard link the files with the same content to the first copy
+        /// instead of copying each of them. Only the files whose size is
+        /// shared are read to be compared.
+        #[arg(long, requires = "copy", conflicts_with_all = ["store", "no_index"])]
+        dedupe: bool,
+
+        /// What to do with the files that already exist in the destination,
+        /// `skip` resumes an interrupted extraction.
+        #[arg(long, value_enum, default_value_t)]
+        on_conflict: ConflictPolicy,
+
+        /// Keep the existing files that were completely written, same as
+        /// `--on-conflict skip`.
+        #[arg(long, conflicts_with_all = ["on_conflict", "overwrite", "fail_on_existing"])]
+        skip_existing: bool,
+
+        /// Replace the existing files, same as `--on-conflict overwrite`.
+        #[arg(long, conflicts_with_all = ["on_conflict", "fail_on_existing"])]
+        overwrite: bool,
+
+        /// Fail at the first existing file, which is the default, same as
+        /// `--on-conflict error`.
+        #[arg(long, conflicts_with = "on_conflict")]
+        fail_on_existing: bool,
+
+        /// Strip this leading path from the paths of extracted files.
+        #[arg(long, conflicts_with = "by_date")]
+        strip_prefix: Option<PathBuf>,
+
+        /// What to do with the files that are not under the stripped prefix.
+        #[arg(long, value_enum, default_value_t, requires = "strip_prefix")]
+        prefix_mismatch: PrefixMismatchPolicy,
+
+        /// Match `--strip-prefix`, `--include`, `--exclude` and `--regex`
+        /// case-insensitively, like the iOS file system does.
+        #[arg(long)]
+        ignore_case: bool,
+
+        /// Place files into per-date folders based on one of their
+        /// timestamps, files without the timestamp go to `undated`.
+        #[arg(long)]
+        by_date: bool,
+
+        /// Timestamp used to route files in `--by-date` mode.
+        #[arg(long, value_enum, default_value_t, requires = "by_date")]
+        date_field: TimestampField,
+
+        /// Folder name format in `--by-date` mode, using the `time` crate's
+        /// format description syntax.
+        #[arg(long, default_value = "[year]/[month]", requires = "by_date")]
+        date_format: String,
+
+        /// Keep at least this much free space (e.g. `10G`) on the
+        /// destination volume while extracting.
+        #[arg(long, value_parser = parse_size)]
+        min_free_space: Option<u64>,
+
+        /// What to do when the free space drops below `--min-free-space`.
+        #[arg(long, value_enum, default_value_t, requires = "min_free_space")]
+        on_low_space: LowSpacePolicy,
+
+        /// Copy the files even if the destination volume has less free
+        /// space than their recorded sizes add up to, e.g. if it compresses
+        /// them.
+        #[arg(long)]
+        force: bool,
+
+        /// Only write a `<file ID>.json` metadata sidecar for each file,
+        /// laid out like the blobs in the backup, without the contents.
+        #[arg(
+            long,
+            requires = "domain",
+            conflicts_with_all = ["copy", "strip_prefix", "by_date"]
+        )]
+        sidecars_only: bool,
+
+        /// Size of the buffer used to copy the files (e.g. `4M`).
+        #[arg(long, value_parser = parse_size, default_value = "1M", requires = "copy")]
+        buffer_size: u64,
+
+        /// Copy the blocks of the files even where the file system could
+        /// clone them (APFS, Btrfs, XFS), so the copies don't share them
+        /// with the backup.
+        #[arg(long)]
+        no_reflink: bool,
+
+        /// Number of files written at once, defaults to the number of CPUs.
+        /// Files are written one at a time with `--no-index` or `--store`.
+        #[arg(short, long, value_name = "N")]
+        jobs: Option<NonZeroUsize>,
+
+        /// Only report how many files and bytes would be extracted.
+        #[arg(long, requires = "domain", conflicts_with_all = ["out_dir", "sidecars_only"])]
+        count: bool,
+
+        /// Print the paths the files would be extracted to, whether they
+        /// would be copied or linked, and the files that would collide,
+        /// without writing anything.
+        #[arg(
+            long,
+            requires = "domain",
+            conflicts_with_all = [
+                "count",
+                "sidecars_only",
+                "write_manifest",
+                "no_index",
+                "checkpoint_dbs",
+                "notes_bundle",
+                "write_deleted",
+            ]
+        )]
+        dry_run: bool,
+
+        /// Extract into a temporary directory next to the destination, and
+        /// only rename it to the destination once every file is written. It
+        /// is removed if the extraction fails or is cancelled.
+        #[arg(
+            long,
+            requires = "domain",
+            conflicts_with_all = [
+                "count",
+                "dry_run",
+                "sidecars_only",
+                "archive",
+                "skip_existing",
+            ]
+        )]
+        atomic: bool,
+
+        /// Replace the destination of `--atomic` if it already exists.
+        #[arg(long, requires = "atomic")]
+        overwrite_dir: bool,
+
+        /// Write the final statistics as JSON to this path (`-` for
+        /// stdout), even if the extraction fails or is cancelled.
+        #[arg(long, requires = "domain", conflicts_with_all = ["count", "sidecars_only"])]
+        summary_json: Option<PathBuf>,
+
+        /// Write a JSON manifest of the extracted files to this path, with
+        /// the relative path and file ID of each one.
+        #[arg(
+            long,
+            value_name = "PATH",
+            requires = "domain",
+            conflicts_with_all = ["count", "sidecars_only"]
+        )]
+        write_manifest: Option<PathBuf>,
+
+        /// Also write the metadata of the extracted files (sizes, modes,
+        /// owners, timestamps and protection classes) with their file IDs
+        /// to `metadata.json` or `metadata.ndjson` in the destination
+        /// directory, which must not be there already.
+        #[arg(
+            long,
+            value_enum,
+            value_name = "FORMAT",
+            requires = "domain",
+            conflicts_with_all = ["count", "sidecars_only", "dry_run"]
+        )]
+        metadata: Option<MetadataFormat>,
+
+        /// Write the files into this tar archive (`.tar` or `.tar.gz`)
+        /// instead of a destination directory, with the modes and
+        /// modification times recorded in the manifest.
+        #[arg(
+            long,
+            value_name = "PATH",
+            requires = "domain",
+            conflicts_with_all = [
+                "out_dir",
+                "copy",
+                "count",
+                "dry_run",
+                "sidecars_only",
+                "summary_json",
+                "by_date",
+                "store",
+                "since_backup",
+                "placeholders",
+                "notes_bundle",
+                "checkpoint_dbs",
+                "write_manifest",
+                "metadata",
+            ]
+        )]
+        archive: Option<PathBuf>,
+
+        /// Only extract the Notes database (`NoteStore.sqlite`) of the
+        /// domain, usually `AppDomainGroup-group.com.apple.notes`.
+        #[arg(
+            long,
+            requires = "domain",
+            conflicts_with_all = ["strip_prefix", "by_date", "sidecars_only"]
+        )]
+        notes_bundle: bool,
+
+        /// Also decode the note bodies into plain text files under
+        /// `notes/`, may not work with every iOS version.
+        #[arg(long, requires = "notes_bundle")]
+        decode_notes: bool,
+
+        /// Fold the `-wal` files of extracted SQLite databases into the
+        /// databases and remove the companion files afterwards.
+        #[arg(long, requires = "copy")]
+        checkpoint_dbs: bool,
+
+        /// Only extract the files whose relative paths match this glob
+        /// pattern, can be repeated.
+        #[arg(long, value_name = "GLOB", conflicts_with = "notes_bundle")]
+        include: Vec<String>,
+
+        /// Leave out the files whose relative paths match this glob pattern,
+        /// even if included, can be repeated.
+        #[arg(long, value_name = "GLOB")]
+        exclude: Vec<String>,
+
+        /// Only extract the files whose relative paths match this regular
+        /// expression, anywhere in the path unless anchored with `^`/`$`.
+        #[arg(
+            long,
+            value_name = "PATTERN",
+            conflicts_with_all = ["include", "exclude", "notes_bundle"]
+        )]
+        regex: Option<regex::Regex>,
+
+        /// Only extract the files last modified at or after this date, like
+        /// `2024-05-01`, `2024-05-01T12:00:00+08:00` or `7d` for a week ago.
+        #[arg(long, value_name = "DATE", value_parser = parse_date)]
+        newer_than: Option<i64>,
+
+        /// Only extract the files last modified before this date, in the
+        /// same forms as `--newer-than`.
+        #[arg(long, value_name = "DATE", value_parser = parse_date)]
+        older_than: Option<i64>,
+
+        /// Only extract this file, or the files under this directory, can be
+        /// repeated.
+        #[arg(
+            long = "path",
+            value_name = "RELATIVE_PATH",
+            requires = "domain",
+            conflicts_with = "notes_bundle"
+        )]
+        paths: Vec<PathBuf>,
+
+        /// Extract the files matching this glob pattern before the others,
+        /// can be repeated.
+        #[arg(long, value_name = "GLOB")]
+        priority_glob: Vec<String>,
+
+        /// Copy the blobs once into this content-addressed store and link
+        /// the extracted files to it, re-extractions only copy new blobs.
+        #[arg(long, conflicts_with_all = ["copy", "count", "sidecars_only"])]
+        store: Option<PathBuf>,
+
+        /// Write the files while reading the manifest instead of indexing
+        /// them first, uses less memory on huge domains. The manifest is
+        /// read a few times over, and the index cache isn't used.
+        #[arg(long, conflicts_with = "since_backup")]
+        no_index: bool,
+
+        /// Only extract the files of this data protection class (number,
+        /// name like `complete`, or `unknown`), can be repeated.
+        #[arg(long, value_name = "CLASS", value_parser = parse_protection_class)]
+        protection_class: Vec<ProtectionClass>,
+
+        /// Write an empty `<name>.MISSING` file in place of each file whose
+        /// content is missing from the backup, instead of failing.
+        #[arg(long, conflicts_with = "sidecars_only")]
+        placeholders: bool,
+
+        /// Set the modification (and, where supported, creation) times of
+        /// the copied files and directories to the ones on the device.
+        #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+        preserve_timestamps: bool,
+
+        /// Set the permission bits of the copied files to the ones on the
+        /// device (Unix only).
+        #[arg(long)]
+        preserve_permissions: bool,
+
+        /// Leave the times and permissions of the extracted files as the
+        /// system sets them, same as `--preserve-timestamps false`.
+        #[arg(long, conflicts_with_all = ["preserve_timestamps", "preserve_permissions"])]
+        no_preserve_metadata: bool,
+
+        /// Only extract the files added or changed since this older backup
+        /// of the same device.
+        #[arg(long, value_name = "OLD_BACKUP_DIR", conflicts_with = "sidecars_only")]
+        since_backup: Option<PathBuf>,
+
+        /// Also list the files deleted since the older backup in
+        /// `deleted.txt` under the destination directory, which must not be
+        /// there already.
+        #[arg(
+            long,
+            requires = "since_backup",
+            requires = "domain",
+            conflicts_with = "count"
+        )]
+        write_deleted: bool,
+
+        /// Replace the characters FAT and Windows reject in file names with
+        /// `_`, done automatically on FAT and exFAT destinations.
+        #[arg(long, conflicts_with = "sidecars_only")]
+        portable_names: bool,
+
+        /// Don't make this adaptation on a FAT or exFAT destination, can be
+        /// repeated.
+        #[arg(long, value_enum, value_name = "ADAPTATION")]
+        no_adapt: Vec<Adaptation>,
+    },
+
+    /// Show everything known about a single file.
+    Stat {
+        #[command(flatten)]
+        backup: BackupArgs,
+
+        /// Domain of the file.
+        #[arg(short, long)]
+        domain: String,
+
+        /// Relative path of the file in the domain.
+        relative_path: String,
+
+        /// Dump the metadata plist as XML instead.
+        #[arg(long)]
+        raw_plist: bool,
+    },
+
+    /// Write the content of a single file to stdout.
+    Cat {
+        #[command(flatten)]
+        backup: BackupArgs,
+
+        /// Domain of the file.
+        #[arg(short, long)]
+        domain: String,
+
+        /// Relative path of the file in the domain.
+        relative_path: String,
+    },
+
+    /// Print the path of the blob storing a file's content.
+    ShowBlob {
+        #[command(flatten)]
+        backup: BackupArgs,
+
+        /// Domain of the file.
+        #[arg(short, long)]
+        domain: String,
+
+        /// Relative path of the file in the domain, or a glob pattern
+        /// with `--all-matches`.
+        relative_path: String,
+
+        /// Treat the path as a glob pattern and print the blobs of all
+        /// matched files.
+        #[arg(long)]
+        all_matches: bool,
+
+        /// Match the glob pattern case-insensitively, like the iOS file
+        /// system does.
+        #[arg(long, requires = "all_matches")]
+        ignore_case: bool,
+    },
+
+    /// Find out which files the given file IDs belong to.
+    #[command(alias = "lookup")]
+    Whois {
+        #[command(flatten)]
+        backup: BackupArgs,
+
+        /// File IDs (blob names) to look up, or unique prefixes of them,
+        /// `-` to read them from stdin.
+        #[arg(required = true)]
+        file_ids: Vec<String>,
+
+        /// Show everything known about each file, like `stat` does.
+        #[arg(long)]
+        metadata: bool,
+    },
+
+    /// Extract the domains of an app, laid out like its containers on the
+    /// device.
+    ExtractApp {
+        #[command(flatten)]
+        backup: BackupArgs,
+
+        /// Path of the destination directory, which gets `Container`,
+        /// `GroupContainers` and `PlugIns` subdirectories.
+        out_dir: PathBuf,
+
+        /// Bundle identifier of the app, e.g. `net.whatsapp.WhatsApp`.
+        #[arg(long)]
+        bundle_id: String,
+
+        /// Copy the files instead of creating symbolic links.
+        #[arg(short)]
+        copy: bool,
+
+        /// Fail on the files with malformed IDs, and stop at the first
+        /// domain that fails to extract.
+        #[arg(long)]
+        strict: bool,
+    },
+
+    /// Extract the blob of a file ID, named after the original file.
+    ExtractId {
+        #[command(flatten)]
+        backup: BackupArgs,
+
+        /// File ID (blob name) of the file.
+        file_id: String,
+
+        /// Destination path, or a directory to write the file into.
+        out_path: PathBuf,
+
+        /// Copy the file instead of creating a symbolic link.
+        #[arg(short)]
+        copy: bool,
+
+        /// Write the blob as it is, even if the manifest lacks the file ID,
+        /// linked to unless `-c` is given.
+        #[arg(long)]
+        raw: bool,
+    },
+
+    /// Search the files of all the domains by path, or the contents of the
+    /// files in a domain.
+    Search {
+        #[command(flatten)]
+        backup: BackupArgs,
+
+        /// Substring of the relative paths to search for, or a glob pattern
+        /// matched against the whole relative paths (e.g. `*/sms.db`).
+        #[arg(long, conflicts_with_all = ["content", "domain", "under", "binary", "jobs"])]
+        name: Option<String>,
+
+        /// Match the names regardless of case, only for ASCII letters with
+        /// substrings.
+        #[arg(long, requires = "name")]
+        ignore_case: bool,
+
+        /// Regular expression to search for, matched line by line.
+        #[arg(long, required_unless_present = "name", requires = "domain")]
+        content: Option<Regex>,
+
+        /// Domain of the files to search.
+        #[arg(short, long)]
+        domain: Option<String>,
+
+        /// Only search the files under this path.
+        #[arg(long)]
+        under: Option<PathBuf>,
+
+        /// Skip the files larger than this size.
+        #[arg(long, value_parser = parse_size, default_value = "16M")]
+        max_size: u64,
+
+        /// Search binary files too.
+        #[arg(long)]
+        binary: bool,
+
+        /// Number of files scanned at once, defaults to the number of CPUs.
+        /// The matches of a file are printed together, but the files come in
+        /// no particular order.
+        #[arg(short, long, value_name = "N")]
+        jobs: Option<NonZeroUsize>,
+    },
+
+    /// Report files with timestamps later than the backup date or
+    /// implausibly old.
+    CheckTimestamps {
+        #[command(flatten)]
+        backup: BackupArgs,
+
+        /// Only check the files of this domain.
+        #[arg(short, long)]
+        domain: Option<String>,
+    },
+
+    /// Check that the blob of every regular file exists and has the size
+    /// recorded in the manifest, failing if any doesn't.
+    Verify {
+        #[command(flatten)]
+        backup: BackupArgs,
+
+        /// Only check the files of this domain.
+        #[arg(short, long)]
+        domain: Option<String>,
+
+        /// Also check that each file ID is the SHA-1 of the domain and
+        /// relative path, as the blob names of genuine backups are.
+        #[arg(long)]
+        check_ids: bool,
+    },
+
+    /// Count the regular files and their total size.
+    Stats {
+        #[command(flatten)]
+        backup: BackupArgs,
+
+        /// Only count the files of this domain.
+        #[arg(short, long)]
+        domain: Option<String>,
+
+        /// Break the totals down by data protection class.
+        #[arg(long)]
+        by_protection_class: bool,
+
+        /// Only count the files of this data protection class, can be
+        /// repeated.
+        #[arg(long, value_name = "CLASS", value_parser = parse_protection_class)]
+        protection_class: Vec<ProtectionClass>,
+    },
+
+    /// Manage a content-addressed store created by `extract --store`.
+    Store {
+        #[command(subcommand)]
+        command: StoreCommand,
+    },
+
+    /// Pack the backup archive into a single `.zip` or `.tar.zst` file.
+    Pack {
+        #[command(flatten)]
+        backup: BackupArgs,
+
+        /// Path of the archive file to create.
+        #[arg(short)]
+        out_path: PathBuf,
+
+        /// Only pack the files of these domains.
+        #[arg(long, value_delimiter = ',')]
+        domains: Vec<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum StoreCommand {
+    /// Check that the content of every object matches its hash.
+    Verify {
+        /// Path of the store.
+        store_dir: PathBuf,
+    },
+}
+
+impl Command {
+    /// Returns the backup the command works on, `None` for the commands
+    /// that don't need one.
+    pub fn backup(&self) -> Option<&BackupArgs> {
+        let backup = match self {
+            Command::ListDomains { backup, .. }
+            | Command::ListFiles { backup, .. }
+            | Command::Tree { backup, .. }
+            | Command::Extract { backup, .. }
+            | Command::Stat { backup, .. }
+            | Command::Cat { backup, .. }
+            | Command::ShowBlob { backup, .. }
+            | Command::Whois { backup, .. }
+            | Command::ExtractApp { backup, .. }
+            | Command::ExtractId { backup, .. }
+            | Command::Search { backup, .. }
+            | Command::CheckTimestamps { backup, .. }
+            | Command::Verify { backup, .. }
+            | Command::Stats { backup, .. }
+            | Command::Pack { backup, .. } => backup,
+            Command::Backups { .. } | Command::Info { .. } | Command::Store { .. } => return None,
+        };
+        Some(backup)
+    }
+}
+
+#[derive(ClapArgs, Debug)]
+pub struct BackupArgs {
     /// Path of the backup archive.
     pub backup_dir: PathBuf,
 
-    /// Domain of the files to extract.
+    /// How the blobs are bucketed inside the backup archive, defaults to
+    /// `none` for backups made before iOS 10 and `first-2-chars` otherwise.
+    #[arg(long, value_enum)]
+    pub bucket: Option<BucketStrategy>,
+
+    /// Cache the parsed manifest of each domain in this directory, so that
+    /// later runs on the same backup start faster. Not available for
+    /// encrypted backups.
+    #[arg(long)]
+    pub index_cache: Option<PathBuf>,
+
+    /// Rebuild the cached manifest of the domains instead of using it.
+    #[arg(long, requires = "index_cache")]
+    pub refresh_index: bool,
+
+    /// Password of an encrypted backup, prompted for if not given.
+    #[arg(long)]
+    pub password: Option<String>,
+}
+
+#[derive(ValueEnum, PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum OutputFormat {
+    /// Plain text for humans, one item per line.
+    #[default]
+    Text,
+    /// A JSON document for scripts, without any other output on stdout.
+    Json,
+}
+
+/// Output format of `list-files`.
+#[derive(ValueEnum, PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum ListFormat {
+    /// Plain text for humans, one entry per line.
+    #[default]
+    Text,
+    /// One JSON object per entry and line, without any other output on
+    /// stdout.
+    Ndjson,
+}
+
+/// Order of the entries listed by `list-files`.
+#[derive(ValueEnum, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum FileSort {
+    /// By file name.
+    Name,
+    /// By size, as recorded in the manifest.
+    Size,
+    /// By modification time.
+    Mtime,
+    /// By relative path.
+    Path,
+}
+
+/// How the progress is reported.
+#[derive(ValueEnum, PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum ProgressMode {
+    /// A progress bar, shown only if stderr is a terminal.
+    #[default]
+    Bar,
+    /// One JSON object per line on stderr, for other programs to parse.
+    Json,
+    /// Nothing.
+    None,
+}
+
+/// The flat invocation of the releases before subcommands, still accepted
+/// so that existing scripts keep working.
+#[derive(Parser, Debug)]
+struct LegacyArgs {
+    backup_dir: PathBuf,
+
     #[arg(required = true, conflicts_with = "list_domains")]
-    pub domain: Option<String>,
+    domain: Option<String>,
 
-    /// Path of the destination directory for extracted files.
     #[arg(short, required = true, conflicts_with = "list_domains")]
-    pub out_dir: Option<PathBuf>,
+    out_dir: Option<PathBuf>,
 
-    /// List all the domains.
     #[arg(short)]
-    pub list_domains: bool,
+    list_domains: bool,
 
-    /// Copy the files instead of creating symbolic links.
     #[arg(short, conflicts_with = "list_domains")]
-    pub copy: bool,
+    copy: bool,
 }
 
 pub fn parse_args() -> Args {
-    Args::parse()
+    let argv: Vec<_> = env::args_os().collect();
+    match Args::try_parse_from(&argv) {
+        Ok(args) => args,
+        Err(err) => match legacy_argv(&argv) {
+            Some(argv) => Args::parse_from(argv),
+            None => err.exit(),
+        },
+    }
+}
+
+/// Rewrites a flat invocation into the matching subcommand invocation,
+/// returns `None` if it's not one.
+fn legacy_argv(argv: &[OsString]) -> Option<Vec<OsString>> {
+    let legacy_args = LegacyArgs::try_parse_from(argv).ok()?;
+    let mut new_argv = vec![argv[0].clone()];
+    if legacy_args.list_domains {
+        new_argv.extend(["list-domains".into(), legacy_args.backup_dir.into()]);
+    } else {
+        new_argv.extend([
+            "extract".into(),
+            legacy_args.backup_dir.into(),
+            legacy_args.domain?.into(),
+            "-o".into(),
+            legacy_args.out_dir?.into(),
+        ]);
+        if legacy_args.copy {
+            new_argv.push("-c".into());
+        }
+    }
+    Some(new_argv)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+
+    use super::legacy_argv;
+
+    fn argv(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn it_rewrites_flat_invocations() {
+        assert_eq!(
+            legacy_argv(&argv(&["ibackupextractor", "-l", "/backup"])),
+            Some(argv(&["ibackupextractor", "list-domains", "/backup"]))
+        );
+        assert_eq!(
+            legacy_argv(&argv(&[
+                "ibackupextractor",
+                "-o",
+                "/out",
+                "/backup",
+                "HomeDomain",
+                "-c"
+            ])),
+            Some(argv(&[
+                "ibackupextractor",
+                "extract",
+                "/backup",
+                "HomeDomain",
+                "-o",
+                "/out",
+                "-c"
+            ]))
+        );
+        assert_eq!(
+            legacy_argv(&argv(&["ibackupextractor", "list-domains", "/backup"])),
+            None
+        );
+    }
 }
@@ -0,0 +1,486 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecryptMut, KeyIvInit};
+use aes_kw::KekAes256;
+use anyhow::{Context, Result};
+use plist::Value;
+
+type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+
+const BLOCK_SIZE: usize = 16;
+/// Class keys wrapped with the key derived from the backup password.
+const WRAP_PASSCODE: u32 = 2;
+
+/// The password doesn't unlock the keybag of the backup.
+#[derive(Debug)]
+pub struct WrongPasswordError;
+
+impl fmt::Display for WrongPasswordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("wrong backup password")
+    }
+}
+
+impl std::error::Error for WrongPasswordError {}
+
+/// Encryption parameters of a backup, read from `Manifest.plist`.
+#[derive(Debug)]
+pub struct EncryptedManifest {
+    keybag: Vec<u8>,
+    /// Wrapped key of `Manifest.db`, prefixed by its protection class.
+    manifest_key: Vec<u8>,
+}
+
+/// Reads the encryption parameters, returns `None` if the backup is not
+/// encrypted.
+pub fn read_encrypted_manifest(backup_dir: &Path) -> Result<Option<EncryptedManifest>> {
+    let manifest_plist_path = backup_dir.join("Manifest.plist");
+    if !manifest_plist_path.exists() {
+        return Ok(None);
+    }
+
+    let manifest_plist: Value =
+        plist::from_file(&manifest_plist_path).context("failed to parse `Manifest.plist`")?;
+    let Some(manifest_plist) = manifest_plist.as_dictionary() else {
+        return Ok(None);
+    };
+    if manifest_plist
+        .get("IsEncrypted")
+        .and_then(Value::as_boolean)
+        != Some(true)
+    {
+        return Ok(None);
+    }
+
+    let data_field = |key| {
+        manifest_plist
+            .get(key)
+            .and_then(Value::as_data)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| anyhow!("`Manifest.plist` has no `{key}`"))
+    };
+    Ok(Some(EncryptedManifest {
+        keybag: data_field("BackupKeyBag")?,
+        manifest_key: data_field("ManifestKey")?,
+    }))
+}
+
+impl EncryptedManifest {
+    /// Unlocks the class keys of the backup, fails with
+    /// `WrongPasswordError` if the password is wrong.
+    pub fn unlock(&self, password: &str) -> Result<BackupKeys> {
+        BackupKeys::unlock(&self.keybag, password)
+    }
+
+    /// Decrypts `Manifest.db` into `dest_path`.
+    pub fn decrypt_manifest_db(
+        &self,
+        keys: &BackupKeys,
+        manifest_db_path: &Path,
+        dest_path: &Path,
+    ) -> Result<()> {
+        let key = keys.unwrap_key(&self.manifest_key)?;
+        decrypt_file(manifest_db_path, dest_path, &key, None, 1024 * 1024)?;
+        Ok(())
+    }
+}
+
+/// The unwrapped class keys of an encrypted backup.
+#[derive(Clone)]
+pub struct BackupKeys {
+    class_keys: HashMap<u32, [u8; 32]>,
+}
+
+impl fmt::Debug for BackupKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the keys.
+        f.debug_struct("BackupKeys").finish_non_exhaustive()
+    }
+}
+
+impl BackupKeys {
+    fn unlock(keybag: &[u8], password: &str) -> Result<Self> {
+        let keybag = Keybag::parse(keybag).context("malformed keybag")?;
+
+        let attr = |tag: &str| {
+            keybag
+                .attrs
+                .get(tag)
+                .ok_or_else(|| anyhow!("keybag has no `{tag}`"))
+        };
+        // Backups before iOS 10.2 have no `DPSL`, their passcode key is
+        // derived from the password directly.
+        let mut derived_key = [0; 32];
+        let password = match keybag.attrs.get("DPSL") {
+            Some(dpsl) => {
+                pbkdf2::pbkdf2_hmac::<sha2::Sha256>(
+                    password.as_bytes(),
+                    dpsl,
+                    be_u32(attr("DPIC")?),
+                    &mut derived_key,
+                );
+                &derived_key[..]
+            }
+            None => password.as_bytes(),
+        };
+        let mut passcode_key = [0; 32];
+        pbkdf2::pbkdf2_hmac::<sha1::Sha1>(
+            password,
+            attr("SALT")?,
+            be_u32(attr("ITER")?),
+            &mut passcode_key,
+        );
+
+        let kek = KekAes256::from(passcode_key);
+        let mut class_keys = HashMap::new();
+        for class_key in &keybag.class_keys {
+            if class_key.wrap & WRAP_PASSCODE == 0 {
+                continue;
+            }
+            let mut key = [0; 32];
+            kek.unwrap(&class_key.wrapped_key, &mut key)
+                .map_err(|_| WrongPasswordError)?;
+            class_keys.insert(class_key.class, key);
+        }
+        Ok(Self { class_keys })
+    }
+
+    /// Unwraps a key stored as its protection class (little-endian) and
+    /// the key wrapped with the class key.
+    pub fn unwrap_key(&self, wrapped: &[u8]) -> Result<[u8; 32]> {
+        if wrapped.len() != 4 + 40 {
+            return Err(anyhow!("malformed wrapped key"));
+        }
+        let class = u32::from_le_bytes(wrapped[0..4].try_into().unwrap());
+        let class_key = self
+            .class_keys
+            .get(&class)
+            .ok_or_else(|| anyhow!("no key for protection class {class}"))?;
+
+        let mut key = [0; 32];
+        KekAes256::from(*class_key)
+            .unwrap(&wrapped[4..], &mut key)
+            .map_err(|_| anyhow!("failed to unwrap key of protection class {class}"))?;
+        Ok(key)
+    }
+}
+
+/// Decrypts a blob (AES-256-CBC with a zero IV) into `dest_path`,
+/// returns the number of bytes written.
+///
+/// The plaintext is truncated to `size` if given, or unpadded otherwise.
+pub fn decrypt_file(
+    src_path: &Path,
+    dest_path: &Path,
+    key: &[u8; 32],
+    size: Option<u64>,
+    buffer_size: usize,
+) -> Result<u64> {
+    let mut src_file = File::open(src_path)
+        .with_context(|| format!("failed to open: {}", src_path.to_string_lossy()))?;
+    let mut dest_file = BufWriter::new(File::create(dest_path)?);
+    let mut decryptor = Aes256CbcDec::new(key.into(), &[0; BLOCK_SIZE].into());
+
+    // The last block is held back until the end for unpadding.
+    let buffer_size = buffer_size.max(BLOCK_SIZE) / BLOCK_SIZE * BLOCK_SIZE;
+    let mut buf = vec![0; buffer_size + BLOCK_SIZE];
+    let mut filled = 0;
+    let mut written = 0;
+    loop {
+        let len = match src_file.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        filled += len;
+
+        let ready = (filled.saturating_sub(1)) / BLOCK_SIZE * BLOCK_SIZE;
+        if ready >= buffer_size {
+            decrypt_blocks(&mut decryptor, &mut buf[..ready]);
+            written += write_limited(&mut dest_file, &buf[..ready], written, size)?;
+            buf.copy_within(ready..filled, 0);
+            filled -= ready;
+        }
+    }
+
+    if filled % BLOCK_SIZE != 0 {
+        return Err(anyhow!(
+            "encrypted data is not a multiple of the block size"
+        ));
+    }
+    decrypt_blocks(&mut decryptor, &mut buf[..filled]);
+    let mut tail = &buf[..filled];
+    if size.is_none() {
+        tail = unpad(tail);
+    }
+    written += write_limited(&mut dest_file, tail, written, size)?;
+    dest_file.flush()?;
+    Ok(written)
+}
+
+fn decrypt_blocks(decryptor: &mut Aes256CbcDec, buf: &mut [u8]) {
+    for block in buf.chunks_exact_mut(BLOCK_SIZE) {
+        decryptor.decrypt_block_mut(GenericArray::from_mut_slice(block));
+    }
+}
+
+/// Writes the part of `data` that fits in `size`, returns its length.
+fn write_limited(
+    writer: &mut impl Write,
+    data: &[u8],
+    written: u64,
+    size: Option<u64>,
+) -> Result<u64> {
+    let len = match size {
+        Some(size) => (size.saturating_sub(written)).min(data.len() as u64) as usize,
+        None => data.len(),
+    };
+    writer.write_all(&data[..len])?;
+    Ok(len as u64)
+}
+
+/// Strips the PKCS#7 padding, leaves the data untouched if it's not padded.
+fn unpad(data: &[u8]) -> &[u8] {
+    let Some(&pad_len) = data.last() else {
+        return data;
+    };
+    let pad_len = pad_len as usize;
+    if pad_len == 0 || pad_len > BLOCK_SIZE || pad_len > data.len() {
+        return data;
+    }
+    let (plaintext, padding) = data.split_at(data.len() - pad_len);
+    if padding.iter().all(|&b| b as usize == pad_len) {
+        plaintext
+    } else {
+        data
+    }
+}
+
+fn be_u32(data: &[u8]) -> u32 {
+    data.iter().fold(0, |value, &b| value << 8 | u32::from(b))
+}
+
+struct Keybag {
+    attrs: HashMap<String, Vec<u8>>,
+    class_keys: Vec<ClassKey>,
+}
+
+struct ClassKey {
+    class: u32,
+    wrap: u32,
+    wrapped_key: Vec<u8>,
+}
+
+impl Keybag {
+    /// Parses the TLV encoded keybag, the attributes come first and each
+    /// class key starts with a `UUID` tag.
+    fn parse(mut data: &[u8]) -> Result<Self> {
+        let mut keybag = Keybag {
+            attrs: HashMap::new(),
+            class_keys: vec![],
+        };
+        let mut current_key: Option<ClassKey> = None;
+        while !data.is_empty() {
+            if data.len() < 8 {
+                return Err(anyhow!("truncated keybag"));
+            }
+            let tag = String::from_utf8_lossy(&data[0..4]).into_owned();
+            let len = be_u32(&data[4..8]) as usize;
+            let Some(value) = data.get(8..8 + len) else {
+                return Err(anyhow!("truncated keybag"));
+            };
+            data = &data[8 + len..];
+
+            match (tag.as_str(), &mut current_key) {
+                ("UUID", _) if !keybag.attrs.contains_key("UUID") => {
+                    keybag.attrs.insert(tag, value.to_vec());
+                }
+                ("UUID", _) => {
+                    keybag.class_keys.extend(current_key.take());
+                    current_key = Some(ClassKey {
+                        class: 0,
+                        wrap: 0,
+                        wrapped_key: vec![],
+                    });
+                }
+                ("CLAS", Some(key)) => key.class = be_u32(value),
+                ("WRAP", Some(key)) => key.wrap = be_u32(value),
+                ("WPKY", Some(key)) => key.wrapped_key = value.to_vec(),
+                (_, Some(_)) => {}
+                (_, None) => {
+                    keybag.attrs.insert(tag, value.to_vec());
+                }
+            }
+        }
+        keybag.class_keys.extend(current_key);
+        Ok(keybag)
+    }
+}
+
+#[cfg(test)]
+pub mod test_support {
+    use aes::cipher::generic_array::GenericArray;
+    use aes::cipher::{BlockEncryptMut, KeyIvInit};
+    use aes_kw::KekAes256;
+
+    use super::BackupKeys;
+
+    pub const PASSWORD: &str = "secret";
+    pub const CLASS_KEY: [u8; 32] = [7; 32];
+
+    fn tlv(tag: &str, value: &[u8]) -> Vec<u8> {
+        let mut field = tag.as_bytes().to_vec();
+        field.extend_from_slice(&(value.len() as u32).to_be_bytes());
+        field.extend_from_slice(value);
+        field
+    }
+
+    /// Builds a keybag with a single class key, wrapped with the key
+    /// derived from `PASSWORD` in a single iteration.
+    pub fn keybag(class: u32) -> Vec<u8> {
+        build_keybag(class, true)
+    }
+
+    /// Like `keybag`, but without `DPSL` as in backups before iOS 10.2.
+    pub fn legacy_keybag(class: u32) -> Vec<u8> {
+        build_keybag(class, false)
+    }
+
+    fn build_keybag(class: u32, with_dpsl: bool) -> Vec<u8> {
+        let mut derived_key = [0; 32];
+        pbkdf2::pbkdf2_hmac::<sha2::Sha256>(PASSWORD.as_bytes(), b"dpsl", 1, &mut derived_key);
+        let password = if with_dpsl {
+            &derived_key[..]
+        } else {
+            PASSWORD.as_bytes()
+        };
+        let mut passcode_key = [0; 32];
+        pbkdf2::pbkdf2_hmac::<sha1::Sha1>(password, b"salt", 1, &mut passcode_key);
+
+        let mut wrapped_key = [0; 40];
+        KekAes256::from(passcode_key)
+            .wrap(&CLASS_KEY, &mut wrapped_key)
+            .unwrap();
+        let mut fields = vec![
+            tlv("VERS", &3u32.to_be_bytes()),
+            tlv("TYPE", &1u32.to_be_bytes()),
+            tlv("UUID", &[1; 16]),
+            tlv("WRAP", &0u32.to_be_bytes()),
+            tlv("SALT", b"salt"),
+            tlv("ITER", &1u32.to_be_bytes()),
+        ];
+        if with_dpsl {
+            fields.extend([
+                tlv("DPWT", &1u32.to_be_bytes()),
+                tlv("DPIC", &1u32.to_be_bytes()),
+                tlv("DPSL", b"dpsl"),
+            ]);
+        }
+        fields.extend([
+            tlv("UUID", &[2; 16]),
+            tlv("CLAS", &class.to_be_bytes()),
+            tlv("WRAP", &2u32.to_be_bytes()),
+            tlv("KTYP", &0u32.to_be_bytes()),
+            tlv("WPKY", &wrapped_key),
+        ]);
+        fields.concat()
+    }
+
+    /// Returns the keys unlocked from `keybag(3)`.
+    pub fn backup_keys() -> BackupKeys {
+        BackupKeys::unlock(&keybag(3), PASSWORD).unwrap()
+    }
+
+    /// Wraps a file key with `CLASS_KEY`, prefixed by its class.
+    pub fn wrap_key(class: u32, key: &[u8; 32]) -> Vec<u8> {
+        let mut wrapped_key = [0; 40];
+        KekAes256::from(CLASS_KEY)
+            .wrap(key, &mut wrapped_key)
+            .unwrap();
+        [class.to_le_bytes().as_slice(), &wrapped_key].concat()
+    }
+
+    /// Encrypts like the backups do, with PKCS#7 padding.
+    pub fn encrypt(key: &[u8; 32], plaintext: &[u8]) -> Vec<u8> {
+        let pad_len = 16 - plaintext.len() % 16;
+        let mut data = plaintext.to_vec();
+        data.extend(std::iter::repeat_n(pad_len as u8, pad_len));
+
+        let mut encryptor = cbc::Encryptor::<aes::Aes256>::new(key.into(), &[0; 16].into());
+        for block in data.chunks_exact_mut(16) {
+            encryptor.encrypt_block_mut(GenericArray::from_mut_slice(block));
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::test_support::*;
+    use plist::{Dictionary, Value};
+
+    use super::{decrypt_file, read_encrypted_manifest, BackupKeys, WrongPasswordError};
+
+    #[test]
+    fn it_detects_encrypted_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        // Old backups may come without `Manifest.plist`.
+        assert!(read_encrypted_manifest(dir.path()).unwrap().is_none());
+
+        let manifest_plist_path = dir.path().join("Manifest.plist");
+        let mut manifest_plist = Dictionary::new();
+        manifest_plist.insert("IsEncrypted".to_owned(), false.into());
+        plist::to_file_xml(&manifest_plist_path, &manifest_plist).unwrap();
+        assert!(read_encrypted_manifest(dir.path()).unwrap().is_none());
+
+        manifest_plist.insert("IsEncrypted".to_owned(), true.into());
+        plist::to_file_xml(&manifest_plist_path, &manifest_plist).unwrap();
+        let err = read_encrypted_manifest(dir.path()).unwrap_err();
+        assert!(err.to_string().contains("BackupKeyBag"));
+
+        manifest_plist.insert("BackupKeyBag".to_owned(), Value::Data(keybag(3)));
+        manifest_plist.insert("ManifestKey".to_owned(), Value::Data(wrap_key(3, &[9; 32])));
+        plist::to_file_xml(&manifest_plist_path, &manifest_plist).unwrap();
+        let encrypted_manifest = read_encrypted_manifest(dir.path()).unwrap().unwrap();
+        assert!(encrypted_manifest.unlock(PASSWORD).is_ok());
+    }
+
+    #[test]
+    fn it_decrypts_files() {
+        let keys = BackupKeys::unlock(&keybag(3), PASSWORD).unwrap();
+        let err = BackupKeys::unlock(&keybag(3), "wrong").unwrap_err();
+        assert!(err.downcast_ref::<WrongPasswordError>().is_some());
+        let legacy_keys = BackupKeys::unlock(&legacy_keybag(3), PASSWORD).unwrap();
+        let err = BackupKeys::unlock(&legacy_keybag(3), "wrong").unwrap_err();
+        assert!(err.downcast_ref::<WrongPasswordError>().is_some());
+
+        let file_key = [9; 32];
+        assert_eq!(keys.unwrap_key(&wrap_key(3, &file_key)).unwrap(), file_key);
+        assert_eq!(
+            legacy_keys.unwrap_key(&wrap_key(3, &file_key)).unwrap(),
+            file_key
+        );
+        assert!(keys.unwrap_key(&wrap_key(1, &file_key)).is_err());
+
+        let plaintext: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        let dir = tempfile::tempdir().unwrap();
+        let src_path = dir.path().join("blob");
+        let dest_path = dir.path().join("plain");
+        fs::write(&src_path, encrypt(&file_key, &plaintext)).unwrap();
+
+        for (size, buffer_size) in [(None, 64), (Some(1000), 16), (Some(1000), 4096)] {
+            let written =
+                decrypt_file(&src_path, &dest_path, &file_key, size, buffer_size).unwrap();
+            assert_eq!(written, 1000);
+            assert_eq!(fs::read(&dest_path).unwrap(), plaintext);
+        }
+    }
+}
@@ -1,58 +1,308 @@
-use std::fs;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File, FileTimes};
+use std::io::{self, Read, Write};
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context as AnyhowContext, Result};
+use clap::ValueEnum;
+use globset::GlobSet;
+use indicatif::HumanBytes;
+use regex::Regex;
+use time::format_description::OwnedFormatItem;
+use time::OffsetDateTime;
 
-use crate::db::{BackupManifest, ManifestFileType};
-use crate::fs_index::FileSystemIndex;
+use crate::bucket::BucketStrategy;
+use crate::crypto::{self, BackupKeys};
+use crate::db::{self, BackupManifest, ManifestFile, ManifestFileType, PathMatch};
+use crate::fs_index::{FileSystemIndex, PathTakenError};
+use crate::store::ObjectStore;
+use crate::utils::cloud_placeholder::{is_cloud_placeholder, CloudPlaceholderError};
+use crate::utils::protection_class::ProtectionClass;
 use crate::utils::string_pool::StringPool;
+use crate::utils::{portable_name, reflink, relative_path, timestamp};
+
+mod archive;
+mod dedupe;
+mod extract_id;
+mod free_space;
+mod incremental;
+mod index_cache;
+mod metadata;
+mod open;
+mod pack;
+mod parallel;
+mod report;
+mod search;
+mod sidecar;
+mod stats;
+mod stream;
+mod timestamps;
+mod verify;
+
+pub use free_space::{FreeSpaceGuard, LowSpacePolicy};
+pub use index_cache::IndexCache;
+pub use metadata::MetadataFormat;
+pub use report::{ExtractionReport, PhaseDurations, ReportRecorder};
+pub use search::ContentSearchOptions;
+pub use timestamps::TimestampAnomalyKind;
+pub use verify::BlobProblemKind;
+
+use dedupe::Deduper;
+use parallel::WriteProgress;
 
 pub struct Context<'p, 'd> {
     backup_dir: &'p Path,
     manifest: &'d mut BackupManifest,
     copy_mode: bool,
+    bucket_strategy: BucketStrategy,
+    index_cache: Option<IndexCache>,
+    backup_keys: Option<BackupKeys>,
 }
 
 impl<'p, 'd> Context<'p, 'd> {
-    pub fn new(backup_dir: &'p Path, manifest: &'d mut BackupManifest, copy_mode: bool) -> Self {
+    pub fn new(
+        backup_dir: &'p Path,
+        manifest: &'d mut BackupManifest,
+        copy_mode: bool,
+        bucket_strategy: BucketStrategy,
+    ) -> Self {
         Self {
             backup_dir,
             manifest,
             copy_mode,
+            bucket_strategy,
+            index_cache: None,
+            backup_keys: None,
         }
     }
 
+    /// Reuses the files of the domains cached on disk.
+    pub fn with_index_cache(mut self, index_cache: Option<IndexCache>) -> Self {
+        self.index_cache = index_cache;
+        self
+    }
+
+    /// Decrypts the files with the keys of an encrypted backup.
+    pub fn with_backup_keys(mut self, backup_keys: Option<BackupKeys>) -> Self {
+        self.backup_keys = backup_keys;
+        self
+    }
+
     pub fn list_domains(&self) -> Result<Vec<String>> {
         self.manifest.query_domains()
     }
 
-    pub fn extract_file<F>(&self, domain: &str, dest_dir: &Path, progress_cb: F) -> Result<()>
+    pub fn list_domain_file_counts(&self) -> Result<Vec<(String, usize)>> {
+        self.manifest.query_domain_file_counts()
+    }
+
+    pub fn list_domain_sizes(&self) -> Result<Vec<(String, u64)>> {
+        self.manifest.query_domain_sizes()
+    }
+
+    /// Finds the entries of all the domains by their relative paths, see
+    /// `BackupManifest::search`.
+    pub fn search_paths(&self, pattern: &str, ignore_case: bool) -> Result<Vec<PathMatch>> {
+        self.manifest.search(pattern, ignore_case)
+    }
+
+    pub fn query_files(&self, domain: &str) -> Result<Vec<ManifestFile>> {
+        let Some(index_cache) = &self.index_cache else {
+            return self.manifest.query_files(domain);
+        };
+        let manifest_path = db::manifest_path(self.backup_dir);
+        index_cache.get_or_build(&manifest_path, domain, || self.manifest.query_files(domain))
+    }
+
+    /// Calls `f` with each file of a domain as its row is read, without
+    /// going through the index cache.
+    pub fn for_each_file<F>(&self, domain: &str, f: F) -> Result<()>
+    where
+        F: FnMut(ManifestFile) -> Result<()>,
+    {
+        self.manifest.for_each_file(domain, f)
+    }
+
+    pub fn query_file(&self, domain: &str, relative_path: &str) -> Result<ManifestFile> {
+        self.manifest
+            .query_file(domain, relative_path)?
+            .ok_or_else(|| anyhow!("file not found in domain `{domain}`: {relative_path}"))
+    }
+
+    /// Looks up a file by its ID, or by a unique prefix of it.
+    pub fn query_file_by_id(&self, file_id: &str) -> Result<Option<(String, ManifestFile)>> {
+        if file_id.len() >= 40 {
+            return self.manifest.query_file_by_id(file_id);
+        }
+        if !file_id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(anyhow!("invalid file ID prefix `{file_id}`"));
+        }
+        let mut files = self
+            .manifest
+            .query_files_by_id_prefix(&file_id.to_ascii_lowercase(), 2)?;
+        if files.len() > 1 {
+            return Err(anyhow!(
+                "file ID prefix `{file_id}` is ambiguous, give more characters"
+            ));
+        }
+        Ok(files.pop())
+    }
+
+    pub fn query_file_plist(&self, file_id: &str) -> Result<plist::Value> {
+        self.manifest.query_file_plist(file_id)
+    }
+
+    /// Extracts the files of a domain, returns the number of extracted
+    /// files.
+    pub fn extract_file<F>(
+        &self,
+        domain: &str,
+        dest_dir: &Path,
+        options: &ExtractOptions,
+        progress_cb: F,
+    ) -> Result<ExtractionReport>
     where
         F: FnMut(ProgressEvent),
     {
         let mut progress_cb = progress_cb;
+        let mut recorder = ReportRecorder::default();
+        self.extract_domain(domain, dest_dir, options, |event| {
+            recorder.record(&event);
+            progress_cb(event);
+        })?;
+        Ok(recorder.report())
+    }
+
+    fn extract_domain<F>(
+        &self,
+        domain: &str,
+        dest_dir: &Path,
+        options: &ExtractOptions,
+        progress_cb: F,
+    ) -> Result<()>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let mut progress_cb = progress_cb;
+
+        if self.backup_keys.is_some() && (!self.copy_mode || options.store.is_some()) {
+            // Links would point to the encrypted blobs.
+            return Err(anyhow!(
+                "encrypted backups can only be extracted in copy mode (`-c`)"
+            ));
+        }
+
+        // A dry run checks the index too.
+        if options.no_index && !options.dry_run {
+            return self.stream_domain(domain, dest_dir, options, progress_cb);
+        }
 
         let string_pool = StringPool::new();
         let mut file_system_index = FileSystemIndex::new(&string_pool);
 
         progress_cb(ProgressEvent::Querying);
-        let files = self
-            .manifest
-            .query_files(domain)
-            .context("failed to query files from database")?;
+        let files = self.query_extraction_files(domain, options, &mut progress_cb)?;
 
+        if !options.paths.is_empty()
+            && !files
+                .iter()
+                .any(|f| f.file_type != ManifestFileType::Directory && options.selects_path(f))
+        {
+            return Err(paths_not_found(options));
+        }
+
+        if !options.dry_run {
+            check_writable(dest_dir)?;
+        }
+
+        if options.filters() {
+            let regular_files = files
+                .iter()
+                .filter(|f| f.file_type == ManifestFileType::File);
+            let (mut matched, mut total) = (0, 0);
+            for file in regular_files {
+                matched += options.includes(file) as usize;
+                total += 1;
+            }
+            progress_cb(ProgressEvent::Filtered { matched, total });
+        }
+
+        let mut oversized_files = vec![];
+        let mut priority_file_ids = HashSet::new();
+        let mut routed_paths = HashSet::new();
+        let mut files_by_id = HashMap::new();
+        let mut dirs = vec![];
+        let mut symlinks = vec![];
         for (idx, file) in files.iter().enumerate() {
-            if file.file_type != ManifestFileType::File {
+            if file.file_type == ManifestFileType::Directory
+                && options.preserve_timestamps
+                && options.date_layout.is_none()
+            {
+                dirs.extend(options.dest_entry_path(file).map(|path| (path, file)));
+            }
+            if file.file_type == ManifestFileType::SymbolicLink
+                && options.date_layout.is_none()
+                && options.includes(file)
+            {
+                match &file.target {
+                    Some(target) => symlinks.extend(
+                        options
+                            .dest_entry_path(file)
+                            .map(|path| (path, target.as_str(), file)),
+                    ),
+                    None => progress_cb(ProgressEvent::Skipped {
+                        relative_path: file.relative_path.clone(),
+                        reason: "symbolic link without target",
+                    }),
+                }
+            }
+            if !select_file(file, options, &mut progress_cb)? {
                 continue;
             }
-            if file.file_id.len() != 40 {
-                // TODO: handle this error, maybe the database is corrupted.
+            // A file the destination can't hold would only fail halfway
+            // through the extraction, fail before writing anything.
+            let size = file.size.unwrap_or_default();
+            if options.max_file_size.is_some_and(|max| size > max) {
+                oversized_files.push((&file.relative_path, size));
                 continue;
             }
-
-            file_system_index
-                .add_file(&file.relative_path, file.file_id.clone())
-                .with_context(|| format!("failed to index file: {file:?}"))?;
+            let dest_relative_path =
+                self.route_file(file, options, &mut routed_paths, &mut progress_cb)?;
+            if options.prioritizes(file) {
+                priority_file_ids.insert(file.file_id.as_str());
+            }
+            match file_system_index.add_file(dest_relative_path, file.file_id.clone()) {
+                Ok(replaced_file_id) => {
+                    files_by_id.insert(file.file_id.as_str(), file);
+                    // The later file wins, as it does when streaming.
+                    if let Some(replaced) = replaced_file_id
+                        .filter(|file_id| *file_id != file.file_id)
+                        .and_then(|file_id| files_by_id.remove(file_id.as_str()))
+                    {
+                        warn!(
+                            "skipped file `{}`: its destination is taken by `{}`",
+                            replaced.relative_path, file.relative_path
+                        );
+                        progress_cb(ProgressEvent::Skipped {
+                            relative_path: replaced.relative_path.clone(),
+                            reason: "destination path taken by another file",
+                        });
+                    }
+                }
+                Err(err) if err.is::<PathTakenError>() => {
+                    warn!("skipped file `{}`: {err}", file.relative_path);
+                    progress_cb(ProgressEvent::Skipped {
+                        relative_path: file.relative_path.clone(),
+                        reason: "destination path taken by another file",
+                    });
+                }
+                Err(err) => {
+                    return Err(err.context(format!("failed to index file: {file:?}")));
+                }
+            }
 
             progress_cb(ProgressEvent::Indexing {
                 indexed: idx + 1,
@@ -60,66 +310,2338 @@ impl<'p, 'd> Context<'p, 'd> {
             });
         }
 
+        if !oversized_files.is_empty() {
+            return Err(oversized_files_error(&oversized_files));
+        }
+
+        // Links go last, as they can't take the place of a file.
+        for (path, target, file) in &symlinks {
+            if let Err(err) = file_system_index.add_symlink(path, (*target).to_owned()) {
+                warn!("skipped symbolic link `{}`: {err}", file.relative_path);
+                progress_cb(ProgressEvent::Skipped {
+                    relative_path: file.relative_path.clone(),
+                    reason: "path taken by another entry",
+                });
+            }
+        }
+
         let total_file_count = file_system_index.file_count();
-        let mut extracted_file_count = 0;
+        info!("indexed {total_file_count} file(s) of domain `{domain}`");
+        if options.dry_run {
+            return file_system_index.walk_files(|path, file_id| -> Result<()> {
+                progress_cb(ProgressEvent::Planned {
+                    path: path.to_owned(),
+                    size: files_by_id[file_id].size.unwrap_or_default(),
+                });
+                Ok(())
+            });
+        }
+        let mut indexed_files = vec![];
         file_system_index.walk_files(|path, file_id| -> Result<()> {
-            let dest_file_path = dest_dir.join(path);
-            let dir = dest_file_path.parent().expect("path should have a parent");
-            if !dir.exists() {
-                fs::create_dir_all(dir).with_context(|| {
-                    format!("failed to create directory: {}", dir.to_string_lossy())
-                })?;
-            } else if !dir.is_dir() {
-                return Err(anyhow!(
-                    "file already exists but not a directory: {}",
-                    dir.to_string_lossy()
-                ));
-            }
+            indexed_files.push((PathBuf::from(path), files_by_id[file_id]));
+            Ok(())
+        })?;
+        self.check_dest_room(
+            dest_dir,
+            indexed_files
+                .iter()
+                .map(|(path, file)| (path.as_path(), *file)),
+            options,
+        )?;
+
+        // The index knows the whole directory tree, create it up front
+        // instead of checking the parent of every file.
+        create_dir(dest_dir, true)?;
+        file_system_index.walk_dirs(|path| create_dir(&dest_dir.join(path), false))?;
 
-            self.write_file(&dest_file_path, file_id).with_context(|| {
-                format!(
-                    "failed to create file: {}",
-                    dest_file_path.to_string_lossy()
-                )
+        let total_byte_count = indexed_files
+            .iter()
+            .map(|(_, file)| file.size.unwrap_or_default())
+            .sum();
+        let mut progress = WriteProgress {
+            extracted: 0,
+            total: total_file_count,
+            bytes_written: 0,
+            bytes_done: 0,
+            bytes_total: total_byte_count,
+        };
+        progress_cb(ProgressEvent::Extracting {
+            extracted: 0,
+            total: total_file_count,
+            bytes_written: 0,
+            bytes_done: 0,
+            bytes_total: total_byte_count,
+        });
+        // The prioritized files are written in a pass of their own before
+        // the rest.
+        let passes: &[bool] = if priority_file_ids.is_empty() {
+            &[false]
+        } else {
+            &[true, false]
+        };
+        // Links to the blobs and the store share their content already, and
+        // encrypted blobs never match.
+        let dedupe = options.dedupe
+            && self.copy_mode
+            && options.store.is_none()
+            && self.backup_keys.is_none();
+        let mut deduper = dedupe.then(|| Deduper::new(indexed_files.iter().map(|(_, file)| *file)));
+        for prioritized in passes {
+            let mut files = vec![];
+            file_system_index.walk_files(|path, file_id| -> Result<()> {
+                if priority_file_ids.contains(file_id) == *prioritized {
+                    files.push((dest_dir.join(path), files_by_id[file_id]));
+                }
+                Ok(())
             })?;
+            let Some(deduper) = &mut deduper else {
+                self.write_files(&files, options, &mut progress, &mut progress_cb)?;
+                continue;
+            };
+            let (files, duplicates) = deduper.split(self, files);
+            self.write_files(&files, options, &mut progress, &mut progress_cb)?;
+            self.link_duplicates(&duplicates, options, &mut progress, &mut progress_cb)?;
+        }
 
-            extracted_file_count += 1;
-            progress_cb(ProgressEvent::Extracting {
-                extracted: extracted_file_count,
-                total: total_file_count,
+        file_system_index.walk_symlinks(|path, target| {
+            create_symlink(dest_dir, Path::new(path), target, &mut progress_cb)
+        })?;
+        restore_dir_timestamps(
+            dest_dir,
+            dirs.iter().map(|(path, dir)| (path.as_path(), *dir)),
+        )
+    }
+}
+
+impl<'p, 'd> Context<'p, 'd> {
+    /// Returns the path a selected file is extracted to, relative to the
+    /// destination, reporting the paths that were cleaned up and the files
+    /// marked missing.
+    fn route_file<'f, F>(
+        &self,
+        file: &'f ManifestFile,
+        options: &ExtractOptions,
+        routed_paths: &mut HashSet<PathBuf>,
+        progress_cb: &mut F,
+    ) -> Result<Cow<'f, Path>>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let blob_missing = options.placeholders && !self.original_file_path(&file.file_id).exists();
+
+        let normalized =
+            relative_path::normalize(&file.relative_path).context("refused to extract file")?;
+        let mut dest_relative_path = match &normalized {
+            Some(normalized) => {
+                progress_cb(ProgressEvent::Normalized {
+                    relative_path: file.relative_path.clone(),
+                    normalized: normalized.clone(),
+                });
+                Cow::Owned(options.dest_relative_path(normalized)?.to_owned())
+            }
+            None => Cow::Borrowed(options.dest_relative_path(&file.relative_path)?),
+        };
+        if let Some(date_layout) = &options.date_layout {
+            let mut routed_path = date_layout.route(file);
+            if !routed_paths.insert(routed_path.clone()) {
+                // Another file with the same name was routed to the same
+                // folder, make the name unique with its file ID.
+                routed_path = disambiguate(&routed_path, &file.file_id);
+                routed_paths.insert(routed_path.clone());
+            }
+            dest_relative_path = Cow::Owned(routed_path);
+        }
+        if options.portable_names {
+            dest_relative_path = Cow::Owned(portable_name::sanitize(&dest_relative_path));
+        }
+        if blob_missing {
+            let mut marked_path = dest_relative_path.into_owned().into_os_string();
+            marked_path.push(MISSING_SUFFIX);
+            dest_relative_path = Cow::Owned(marked_path.into());
+            progress_cb(ProgressEvent::Placeholder {
+                relative_path: file.relative_path.clone(),
             });
+        }
+        Ok(dest_relative_path)
+    }
 
-            Ok(())
-        })?;
+    /// Fails before copying anything if the destination volume can't hold
+    /// the files, links take next to no space.
+    fn check_dest_room<'f, I>(
+        &self,
+        dest_dir: &Path,
+        files: I,
+        options: &ExtractOptions,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item = (&'f Path, &'f ManifestFile)>,
+    {
+        if !self.checks_room(options) {
+            return Ok(());
+        }
+        let (required, unsized_count) = self.required_room(dest_dir, files, options);
+        free_space::check_room(dest_dir, required, unsized_count)
+    }
 
-        Ok(())
+    fn checks_room(&self, options: &ExtractOptions) -> bool {
+        self.copy_mode && options.store.is_none() && !options.force
+    }
+
+    /// Sums the sizes of the files at their paths under `dest_dir`, and
+    /// counts the ones of unknown size. With `ConflictPolicy::Skip`, the
+    /// files already written there are left out, as they are kept.
+    fn required_room<'f, I>(
+        &self,
+        dest_dir: &Path,
+        files: I,
+        options: &ExtractOptions,
+    ) -> (u64, usize)
+    where
+        I: IntoIterator<Item = (&'f Path, &'f ManifestFile)>,
+    {
+        let writer = self.file_writer(options);
+        let (mut required, mut unsized_count) = (0, 0);
+        for (path, file) in files {
+            if options.on_conflict == ConflictPolicy::Skip {
+                let dest_path = dest_dir.join(path);
+                let is_written = fs::symlink_metadata(&dest_path).is_ok_and(|metadata| {
+                    let original_file_path = self.original_file_path(&file.file_id);
+                    writer.is_written(&dest_path, &metadata, file, &original_file_path)
+                });
+                if is_written {
+                    continue;
+                }
+            }
+            match file.size {
+                Some(size) => required += size,
+                None => unsized_count += 1,
+            }
+        }
+        (required, unsized_count)
+    }
+
+    /// Counts the files an extraction with the same options would write,
+    /// without touching the destination.
+    pub fn measure_extraction(
+        &self,
+        domain: &str,
+        options: &ExtractOptions,
+    ) -> Result<ExtractionSize> {
+        let files = self.query_extraction_files(domain, options, &mut |_| {})?;
+
+        let mut size = ExtractionSize::default();
+        for file in &files {
+            if options.select(file) != Selection::Write {
+                continue;
+            }
+            options.dest_relative_path(&file.relative_path)?;
+
+            size.files += 1;
+            size.bytes += file.size.unwrap_or_default();
+        }
+        Ok(size)
+    }
+
+    /// Queries the rows an extraction goes through, only the ones changed
+    /// since the older backup with `since_backup`.
+    fn query_extraction_files<F>(
+        &self,
+        domain: &str,
+        options: &ExtractOptions,
+        progress_cb: &mut F,
+    ) -> Result<Vec<ManifestFile>>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        if let Some(since_manifest) = &options.since_backup {
+            return self.query_changed_files(domain, since_manifest, progress_cb);
+        }
+        let files = self
+            .query_files(domain)
+            .context("failed to query files from database")?;
+        if files.is_empty() {
+            return Err(anyhow!("domain not found: {domain}"));
+        }
+        Ok(files)
     }
 }
 
 impl<'p, 'd> Context<'p, 'd> {
-    fn write_file(&self, file_path: &Path, file_id: &str) -> Result<()> {
-        let original_file_path = self.original_file_path(file_id);
+    /// Writes a file, returns the number of bytes copied, which is zero
+    /// when linking, or `None` if an existing file was kept.
+    fn write_file(
+        &self,
+        file_path: &Path,
+        file: &ManifestFile,
+        options: &ExtractOptions,
+    ) -> Result<Option<u64>> {
+        self.file_writer(options).write(file_path, file)
+    }
 
-        if self.copy_mode {
-            fs::copy(original_file_path, file_path)?;
+    fn file_writer<'a>(&'a self, options: &'a ExtractOptions) -> FileWriter<'a> {
+        FileWriter {
+            backup_dir: self.backup_dir,
+            bucket_strategy: self.bucket_strategy,
+            copy_mode: self.copy_mode,
+            backup_keys: self.backup_keys.as_ref(),
+            store: options.store.as_ref(),
+            placeholders: options.placeholders,
+            copy_buffer_size: options.copy_buffer_size,
+            reflink: options.reflink,
+            preserve_timestamps: options.preserve_timestamps,
+            preserve_permissions: options.preserve_permissions,
+            on_conflict: options.on_conflict,
+            hard_links: options.hard_links && !self.copy_mode,
+        }
+    }
+
+    pub fn original_file_path(&self, file_id: &str) -> PathBuf {
+        self.bucket_strategy.blob_path(self.backup_dir, file_id)
+    }
+
+    /// Fails for commands that read the blobs as they are, which only
+    /// extraction can decrypt for now.
+    fn reject_encrypted(&self, action: &str) -> Result<()> {
+        if self.backup_keys.is_some() {
+            return Err(anyhow!("{action} is not supported for encrypted backups"));
+        }
+        Ok(())
+    }
+}
+
+/// Number and total size of the files an extraction would write.
+#[derive(Default, Debug)]
+pub struct ExtractionSize {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Appended to the names of the placeholders of missing files.
+pub const MISSING_SUFFIX: &str = ".MISSING";
+
+/// Buffer size used to copy the files unless specified otherwise.
+const DEFAULT_COPY_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Options that control how the extracted files are laid out.
+#[derive(Debug)]
+pub struct ExtractOptions {
+    /// Leading path to strip from the relative paths of extracted files.
+    pub strip_prefix: Option<PathBuf>,
+    /// What to do with the files that are not under `strip_prefix`.
+    pub prefix_mismatch: PrefixMismatchPolicy,
+    /// Matches the paths case-insensitively, like the iOS file system.
+    pub ignore_case: bool,
+    /// Routes files into per-date folders instead of their original paths.
+    pub date_layout: Option<DateLayout>,
+    /// Pauses or stops the extraction when the destination is low on space.
+    pub free_space_guard: Option<FreeSpaceGuard>,
+    /// Writes the files as the manifest rows are read instead of building
+    /// the file system index first, which saves memory on huge domains.
+    pub no_index: bool,
+    /// Size of the buffer used to copy the files in copy mode.
+    pub copy_buffer_size: usize,
+    /// Clones the files in copy mode where the file system can, sharing
+    /// their blocks with the blobs until either is modified.
+    pub reflink: bool,
+    /// Only extracts the files whose relative paths match.
+    pub include: Option<GlobSet>,
+    /// Leaves out the files whose relative paths match, even if included.
+    pub exclude: Option<GlobSet>,
+    /// Only extracts the files whose relative paths match, anywhere in the
+    /// path unless anchored.
+    pub regex: Option<Regex>,
+    /// Only extracts the files last modified at or after this time, in
+    /// seconds since the Unix epoch. Files without the time are kept.
+    pub modified_after: Option<i64>,
+    /// Only extracts the files last modified before this time.
+    pub modified_before: Option<i64>,
+    /// Extracts the files whose relative paths match before the others.
+    pub priority: Option<GlobSet>,
+    /// Copies the blobs into a content-addressed store and links the
+    /// extracted files to the stored objects.
+    pub store: Option<ObjectStore>,
+    /// Replaces the characters FAT and Windows reject in file names.
+    pub portable_names: bool,
+    /// Fails before writing anything if a file is larger than this.
+    pub max_file_size: Option<u64>,
+    /// Only extracts the files added or changed since this older backup.
+    pub since_backup: Option<BackupManifest>,
+    /// Only extracts the files of these protection classes, unless empty.
+    pub protection_classes: Vec<ProtectionClass>,
+    /// Writes an empty `<name>.MISSING` file in place of each file whose
+    /// blob is missing, instead of failing or linking to nothing.
+    pub placeholders: bool,
+    /// Sets the times of the copied files and directories from their
+    /// metadata, links are left alone.
+    pub preserve_timestamps: bool,
+    /// Sets the permission bits of the copied files from their metadata,
+    /// only on Unix.
+    pub preserve_permissions: bool,
+    /// Only extracts these files and the files under these directories,
+    /// unless empty.
+    pub paths: Vec<PathBuf>,
+    /// Builds the index but writes nothing, sending a `Planned` event for
+    /// each file instead.
+    pub dry_run: bool,
+    /// Number of threads writing the files, only used with the index and
+    /// without a store.
+    pub jobs: NonZeroUsize,
+    /// What to do with the files whose destination paths already exist.
+    pub on_conflict: ConflictPolicy,
+    /// Reports the files that fail to be written with `Failed` events and
+    /// goes on with the others, instead of stopping at the first one.
+    pub keep_going: bool,
+    /// Fails on the rows with malformed file IDs instead of skipping them.
+    pub strict: bool,
+    /// Copies the files even if the destination volume looks too small.
+    pub force: bool,
+    /// Creates hard links to the blobs instead of symbolic links, outside
+    /// of copy mode. The destination must be on the volume of the backup.
+    pub hard_links: bool,
+    /// Hard links the files with the same content to the first copy in
+    /// copy mode, instead of copying each of them.
+    pub dedupe: bool,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            strip_prefix: None,
+            prefix_mismatch: Default::default(),
+            ignore_case: false,
+            date_layout: None,
+            free_space_guard: None,
+            no_index: false,
+            copy_buffer_size: DEFAULT_COPY_BUFFER_SIZE,
+            reflink: true,
+            include: None,
+            exclude: None,
+            regex: None,
+            modified_after: None,
+            modified_before: None,
+            priority: None,
+            store: None,
+            portable_names: false,
+            max_file_size: None,
+            since_backup: None,
+            protection_classes: vec![],
+            placeholders: false,
+            preserve_timestamps: true,
+            preserve_permissions: false,
+            paths: vec![],
+            dry_run: false,
+            jobs: NonZeroUsize::MIN,
+            on_conflict: Default::default(),
+            keep_going: false,
+            strict: false,
+            force: false,
+            hard_links: false,
+            dedupe: false,
+        }
+    }
+}
+
+impl ExtractOptions {
+    /// How an extraction treats a row: it writes the regular files the
+    /// options include, as long as their IDs can name blobs.
+    fn select(&self, file: &ManifestFile) -> Selection {
+        if file.file_type != ManifestFileType::File || !self.includes(file) {
+            Selection::Leave
+        } else if !has_valid_file_id(file) {
+            Selection::MalformedId
+        } else {
+            Selection::Write
+        }
+    }
+
+    fn prioritizes(&self, file: &ManifestFile) -> bool {
+        self.priority
+            .as_ref()
+            .is_some_and(|priority| priority.is_match(&file.relative_path))
+    }
+
+    fn includes(&self, file: &ManifestFile) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.is_match(&file.relative_path))
+            && !self
+                .exclude
+                .as_ref()
+                .is_some_and(|exclude| exclude.is_match(&file.relative_path))
+            && self
+                .regex
+                .as_ref()
+                .is_none_or(|regex| regex.is_match(&file.relative_path))
+            && (self.protection_classes.is_empty()
+                || self.protection_classes.contains(&ProtectionClass::of(file)))
+            && self.selects_path(file)
+            && self.modified_in_window(file)
+    }
+
+    fn filters(&self) -> bool {
+        self.include.is_some()
+            || self.exclude.is_some()
+            || self.regex.is_some()
+            || self.filters_by_time()
+            || !self.protection_classes.is_empty()
+            || !self.paths.is_empty()
+    }
+
+    fn filters_by_time(&self) -> bool {
+        self.modified_after.is_some() || self.modified_before.is_some()
+    }
+
+    fn modified_in_window(&self, file: &ManifestFile) -> bool {
+        let Some(last_modified) = file.last_modified else {
+            return true;
+        };
+        self.modified_after
+            .is_none_or(|after| last_modified >= after)
+            && self
+                .modified_before
+                .is_none_or(|before| last_modified < before)
+    }
+
+    fn selects_path(&self, file: &ManifestFile) -> bool {
+        // Matches whole components, so `Library/SMS` doesn't select
+        // `Library/SMSBackup`.
+        self.paths.is_empty()
+            || self
+                .paths
+                .iter()
+                .any(|path| Path::new(&file.relative_path).starts_with(path))
+    }
+
+    /// Returns where a directory or symbolic link of the domain ends up in
+    /// the destination, if anywhere.
+    fn dest_entry_path(&self, entry: &ManifestFile) -> Option<PathBuf> {
+        self.dest_path(entry)
+            .ok()
+            .filter(|path| !path.as_os_str().is_empty())
+    }
+
+    /// Returns the path of an entry relative to the destination, which is
+    /// empty for the root of the domain. The date layout isn't applied.
+    fn dest_path(&self, entry: &ManifestFile) -> Result<PathBuf> {
+        let normalized =
+            relative_path::normalize(&entry.relative_path).context("refused to extract file")?;
+        let relative_path = normalized.as_deref().unwrap_or(&entry.relative_path);
+        let path = self.dest_relative_path(relative_path)?;
+        if self.portable_names {
+            return Ok(portable_name::sanitize(path));
+        }
+        Ok(path.to_owned())
+    }
+
+    fn dest_relative_path<'a>(&self, relative_path: &'a str) -> Result<&'a Path> {
+        let path = Path::new(relative_path);
+        let Some(prefix) = &self.strip_prefix else {
+            return Ok(path);
+        };
+
+        let stripped = if self.ignore_case {
+            strip_prefix_ignore_case(path, prefix)
         } else {
-            #[cfg(unix)]
-            std::os::unix::fs::symlink(original_file_path, file_path)?;
-            #[cfg(windows)]
-            panic!("symbolic link mode is not supported on Windows");
+            path.strip_prefix(prefix).ok()
+        };
+        match stripped {
+            // The prefix is the file itself, keep its name at least.
+            Some(stripped) if stripped.as_os_str().is_empty() => {
+                Ok(path.file_name().map(Path::new).unwrap_or(path))
+            }
+            Some(stripped) => Ok(stripped),
+            None => match self.prefix_mismatch {
+                PrefixMismatchPolicy::Error => Err(anyhow!(
+                    "file is not under the stripped prefix `{}`: {relative_path}",
+                    prefix.to_string_lossy()
+                )),
+                PrefixMismatchPolicy::Keep => Ok(path),
+            },
+        }
+    }
+}
+
+/// Like `Path::strip_prefix`, but compares the components ignoring case.
+fn strip_prefix_ignore_case<'a>(path: &'a Path, prefix: &Path) -> Option<&'a Path> {
+    let mut components = path.components();
+    for prefix_component in prefix.components() {
+        let component = components.next()?.as_os_str().to_str()?;
+        let prefix_component = prefix_component.as_os_str().to_str()?;
+        if component.to_lowercase() != prefix_component.to_lowercase() {
+            return None;
+        }
+    }
+    Some(components.as_path())
+}
+
+/// Places each file as `<formatted date>/<file name>`, or under
+/// `undated/` when the timestamp is missing.
+#[derive(Debug)]
+pub struct DateLayout {
+    pub field: TimestampField,
+    pub format: OwnedFormatItem,
+}
+
+impl DateLayout {
+    fn route(&self, file: &ManifestFile) -> PathBuf {
+        let file_name = Path::new(&file.relative_path)
+            .file_name()
+            .map(Path::new)
+            .unwrap_or(Path::new(&file.relative_path));
+        let folder = self
+            .field
+            .get(file)
+            .and_then(|t| OffsetDateTime::from_unix_timestamp(t).ok())
+            .and_then(|d| d.format(&self.format).ok())
+            .unwrap_or_else(|| "undated".to_owned());
+        Path::new(&folder).join(file_name)
+    }
+}
+
+#[derive(ValueEnum, PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum TimestampField {
+    #[default]
+    LastModified,
+    LastStatusChange,
+    Birth,
+}
+
+impl TimestampField {
+    fn get(&self, file: &ManifestFile) -> Option<i64> {
+        match self {
+            Self::LastModified => file.last_modified,
+            Self::LastStatusChange => file.last_status_change,
+            Self::Birth => file.birth,
+        }
+    }
+}
+
+/// What an extraction does with a row of the manifest.
+#[derive(PartialEq, Eq, Debug)]
+enum Selection {
+    Write,
+    /// Not a regular file, or not included by the options.
+    Leave,
+    MalformedId,
+}
+
+/// Checks that the file ID is a lowercase hex SHA-1, as blobs are named.
+fn has_valid_file_id(file: &ManifestFile) -> bool {
+    file.file_id.len() == 40
+        && file
+            .file_id
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Copies a file with a buffer of the given size, the permissions are
+/// copied as well like `fs::copy` does. On Linux, the kernel copies what
+/// it can first.
+fn copy_file(from: &Path, to: &Path, buffer_size: usize) -> io::Result<u64> {
+    let mut reader = fs::File::open(from)?;
+    let metadata = reader.metadata()?;
+    let mut writer = fs::File::create(to)?;
+
+    #[cfg(target_os = "linux")]
+    let mut size = crate::utils::kernel_copy::copy_file_range(&reader, &writer)?;
+    #[cfg(not(target_os = "linux"))]
+    let mut size = 0;
+
+    // Small files, or what the kernel left, don't need the whole buffer.
+    let left = metadata.len().saturating_sub(size);
+    let mut buf = vec![0; buffer_size.min(left as usize).max(1)];
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        writer.write_all(&buf[..len])?;
+        size += len as u64;
+    }
+    writer.set_permissions(metadata.permissions())?;
+    Ok(size)
+}
+
+/// The parts of the context and options that writing a file needs, which
+/// unlike the context can be shared with the extraction threads.
+struct FileWriter<'a> {
+    backup_dir: &'a Path,
+    bucket_strategy: BucketStrategy,
+    copy_mode: bool,
+    backup_keys: Option<&'a BackupKeys>,
+    store: Option<&'a ObjectStore>,
+    placeholders: bool,
+    copy_buffer_size: usize,
+    reflink: bool,
+    preserve_timestamps: bool,
+    preserve_permissions: bool,
+    on_conflict: ConflictPolicy,
+    hard_links: bool,
+}
+
+impl FileWriter<'_> {
+    fn write(&self, file_path: &Path, file: &ManifestFile) -> Result<Option<u64>> {
+        let original_file_path = self
+            .bucket_strategy
+            .blob_path(self.backup_dir, &file.file_id);
+
+        if !self.clear_dest(file_path, file, &original_file_path)? {
+            return Ok(None);
+        }
+        self.write_new(file_path, file, &original_file_path)
+            .map(Some)
+    }
+
+    /// Writes a file with the same content as the one already written at
+    /// `first_path` as a hard link to it, which shares the metadata of the
+    /// first file then. If it can't be linked, it's written like any other
+    /// file. Returns what `write` does, and whether it was linked.
+    fn write_duplicate(
+        &self,
+        file_path: &Path,
+        file: &ManifestFile,
+        first_path: &Path,
+    ) -> Result<(Option<u64>, bool)> {
+        let original_file_path = self
+            .bucket_strategy
+            .blob_path(self.backup_dir, &file.file_id);
+        if !self.clear_dest(file_path, file, &original_file_path)? {
+            return Ok((None, false));
+        }
+        match fs::hard_link(first_path, file_path) {
+            Ok(()) => {
+                debug!(
+                    "hard linked {} -> {}",
+                    file_path.to_string_lossy(),
+                    first_path.to_string_lossy()
+                );
+                Ok((Some(0), true))
+            }
+            Err(err) => {
+                debug!(
+                    "failed to hard link {}, copying instead: {err}",
+                    file_path.to_string_lossy()
+                );
+                let size = self.write_new(file_path, file, &original_file_path)?;
+                Ok((Some(size), false))
+            }
+        }
+    }
+
+    /// Makes way for a file according to the conflict policy, returns
+    /// `false` if the existing file is kept instead.
+    fn clear_dest(
+        &self,
+        file_path: &Path,
+        file: &ManifestFile,
+        original_file_path: &Path,
+    ) -> Result<bool> {
+        match fs::symlink_metadata(file_path) {
+            Ok(metadata) => match self.on_conflict {
+                ConflictPolicy::Error => {
+                    return Err(anyhow!(
+                        "destination already exists, \
+                         pass `--skip-existing` or `--overwrite` to extract anyway"
+                    ))
+                }
+                ConflictPolicy::Skip
+                    if self.is_written(file_path, &metadata, file, original_file_path) =>
+                {
+                    debug!("kept existing {}", file_path.to_string_lossy());
+                    return Ok(false);
+                }
+                // Removed rather than written through, as it may be a link
+                // to the blob.
+                _ => fs::remove_file(file_path)?,
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        Ok(true)
+    }
+
+    fn write_new(
+        &self,
+        file_path: &Path,
+        file: &ManifestFile,
+        original_file_path: &Path,
+    ) -> Result<u64> {
+        if self.placeholders && !original_file_path.exists() {
+            File::create(file_path)?;
+            debug!("marked missing {}", file_path.to_string_lossy());
+            return Ok(0);
+        }
+        // Extracting a dataless placeholder would silently produce an empty
+        // file.
+        if fs::metadata(original_file_path).is_ok_and(|m| is_cloud_placeholder(&m, file.size)) {
+            return Err(CloudPlaceholderError.into());
+        }
+
+        if let Some(store) = self.store {
+            let (object_path, size) = store.insert(original_file_path)?;
+            // Hard links keep the tree usable without the store path, fall
+            // back to a symbolic link across volumes.
+            if fs::hard_link(&object_path, file_path).is_err() {
+                #[cfg(unix)]
+                std::os::unix::fs::symlink(&object_path, file_path)?;
+                #[cfg(windows)]
+                std::os::windows::fs::symlink_file(&object_path, file_path)?;
+            }
+            debug!(
+                "stored {} -> {} ({size} bytes copied)",
+                file_path.to_string_lossy(),
+                object_path.to_string_lossy()
+            );
+            return Ok(size);
+        }
+
+        if let Some(backup_keys) = self.backup_keys {
+            let wrapped_key = file
+                .encryption_key
+                .as_deref()
+                .ok_or_else(|| anyhow!("file has no encryption key"))?;
+            let key = backup_keys.unwrap_key(wrapped_key)?;
+            let size = crypto::decrypt_file(
+                original_file_path,
+                file_path,
+                &key,
+                file.size,
+                self.copy_buffer_size,
+            )?;
+            self.restore_metadata(file_path, file)?;
+            debug!(
+                "decrypted {} -> {} ({size} bytes)",
+                original_file_path.to_string_lossy(),
+                file_path.to_string_lossy()
+            );
+            return Ok(size);
+        }
+
+        if self.hard_links {
+            // Shares the blob, so its metadata is left alone.
+            fs::hard_link(original_file_path, file_path)?;
+            debug!(
+                "hard linked {} -> {}",
+                file_path.to_string_lossy(),
+                original_file_path.to_string_lossy()
+            );
+            return Ok(0);
+        }
+
+        if self.copy_mode {
+            let cloned_size = if self.reflink {
+                reflink::clone_file(original_file_path, file_path)?
+            } else {
+                None
+            };
+            let size = match cloned_size {
+                Some(size) => size,
+                None => copy_file(original_file_path, file_path, self.copy_buffer_size)?,
+            };
+            self.restore_metadata(file_path, file)?;
+            debug!(
+                "{} {} -> {} ({size} bytes)",
+                if cloned_size.is_some() {
+                    "cloned"
+                } else {
+                    "copied"
+                },
+                original_file_path.to_string_lossy(),
+                file_path.to_string_lossy()
+            );
+            return Ok(size);
+        }
+
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(original_file_path, file_path)?;
+        #[cfg(windows)]
+        std::os::windows::fs::symlink_file(original_file_path, file_path)?;
+        debug!(
+            "linked {} -> {}",
+            file_path.to_string_lossy(),
+            original_file_path.to_string_lossy()
+        );
+        Ok(0)
+    }
+
+    /// Checks whether an existing destination already holds the file, as
+    /// after an interrupted extraction.
+    fn is_written(
+        &self,
+        path: &Path,
+        metadata: &fs::Metadata,
+        file: &ManifestFile,
+        original_file_path: &Path,
+    ) -> bool {
+        if metadata.is_symlink() {
+            return !self.copy_mode
+                && self.store.is_none()
+                && !self.hard_links
+                && fs::read_link(path).is_ok_and(|target| target == original_file_path);
+        }
+        if self.hard_links {
+            return is_same_file(metadata, original_file_path);
+        }
+        // A copy is only trusted if it's complete and was finished, which
+        // is when its time is restored.
+        metadata.is_file()
+            && file.size.is_none_or(|size| metadata.len() == size)
+            && (!self.preserve_timestamps
+                || file.last_modified.is_none_or(|last_modified| {
+                    metadata
+                        .modified()
+                        .is_ok_and(|modified| timestamp::unix_timestamp(modified) == last_modified)
+                }))
+    }
+
+    /// Restores the metadata of a copied file as the options ask for.
+    fn restore_metadata(&self, path: &Path, file: &ManifestFile) -> io::Result<()> {
+        if self.preserve_timestamps {
+            restore_timestamps(path, file)?;
+        }
+        // Last, as the file may not be readable afterwards.
+        #[cfg(unix)]
+        if let Some(mode) = file.mode.filter(|_| self.preserve_permissions) {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777))?;
         }
         Ok(())
     }
+}
+
+#[cfg(unix)]
+fn is_same_file(metadata: &fs::Metadata, other_path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(other_path)
+        .is_ok_and(|other| (other.dev(), other.ino()) == (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn is_same_file(_metadata: &fs::Metadata, _other_path: &Path) -> bool {
+    false
+}
+
+/// Whether the files extracted to `dest_dir` can be hard links to the
+/// blobs, which needs the destination on the volume of the backup and a
+/// file system that supports them. It's tried on the closest existing
+/// directory, as the destination may not exist yet.
+pub fn can_hard_link(backup_dir: &Path, dest_dir: &Path) -> bool {
+    let probe_path =
+        existing_dir(dest_dir).join(format!(".ibackupextractor-link-{}", std::process::id()));
+    let linked = fs::hard_link(db::manifest_path(backup_dir), &probe_path).is_ok();
+    if linked {
+        let _ = fs::remove_file(&probe_path);
+    }
+    linked
+}
+
+/// Returns the closest directory of `path` that exists, itself included.
+fn existing_dir(path: &Path) -> &Path {
+    path.ancestors()
+        .find(|dir| dir.is_dir())
+        .unwrap_or(Path::new("."))
+}
+
+/// Sets the modification time, and the creation time where supported, of
+/// an extracted file or directory from its metadata.
+fn restore_timestamps(path: &Path, file: &ManifestFile) -> io::Result<()> {
+    let Some(last_modified) = file.last_modified else {
+        return Ok(());
+    };
+    let times = FileTimes::new().set_modified(timestamp::system_time(last_modified));
+    #[cfg(target_os = "macos")]
+    let times = match file.birth {
+        Some(birth) => {
+            use std::os::macos::fs::FileTimesExt;
+            times.set_created(timestamp::system_time(birth))
+        }
+        None => times,
+    };
+    #[cfg(windows)]
+    let times = match file.birth {
+        Some(birth) => {
+            use std::os::windows::fs::FileTimesExt;
+            times.set_created(timestamp::system_time(birth))
+        }
+        None => times,
+    };
+    #[cfg(not(windows))]
+    let file = File::open(path)?;
+    // `SetFileTime` needs a writable handle, and directories can only be
+    // opened with backup semantics.
+    #[cfg(windows)]
+    let file = {
+        use std::os::windows::fs::OpenOptionsExt;
+        const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x02000000;
+        fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+            .open(path)?
+    };
+    file.set_times(times)
+}
+
+/// Creates a symbolic link, replacing an earlier link but never another
+/// kind of entry. The target is never followed, so dangling or cyclic
+/// links are fine.
+fn create_symlink<F>(dest_dir: &Path, path: &Path, target: &str, progress_cb: &mut F) -> Result<()>
+where
+    F: FnMut(ProgressEvent),
+{
+    let link_path = dest_dir.join(path);
+    if is_behind_symlink(dest_dir, path) {
+        warn!(
+            "skipped symbolic link, path goes through another link: {}",
+            link_path.to_string_lossy()
+        );
+        progress_cb(ProgressEvent::Skipped {
+            relative_path: path.to_string_lossy().into_owned(),
+            reason: "path goes through a symbolic link",
+        });
+        return Ok(());
+    }
+    match fs::symlink_metadata(&link_path) {
+        Ok(metadata) if metadata.is_symlink() => fs::remove_file(&link_path)?,
+        Ok(_) => {
+            warn!(
+                "skipped symbolic link, path is taken: {}",
+                link_path.to_string_lossy()
+            );
+            progress_cb(ProgressEvent::Skipped {
+                relative_path: path.to_string_lossy().into_owned(),
+                reason: "path taken by another entry",
+            });
+            return Ok(());
+        }
+        Err(_) => {}
+    }
+
+    #[cfg(unix)]
+    let res = std::os::unix::fs::symlink(target, &link_path);
+    // Creating links needs extra privileges on Windows, write the target
+    // into a plain file instead.
+    #[cfg(windows)]
+    let res = fs::write(&link_path, target);
+    res.with_context(|| {
+        format!(
+            "failed to create symbolic link: {}",
+            link_path.to_string_lossy()
+        )
+    })?;
+    debug!("linked {} -> {target}", link_path.to_string_lossy());
+    Ok(())
+}
+
+/// Sets the times of the directories after all their children are
+/// written, as writing a child changes the time of its parent.
+/// Whether a file of the domain is extracted, reporting the ones skipped
+/// for their malformed IDs, which fail the extraction with `strict`.
+fn select_file<F>(
+    file: &ManifestFile,
+    options: &ExtractOptions,
+    progress_cb: &mut F,
+) -> Result<bool>
+where
+    F: FnMut(ProgressEvent),
+{
+    match options.select(file) {
+        Selection::Write => {}
+        Selection::Leave => return Ok(false),
+        Selection::MalformedId => {
+            // The row can't map to a blob, maybe the database is corrupted.
+            if options.strict {
+                return Err(anyhow!(
+                    "file has a malformed ID `{}`: {}",
+                    file.file_id,
+                    file.relative_path
+                ));
+            }
+            warn!("skipped file with malformed ID: {}", file.relative_path);
+            progress_cb(ProgressEvent::Skipped {
+                relative_path: file.relative_path.clone(),
+                reason: "malformed file ID",
+            });
+            return Ok(false);
+        }
+    }
+    if options.filters_by_time() && file.last_modified.is_none() {
+        warn!(
+            "included file without modification time: {}",
+            file.relative_path
+        );
+    }
+    Ok(true)
+}
+
+fn paths_not_found(options: &ExtractOptions) -> anyhow::Error {
+    let paths: Vec<_> = options.paths.iter().map(|p| p.to_string_lossy()).collect();
+    anyhow!("no file found at the given path(s): {}", paths.join(", "))
+}
+
+fn oversized_files_error<P>(files: &[(P, u64)]) -> anyhow::Error
+where
+    P: fmt::Display,
+{
+    let list: Vec<_> = files
+        .iter()
+        .map(|(path, size)| format!("  {path} ({})", HumanBytes(*size)))
+        .collect();
+    anyhow!(
+        "{} file(s) exceed the file size limit of the destination:\n{}",
+        files.len(),
+        list.join("\n")
+    )
+}
+
+fn restore_dir_timestamps<'f, I>(dest_dir: &Path, dirs: I) -> Result<()>
+where
+    I: IntoIterator<Item = (&'f Path, &'f ManifestFile)>,
+{
+    for (path, dir) in dirs {
+        let dest_path = dest_dir.join(path);
+        // Directories without extracted files are never created, and the
+        // extracted links in their place may point anywhere.
+        let is_dir = fs::symlink_metadata(&dest_path).is_ok_and(|m| m.is_dir());
+        if !is_dir || is_behind_symlink(dest_dir, path) {
+            continue;
+        }
+        restore_timestamps(&dest_path, dir).with_context(|| {
+            format!(
+                "failed to set the times of directory: {}",
+                dest_path.to_string_lossy()
+            )
+        })?;
+    }
+    Ok(())
+}
 
-    fn original_file_path(&self, file_id: &str) -> PathBuf {
-        let bucket = &file_id[0..2];
-        self.backup_dir.join(bucket).join(file_id)
+/// Whether an entry of the destination would be reached through one of
+/// the extracted symbolic links, which may point outside of it.
+fn is_behind_symlink(dest_dir: &Path, path: &Path) -> bool {
+    let Some(parent) = path.parent() else {
+        return false;
+    };
+    let mut dir = dest_dir.to_owned();
+    parent.components().any(|component| {
+        dir.push(component);
+        fs::symlink_metadata(&dir).is_ok_and(|m| m.is_symlink())
+    })
+}
+
+/// Creates a directory, an existing one is fine but an existing file in
+/// its place is an error.
+fn create_dir(dir: &Path, recursive: bool) -> Result<()> {
+    let res = if recursive {
+        fs::create_dir_all(dir)
+    } else {
+        fs::create_dir(dir)
+    };
+    match res {
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+        res => {
+            return res
+                .with_context(|| format!("failed to create directory: {}", dir.to_string_lossy()))
+        }
     }
+    if !dir.is_dir() {
+        return Err(anyhow!(
+            "file already exists but not a directory: {}",
+            dir.to_string_lossy()
+        ));
+    }
+    Ok(())
+}
+
+/// Fails if files can't be written into `dest_dir`, which is created if
+/// needed, so that a bad destination is reported before the domain is
+/// indexed rather than at the first file.
+fn check_writable(dest_dir: &Path) -> Result<()> {
+    create_dir(dest_dir, true)?;
+    tempfile::Builder::new()
+        .prefix(".ibackupextractor-")
+        .tempfile_in(dest_dir)
+        .with_context(|| {
+            format!(
+                "destination is not writable: {}",
+                dest_dir.to_string_lossy()
+            )
+        })?;
+    Ok(())
+}
+
+fn disambiguate(path: &Path, file_id: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let suffix = &file_id[0..8];
+    let file_name = match path.extension() {
+        Some(ext) => format!("{stem}-{suffix}.{}", ext.to_string_lossy()),
+        None => format!("{stem}-{suffix}"),
+    };
+    path.with_file_name(file_name)
+}
+
+#[derive(ValueEnum, PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum PrefixMismatchPolicy {
+    /// Abort the extraction.
+    #[default]
+    Error,
+    /// Extract the file with its original path.
+    Keep,
+}
+
+/// What to do when the destination path of a file already exists.
+#[derive(ValueEnum, PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum ConflictPolicy {
+    /// Replace the existing file.
+    Overwrite,
+    /// Keep the existing file if it matches in size and modification time
+    /// (or links to the blob), to resume an interrupted extraction.
+    Skip,
+    /// Abort the extraction.
+    #[default]
+    Error,
 }
 
 #[derive(Debug)]
 pub enum ProgressEvent {
     Querying,
-    Indexing { indexed: usize, total: usize },
-    Extracting { extracted: usize, total: usize },
+    Indexing {
+        indexed: usize,
+        total: usize,
+    },
+    Extracting {
+        /// Files done so far, the ones that failed with `keep_going`
+        /// included.
+        extracted: usize,
+        total: usize,
+        /// Bytes copied so far, links don't count.
+        bytes_written: u64,
+        /// Recorded size of the files done so far, or the bytes copied for
+        /// the copied ones. Files of unknown size count as zero.
+        bytes_done: u64,
+        /// Recorded size of all the files to extract.
+        bytes_total: u64,
+    },
+    Skipped {
+        relative_path: String,
+        reason: &'static str,
+    },
+    /// The domain was compared with an older backup.
+    Compared {
+        added: usize,
+        changed: usize,
+        /// Relative paths of the files that no longer exist.
+        deleted: Vec<String>,
+    },
+    /// The extraction moved on to the domain at `index` (from 0) of the
+    /// `total` domains, sent when extracting several of them. The domains
+    /// before it had `files_before` of the `files_total` files.
+    Domain {
+        index: usize,
+        total: usize,
+        files_before: usize,
+        files_total: usize,
+    },
+    /// A dry run would write a file at `path`, relative to the destination.
+    Planned {
+        path: String,
+        size: u64,
+    },
+    /// The files of the domain were filtered, `matched` of the `total`
+    /// regular files are extracted.
+    Filtered {
+        matched: usize,
+        total: usize,
+    },
+    /// An existing file was kept in place of the file.
+    Kept {
+        relative_path: String,
+    },
+    /// The file was written, or kept, at `path` in the destination.
+    Written {
+        relative_path: String,
+        file_id: String,
+        path: PathBuf,
+        size: u64,
+    },
+    /// The file couldn't be written, with `keep_going`.
+    Failed {
+        relative_path: String,
+        error: String,
+    },
+    /// The blob of the file is missing, a placeholder is written instead.
+    Placeholder {
+        relative_path: String,
+    },
+    /// The blob of the file is a cloud placeholder, the file is left
+    /// unwritten and reported as `Failed` too.
+    Offloaded {
+        relative_path: String,
+    },
+    /// The file has the same content as one written before, and was hard
+    /// linked to it instead of copying `size` bytes.
+    Deduplicated {
+        relative_path: String,
+        size: u64,
+    },
+    /// The relative path had odd but harmless components.
+    Normalized {
+        relative_path: String,
+        normalized: String,
+    },
+    WaitingForSpace {
+        available: u64,
+        required: u64,
+    },
+    Packing {
+        packed: usize,
+        total: usize,
+    },
+    Verifying,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::num::NonZeroUsize;
+    use std::path::{Path, PathBuf};
+
+    use globset::{Glob, GlobSetBuilder};
+    use regex::Regex;
+
+    use super::{
+        can_hard_link, ConflictPolicy, Context, DateLayout, ExtractOptions, PrefixMismatchPolicy,
+        ProgressEvent, TimestampField,
+    };
+    use crate::bucket::BucketStrategy;
+    use crate::crypto::test_support;
+    use crate::utils::protection_class::ProtectionClass;
+    use crate::utils::test_fixture::FixtureBackup;
+    use crate::utils::timestamp::{system_time, unix_timestamp};
+
+    #[test]
+    fn it_strips_prefix() {
+        let mut options = ExtractOptions {
+            strip_prefix: Some(PathBuf::from("Library/SMS")),
+            ..Default::default()
+        };
+
+        let dest = |options: &ExtractOptions, path| {
+            options.dest_relative_path(path).map(Path::to_path_buf).ok()
+        };
+        assert_eq!(
+            dest(&options, "Library/SMS/sms.db"),
+            Some(PathBuf::from("sms.db"))
+        );
+        assert_eq!(dest(&options, "Library/SMS"), Some(PathBuf::from("SMS")));
+        assert_eq!(dest(&options, "Library/SMSBackup/a"), None);
+        assert_eq!(dest(&options, "library/sms/sms.db"), None);
+
+        options.ignore_case = true;
+        assert_eq!(
+            dest(&options, "library/sms/sms.db"),
+            Some(PathBuf::from("sms.db"))
+        );
+        assert_eq!(dest(&options, "Library/SMSBackup/a"), None);
+
+        options.prefix_mismatch = PrefixMismatchPolicy::Keep;
+        assert_eq!(
+            dest(&options, "Library/Notes/a"),
+            Some(PathBuf::from("Library/Notes/a"))
+        );
+    }
+
+    #[test]
+    fn it_fails_on_cloud_placeholders() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/a.db", b"a");
+        let offloaded_id = fixture.add_file("HomeDomain", "Library/b.db", b"b");
+        fs::write(
+            fixture.path().join(&offloaded_id[0..2]).join(&offloaded_id),
+            b"",
+        )
+        .unwrap();
+
+        for no_index in [false, true] {
+            let out_dir = tempfile::tempdir().unwrap();
+            let mut manifest = fixture.manifest();
+            let context = fixture.context(&mut manifest);
+            let options = ExtractOptions {
+                no_index,
+                ..Default::default()
+            };
+            let report = context
+                .extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+                .unwrap();
+            assert_eq!(report.offloaded, ["Library/b.db"]);
+            assert_eq!(report.failed.len(), 1);
+            assert!(report.failed[0].1.contains("cloud placeholder"));
+            assert_eq!(report.files_extracted, 1);
+            assert_eq!(fs::read(out_dir.path().join("Library/a.db")).unwrap(), b"a");
+            assert!(!out_dir.path().join("Library/b.db").exists());
+        }
+    }
+
+    #[test]
+    fn it_routes_files_into_date_folders() {
+        let fixture = FixtureBackup::new();
+        // The fixture files are born on 2020-09-13 and modified on
+        // 2022-04-15.
+        fixture.add_file("CameraRollDomain", "Media/DCIM/100APPLE/a.jpg", b"a");
+        let second_id = fixture.add_file("CameraRollDomain", "Media/DCIM/101APPLE/a.jpg", b"b");
+        fixture.add_file("CameraRollDomain", "Media/DCIM/101APPLE/b", b"c");
+
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+        for (field, folder) in [
+            (TimestampField::LastModified, "2022/04"),
+            (TimestampField::Birth, "2020/09"),
+        ] {
+            let out_dir = tempfile::tempdir().unwrap();
+            let options = ExtractOptions {
+                date_layout: Some(DateLayout {
+                    field,
+                    format: time::format_description::parse_owned::<2>("[year]/[month]").unwrap(),
+                }),
+                ..Default::default()
+            };
+            let report = context
+                .extract_file("CameraRollDomain", out_dir.path(), &options, |_| {})
+                .unwrap();
+            assert_eq!(report.files_extracted, 3);
+
+            let folder = out_dir.path().join(folder);
+            assert_eq!(fs::read(folder.join("a.jpg")).unwrap(), b"a");
+            let renamed = format!("a-{}.jpg", &second_id[0..8]);
+            assert_eq!(fs::read(folder.join(renamed)).unwrap(), b"b");
+            assert_eq!(fs::read(folder.join("b")).unwrap(), b"c");
+            assert!(!out_dir.path().join("Media").exists());
+        }
+    }
+
+    #[test]
+    fn it_filters_by_protection_class() {
+        let fixture = FixtureBackup::new();
+        // The fixture files are in protection class 3.
+        fixture.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+        fixture.add_file("HomeDomain", "Media/a.jpg", b"jpg");
+
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+        for (protection_classes, matched) in [
+            (vec![ProtectionClass(Some(3))], 2),
+            (vec![ProtectionClass(Some(1))], 0),
+            (vec![ProtectionClass(None)], 0),
+            (vec![ProtectionClass(Some(4)), ProtectionClass(Some(3))], 2),
+        ] {
+            let out_dir = tempfile::tempdir().unwrap();
+            let options = ExtractOptions {
+                protection_classes,
+                ..Default::default()
+            };
+            let report = context
+                .extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+                .unwrap();
+            assert_eq!(report.filtered, Some((matched, 2)));
+            assert_eq!(report.files_extracted, matched);
+            assert_eq!(
+                out_dir.path().join("Library/SMS/sms.db").exists(),
+                matched > 0
+            );
+        }
+    }
+
+    #[test]
+    fn it_extracts_selected_paths() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+        fixture.add_file("HomeDomain", "Library/SMS/Attachments/a.jpg", b"jpg!");
+        fixture.add_file("HomeDomain", "Library/SMSBackup/b.db", b"b");
+        fixture.add_file("HomeDomain", "Library/Notes/notes.db", b"notes");
+
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+        let extract = |path: &str| {
+            let out_dir = tempfile::tempdir().unwrap();
+            let options = ExtractOptions {
+                paths: vec![PathBuf::from(path)],
+                ..Default::default()
+            };
+            let mut total = 0;
+            context
+                .extract_file("HomeDomain", out_dir.path(), &options, |event| {
+                    if let ProgressEvent::Extracting { total: t, .. } = event {
+                        total = t;
+                    }
+                })
+                .map(|_| (total, out_dir))
+        };
+
+        let (total, out_dir) = extract("Library/SMS/sms.db").unwrap();
+        assert_eq!(total, 1);
+        assert!(out_dir.path().join("Library/SMS/sms.db").exists());
+        assert!(!out_dir.path().join("Library/SMS/Attachments").exists());
+
+        let (total, out_dir) = extract("Library/SMS/").unwrap();
+        assert_eq!(total, 2);
+        assert!(out_dir
+            .path()
+            .join("Library/SMS/Attachments/a.jpg")
+            .exists());
+        assert!(!out_dir.path().join("Library/SMSBackup").exists());
+        assert!(!out_dir.path().join("Library/Notes").exists());
+
+        assert!(extract("Library/Missing").is_err());
+    }
+
+    #[test]
+    fn it_filters_by_globs() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+        fixture.add_file("HomeDomain", "Library/SMS/sms.db-wal", b"wal");
+        fixture.add_file("HomeDomain", "Library/Notes/notes.db", b"notes");
+        fixture.add_file("HomeDomain", "Media/a.jpg", b"jpg");
+
+        let glob_set = |pattern| {
+            let mut builder = GlobSetBuilder::new();
+            builder.add(Glob::new(pattern).unwrap());
+            Some(builder.build().unwrap())
+        };
+        let out_dir = tempfile::tempdir().unwrap();
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+        let options = ExtractOptions {
+            include: glob_set("Library/**"),
+            exclude: glob_set("**/*-wal"),
+            ..Default::default()
+        };
+        let report = context
+            .extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+            .unwrap();
+        assert_eq!(report.filtered, Some((2, 4)));
+        assert!(out_dir.path().join("Library/SMS/sms.db").exists());
+        assert!(out_dir.path().join("Library/Notes/notes.db").exists());
+        assert!(!out_dir.path().join("Library/SMS/sms.db-wal").exists());
+        assert!(!out_dir.path().join("Media").exists());
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let report = context
+            .extract_file(
+                "HomeDomain",
+                out_dir.path(),
+                &ExtractOptions::default(),
+                |_| {},
+            )
+            .unwrap();
+        assert_eq!(report.filtered, None);
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let options = ExtractOptions {
+            regex: Some(Regex::new(r"^Library/.*\.db(-wal)?$").unwrap()),
+            ..Default::default()
+        };
+        let report = context
+            .extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+            .unwrap();
+        assert_eq!(report.filtered, Some((3, 4)));
+        assert!(out_dir.path().join("Library/SMS/sms.db-wal").exists());
+        assert!(!out_dir.path().join("Media").exists());
+    }
+
+    #[test]
+    fn it_filters_by_modification_time() {
+        let fixture = FixtureBackup::new();
+        // The fixture files are modified at 1_650_000_000.
+        fixture.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+        for (modified_after, modified_before, matched) in [
+            (Some(1_650_000_000), None, 1),
+            (Some(1_650_000_001), None, 0),
+            (None, Some(1_650_000_000), 0),
+            (Some(1_600_000_000), Some(1_700_000_000), 1),
+        ] {
+            let out_dir = tempfile::tempdir().unwrap();
+            let options = ExtractOptions {
+                modified_after,
+                modified_before,
+                ..Default::default()
+            };
+            let report = context
+                .extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+                .unwrap();
+            assert_eq!(report.filtered, Some((matched, 1)));
+            assert_eq!(report.files_extracted, matched);
+        }
+    }
+
+    #[test]
+    fn it_extracts_prioritized_files_first() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/Notes/notes.db", b"notes");
+        fixture.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+        fixture.add_file("HomeDomain", "Media/a.jpg", b"jpg");
+
+        for no_index in [false, true] {
+            let out_dir = tempfile::tempdir().unwrap();
+            let mut manifest = fixture.manifest();
+            let context = fixture.context(&mut manifest);
+            let mut priority = GlobSetBuilder::new();
+            priority.add(Glob::new("Library/SMS/**").unwrap());
+            let options = ExtractOptions {
+                priority: Some(priority.build().unwrap()),
+                no_index,
+                ..Default::default()
+            };
+
+            let sms_path = out_dir.path().join("Library/SMS/sms.db");
+            let mut first_extracted = None;
+            context
+                .extract_file("HomeDomain", out_dir.path(), &options, |event| {
+                    if let ProgressEvent::Extracting { extracted: 1, .. } = event {
+                        first_extracted = Some(sms_path.exists());
+                    }
+                })
+                .unwrap();
+            assert_eq!(first_extracted, Some(true));
+        }
+    }
+
+    #[test]
+    fn it_leaves_kept_files_out_of_the_room_check() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+        fixture.add_file("HomeDomain", "Library/Notes/notes.db", b"notes");
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let mut manifest = fixture.manifest();
+        let files = manifest.query_files("HomeDomain").unwrap();
+        let context = fixture.context(&mut manifest);
+        let mut options = ExtractOptions::default();
+        context
+            .extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+            .unwrap();
+        fs::remove_file(out_dir.path().join("Library/Notes/notes.db")).unwrap();
+
+        let paths: Vec<_> = files
+            .iter()
+            .map(|file| (Path::new(&file.relative_path), file))
+            .collect();
+        // Only the removed file is written again when skipping.
+        for (on_conflict, required) in [(ConflictPolicy::Error, 8), (ConflictPolicy::Skip, 5)] {
+            options.on_conflict = on_conflict;
+            assert_eq!(
+                context.required_room(out_dir.path(), paths.iter().copied(), &options),
+                (required, 0)
+            );
+        }
+    }
+
+    #[test]
+    fn it_handles_conflicts() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+        fixture.add_file("HomeDomain", "Library/Notes/notes.db", b"notes");
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+        let extract = |on_conflict| {
+            let options = ExtractOptions {
+                on_conflict,
+                ..Default::default()
+            };
+            context.extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+        };
+        extract(ConflictPolicy::Overwrite).unwrap();
+
+        // A complete copy is kept, an interrupted one is written again.
+        let sms_path = out_dir.path().join("Library/SMS/sms.db");
+        let notes_path = out_dir.path().join("Library/Notes/notes.db");
+        fs::write(&sms_path, b"SMS").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&sms_path)
+            .unwrap()
+            .set_modified(system_time(1_650_000_000))
+            .unwrap();
+        fs::write(&notes_path, b"no").unwrap();
+        let report = extract(ConflictPolicy::Skip).unwrap();
+        assert_eq!(report.files_extracted, 2);
+        assert_eq!(report.kept, ["Library/SMS/sms.db"]);
+        assert_eq!(fs::read(&sms_path).unwrap(), b"SMS");
+        assert_eq!(fs::read(&notes_path).unwrap(), b"notes");
+
+        assert!(extract(ConflictPolicy::Error).is_err());
+        extract(ConflictPolicy::Overwrite).unwrap();
+        assert_eq!(fs::read(&sms_path).unwrap(), b"sms");
+    }
+
+    #[test]
+    fn it_keeps_going_past_failed_files() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+        let missing_id = fixture.add_file("HomeDomain", "Library/Notes/notes.db", b"notes");
+        fixture.add_file("HomeDomain", "Media/a.jpg", b"jpg");
+        fs::remove_file(fixture.path().join(&missing_id[..2]).join(&missing_id)).unwrap();
+
+        for (no_index, jobs) in [(false, 1), (false, 4), (true, 1)] {
+            let out_dir = tempfile::tempdir().unwrap();
+            let mut manifest = fixture.manifest();
+            let context = fixture.context(&mut manifest);
+            let options = ExtractOptions {
+                no_index,
+                jobs: NonZeroUsize::new(jobs).unwrap(),
+                keep_going: true,
+                ..Default::default()
+            };
+            let mut last_progress = None;
+            let report = context
+                .extract_file("HomeDomain", out_dir.path(), &options, |event| {
+                    if let ProgressEvent::Extracting {
+                        extracted, total, ..
+                    } = event
+                    {
+                        last_progress = Some((extracted, total));
+                    }
+                })
+                .unwrap();
+            // The failed file moves the progress along too.
+            assert_eq!(last_progress, Some((3, 3)));
+            assert_eq!(report.files_extracted, 2);
+            assert_eq!(report.files_unwritten(), 1);
+            assert_eq!(report.failed.len(), 1);
+            assert_eq!(report.failed[0].0, "Library/Notes/notes.db");
+            assert!(out_dir.path().join("Media/a.jpg").exists());
+
+            let options = ExtractOptions {
+                keep_going: false,
+                ..options
+            };
+            let out_dir = tempfile::tempdir().unwrap();
+            assert!(context
+                .extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn it_reports_extraction() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+        fixture.add_file("HomeDomain", "Library/Notes/notes.db", b"notes");
+        fixture.add_malformed_file("HomeDomain", "Library/broken");
+
+        for no_index in [false, true] {
+            let out_dir = tempfile::tempdir().unwrap();
+            let mut manifest = fixture.manifest();
+            let context = fixture.context(&mut manifest);
+            let options = ExtractOptions {
+                no_index,
+                ..Default::default()
+            };
+            let report = context
+                .extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+                .unwrap();
+            assert_eq!(report.files_extracted, 2);
+            assert_eq!(report.files_total, 2);
+            assert_eq!(report.bytes_written, 8);
+            assert_eq!(report.files_unwritten(), 0);
+            assert_eq!(
+                report.skipped,
+                BTreeMap::from([("malformed file ID", vec!["Library/broken".to_owned()])])
+            );
+        }
+    }
+
+    #[test]
+    fn it_looks_up_file_id_prefixes() {
+        let fixture = FixtureBackup::new();
+        let file_ids: Vec<_> = (0..16)
+            .map(|idx| fixture.add_file("HomeDomain", &format!("Library/{idx}.txt"), b"text"))
+            .collect();
+
+        let mut manifest = fixture.manifest();
+        let context = Context::new(
+            fixture.path(),
+            &mut manifest,
+            false,
+            BucketStrategy::default(),
+        );
+        let (domain, file) = context.query_file_by_id(&file_ids[0]).unwrap().unwrap();
+        assert_eq!(domain, "HomeDomain");
+        assert_eq!(file.relative_path, "Library/0.txt");
+
+        // Only the last ID ends with `10`.
+        let (_, file) = context
+            .query_file_by_id(&file_ids[15][..39])
+            .unwrap()
+            .unwrap();
+        assert_eq!(file.file_id, file_ids[15]);
+        assert!(context.query_file_by_id(&file_ids[0][..39]).is_err());
+        assert!(context.query_file_by_id("ff").unwrap().is_none());
+        assert!(context.query_file_by_id("not hex").is_err());
+    }
+
+    #[test]
+    fn it_rejects_malformed_ids_if_strict() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+        fixture.add_malformed_file("HomeDomain", "Library/broken");
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+        let options = ExtractOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let err = context
+            .extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+            .unwrap_err();
+        assert!(err.to_string().contains("Library/broken"));
+        assert!(!out_dir.path().join("Library/SMS/sms.db").exists());
+    }
+
+    #[test]
+    fn it_reports_progress_in_bytes() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+        fixture.add_file("HomeDomain", "Media/a.jpg", b"jpeg data");
+
+        for (copy, no_index) in [(true, false), (false, false), (false, true)] {
+            let out_dir = tempfile::tempdir().unwrap();
+            let mut manifest = fixture.manifest();
+            let context = Context::new(
+                fixture.path(),
+                &mut manifest,
+                copy,
+                BucketStrategy::default(),
+            );
+            let options = ExtractOptions {
+                no_index,
+                ..Default::default()
+            };
+            let mut last_bytes = (0, 0);
+            context
+                .extract_file("HomeDomain", out_dir.path(), &options, |event| {
+                    if let ProgressEvent::Extracting {
+                        bytes_done,
+                        bytes_total,
+                        ..
+                    } = event
+                    {
+                        assert!(bytes_done >= last_bytes.0);
+                        last_bytes = (bytes_done, bytes_total);
+                    }
+                })
+                .unwrap();
+            // Links count by the recorded size.
+            assert_eq!(last_bytes, (12, 12));
+        }
+    }
+
+    #[test]
+    fn it_extracts_on_several_threads() {
+        let fixture = FixtureBackup::new();
+        for idx in 0..20 {
+            let contents = format!("file {idx}");
+            fixture.add_file(
+                "HomeDomain",
+                &format!("Library/{}/{idx}.txt", idx % 3),
+                contents.as_bytes(),
+            );
+        }
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+        let options = ExtractOptions {
+            jobs: NonZeroUsize::new(4).unwrap(),
+            ..Default::default()
+        };
+        let mut last_extracted = 0;
+        let report = context
+            .extract_file("HomeDomain", out_dir.path(), &options, |event| {
+                if let ProgressEvent::Extracting { extracted, .. } = event {
+                    assert!(extracted >= last_extracted);
+                    last_extracted = extracted;
+                }
+            })
+            .unwrap();
+        assert_eq!(last_extracted, 20);
+        assert_eq!(report.files_extracted, 20);
+        for idx in 0..20 {
+            let path = out_dir
+                .path()
+                .join(format!("Library/{}/{idx}.txt", idx % 3));
+            assert_eq!(fs::read_to_string(path).unwrap(), format!("file {idx}"));
+        }
+    }
+
+    #[test]
+    fn it_restores_timestamps() {
+        let fixture = FixtureBackup::new();
+        fixture.add_dir("HomeDomain", "Library/SMS");
+        fixture.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+
+        for no_index in [false, true] {
+            let out_dir = tempfile::tempdir().unwrap();
+            let mut manifest = fixture.manifest();
+            let context = fixture.context(&mut manifest);
+            let options = ExtractOptions {
+                no_index,
+                ..Default::default()
+            };
+            context
+                .extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+                .unwrap();
+
+            // Both carry the `LastModified` of the fixture.
+            for path in ["Library/SMS", "Library/SMS/sms.db"] {
+                let modified = fs::metadata(out_dir.path().join(path))
+                    .unwrap()
+                    .modified()
+                    .unwrap();
+                assert_eq!(unix_timestamp(modified), 1_650_000_000, "{path}");
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_restores_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let fixture = FixtureBackup::new();
+        let file_id = fixture.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+        let blob_path = fixture.path().join(&file_id[0..2]).join(&file_id);
+        fs::set_permissions(&blob_path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        for preserve_permissions in [false, true] {
+            let out_dir = tempfile::tempdir().unwrap();
+            let mut manifest = fixture.manifest();
+            let context = fixture.context(&mut manifest);
+            let options = ExtractOptions {
+                preserve_permissions,
+                ..Default::default()
+            };
+            context
+                .extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+                .unwrap();
+
+            let metadata = fs::metadata(out_dir.path().join("Library/SMS/sms.db")).unwrap();
+            let expected_mode = if preserve_permissions { 0o644 } else { 0o600 };
+            assert_eq!(metadata.permissions().mode() & 0o7777, expected_mode);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_extracts_symlinks() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/a.db", b"a");
+        fixture.add_symlink("HomeDomain", "Library/Links/a", "../a.db");
+        fixture.add_symlink("HomeDomain", "Library/Links/dangling", "/nowhere");
+        fixture.add_symlink("HomeDomain", "Library/Links/loop", "loop");
+        fixture.add_symlink("HomeDomain", "Library/a.db", "elsewhere");
+
+        for no_index in [false, true] {
+            let out_dir = tempfile::tempdir().unwrap();
+            let mut manifest = fixture.manifest();
+            let context = fixture.context(&mut manifest);
+            let options = ExtractOptions {
+                no_index,
+                ..Default::default()
+            };
+            let report = context
+                .extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+                .unwrap();
+            assert_eq!(report.files_extracted, 1);
+            assert_eq!(
+                report.skipped,
+                BTreeMap::from([(
+                    "path taken by another entry",
+                    vec!["Library/a.db".to_owned()]
+                )])
+            );
+
+            let links_dir = out_dir.path().join("Library/Links");
+            assert_eq!(fs::read(links_dir.join("a")).unwrap(), b"a");
+            for (name, target) in [("dangling", "/nowhere"), ("loop", "loop")] {
+                assert_eq!(
+                    fs::read_link(links_dir.join(name)).unwrap(),
+                    Path::new(target)
+                );
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_extracts_hard_links() {
+        use std::os::unix::fs::MetadataExt;
+
+        let fixture = FixtureBackup::new();
+        let file_id = fixture.add_file("HomeDomain", "Library/a.db", b"a");
+        fixture.add_symlink("HomeDomain", "Library/b.db", "a.db");
+
+        let out_dir = tempfile::tempdir().unwrap();
+        assert!(can_hard_link(fixture.path(), &out_dir.path().join("new")));
+        let mut manifest = fixture.manifest();
+        let context = Context::new(
+            fixture.path(),
+            &mut manifest,
+            false,
+            BucketStrategy::default(),
+        );
+        let blob_path = context.original_file_path(&file_id);
+        let blob_modified = fs::metadata(&blob_path).unwrap().modified().unwrap();
+        let extract = |on_conflict| {
+            let options = ExtractOptions {
+                hard_links: true,
+                on_conflict,
+                ..Default::default()
+            };
+            context
+                .extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+                .unwrap()
+        };
+        let report = extract(ConflictPolicy::Error);
+        assert_eq!(report.files_extracted, 1);
+
+        let file_path = out_dir.path().join("Library/a.db");
+        let metadata = fs::symlink_metadata(&file_path).unwrap();
+        let blob_metadata = fs::metadata(&blob_path).unwrap();
+        assert!(metadata.is_file());
+        assert_eq!(metadata.ino(), blob_metadata.ino());
+        assert_eq!(blob_metadata.modified().unwrap(), blob_modified);
+        assert_eq!(
+            fs::read_link(out_dir.path().join("Library/b.db")).unwrap(),
+            Path::new("a.db")
+        );
+
+        let report = extract(ConflictPolicy::Skip);
+        assert_eq!(report.kept, ["Library/a.db"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_dedupes_identical_files() {
+        use std::os::unix::fs::MetadataExt;
+
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/a.db", b"same");
+        fixture.add_file("HomeDomain", "Library/b.db", b"same");
+        fixture.add_file("HomeDomain", "Library/c.db", b"diff");
+        fixture.add_file("HomeDomain", "Library/d.db", b"other");
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+        let options = ExtractOptions {
+            dedupe: true,
+            ..Default::default()
+        };
+        let report = context
+            .extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+            .unwrap();
+        assert_eq!(report.files_extracted, 4);
+        assert_eq!(report.deduplicated, 1);
+        assert_eq!(report.bytes_saved, 4);
+        assert_eq!(report.bytes_written, 13);
+
+        let library_dir = out_dir.path().join("Library");
+        let inode = |name| fs::metadata(library_dir.join(name)).unwrap().ino();
+        assert_eq!(inode("a.db"), inode("b.db"));
+        assert_ne!(inode("a.db"), inode("c.db"));
+        assert_eq!(fs::read(library_dir.join("b.db")).unwrap(), b"same");
+        assert_eq!(fs::read(library_dir.join("c.db")).unwrap(), b"diff");
+    }
+
+    #[test]
+    fn it_decrypts_encrypted_backups() {
+        let fixture = FixtureBackup::new();
+        fixture.add_encrypted_file("HomeDomain", "Library/SMS/sms.db", b"encrypted sms");
+
+        for copy_mode in [false, true] {
+            let out_dir = tempfile::tempdir().unwrap();
+            let mut manifest = fixture.manifest();
+            let context = Context::new(
+                fixture.path(),
+                &mut manifest,
+                copy_mode,
+                BucketStrategy::default(),
+            )
+            .with_backup_keys(Some(test_support::backup_keys()));
+            let result = context.extract_file(
+                "HomeDomain",
+                out_dir.path(),
+                &ExtractOptions::default(),
+                |_| {},
+            );
+            if !copy_mode {
+                assert!(result.is_err());
+                continue;
+            }
+            assert_eq!(result.unwrap().bytes_written, 13);
+            assert_eq!(
+                fs::read(out_dir.path().join("Library/SMS/sms.db")).unwrap(),
+                b"encrypted sms"
+            );
+        }
+    }
+
+    #[test]
+    fn it_rejects_oversized_files_up_front() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/a:b.db", b"ab");
+        fixture.add_file("HomeDomain", "Library/large.db", b"large");
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+        let mut options = ExtractOptions {
+            portable_names: true,
+            max_file_size: Some(4),
+            ..Default::default()
+        };
+        let err = context
+            .extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+            .unwrap_err();
+        assert!(err.to_string().contains("Library/large.db"));
+        assert!(!out_dir.path().join("Library").exists());
+
+        options.max_file_size = None;
+        context
+            .extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+            .unwrap();
+        assert!(out_dir.path().join("Library/a_b.db").exists());
+    }
+
+    #[test]
+    fn it_rejects_unwritable_destinations_up_front() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/a.db", b"a");
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let blocker = out_dir.path().join("file");
+        fs::write(&blocker, b"").unwrap();
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+        let mut indexed = false;
+        let err = context
+            .extract_file(
+                "HomeDomain",
+                &blocker.join("dest"),
+                &ExtractOptions::default(),
+                |event| indexed |= matches!(event, ProgressEvent::Indexing { .. }),
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("failed to create directory"));
+        assert!(!indexed);
+    }
+
+    #[test]
+    fn it_writes_placeholders_of_missing_blobs() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/a.db", b"a");
+        let missing_id = fixture.add_file("HomeDomain", "Library/b.db", b"b");
+        fs::remove_file(fixture.path().join(&missing_id[0..2]).join(&missing_id)).unwrap();
+
+        for no_index in [false, true] {
+            let out_dir = tempfile::tempdir().unwrap();
+            let mut manifest = fixture.manifest();
+            let context = fixture.context(&mut manifest);
+            let options = ExtractOptions {
+                placeholders: true,
+                no_index,
+                ..Default::default()
+            };
+            let report = context
+                .extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+                .unwrap();
+            assert_eq!(report.files_extracted, 2);
+            assert_eq!(report.placeholders, ["Library/b.db"]);
+            assert_eq!(
+                fs::read(out_dir.path().join("Library/b.db.MISSING")).unwrap(),
+                b""
+            );
+            assert!(!out_dir.path().join("Library/b.db").exists());
+        }
+    }
+
+    #[test]
+    fn it_plans_dry_runs() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "a.db", b"a");
+        fixture.add_file("HomeDomain", "Library/SMS/a.db", b"sms");
+        fixture.add_file("HomeDomain", "Library/Notes/notes.db", b"notes");
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let dest_dir = out_dir.path().join("dest");
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+        let options = ExtractOptions {
+            strip_prefix: Some(PathBuf::from("Library/SMS")),
+            prefix_mismatch: PrefixMismatchPolicy::Keep,
+            dry_run: true,
+            ..Default::default()
+        };
+        let mut planned = vec![];
+        let report = context
+            .extract_file("HomeDomain", &dest_dir, &options, |event| {
+                if let ProgressEvent::Planned { path, size } = event {
+                    planned.push((path, size));
+                }
+            })
+            .unwrap();
+        planned.sort();
+        assert_eq!(
+            planned,
+            [
+                ("Library/Notes/notes.db".to_owned(), 5),
+                ("a.db".to_owned(), 3)
+            ]
+        );
+        assert_eq!(
+            report.skipped["destination path taken by another file"],
+            ["a.db"]
+        );
+        assert!(!dest_dir.exists());
+    }
+
+    #[test]
+    fn it_extracts_same_files_without_index() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+        fixture.add_file("HomeDomain", "Library/SMS/Attachments/a.jpg", b"jpg");
+        fixture.add_file("HomeDomain", "Library/Notes/notes.db", b"notes");
+        fixture.add_file("HomeDomain", "Library/Notes/notes.db", b"notes 2");
+
+        let extract = |no_index| {
+            let out_dir = tempfile::tempdir().unwrap();
+            let mut manifest = fixture.manifest();
+            let context = fixture.context(&mut manifest);
+            let options = ExtractOptions {
+                strip_prefix: Some(PathBuf::from("Library/SMS")),
+                prefix_mismatch: PrefixMismatchPolicy::Keep,
+                no_index,
+                ..Default::default()
+            };
+            context
+                .extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+                .unwrap();
+            out_dir
+        };
+
+        let indexed_dir = extract(false);
+        let streamed_dir = extract(true);
+        for path in ["sms.db", "Attachments/a.jpg", "Library/Notes/notes.db"] {
+            assert_eq!(
+                fs::read(indexed_dir.path().join(path)).unwrap(),
+                fs::read(streamed_dir.path().join(path)).unwrap(),
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn it_reports_streamed_files_once() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+        fixture.add_file("HomeDomain", "Library/./Notes/notes.db", b"notes");
+        let missing_id = fixture.add_file("HomeDomain", "Library/a.db", b"a");
+        fs::remove_file(fixture.path().join(&missing_id[0..2]).join(&missing_id)).unwrap();
+        fixture.add_malformed_file("HomeDomain", "Library/broken");
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+        let mut priority = GlobSetBuilder::new();
+        priority.add(Glob::new("Library/SMS/**").unwrap());
+        let options = ExtractOptions {
+            priority: Some(priority.build().unwrap()),
+            placeholders: true,
+            no_index: true,
+            ..Default::default()
+        };
+        let report = context
+            .extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+            .unwrap();
+        assert_eq!(report.files_extracted, 3);
+        assert_eq!(report.files_total, 3);
+        assert_eq!(report.placeholders, ["Library/a.db"]);
+        assert_eq!(report.normalized.len(), 1);
+        assert_eq!(report.files_skipped(), 1);
+        assert!(out_dir.path().join("Library/Notes/notes.db").exists());
+    }
+
+    #[test]
+    fn it_measures_extraction() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+        fixture.add_file("HomeDomain", "Library/SMS/Attachments/a.jpg", b"jpg!");
+        fixture.add_file("HomeDomain", "Library/Notes/notes.db", b"notes");
+        fixture.add_malformed_file("HomeDomain", "Library/broken");
+
+        let mut manifest = fixture.manifest();
+        let context = Context::new(
+            fixture.path(),
+            &mut manifest,
+            false,
+            BucketStrategy::default(),
+        );
+        let mut options = ExtractOptions::default();
+        // The same files as the extraction writes.
+        let size = context.measure_extraction("HomeDomain", &options).unwrap();
+        assert_eq!((size.files, size.bytes), (3, 12));
+        assert!(context.measure_extraction("NoDomain", &options).is_err());
+
+        options.strip_prefix = Some(PathBuf::from("Library/SMS"));
+        assert!(context.measure_extraction("HomeDomain", &options).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_stays_inside_the_destination_through_symlinks() {
+        let outside_dir = tempfile::tempdir().unwrap();
+        let outside_path = outside_dir.path().to_str().unwrap();
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/a.db", b"a");
+        fixture.add_dir("HomeDomain", "Library/Out");
+        fixture.add_symlink("HomeDomain", "Library/Out", outside_path);
+        fixture.add_symlink("HomeDomain", "Library/Out/x", "y");
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+        let options = ExtractOptions {
+            preserve_timestamps: true,
+            ..Default::default()
+        };
+        let outside_modified = fs::metadata(outside_dir.path())
+            .unwrap()
+            .modified()
+            .unwrap();
+        context
+            .extract_file("HomeDomain", out_dir.path(), &options, |_| {})
+            .unwrap();
+
+        assert_eq!(
+            fs::read_link(out_dir.path().join("Library/Out")).unwrap(),
+            outside_dir.path()
+        );
+        assert_eq!(fs::read_dir(outside_dir.path()).unwrap().count(), 0);
+        assert_eq!(
+            fs::metadata(outside_dir.path())
+                .unwrap()
+                .modified()
+                .unwrap(),
+            outside_modified
+        );
+    }
 }
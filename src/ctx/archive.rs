@@ -0,0 +1,339 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as AnyhowContext, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use super::{
+    has_valid_file_id, Context, ExtractOptions, ExtractionReport, ProgressEvent, ReportRecorder,
+};
+use crate::db::{ManifestFile, ManifestFileType};
+
+/// Whether an archive should be compressed with gzip, given its name.
+fn is_gzip(archive_path: &Path) -> Option<bool> {
+    let file_name = archive_path.file_name()?.to_str()?.to_ascii_lowercase();
+    if file_name.ends_with(".tar") {
+        Some(false)
+    } else if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+impl<'p, 'd> Context<'p, 'd> {
+    /// Extracts the files and symbolic links of a domain into a tar
+    /// archive instead of a directory, compressed with gzip if its name
+    /// ends with `.tar.gz`.
+    ///
+    /// The entries are named after the paths the files would be extracted
+    /// to, with the modes and modification times recorded in the manifest.
+    /// The blobs are streamed into the archive, nothing is written besides.
+    pub fn extract_to_archive<F>(
+        &self,
+        domain: &str,
+        archive_path: &Path,
+        options: &ExtractOptions,
+        progress_cb: F,
+    ) -> Result<ExtractionReport>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let mut progress_cb = progress_cb;
+        let mut recorder = ReportRecorder::default();
+        self.archive_domain(domain, archive_path, options, &mut |event| {
+            recorder.record(&event);
+            progress_cb(event);
+        })?;
+        Ok(recorder.report())
+    }
+
+    fn archive_domain<F>(
+        &self,
+        domain: &str,
+        archive_path: &Path,
+        options: &ExtractOptions,
+        progress_cb: &mut F,
+    ) -> Result<()>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        self.reject_encrypted("extracting into an archive")?;
+        let Some(gzip) = is_gzip(archive_path) else {
+            return Err(anyhow!(
+                "unsupported archive format, expected `.tar` or `.tar.gz`: {}",
+                archive_path.to_string_lossy()
+            ));
+        };
+        if archive_path.exists() {
+            return Err(anyhow!(
+                "file already exists: {}",
+                archive_path.to_string_lossy()
+            ));
+        }
+
+        progress_cb(ProgressEvent::Querying);
+        let files = self
+            .query_files(domain)
+            .context("failed to query files from database")?;
+        if files.is_empty() {
+            return Err(anyhow!("domain not found: {domain}"));
+        }
+
+        // Directories are left out, the archive creates them as needed.
+        let mut entries = vec![];
+        for file in &files {
+            if file.file_type == ManifestFileType::Directory || !options.includes(file) {
+                continue;
+            }
+            if file.file_type == ManifestFileType::File && !has_valid_file_id(file) {
+                if options.strict {
+                    return Err(anyhow!(
+                        "file has a malformed ID `{}`: {}",
+                        file.file_id,
+                        file.relative_path
+                    ));
+                }
+                warn!("skipped file with malformed ID: {}", file.relative_path);
+                progress_cb(ProgressEvent::Skipped {
+                    relative_path: file.relative_path.clone(),
+                    reason: "malformed file ID",
+                });
+                continue;
+            }
+            entries.push((options.dest_path(file)?, file));
+        }
+
+        // Written next to the archive and only moved in place once
+        // complete, so that a failure leaves no partial archive behind.
+        let dir = archive_path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let mut builder = tempfile::Builder::new();
+        builder.prefix(".ibackupextractor-");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            // Like `File::create`, subject to the umask.
+            builder.permissions(fs::Permissions::from_mode(0o666));
+        }
+        let tmp_file = builder.tempfile_in(dir).with_context(|| {
+            format!(
+                "failed to create archive: {}",
+                archive_path.to_string_lossy()
+            )
+        })?;
+        let writer = BufWriter::new(tmp_file);
+        let res = if gzip {
+            let encoder = GzEncoder::new(writer, Compression::default());
+            self.write_tar(encoder, &entries, options, progress_cb)
+                .and_then(|encoder| Ok(encoder.finish()?.into_inner()?))
+        } else {
+            self.write_tar(writer, &entries, options, progress_cb)
+                .and_then(|writer| Ok(writer.into_inner()?))
+        };
+        let tmp_file = res.context("failed to write archive")?;
+        tmp_file.persist_noclobber(archive_path).with_context(|| {
+            format!(
+                "failed to create archive: {}",
+                archive_path.to_string_lossy()
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Appends the entries to a tar stream, returns the underlying writer.
+    fn write_tar<W, F>(
+        &self,
+        writer: W,
+        entries: &[(PathBuf, &ManifestFile)],
+        options: &ExtractOptions,
+        progress_cb: &mut F,
+    ) -> Result<W>
+    where
+        W: Write,
+        F: FnMut(ProgressEvent),
+    {
+        let mut builder = tar::Builder::new(writer);
+        let bytes_total = entries
+            .iter()
+            .map(|(_, file)| file.size.unwrap_or_default())
+            .sum();
+        let total = entries
+            .iter()
+            .filter(|(_, file)| file.file_type == ManifestFileType::File)
+            .count();
+        let (mut bytes_written, mut extracted) = (0, 0);
+        for (path, file) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(file.mode.map_or(0o644, |mode| mode & 0o7777));
+            header.set_mtime(file.last_modified.unwrap_or_default().max(0) as u64);
+
+            if file.file_type == ManifestFileType::SymbolicLink {
+                let Some(target) = &file.target else {
+                    progress_cb(ProgressEvent::Skipped {
+                        relative_path: file.relative_path.clone(),
+                        reason: "symbolic link without target",
+                    });
+                    continue;
+                };
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_size(0);
+                builder
+                    .append_link(&mut header, path, target)
+                    .with_context(|| format!("failed to archive `{}`", file.relative_path))?;
+                continue;
+            }
+
+            let blob_path = self.original_file_path(&file.file_id);
+            let blob = File::open(&blob_path).and_then(|blob| {
+                let size = blob.metadata()?.len();
+                Ok((blob, size))
+            });
+            let (blob, size) = match blob {
+                Ok(blob) => blob,
+                Err(err) if options.keep_going => {
+                    progress_cb(ProgressEvent::Failed {
+                        relative_path: file.relative_path.clone(),
+                        error: format!("failed to open: {}: {err}", blob_path.to_string_lossy()),
+                    });
+                    continue;
+                }
+                Err(err) => {
+                    return Err(anyhow::Error::new(err)
+                        .context(format!("failed to open: {}", blob_path.to_string_lossy())))
+                }
+            };
+            // The size in the header must match the bytes that follow,
+            // whatever the manifest records.
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_size(size);
+            builder
+                .append_data(&mut header, path, blob.take(size))
+                .with_context(|| format!("failed to archive `{}`", file.relative_path))?;
+
+            bytes_written += size;
+            extracted += 1;
+            progress_cb(ProgressEvent::Written {
+                relative_path: file.relative_path.clone(),
+                file_id: file.file_id.clone(),
+                path: path.clone(),
+                size: file.size.unwrap_or_default(),
+            });
+            progress_cb(ProgressEvent::Extracting {
+                extracted,
+                total,
+                bytes_written,
+                bytes_done: bytes_written,
+                bytes_total,
+            });
+        }
+        Ok(builder.into_inner()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use crate::bucket::BucketStrategy;
+    use crate::ctx::{Context, ExtractOptions};
+    use crate::utils::test_fixture::FixtureBackup;
+
+    #[test]
+    fn it_extracts_into_archive() {
+        let fixture = FixtureBackup::new();
+        fixture.add_dir("HomeDomain", "Library");
+        fixture.add_file("HomeDomain", "Library/a.txt", b"abc");
+        fixture.add_symlink("HomeDomain", "Library/link", "a.txt");
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let mut manifest = fixture.manifest();
+        let context = Context::new(
+            fixture.path(),
+            &mut manifest,
+            false,
+            BucketStrategy::default(),
+        );
+        let options = ExtractOptions::default();
+
+        for file_name in ["domain.tar", "domain.tar.gz"] {
+            let archive_path = out_dir.path().join(file_name);
+            let report = context
+                .extract_to_archive("HomeDomain", &archive_path, &options, |_| {})
+                .unwrap();
+            assert_eq!(report.files_extracted, 1);
+
+            let archive_file = File::open(&archive_path).unwrap();
+            let reader: Box<dyn Read> = if file_name.ends_with(".gz") {
+                Box::new(GzDecoder::new(archive_file))
+            } else {
+                Box::new(archive_file)
+            };
+            let mut archive = tar::Archive::new(reader);
+            let mut entries = vec![];
+            for entry in archive.entries().unwrap() {
+                let mut entry = entry.unwrap();
+                let path = entry.path().unwrap().to_string_lossy().into_owned();
+                let mode = entry.header().mode().unwrap();
+                let link_name = entry
+                    .link_name()
+                    .unwrap()
+                    .map(|name| name.to_string_lossy().into_owned());
+                let mut contents = String::new();
+                entry.read_to_string(&mut contents).unwrap();
+                entries.push((path, mode, link_name, contents));
+            }
+            entries.sort();
+            assert_eq!(
+                entries,
+                [
+                    ("Library/a.txt".to_owned(), 0o644, None, "abc".to_owned()),
+                    (
+                        "Library/link".to_owned(),
+                        0o755,
+                        Some("a.txt".to_owned()),
+                        String::new()
+                    ),
+                ]
+            );
+
+            assert!(context
+                .extract_to_archive("HomeDomain", &archive_path, &options, |_| {})
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn it_leaves_no_partial_archive() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/a.txt", b"abc");
+        let missing_id = fixture.add_file("HomeDomain", "Library/b.txt", b"b");
+        fs::remove_file(fixture.path().join(&missing_id[0..2]).join(&missing_id)).unwrap();
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let mut manifest = fixture.manifest();
+        let context = Context::new(
+            fixture.path(),
+            &mut manifest,
+            false,
+            BucketStrategy::default(),
+        );
+        let archive_path = out_dir.path().join("domain.tar");
+        assert!(context
+            .extract_to_archive(
+                "HomeDomain",
+                &archive_path,
+                &ExtractOptions::default(),
+                |_| {}
+            )
+            .is_err());
+        assert_eq!(fs::read_dir(out_dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::{Context as AnyhowContext, Result};
+
+use super::parallel::WriteProgress;
+use super::{Context, ExtractOptions, ProgressEvent};
+use crate::db::ManifestFile;
+use crate::store;
+
+/// Finds the files of an extraction whose blobs have the same content, so
+/// that the repeated ones can be hard links to the first one written.
+///
+/// The rows of a domain never share a file ID, so the contents are
+/// compared instead: only the blobs whose recorded size is shared with
+/// another file are hashed.
+pub(super) struct Deduper {
+    size_counts: HashMap<u64, usize>,
+    first_paths: HashMap<(u64, String), PathBuf>,
+}
+
+impl Deduper {
+    pub fn new<'f>(files: impl IntoIterator<Item = &'f ManifestFile>) -> Self {
+        let mut size_counts = HashMap::new();
+        for file in files {
+            if let Some(size) = file.size.filter(|size| *size > 0) {
+                *size_counts.entry(size).or_default() += 1;
+            }
+        }
+        Self {
+            size_counts,
+            first_paths: HashMap::new(),
+        }
+    }
+
+    /// Splits the files into the ones to write and the ones to link, with
+    /// the paths of the files they repeat.
+    #[allow(clippy::type_complexity)]
+    pub fn split<'f>(
+        &mut self,
+        context: &Context,
+        files: Vec<(PathBuf, &'f ManifestFile)>,
+    ) -> (
+        Vec<(PathBuf, &'f ManifestFile)>,
+        Vec<(PathBuf, &'f ManifestFile, PathBuf)>,
+    ) {
+        let (mut originals, mut duplicates) = (vec![], vec![]);
+        for (path, file) in files {
+            let shared_size = file
+                .size
+                .filter(|size| self.size_counts.get(size).is_some_and(|count| *count > 1));
+            let Some(size) = shared_size else {
+                originals.push((path, file));
+                continue;
+            };
+            // An unreadable blob is left for the writer to report.
+            let Ok(digest) = store::hash_file(&context.original_file_path(&file.file_id)) else {
+                originals.push((path, file));
+                continue;
+            };
+            match self.first_paths.get(&(size, digest.clone())) {
+                Some(first_path) => duplicates.push((path, file, first_path.clone())),
+                None => {
+                    self.first_paths.insert((size, digest), path.clone());
+                    originals.push((path, file));
+                }
+            }
+        }
+        (originals, duplicates)
+    }
+}
+
+impl<'p, 'd> Context<'p, 'd> {
+    /// Writes the files found by `Deduper` as hard links to the files they
+    /// repeat, or copies if the links fail.
+    pub(super) fn link_duplicates<F>(
+        &self,
+        duplicates: &[(PathBuf, &ManifestFile, PathBuf)],
+        options: &ExtractOptions,
+        progress: &mut WriteProgress,
+        progress_cb: &mut F,
+    ) -> Result<()>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let writer = self.file_writer(options);
+        for (path, file, first_path) in duplicates {
+            let res = writer
+                .write_duplicate(path, file, first_path)
+                .map(|(written, linked)| {
+                    if linked {
+                        progress_cb(ProgressEvent::Deduplicated {
+                            relative_path: file.relative_path.clone(),
+                            size: file.size.unwrap_or_default(),
+                        });
+                    }
+                    written
+                })
+                .with_context(|| format!("failed to create file: {}", path.to_string_lossy()));
+            progress.record_result(path, file, res, options.keep_going, progress_cb)?;
+        }
+        Ok(())
+    }
+}
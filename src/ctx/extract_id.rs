@@ -0,0 +1,188 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as AnyhowContext, Result};
+
+use super::{ConflictPolicy, Context, ExtractOptions};
+use crate::db::{ManifestFile, ManifestFileType};
+
+/// A blob written by `Context::extract_by_id`.
+#[derive(Debug)]
+pub struct ExtractedBlob {
+    pub path: PathBuf,
+    /// Domain and metadata of the file, `None` if the manifest lacks it.
+    pub entry: Option<(String, ManifestFile)>,
+}
+
+impl<'p, 'd> Context<'p, 'd> {
+    /// Writes the blob of a file ID to `out_path`, or into it with the
+    /// original file name if it's a directory.
+    ///
+    /// With `raw`, the blob is copied as it is, or linked to unless in copy
+    /// mode, even if the manifest lacks the file ID.
+    pub fn extract_by_id(
+        &self,
+        file_id: &str,
+        out_path: &Path,
+        raw: bool,
+    ) -> Result<ExtractedBlob> {
+        if file_id.len() != 40 || !file_id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(anyhow!(
+                "invalid file ID `{file_id}`, expected 40 hexadecimal characters (got {})",
+                file_id.chars().count()
+            ));
+        }
+        let file_id = file_id.to_ascii_lowercase();
+
+        let entry = self
+            .query_file_by_id(&file_id)
+            .context("failed to query file")?;
+        if entry.is_none() && !raw {
+            return Err(anyhow!(
+                "file ID `{file_id}` is not in the manifest, \
+                 pass `--raw` to copy its blob as it is"
+            ));
+        }
+        if let Some((_, file)) = entry.as_ref().filter(|_| !raw) {
+            if file.file_type != ManifestFileType::File {
+                return Err(anyhow!("not a regular file: {}", file.relative_path));
+            }
+            if self.backup_keys.is_some() && !self.copy_mode {
+                return Err(anyhow!(
+                    "encrypted backups can only be extracted in copy mode (`-c`)"
+                ));
+            }
+        }
+
+        let blob_path = self.original_file_path(&file_id);
+        if !blob_path.exists() {
+            return Err(anyhow!("blob is missing: {}", blob_path.to_string_lossy()));
+        }
+
+        let path = if out_path.is_dir() {
+            let file_name = entry
+                .as_ref()
+                .and_then(|(_, file)| Path::new(&file.relative_path).file_name())
+                .unwrap_or(file_id.as_ref());
+            out_path.join(file_name)
+        } else {
+            out_path.to_owned()
+        };
+        match &entry {
+            Some((_, file)) if !raw => {
+                let options = ExtractOptions {
+                    on_conflict: ConflictPolicy::Overwrite,
+                    ..Default::default()
+                };
+                self.write_file(&path, file, &options).map(|_| ())
+            }
+            _ => write_raw_blob(&blob_path, &path, self.copy_mode).map_err(Into::into),
+        }
+        .with_context(|| format!("failed to write file: {}", path.to_string_lossy()))?;
+
+        Ok(ExtractedBlob { path, entry })
+    }
+}
+
+/// Copies the blob to `path`, or links `path` to it, replacing the file
+/// there if any. It's removed first so that copying doesn't write through a
+/// link left by a previous run.
+fn write_raw_blob(blob_path: &Path, path: &Path, copy_mode: bool) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    if copy_mode {
+        fs::copy(blob_path, path)?;
+        return Ok(());
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(blob_path, path)?;
+    #[cfg(windows)]
+    std::os::windows::fs::symlink_file(blob_path, path)?;
+    debug!(
+        "linked {} -> {}",
+        path.to_string_lossy(),
+        blob_path.to_string_lossy()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::bucket::BucketStrategy;
+    use crate::ctx::Context;
+    use crate::utils::test_fixture::FixtureBackup;
+
+    #[test]
+    fn it_extracts_by_id() {
+        let fixture = FixtureBackup::new();
+        let file_id = fixture.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+        let orphan_id = "ab".repeat(20);
+        let orphan_path = fixture.path().join("ab").join(&orphan_id);
+        fs::create_dir_all(orphan_path.parent().unwrap()).unwrap();
+        fs::write(&orphan_path, b"orphan").unwrap();
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+
+        let blob = context
+            .extract_by_id(&file_id, out_dir.path(), false)
+            .unwrap();
+        assert_eq!(blob.path, out_dir.path().join("sms.db"));
+        assert_eq!(blob.entry.unwrap().0, "HomeDomain");
+        assert_eq!(fs::read(&blob.path).unwrap(), b"sms");
+
+        let renamed_path = out_dir.path().join("renamed.db");
+        context
+            .extract_by_id(&file_id.to_uppercase(), &renamed_path, false)
+            .unwrap();
+        assert_eq!(fs::read(&renamed_path).unwrap(), b"sms");
+
+        assert!(context
+            .extract_by_id(&orphan_id, out_dir.path(), false)
+            .is_err());
+        let blob = context
+            .extract_by_id(&orphan_id, out_dir.path(), true)
+            .unwrap();
+        assert_eq!(blob.path, out_dir.path().join(&orphan_id));
+        assert!(blob.entry.is_none());
+        assert_eq!(fs::read(&blob.path).unwrap(), b"orphan");
+
+        assert!(context
+            .extract_by_id(&file_id[..39], out_dir.path(), true)
+            .is_err());
+    }
+
+    #[test]
+    fn it_links_raw_blobs_unless_copying() {
+        let fixture = FixtureBackup::new();
+        let file_id = fixture.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+        let blob_path = fixture.path().join(&file_id[0..2]).join(&file_id);
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let mut manifest = fixture.manifest();
+        for copy_mode in [false, true] {
+            let context = Context::new(
+                fixture.path(),
+                &mut manifest,
+                copy_mode,
+                BucketStrategy::default(),
+            );
+            let blob = context
+                .extract_by_id(&file_id, &out_dir.path().join("raw"), true)
+                .unwrap();
+            assert_eq!(fs::read(&blob.path).unwrap(), b"sms");
+            let metadata = fs::symlink_metadata(&blob.path).unwrap();
+            assert_eq!(metadata.file_type().is_symlink(), !copy_mode);
+            if !copy_mode {
+                assert_eq!(fs::read_link(&blob.path).unwrap(), blob_path);
+            }
+        }
+        assert_eq!(fs::read(&blob_path).unwrap(), b"sms");
+    }
+}
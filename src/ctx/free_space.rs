@@ -0,0 +1,138 @@
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context as AnyhowContext, Result};
+use clap::ValueEnum;
+use indicatif::HumanBytes;
+
+use super::ProgressEvent;
+
+/// Number of files written between two free space checks.
+pub(super) const CHECK_INTERVAL: usize = 64;
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Fails if the volume of `dest_dir` has less than `required` bytes free,
+/// checked before copying anything. The `unsized_count` files without a
+/// recorded size are left out, which is only worth a warning, as is a free
+/// space that can't be queried.
+pub(super) fn check_room(dest_dir: &Path, required: u64, unsized_count: usize) -> Result<()> {
+    if unsized_count > 0 {
+        warn!(
+            "{unsized_count} file(s) have no recorded size, \
+             the space they need can't be checked up front"
+        );
+    }
+    // The destination may not exist yet.
+    let dir = super::existing_dir(dest_dir);
+    let available = match fs2::available_space(dir) {
+        Ok(available) => available,
+        Err(err) => {
+            warn!(
+                "failed to query free space: {}, extracting without checking: {err}",
+                dir.to_string_lossy()
+            );
+            return Ok(());
+        }
+    };
+    if required > available {
+        return Err(anyhow!(
+            "need ~{} to copy the files, only {} free on the destination volume, \
+             pass `--force` to extract anyway",
+            HumanBytes(required),
+            HumanBytes(available)
+        ));
+    }
+    Ok(())
+}
+
+/// Watches the free space of the destination volume during extraction.
+#[derive(Debug)]
+pub struct FreeSpaceGuard {
+    pub min_free_space: u64,
+    pub policy: LowSpacePolicy,
+}
+
+#[derive(ValueEnum, PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum LowSpacePolicy {
+    /// Wait until enough space is freed, then continue.
+    #[default]
+    Pause,
+    /// Stop before writing the next file.
+    Abort,
+}
+
+impl FreeSpaceGuard {
+    /// Returns when the volume containing `path` has enough free space,
+    /// blocking or failing according to the policy otherwise.
+    pub(super) fn check<F>(&self, path: &Path, progress_cb: &mut F) -> Result<()>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        loop {
+            let available = fs2::available_space(path).with_context(|| {
+                format!("failed to query free space: {}", path.to_string_lossy())
+            })?;
+            if available >= self.min_free_space {
+                return Ok(());
+            }
+
+            match self.policy {
+                LowSpacePolicy::Abort => {
+                    return Err(anyhow!(
+                        "free space dropped to {} (below {}), stopped before writing the next file",
+                        HumanBytes(available),
+                        HumanBytes(self.min_free_space)
+                    ));
+                }
+                LowSpacePolicy::Pause => {
+                    progress_cb(ProgressEvent::WaitingForSpace {
+                        available,
+                        required: self.min_free_space,
+                    });
+                    thread::sleep(RETRY_INTERVAL);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_room, FreeSpaceGuard, LowSpacePolicy};
+
+    #[test]
+    fn it_checks_room_up_front() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest_dir = dir.path().join("not/created");
+
+        check_room(&dest_dir, 0, 1).unwrap();
+        let err = check_room(&dest_dir, u64::MAX, 0).unwrap_err();
+        assert!(err.to_string().contains("--force"));
+    }
+
+    #[test]
+    fn it_aborts_when_low_on_space() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut events = vec![];
+
+        let guard = FreeSpaceGuard {
+            min_free_space: 0,
+            policy: LowSpacePolicy::Pause,
+        };
+        guard
+            .check(dir.path(), &mut |event| events.push(event))
+            .unwrap();
+
+        let guard = FreeSpaceGuard {
+            min_free_space: u64::MAX,
+            policy: LowSpacePolicy::Abort,
+        };
+        let err = guard
+            .check(dir.path(), &mut |event| events.push(event))
+            .unwrap_err();
+        assert!(err.to_string().contains("stopped before writing"));
+        assert!(events.is_empty());
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::{Context as AnyhowContext, Result};
+
+use super::{Context, ProgressEvent};
+use crate::db::{BackupManifest, FileChange, ManifestFile};
+
+impl<'p, 'd> Context<'p, 'd> {
+    /// Returns the files of the domain that were added or changed since
+    /// the older backup.
+    pub(super) fn query_changed_files<F>(
+        &self,
+        domain: &str,
+        since_manifest: &BackupManifest,
+        progress_cb: &mut F,
+    ) -> Result<Vec<ManifestFile>>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        if !self.manifest.has_domain(domain)? {
+            return Err(anyhow!("domain not found: {domain}"));
+        }
+
+        let mut files = vec![];
+        let mut changed = 0;
+        let mut deleted = vec![];
+        self.manifest
+            .diff_files(since_manifest, domain, |change| {
+                match change {
+                    FileChange::Added(file) => files.push(file),
+                    FileChange::Changed(file) => {
+                        changed += 1;
+                        files.push(file);
+                    }
+                    FileChange::Deleted(file) => deleted.push(file.relative_path.clone()),
+                }
+                Ok(())
+            })
+            .context("failed to compare with the older backup")?;
+
+        info!(
+            "{} file(s) added, {changed} changed and {} deleted since the older backup",
+            files.len() - changed,
+            deleted.len()
+        );
+        progress_cb(ProgressEvent::Compared {
+            added: files.len() - changed,
+            changed,
+            deleted,
+        });
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bucket::BucketStrategy;
+    use crate::ctx::{Context, ProgressEvent};
+    use crate::utils::test_fixture::FixtureBackup;
+
+    #[test]
+    fn it_finds_changed_files() {
+        let old_fixture = FixtureBackup::new();
+        old_fixture.add_file("CameraRollDomain", "Media/a.jpg", b"a");
+        old_fixture.add_file("CameraRollDomain", "Media/b.jpg", b"b");
+        old_fixture.add_file("CameraRollDomain", "Media/c.jpg", b"c");
+
+        let new_fixture = FixtureBackup::new();
+        new_fixture.add_file("CameraRollDomain", "Media/a.jpg", b"a");
+        new_fixture.add_file("CameraRollDomain", "Media/b.jpg", b"b2");
+        new_fixture.add_file("CameraRollDomain", "Media/d.jpg", b"d");
+
+        let mut manifest = new_fixture.manifest();
+        let context = Context::new(
+            new_fixture.path(),
+            &mut manifest,
+            false,
+            BucketStrategy::default(),
+        );
+        let mut compared = None;
+        let files = context
+            .query_changed_files("CameraRollDomain", &old_fixture.manifest(), &mut |e| {
+                if let ProgressEvent::Compared {
+                    added,
+                    changed,
+                    deleted,
+                } = e
+                {
+                    compared = Some((added, changed, deleted));
+                }
+            })
+            .unwrap();
+
+        let paths: Vec<_> = files.iter().map(|f| f.relative_path.as_str()).collect();
+        assert_eq!(paths, ["Media/b.jpg", "Media/d.jpg"]);
+        assert_eq!(compared, Some((1, 1, vec!["Media/c.jpg".to_owned()])));
+    }
+}
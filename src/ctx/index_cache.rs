@@ -0,0 +1,181 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context as AnyhowContext, Result};
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::db::ManifestFile;
+use crate::utils::hex;
+
+/// Bumped whenever the layout of the cached data changes.
+const CACHE_VERSION: u32 = 2;
+
+/// On-disk cache of the parsed manifest rows of each domain, which is what
+/// makes building the file system index of a huge domain slow.
+#[derive(Debug)]
+pub struct IndexCache {
+    pub dir: PathBuf,
+    /// Ignores the cached data and rebuilds it.
+    pub refresh: bool,
+}
+
+/// Identifies the manifest database the cache was built from.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+struct ManifestStamp {
+    size: u64,
+    modified_nanos: u128,
+}
+
+impl ManifestStamp {
+    fn of(manifest_path: &Path) -> Result<Self> {
+        let metadata = fs::metadata(manifest_path)?;
+        Ok(Self {
+            size: metadata.len(),
+            modified_nanos: metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedDomain {
+    version: u32,
+    manifest: ManifestStamp,
+    domain: String,
+    files: Vec<ManifestFile>,
+}
+
+impl IndexCache {
+    /// Returns the cached files of the domain, or builds them with `query`
+    /// and caches them.
+    ///
+    /// A stale or unreadable cache is rebuilt, failing to write the cache
+    /// only logs a warning.
+    pub fn get_or_build<F>(
+        &self,
+        manifest_path: &Path,
+        domain: &str,
+        query: F,
+    ) -> Result<Vec<ManifestFile>>
+    where
+        F: FnOnce() -> Result<Vec<ManifestFile>>,
+    {
+        let stamp = ManifestStamp::of(manifest_path).context("failed to stat the manifest")?;
+        let cache_path = self.cache_path(domain);
+
+        if !self.refresh {
+            match load(&cache_path) {
+                Ok(cached)
+                    if cached.version == CACHE_VERSION
+                        && cached.manifest == stamp
+                        && cached.domain == domain =>
+                {
+                    debug!("loaded index cache: {}", cache_path.to_string_lossy());
+                    return Ok(cached.files);
+                }
+                Ok(_) => info!("index cache of domain `{domain}` is stale, rebuilding"),
+                Err(err) if cache_path.exists() => {
+                    warn!("ignored broken index cache of domain `{domain}`: {err:#}")
+                }
+                Err(_) => {}
+            }
+        }
+
+        let cached = CachedDomain {
+            version: CACHE_VERSION,
+            manifest: stamp,
+            domain: domain.to_owned(),
+            files: query()?,
+        };
+        if let Err(err) = self.store(&cache_path, &cached) {
+            warn!("failed to write index cache of domain `{domain}`: {err:#}");
+        }
+        Ok(cached.files)
+    }
+
+    fn cache_path(&self, domain: &str) -> PathBuf {
+        let key = hex::encode(&Sha256::digest(domain.as_bytes()));
+        self.dir.join(format!("{key}.index"))
+    }
+
+    fn store(&self, cache_path: &Path, cached: &CachedDomain) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        // Write to a temporary file first, so that readers never see a
+        // partially written cache.
+        let tmp_path = cache_path.with_extension("tmp");
+        let writer = BufWriter::new(File::create(&tmp_path)?);
+        bincode::serialize_into(writer, cached)?;
+        fs::rename(&tmp_path, cache_path)?;
+        Ok(())
+    }
+}
+
+fn load(cache_path: &Path) -> Result<CachedDomain> {
+    let file = File::open(cache_path)?;
+    // A corrupt length mustn't make it allocate more than the cache could
+    // hold, and the cache can't hold more than its size.
+    let limit = file.metadata()?.len();
+    let reader = BufReader::new(file);
+    Ok(bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(limit)
+        .deserialize_from(reader)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{IndexCache, CACHE_VERSION};
+    use crate::utils::test_fixture::FixtureBackup;
+
+    #[test]
+    fn it_rebuilds_broken_cache() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/a.plist", b"a");
+        let manifest_path = fixture.path().join("Manifest.db");
+        let manifest = fixture.manifest();
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = IndexCache {
+            dir: cache_dir.path().to_owned(),
+            refresh: false,
+        };
+        let build = || manifest.query_files("HomeDomain");
+
+        let files = cache
+            .get_or_build(&manifest_path, "HomeDomain", build)
+            .unwrap();
+        assert_eq!(files.len(), 1);
+
+        // Served from the cache without querying.
+        let files = cache
+            .get_or_build(&manifest_path, "HomeDomain", || unreachable!())
+            .unwrap();
+        assert_eq!(files[0].relative_path, "Library/a.plist");
+
+        fs::write(cache.cache_path("HomeDomain"), b"garbage").unwrap();
+        let files = cache
+            .get_or_build(&manifest_path, "HomeDomain", build)
+            .unwrap();
+        assert_eq!(files.len(), 1);
+
+        // A domain name claiming to be exabytes long.
+        let mut huge_len = CACHE_VERSION.to_le_bytes().to_vec();
+        huge_len.extend_from_slice(&[0; 24]);
+        huge_len.extend_from_slice(&u64::MAX.to_le_bytes());
+        fs::write(cache.cache_path("HomeDomain"), huge_len).unwrap();
+        let files = cache
+            .get_or_build(&manifest_path, "HomeDomain", build)
+            .unwrap();
+        assert_eq!(files.len(), 1);
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{Context as AnyhowContext, Result};
+use clap::ValueEnum;
+use serde_json::json;
+
+use super::{sidecar, Context};
+
+/// Format of the metadata written by `--metadata`.
+#[derive(ValueEnum, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum MetadataFormat {
+    /// `metadata.json`, a single object keyed by the paths of the files.
+    Json,
+    /// `metadata.ndjson`, an object per line with the path of the file.
+    Ndjson,
+}
+
+impl MetadataFormat {
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Self::Json => "metadata.json",
+            Self::Ndjson => "metadata.ndjson",
+        }
+    }
+}
+
+impl<'p, 'd> Context<'p, 'd> {
+    /// Writes the metadata of the extracted files of a domain to
+    /// `dest_path`, `extracted` maps their file IDs to their paths in the
+    /// destination directory. The files whose metadata can't be decoded
+    /// are listed with an `error` instead, by their relative paths if they
+    /// weren't extracted. Returns the number of written entries, `dest_path`
+    /// must not exist.
+    pub fn write_metadata(
+        &self,
+        domain: &str,
+        extracted: &HashMap<String, String>,
+        dest_path: &Path,
+        format: MetadataFormat,
+    ) -> Result<usize> {
+        let rows = self
+            .manifest
+            .query_file_metadata(domain)
+            .context("failed to query files from database")?;
+
+        let mut entries = BTreeMap::new();
+        for row in &rows {
+            match &row.file {
+                Ok(file) => {
+                    let Some(path) = extracted.get(&row.file_id) else {
+                        continue;
+                    };
+                    entries.insert(path.as_str(), sidecar::sidecar(domain, file));
+                }
+                Err(err) => {
+                    let entry = json!({
+                        "fileID": row.file_id,
+                        "domain": domain,
+                        "relativePath": row.relative_path,
+                        "error": format!("{err:#}"),
+                    });
+                    let path = extracted.get(&row.file_id).unwrap_or(&row.relative_path);
+                    entries.insert(path.as_str(), entry);
+                }
+            }
+        }
+
+        // The destination directory may hold an extracted file or symlink
+        // of the same name, which must not be followed or overwritten.
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dest_path)
+            .with_context(|| format!("failed to create file: {}", dest_path.to_string_lossy()))?;
+        let mut writer = BufWriter::new(file);
+        let res = (|| -> Result<()> {
+            match format {
+                MetadataFormat::Json => serde_json::to_writer_pretty(&mut writer, &entries)?,
+                MetadataFormat::Ndjson => {
+                    for (path, entry) in &entries {
+                        let mut entry = entry.clone();
+                        entry["path"] = (*path).into();
+                        serde_json::to_writer(&mut writer, &entry)?;
+                        writeln!(writer)?;
+                    }
+                }
+            }
+            Ok(writer.flush()?)
+        })();
+        res.with_context(|| format!("failed to write file: {}", dest_path.to_string_lossy()))?;
+        Ok(entries.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+
+    use rusqlite::Connection as SqliteConnection;
+
+    use super::MetadataFormat;
+    use crate::bucket::BucketStrategy;
+    use crate::ctx::Context;
+    use crate::utils::test_fixture::FixtureBackup;
+
+    #[test]
+    fn it_writes_metadata() {
+        let fixture = FixtureBackup::new();
+        let file_id = fixture.add_file("HomeDomain", "Library/a.plist", b"abc");
+        fixture.add_file("HomeDomain", "Library/b.plist", b"b");
+        let corrupt_id = fixture.add_file("HomeDomain", "Library/c.plist", b"c");
+        SqliteConnection::open(fixture.path().join("Manifest.db"))
+            .unwrap()
+            .execute(
+                "UPDATE Files SET file = x'00' WHERE fileID = ?",
+                [&corrupt_id],
+            )
+            .unwrap();
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let mut manifest = fixture.manifest();
+        let context = Context::new(
+            fixture.path(),
+            &mut manifest,
+            false,
+            BucketStrategy::default(),
+        );
+        let extracted = HashMap::from([(file_id.clone(), "a.plist".to_owned())]);
+
+        let json_path = out_dir.path().join(MetadataFormat::Json.file_name());
+        let count = context
+            .write_metadata("HomeDomain", &extracted, &json_path, MetadataFormat::Json)
+            .unwrap();
+        assert_eq!(count, 2);
+        let metadata: serde_json::Value =
+            serde_json::from_slice(&fs::read(&json_path).unwrap()).unwrap();
+        assert_eq!(metadata["a.plist"]["fileID"], file_id.as_str());
+        assert_eq!(metadata["a.plist"]["size"], 3);
+        assert_eq!(metadata["a.plist"]["mode"], 0o100644);
+        assert!(metadata["Library/c.plist"]["error"].is_string());
+        assert!(metadata.get("Library/b.plist").is_none());
+
+        let ndjson_path = out_dir.path().join(MetadataFormat::Ndjson.file_name());
+        context
+            .write_metadata(
+                "HomeDomain",
+                &extracted,
+                &ndjson_path,
+                MetadataFormat::Ndjson,
+            )
+            .unwrap();
+        let lines: Vec<serde_json::Value> = fs::read_to_string(&ndjson_path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["path"], "Library/c.plist");
+        assert_eq!(lines[1]["path"], "a.plist");
+        assert_eq!(lines[1]["fileID"], file_id.as_str());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_does_not_follow_existing_files() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/a.plist", b"abc");
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let mut manifest = fixture.manifest();
+        let context = Context::new(
+            fixture.path(),
+            &mut manifest,
+            false,
+            BucketStrategy::default(),
+        );
+
+        let target_path = out_dir.path().join("target");
+        fs::write(&target_path, b"blob").unwrap();
+        let json_path = out_dir.path().join(MetadataFormat::Json.file_name());
+        std::os::unix::fs::symlink(&target_path, &json_path).unwrap();
+        assert!(context
+            .write_metadata(
+                "HomeDomain",
+                &HashMap::new(),
+                &json_path,
+                MetadataFormat::Json
+            )
+            .is_err());
+        assert_eq!(fs::read(&target_path).unwrap(), b"blob");
+    }
+}
@@ -0,0 +1,193 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use anyhow::{Context as AnyhowContext, Result};
+use tempfile::NamedTempFile;
+
+use super::Context;
+use crate::bucket::BucketStrategy;
+use crate::crypto;
+use crate::db::{ManifestFile, ManifestFileType};
+
+const DECRYPT_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Errors of `Context::open_file` that callers may want to tell apart,
+/// retrieved with `anyhow::Error::downcast_ref`.
+#[derive(Debug)]
+pub enum OpenFileError {
+    DomainNotFound(String),
+    FileNotFound(String),
+    /// The entry is a directory or a symbolic link.
+    NotAFile(String),
+    /// The manifest has the file but the backup lacks its content.
+    BlobMissing(PathBuf),
+}
+
+impl fmt::Display for OpenFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DomainNotFound(domain) => write!(f, "domain not found: {domain}"),
+            Self::FileNotFound(relative_path) => write!(f, "file not found: {relative_path}"),
+            Self::NotAFile(relative_path) => write!(f, "not a regular file: {relative_path}"),
+            Self::BlobMissing(path) => write!(f, "blob is missing: {}", path.to_string_lossy()),
+        }
+    }
+}
+
+impl std::error::Error for OpenFileError {}
+
+/// Content of a file in the backup, along with its metadata.
+#[derive(Debug)]
+pub struct BackupFile {
+    file: File,
+    metadata: ManifestFile,
+}
+
+impl BackupFile {
+    pub fn metadata(&self) -> &ManifestFile {
+        &self.metadata
+    }
+}
+
+impl Read for BackupFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Seek for BackupFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+impl<'p, 'd> Context<'p, 'd> {
+    /// Opens the content of a single file for reading, without indexing
+    /// the domain.
+    pub fn open_file(&self, domain: &str, relative_path: &str) -> Result<BackupFile> {
+        let Some(metadata) = self.manifest.query_file(domain, relative_path)? else {
+            if !self.manifest.has_domain(domain)? {
+                return Err(OpenFileError::DomainNotFound(domain.to_owned()).into());
+            }
+            return Err(OpenFileError::FileNotFound(relative_path.to_owned()).into());
+        };
+        if metadata.file_type != ManifestFileType::File {
+            return Err(OpenFileError::NotAFile(relative_path.to_owned()).into());
+        }
+
+        let mut blob_path = self.original_file_path(&metadata.file_id);
+        if !blob_path.exists() {
+            // Legacy backups keep all the blobs in a flat directory.
+            let flat_path = BucketStrategy::None.blob_path(self.backup_dir, &metadata.file_id);
+            if !flat_path.is_file() {
+                return Err(OpenFileError::BlobMissing(blob_path).into());
+            }
+            blob_path = flat_path;
+        }
+
+        let Some(backup_keys) = &self.backup_keys else {
+            let file = File::open(&blob_path)
+                .with_context(|| format!("failed to open: {}", blob_path.to_string_lossy()))?;
+            return Ok(BackupFile { file, metadata });
+        };
+
+        // Decrypted into an anonymous file, so that it can still be read
+        // in any order.
+        let wrapped_key = metadata
+            .encryption_key
+            .as_deref()
+            .ok_or_else(|| anyhow!("file has no encryption key"))?;
+        let key = backup_keys.unwrap_key(wrapped_key)?;
+        let decrypted_file = NamedTempFile::new().context("failed to create temporary file")?;
+        crypto::decrypt_file(
+            &blob_path,
+            decrypted_file.path(),
+            &key,
+            metadata.size,
+            DECRYPT_BUFFER_SIZE,
+        )
+        .context("failed to decrypt file")?;
+        Ok(BackupFile {
+            file: decrypted_file.into_file(),
+            metadata,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{Read, Seek, SeekFrom};
+
+    use super::OpenFileError;
+    use crate::bucket::BucketStrategy;
+    use crate::crypto::test_support;
+    use crate::ctx::Context;
+    use crate::utils::test_fixture::FixtureBackup;
+
+    #[test]
+    fn it_opens_files() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/a.txt", b"hello world");
+        fixture.add_dir("HomeDomain", "Library");
+        let missing_id = fixture.add_file("HomeDomain", "Library/missing.txt", b"gone");
+        fs::remove_file(fixture.path().join(&missing_id[0..2]).join(&missing_id)).unwrap();
+
+        let mut manifest = fixture.manifest();
+        let context = Context::new(
+            fixture.path(),
+            &mut manifest,
+            false,
+            BucketStrategy::default(),
+        );
+
+        let mut file = context.open_file("HomeDomain", "Library/a.txt").unwrap();
+        assert_eq!(file.metadata().size, Some(11));
+        file.seek(SeekFrom::Start(6)).unwrap();
+        let mut content = String::new();
+        file.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "world");
+
+        let open_err = |domain, relative_path| {
+            context
+                .open_file(domain, relative_path)
+                .unwrap_err()
+                .downcast::<OpenFileError>()
+                .unwrap()
+        };
+        assert!(matches!(
+            open_err("HomeDomain", "Library"),
+            OpenFileError::NotAFile(_)
+        ));
+        assert!(matches!(
+            open_err("HomeDomain", "Library/missing.txt"),
+            OpenFileError::BlobMissing(_)
+        ));
+        assert!(matches!(
+            open_err("NoDomain", "Library/a.txt"),
+            OpenFileError::DomainNotFound(_)
+        ));
+    }
+
+    #[test]
+    fn it_opens_encrypted_files() {
+        let fixture = FixtureBackup::new();
+        fixture.add_encrypted_file("HomeDomain", "Library/a.txt", b"hello world");
+
+        let mut manifest = fixture.manifest();
+        let context = Context::new(
+            fixture.path(),
+            &mut manifest,
+            false,
+            BucketStrategy::default(),
+        )
+        .with_backup_keys(Some(test_support::backup_keys()));
+
+        let mut file = context.open_file("HomeDomain", "Library/a.txt").unwrap();
+        let mut content = String::new();
+        file.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "hello world");
+    }
+}
@@ -0,0 +1,468 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as AnyhowContext, Result};
+
+use super::{Context, ProgressEvent};
+use crate::db::{ManifestFileType, MANIFEST_DB_NAME};
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ArchiveFormat {
+    Zip,
+    TarZst,
+}
+
+impl ArchiveFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let file_name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if file_name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if file_name.ends_with(".tar.zst") || file_name.ends_with(".tzst") {
+            Some(Self::TarZst)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug)]
+struct PackEntry {
+    source: PathBuf,
+    size: u64,
+}
+
+impl<'p, 'd> Context<'p, 'd> {
+    /// Packs the backup archive into a single archive file.
+    ///
+    /// When `domains` is not empty, only the files of these domains are
+    /// packed, along with a manifest database filtered accordingly.
+    pub fn pack<F>(&self, out_path: &Path, domains: &[String], progress_cb: F) -> Result<()>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let mut progress_cb = progress_cb;
+        self.reject_encrypted("packing")?;
+
+        let Some(format) = ArchiveFormat::from_path(out_path) else {
+            return Err(anyhow!(
+                "unsupported archive format, expected `.zip` or `.tar.zst`: {}",
+                out_path.to_string_lossy()
+            ));
+        };
+        if out_path.exists() {
+            return Err(anyhow!(
+                "file already exists: {}",
+                out_path.to_string_lossy()
+            ));
+        }
+
+        progress_cb(ProgressEvent::Querying);
+        // The filtered manifest lives in this directory, it must outlive
+        // the archive writing.
+        let temp_dir = tempfile::tempdir().context("failed to create temporary directory")?;
+        let entries = if domains.is_empty() {
+            collect_dir_entries(self.backup_dir)?
+        } else {
+            self.collect_domain_entries(domains, temp_dir.path())?
+        };
+
+        // Written next to the archive and only moved in place once
+        // verified, so that a failure leaves no partial archive behind.
+        let dir = out_path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let tmp_file = tempfile::Builder::new()
+            .prefix(".ibackupextractor-")
+            .tempfile_in(dir)
+            .with_context(|| format!("failed to create archive: {}", out_path.to_string_lossy()))?;
+        match format {
+            ArchiveFormat::Zip => write_zip(tmp_file.as_file(), &entries, &mut progress_cb),
+            ArchiveFormat::TarZst => write_tar_zst(tmp_file.as_file(), &entries, &mut progress_cb),
+        }
+        .context("failed to write archive")?;
+
+        progress_cb(ProgressEvent::Verifying);
+        let packed_entries = match format {
+            ArchiveFormat::Zip => read_zip_entries(tmp_file.path()),
+            ArchiveFormat::TarZst => read_tar_zst_entries(tmp_file.path()),
+        }
+        .context("failed to read back the archive")?;
+        verify_entries(&entries, &packed_entries).context("archive verification failed")?;
+
+        tmp_file
+            .persist_noclobber(out_path)
+            .with_context(|| format!("failed to create archive: {}", out_path.to_string_lossy()))?;
+
+        Ok(())
+    }
+
+    fn collect_domain_entries(
+        &self,
+        domains: &[String],
+        temp_dir: &Path,
+    ) -> Result<BTreeMap<String, PackEntry>> {
+        let known_domains = self.manifest.query_domains()?;
+        if let Some(domain) = domains.iter().find(|d| !known_domains.contains(d)) {
+            return Err(anyhow!("domain not found: {domain}"));
+        }
+
+        // Start with the top-level metadata files (`Info.plist`, etc.).
+        let mut entries = BTreeMap::new();
+        for dir_entry in fs::read_dir(self.backup_dir)? {
+            let dir_entry = dir_entry?;
+            let metadata = dir_entry.metadata()?;
+            if !metadata.is_file() || dir_entry.file_name() == MANIFEST_DB_NAME {
+                continue;
+            }
+            let Some(name) = dir_entry.file_name().to_str().map(str::to_owned) else {
+                return Err(anyhow!("unsupported file name, not UTF-8 compatible"));
+            };
+            entries.insert(
+                name,
+                PackEntry {
+                    source: dir_entry.path(),
+                    size: metadata.len(),
+                },
+            );
+        }
+
+        let manifest_path = temp_dir.join(MANIFEST_DB_NAME);
+        self.manifest
+            .export_domains(&manifest_path, domains)
+            .context("failed to export the manifest database")?;
+        entries.insert(
+            MANIFEST_DB_NAME.to_owned(),
+            PackEntry {
+                size: fs::metadata(&manifest_path)?.len(),
+                source: manifest_path,
+            },
+        );
+
+        for domain in domains {
+            let files = self
+                .query_files(domain)
+                .context("failed to query files from database")?;
+            for file in files {
+                if file.file_type != ManifestFileType::File {
+                    continue;
+                }
+
+                let blob_path = self.original_file_path(&file.file_id);
+                let metadata = fs::metadata(&blob_path).with_context(|| {
+                    format!(
+                        "referenced blob is missing: {}",
+                        blob_path.to_string_lossy()
+                    )
+                })?;
+                let name = archive_entry_name(self.backup_dir, &blob_path)?;
+                entries.insert(
+                    name,
+                    PackEntry {
+                        source: blob_path,
+                        size: metadata.len(),
+                    },
+                );
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+fn collect_dir_entries(backup_dir: &Path) -> Result<BTreeMap<String, PackEntry>> {
+    let mut entries = BTreeMap::new();
+    let mut pending_dirs = vec![backup_dir.to_owned()];
+    while let Some(dir) = pending_dirs.pop() {
+        for dir_entry in fs::read_dir(&dir)? {
+            let dir_entry = dir_entry?;
+            let path = dir_entry.path();
+            let file_type = dir_entry.file_type()?;
+            if file_type.is_dir() {
+                pending_dirs.push(path);
+                continue;
+            }
+            if !file_type.is_file() {
+                // A backup has no links of its own, following one could
+                // loop forever or pack files from outside of the backup.
+                continue;
+            }
+
+            let name = archive_entry_name(backup_dir, &path)?;
+            entries.insert(
+                name,
+                PackEntry {
+                    size: dir_entry.metadata()?.len(),
+                    source: path,
+                },
+            );
+        }
+    }
+    Ok(entries)
+}
+
+fn archive_entry_name(backup_dir: &Path, path: &Path) -> Result<String> {
+    let relative_path = path
+        .strip_prefix(backup_dir)
+        .expect("path should be inside the backup directory");
+    let components: Option<Vec<_>> = relative_path
+        .components()
+        .map(|c| c.as_os_str().to_str())
+        .collect();
+    let Some(components) = components else {
+        return Err(anyhow!("unsupported path, not UTF-8 compatible"));
+    };
+    Ok(components.join("/"))
+}
+
+fn write_zip<F>(
+    out_file: &File,
+    entries: &BTreeMap<String, PackEntry>,
+    progress_cb: &mut F,
+) -> Result<()>
+where
+    F: FnMut(ProgressEvent),
+{
+    use zip::write::FileOptions;
+    use zip::{CompressionMethod, DateTime, ZipWriter};
+
+    let mut writer = ZipWriter::new(BufWriter::new(out_file));
+    for (idx, (name, entry)) in entries.iter().enumerate() {
+        // Blobs are mostly already compressed media, store them as-is and
+        // pin the timestamps so that the output is deterministic.
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .last_modified_time(DateTime::default())
+            .large_file(entry.size >= u32::MAX as u64);
+        writer.start_file(name, options)?;
+        let mut source = File::open(&entry.source)
+            .with_context(|| format!("failed to open: {}", entry.source.to_string_lossy()))?;
+        io::copy(&mut source, &mut writer)?;
+
+        progress_cb(ProgressEvent::Packing {
+            packed: idx + 1,
+            total: entries.len(),
+        });
+    }
+    writer.finish()?.flush()?;
+
+    Ok(())
+}
+
+fn write_tar_zst<F>(
+    out_file: &File,
+    entries: &BTreeMap<String, PackEntry>,
+    progress_cb: &mut F,
+) -> Result<()>
+where
+    F: FnMut(ProgressEvent),
+{
+    let encoder = zstd::Encoder::new(BufWriter::new(out_file), 0)?;
+    let mut builder = tar::Builder::new(encoder);
+    builder.mode(tar::HeaderMode::Deterministic);
+    for (idx, (name, entry)) in entries.iter().enumerate() {
+        builder
+            .append_path_with_name(&entry.source, name)
+            .with_context(|| format!("failed to pack: {}", entry.source.to_string_lossy()))?;
+
+        progress_cb(ProgressEvent::Packing {
+            packed: idx + 1,
+            total: entries.len(),
+        });
+    }
+    builder.into_inner()?.finish()?.flush()?;
+
+    Ok(())
+}
+
+fn read_zip_entries(path: &Path) -> Result<Vec<(String, u64)>> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+    (0..archive.len())
+        .map(|idx| {
+            let file = archive.by_index_raw(idx)?;
+            Ok((file.name().to_owned(), file.size()))
+        })
+        .collect()
+}
+
+fn read_tar_zst_entries(path: &Path) -> Result<Vec<(String, u64)>> {
+    let decoder = zstd::Decoder::new(File::open(path)?)?;
+    let mut archive = tar::Archive::new(decoder);
+    archive
+        .entries()?
+        .map(|entry| {
+            let entry = entry?;
+            let name = entry.path()?.to_string_lossy().into_owned();
+            Ok((name, entry.header().size()?))
+        })
+        .collect()
+}
+
+fn verify_entries(
+    entries: &BTreeMap<String, PackEntry>,
+    packed_entries: &[(String, u64)],
+) -> Result<()> {
+    if entries.len() != packed_entries.len() {
+        return Err(anyhow!(
+            "entry count is not matched, expected {} but got {}",
+            entries.len(),
+            packed_entries.len()
+        ));
+    }
+    for ((name, entry), (packed_name, packed_size)) in entries.iter().zip(packed_entries) {
+        if name != packed_name || entry.size != *packed_size {
+            return Err(anyhow!(
+                "entry is not matched, expected `{name}` ({} bytes) but got `{packed_name}` ({packed_size} bytes)",
+                entry.size
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io;
+
+    use super::read_zip_entries;
+    use crate::bucket::BucketStrategy;
+    use crate::ctx::{Context, ExtractOptions};
+    use crate::db::BackupManifest;
+    use crate::utils::test_fixture::FixtureBackup;
+
+    #[test]
+    fn it_packs_whole_backup() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/a.plist", b"a");
+        fixture.add_file("MediaDomain", "Media/b.jpg", b"bb");
+
+        let out_dir = tempfile::tempdir().unwrap();
+        for file_name in ["backup.zip", "backup.tar.zst"] {
+            let out_path = out_dir.path().join(file_name);
+            let mut manifest = fixture.manifest();
+            let context = Context::new(
+                fixture.path(),
+                &mut manifest,
+                false,
+                BucketStrategy::default(),
+            );
+            context.pack(&out_path, &[], |_| {}).unwrap();
+        }
+
+        let names: Vec<_> = read_zip_entries(&out_dir.path().join("backup.zip"))
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        let mut sorted_names = names.clone();
+        sorted_names.sort();
+        assert_eq!(names, sorted_names);
+        assert_eq!(names.len(), 3);
+    }
+
+    #[test]
+    fn it_packs_filtered_domains() {
+        let fixture = FixtureBackup::new();
+        let kept_id = fixture.add_file("HomeDomain", "Library/a.plist", b"a");
+        let dropped_id = fixture.add_file("MediaDomain", "Media/b.jpg", b"bb");
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let out_path = out_dir.path().join("backup.zip");
+        let mut manifest = fixture.manifest();
+        let context = Context::new(
+            fixture.path(),
+            &mut manifest,
+            false,
+            BucketStrategy::default(),
+        );
+        context
+            .pack(&out_path, &["HomeDomain".to_owned()], |_| {})
+            .unwrap();
+
+        let names: Vec<_> = read_zip_entries(&out_path)
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert!(names.iter().any(|name| name.ends_with(&kept_id)));
+        assert!(!names.iter().any(|name| name.ends_with(&dropped_id)));
+
+        // Round-trip the packed manifest and make sure it's filtered.
+        let mut archive = zip::ZipArchive::new(File::open(&out_path).unwrap()).unwrap();
+        let manifest_path = out_dir.path().join("Manifest.db");
+        io::copy(
+            &mut archive.by_name("Manifest.db").unwrap(),
+            &mut File::create(&manifest_path).unwrap(),
+        )
+        .unwrap();
+        let packed_manifest = BackupManifest::open(&manifest_path).unwrap();
+        assert_eq!(packed_manifest.query_domains().unwrap(), vec!["HomeDomain"]);
+    }
+
+    #[test]
+    fn it_round_trips_through_extraction() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/a.plist", b"a");
+        fixture.add_file("MediaDomain", "Media/b.jpg", b"bb");
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let out_path = out_dir.path().join("backup.zip");
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+        context.pack(&out_path, &[], |_| {}).unwrap();
+
+        // The unpacked archive must extract like the original backup.
+        let unpacked_dir = out_dir.path().join("backup");
+        zip::ZipArchive::new(File::open(&out_path).unwrap())
+            .unwrap()
+            .extract(&unpacked_dir)
+            .unwrap();
+        let mut unpacked_manifest = BackupManifest::open(unpacked_dir.join("Manifest.db")).unwrap();
+        let unpacked_context = Context::new(
+            &unpacked_dir,
+            &mut unpacked_manifest,
+            true,
+            BucketStrategy::default(),
+        );
+        let extracted_dir = out_dir.path().join("extracted");
+        unpacked_context
+            .extract_file(
+                "MediaDomain",
+                &extracted_dir,
+                &ExtractOptions::default(),
+                |_| {},
+            )
+            .unwrap();
+        assert_eq!(fs::read(extracted_dir.join("Media/b.jpg")).unwrap(), b"bb");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_skips_symbolic_links() {
+        use std::os::unix::fs::symlink;
+
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/a.plist", b"a");
+        symlink(".", fixture.path().join("loop")).unwrap();
+        symlink("missing", fixture.path().join("dangling")).unwrap();
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let out_path = out_dir.path().join("backup.zip");
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+        context.pack(&out_path, &[], |_| {}).unwrap();
+
+        let names: Vec<_> = read_zip_entries(&out_path)
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names.len(), 2);
+        assert!(!names
+            .iter()
+            .any(|name| name == "loop" || name == "dangling"));
+    }
+}
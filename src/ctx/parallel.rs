@@ -0,0 +1,234 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use anyhow::{Context as AnyhowContext, Result};
+
+use super::{free_space, Context, ExtractOptions, FileWriter, ProgressEvent};
+use crate::db::ManifestFile;
+use crate::utils::cloud_placeholder::CloudPlaceholderError;
+
+/// Counters of an extraction, reported with `ProgressEvent::Extracting`.
+pub(super) struct WriteProgress {
+    pub extracted: usize,
+    pub total: usize,
+    pub bytes_written: u64,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+impl WriteProgress {
+    /// Counts the outcome of writing a file, a failure is only reported
+    /// with `keep_going`, or if the blob is a cloud placeholder.
+    pub fn record_result<F>(
+        &mut self,
+        path: &Path,
+        file: &ManifestFile,
+        res: Result<Option<u64>>,
+        keep_going: bool,
+        progress_cb: &mut F,
+    ) -> Result<()>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        match res {
+            Ok(written) => self.record(path, file, written, progress_cb),
+            Err(err) if keep_going || err.is::<CloudPlaceholderError>() => {
+                if err.is::<CloudPlaceholderError>() {
+                    progress_cb(ProgressEvent::Offloaded {
+                        relative_path: file.relative_path.clone(),
+                    });
+                }
+                progress_cb(ProgressEvent::Failed {
+                    relative_path: file.relative_path.clone(),
+                    error: format!("{err:#}"),
+                });
+                self.advance(file.size.unwrap_or_default(), progress_cb);
+            }
+            Err(err) => return Err(err),
+        }
+        Ok(())
+    }
+
+    /// Counts a file written at `path`, or kept there if `written` is
+    /// `None`.
+    fn record<F>(
+        &mut self,
+        path: &Path,
+        file: &ManifestFile,
+        written: Option<u64>,
+        progress_cb: &mut F,
+    ) where
+        F: FnMut(ProgressEvent),
+    {
+        match written {
+            Some(size) => self.bytes_written += size,
+            None => progress_cb(ProgressEvent::Kept {
+                relative_path: file.relative_path.clone(),
+            }),
+        }
+        progress_cb(ProgressEvent::Written {
+            relative_path: file.relative_path.clone(),
+            file_id: file.file_id.clone(),
+            path: path.to_owned(),
+            size: file.size.unwrap_or_default(),
+        });
+        self.advance(bytes_done(file, written), progress_cb);
+    }
+
+    /// Counts a file done, written or failed, and reports the progress.
+    fn advance<F>(&mut self, bytes_done: u64, progress_cb: &mut F)
+    where
+        F: FnMut(ProgressEvent),
+    {
+        self.bytes_done += bytes_done;
+        self.extracted += 1;
+        progress_cb(ProgressEvent::Extracting {
+            extracted: self.extracted,
+            total: self.total,
+            bytes_written: self.bytes_written,
+            bytes_done: self.bytes_done,
+            bytes_total: self.bytes_total,
+        });
+    }
+}
+
+/// Bytes a written file advances the progress by, the copied size or the
+/// recorded one for the files that were linked or kept.
+pub(super) fn bytes_done(file: &ManifestFile, written: Option<u64>) -> u64 {
+    match written {
+        Some(size) if size > 0 => size,
+        _ => file.size.unwrap_or_default(),
+    }
+}
+
+impl<'p, 'd> Context<'p, 'd> {
+    /// Writes the files to their destination paths, whose directories must
+    /// exist, on `options.jobs` threads. The progress is reported from the
+    /// calling thread.
+    pub(super) fn write_files<F>(
+        &self,
+        files: &[(PathBuf, &ManifestFile)],
+        options: &ExtractOptions,
+        progress: &mut WriteProgress,
+        progress_cb: &mut F,
+    ) -> Result<()>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let writer = self.file_writer(options);
+        // Blobs of the same content would race for the same temporary file
+        // of the store.
+        let jobs = if options.store.is_some() {
+            1
+        } else {
+            options.jobs.get()
+        };
+        if jobs == 1 {
+            for (idx, (path, file)) in files.iter().enumerate() {
+                check_free_space(options, idx, path, progress_cb)?;
+                let res = write(&writer, path, file);
+                progress.record_result(path, file, res, options.keep_going, progress_cb)?;
+            }
+            return Ok(());
+        }
+
+        let failed = AtomicBool::new(false);
+        let (job_tx, job_rx) = mpsc::sync_channel::<&(PathBuf, &ManifestFile)>(jobs);
+        let job_rx = Mutex::new(job_rx);
+        let (result_tx, result_rx) = mpsc::channel();
+        thread::scope(|scope| {
+            for _ in 0..jobs {
+                let result_tx = result_tx.clone();
+                let (job_rx, writer, failed) = (&job_rx, &writer, &failed);
+                let keep_going = options.keep_going;
+                scope.spawn(move || loop {
+                    let job = job_rx.lock().unwrap().recv();
+                    let Ok((path, file)) = job else {
+                        break;
+                    };
+                    // Keep taking the queued jobs after a failure, so that
+                    // the feeding thread never blocks.
+                    if failed.load(Ordering::Relaxed) {
+                        continue;
+                    }
+                    let res = write(writer, path, file);
+                    let stops = res
+                        .as_ref()
+                        .is_err_and(|err| !keep_going && !err.is::<CloudPlaceholderError>());
+                    if stops {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    if result_tx.send((path, *file, res)).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(result_tx);
+
+            // Dropping the sender on return stops the workers.
+            let job_tx = job_tx;
+            let res = (|| -> Result<()> {
+                for (idx, job) in files.iter().enumerate() {
+                    while let Ok((path, file, res)) = result_rx.try_recv() {
+                        progress.record_result(path, file, res, options.keep_going, progress_cb)?;
+                    }
+                    check_free_space(options, idx, &job.0, progress_cb)?;
+                    job_tx.send(job).expect("workers should be running");
+                }
+                drop(job_tx);
+                for (path, file, res) in result_rx.iter() {
+                    progress.record_result(path, file, res, options.keep_going, progress_cb)?;
+                }
+                Ok(())
+            })();
+            let Err(err) = res else {
+                return Ok(());
+            };
+            failed.store(true, Ordering::Relaxed);
+
+            // The writes in flight on the other threads may fail too, report
+            // them instead of dropping them with the channel.
+            let mut other_failed_count = 0;
+            for (_, file, res) in result_rx.iter() {
+                if let Err(err) = res {
+                    error!("failed to extract `{}`: {err:#}", file.relative_path);
+                    other_failed_count += 1;
+                }
+            }
+            if other_failed_count > 0 {
+                return Err(err.context(format!(
+                    "{} file(s) failed to extract",
+                    other_failed_count + 1
+                )));
+            }
+            Err(err)
+        })
+    }
+}
+
+fn write(writer: &FileWriter, path: &Path, file: &ManifestFile) -> Result<Option<u64>> {
+    writer
+        .write(path, file)
+        .with_context(|| format!("failed to create file: {}", path.to_string_lossy()))
+}
+
+fn check_free_space<F>(
+    options: &ExtractOptions,
+    idx: usize,
+    path: &Path,
+    progress_cb: &mut F,
+) -> Result<()>
+where
+    F: FnMut(ProgressEvent),
+{
+    let Some(free_space_guard) = &options.free_space_guard else {
+        return Ok(());
+    };
+    if !idx.is_multiple_of(free_space::CHECK_INTERVAL) {
+        return Ok(());
+    }
+    let dir = path.parent().expect("path should have a parent");
+    free_space_guard.check(dir, progress_cb)
+}
@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use serde::Serialize;
+
+use super::ProgressEvent;
+
+/// What an extraction did, returned by `Context::extract_file`.
+#[derive(Default, Clone, Debug)]
+pub struct ExtractionReport {
+    pub files_extracted: usize,
+    /// Files the extraction set out to write.
+    pub files_total: usize,
+    pub bytes_written: u64,
+    /// Relative paths of the skipped files, by the reason they were skipped.
+    pub skipped: BTreeMap<&'static str, Vec<String>>,
+    /// Relative paths of the files written as placeholders as their blobs
+    /// are missing, included in `files_extracted`.
+    pub placeholders: Vec<String>,
+    /// Relative paths of the files whose existing copies were kept,
+    /// included in `files_extracted`.
+    pub kept: Vec<String>,
+    /// Files hard linked to an earlier copy of the same content, included
+    /// in `files_extracted`.
+    pub deduplicated: usize,
+    /// Bytes the deduplicated files would have taken.
+    pub bytes_saved: u64,
+    /// Relative paths of the files that failed to be written with
+    /// `keep_going`, or whose blobs are cloud placeholders, with the errors.
+    pub failed: Vec<(String, String)>,
+    /// Relative paths of the files left unwritten as their blobs are cloud
+    /// placeholders, included in `failed`.
+    pub offloaded: Vec<String>,
+    /// Relative paths that were cleaned up, with their normalized forms.
+    pub normalized: Vec<(String, String)>,
+    /// Differences from the older backup in incremental extractions.
+    pub changes: Option<BackupChanges>,
+    /// Regular files that passed the filters, and all the regular files of
+    /// the domain, if the extraction was filtered.
+    pub filtered: Option<(usize, usize)>,
+    /// Seconds spent in each phase.
+    pub phases: PhaseDurations,
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct BackupChanges {
+    pub added: usize,
+    pub changed: usize,
+    /// Relative paths of the files that no longer exist.
+    pub deleted: Vec<String>,
+}
+
+impl ExtractionReport {
+    pub fn files_skipped(&self) -> usize {
+        self.skipped.values().map(Vec::len).sum()
+    }
+
+    /// Files left unwritten, which is nonzero only if the extraction
+    /// stopped early.
+    pub fn files_unwritten(&self) -> usize {
+        self.files_total.saturating_sub(self.files_extracted)
+    }
+}
+
+#[derive(Serialize, Default, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PhaseDurations {
+    pub querying: f64,
+    pub indexing: f64,
+    pub extracting: f64,
+    pub waiting_for_space: f64,
+}
+
+impl PhaseDurations {
+    fn get_mut(&mut self, phase: Phase) -> &mut f64 {
+        match phase {
+            Phase::Querying => &mut self.querying,
+            Phase::Indexing => &mut self.indexing,
+            Phase::Extracting => &mut self.extracting,
+            Phase::WaitingForSpace => &mut self.waiting_for_space,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum Phase {
+    Querying,
+    Indexing,
+    Extracting,
+    WaitingForSpace,
+}
+
+/// Builds an `ExtractionReport` from the progress events of an extraction,
+/// so that a report is available whenever the extraction stops.
+#[derive(Default)]
+pub struct ReportRecorder {
+    report: ExtractionReport,
+    current_phase: Option<(Phase, Instant)>,
+}
+
+impl ReportRecorder {
+    pub fn record(&mut self, event: &ProgressEvent) {
+        let report = &mut self.report;
+        let phase = match event {
+            ProgressEvent::Querying => Phase::Querying,
+            ProgressEvent::Indexing { .. } => Phase::Indexing,
+            ProgressEvent::Extracting {
+                extracted,
+                total,
+                bytes_written,
+                ..
+            } => {
+                // The failed files are counted as done too.
+                report.files_extracted = extracted.saturating_sub(report.failed.len());
+                report.files_total = *total;
+                report.bytes_written = *bytes_written;
+                Phase::Extracting
+            }
+            ProgressEvent::WaitingForSpace { .. } => Phase::WaitingForSpace,
+            ProgressEvent::Skipped {
+                relative_path,
+                reason,
+            } => {
+                report
+                    .skipped
+                    .entry(reason)
+                    .or_default()
+                    .push(relative_path.clone());
+                return;
+            }
+            ProgressEvent::Placeholder { relative_path } => {
+                report.placeholders.push(relative_path.clone());
+                return;
+            }
+            ProgressEvent::Offloaded { relative_path } => {
+                report.offloaded.push(relative_path.clone());
+                return;
+            }
+            ProgressEvent::Failed {
+                relative_path,
+                error,
+            } => {
+                report.failed.push((relative_path.clone(), error.clone()));
+                return;
+            }
+            ProgressEvent::Kept { relative_path } => {
+                report.kept.push(relative_path.clone());
+                return;
+            }
+            ProgressEvent::Deduplicated { size, .. } => {
+                report.deduplicated += 1;
+                report.bytes_saved += size;
+                return;
+            }
+            ProgressEvent::Normalized {
+                relative_path,
+                normalized,
+            } => {
+                report
+                    .normalized
+                    .push((relative_path.clone(), normalized.clone()));
+                return;
+            }
+            ProgressEvent::Compared {
+                added,
+                changed,
+                deleted,
+            } => {
+                report.changes = Some(BackupChanges {
+                    added: *added,
+                    changed: *changed,
+                    deleted: deleted.clone(),
+                });
+                return;
+            }
+            ProgressEvent::Filtered { matched, total } => {
+                report.filtered = Some((*matched, *total));
+                return;
+            }
+            ProgressEvent::Domain { .. }
+            | ProgressEvent::Planned { .. }
+            | ProgressEvent::Written { .. }
+            | ProgressEvent::Packing { .. }
+            | ProgressEvent::Verifying => return,
+        };
+        self.enter_phase(Some(phase));
+    }
+
+    /// Returns the report so far, counting the current phase up to now.
+    pub fn report(&mut self) -> ExtractionReport {
+        self.enter_phase(None);
+        self.report.clone()
+    }
+
+    fn enter_phase(&mut self, phase: Option<Phase>) {
+        if let Some((current_phase, started_at)) = self.current_phase {
+            if Some(current_phase) == phase {
+                return;
+            }
+            *self.report.phases.get_mut(current_phase) += started_at.elapsed().as_secs_f64();
+        }
+        self.current_phase = phase.map(|phase| (phase, Instant::now()));
+    }
+}
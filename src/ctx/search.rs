@@ -0,0 +1,291 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use anyhow::{Context as AnyhowContext, Result};
+use regex::bytes::Regex;
+
+use super::Context;
+use crate::db::{ManifestFile, ManifestFileType};
+
+/// Number of leading bytes inspected to tell binary files apart.
+const BINARY_SNIFF_LEN: usize = 8192;
+
+/// Matched lines longer than this are truncated in the output.
+const MAX_CONTEXT_LEN: usize = 200;
+
+#[derive(Debug)]
+pub struct ContentSearchOptions<'a> {
+    /// Only search the files under this path.
+    pub under: Option<&'a Path>,
+    /// Files larger than this are skipped.
+    pub max_size: u64,
+    /// Search binary files too.
+    pub binary: bool,
+    /// Number of files scanned at once.
+    pub jobs: NonZeroUsize,
+}
+
+#[derive(Debug)]
+pub struct ContentMatch<'a> {
+    pub relative_path: &'a str,
+    /// Offset of the matched line in the file.
+    pub offset: u64,
+    pub context: String,
+}
+
+#[derive(Default, Debug)]
+pub struct ContentSearchStats {
+    pub searched: usize,
+    pub matched: usize,
+    pub skipped_too_large: usize,
+    pub skipped_binary: usize,
+    pub skipped_missing: usize,
+}
+
+/// What scanning a blob found.
+enum Scan {
+    Missing,
+    TooLarge,
+    Binary,
+    /// Offsets and contexts of the matched lines.
+    Searched(Vec<(u64, String)>),
+}
+
+impl<'p, 'd> Context<'p, 'd> {
+    /// Scans the blobs of a domain line by line for `pattern`, reading the
+    /// rows as they come and scanning `options.jobs` blobs at once. The
+    /// matches of a file are reported together, but the files come in no
+    /// particular order with more than one job.
+    pub fn search_content<F>(
+        &self,
+        domain: &str,
+        pattern: &Regex,
+        options: &ContentSearchOptions,
+        on_match: F,
+    ) -> Result<ContentSearchStats>
+    where
+        F: FnMut(ContentMatch),
+    {
+        let mut on_match = on_match;
+        self.reject_encrypted("searching content")?;
+
+        let mut stats = ContentSearchStats::default();
+        let mut report = |relative_path: &str, scan: Scan| {
+            let matches = match scan {
+                Scan::Missing => return stats.skipped_missing += 1,
+                Scan::TooLarge => return stats.skipped_too_large += 1,
+                Scan::Binary => return stats.skipped_binary += 1,
+                Scan::Searched(matches) => matches,
+            };
+            stats.searched += 1;
+            if !matches.is_empty() {
+                stats.matched += 1;
+            }
+            for (offset, context) in matches {
+                on_match(ContentMatch {
+                    relative_path,
+                    offset,
+                    context,
+                });
+            }
+        };
+
+        let jobs = options.jobs.get();
+        if jobs == 1 {
+            self.for_each_file(domain, |file| {
+                if let Some(blob_path) = self.blob_to_search(&file, options) {
+                    report(
+                        &file.relative_path,
+                        scan_blob(&blob_path, pattern, options)?,
+                    );
+                }
+                Ok(())
+            })
+            .context("failed to query files from database")?;
+            return Ok(stats);
+        }
+
+        let (job_tx, job_rx) = mpsc::sync_channel::<(String, PathBuf)>(jobs);
+        let job_rx = Mutex::new(job_rx);
+        let (result_tx, result_rx) = mpsc::channel();
+        thread::scope(|scope| {
+            for _ in 0..jobs {
+                let result_tx = result_tx.clone();
+                let job_rx = &job_rx;
+                scope.spawn(move || loop {
+                    let job = job_rx.lock().unwrap().recv();
+                    let Ok((relative_path, blob_path)) = job else {
+                        break;
+                    };
+                    let scan = scan_blob(&blob_path, pattern, options);
+                    if result_tx.send((relative_path, scan)).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(result_tx);
+
+            // Dropping the sender on return stops the workers.
+            let job_tx = job_tx;
+            self.for_each_file(domain, |file| {
+                while let Ok((relative_path, scan)) = result_rx.try_recv() {
+                    report(&relative_path, scan?);
+                }
+                if let Some(blob_path) = self.blob_to_search(&file, options) {
+                    job_tx
+                        .send((file.relative_path.clone(), blob_path))
+                        .expect("workers should be running");
+                }
+                Ok(())
+            })
+            .context("failed to query files from database")?;
+            drop(job_tx);
+            for (relative_path, scan) in result_rx.iter() {
+                report(&relative_path, scan?);
+            }
+            anyhow::Ok(())
+        })?;
+        Ok(stats)
+    }
+
+    /// Returns the path of the blob of a file to search, if it's a regular
+    /// file under `options.under`.
+    fn blob_to_search(
+        &self,
+        file: &ManifestFile,
+        options: &ContentSearchOptions,
+    ) -> Option<PathBuf> {
+        if file.file_type != ManifestFileType::File {
+            return None;
+        }
+        if let Some(under) = options.under {
+            if !Path::new(&file.relative_path).starts_with(under) {
+                return None;
+            }
+        }
+        Some(self.original_file_path(&file.file_id))
+    }
+}
+
+/// Scans a blob line by line, unless it's missing, too large or binary.
+fn scan_blob(blob_path: &Path, pattern: &Regex, options: &ContentSearchOptions) -> Result<Scan> {
+    let read_context = || format!("failed to read: {}", blob_path.to_string_lossy());
+    let Ok(blob) = File::open(blob_path) else {
+        return Ok(Scan::Missing);
+    };
+    if blob.metadata().with_context(read_context)?.len() > options.max_size {
+        return Ok(Scan::TooLarge);
+    }
+
+    let mut reader = BufReader::new(blob);
+    if !options.binary && is_binary(&mut reader).with_context(read_context)? {
+        return Ok(Scan::Binary);
+    }
+
+    let mut matches = vec![];
+    let mut offset = 0;
+    let mut line = vec![];
+    loop {
+        line.clear();
+        let len = reader
+            .read_until(b'\n', &mut line)
+            .with_context(read_context)?;
+        if len == 0 {
+            break;
+        }
+
+        if pattern.is_match(&line) {
+            let context = String::from_utf8_lossy(&line);
+            matches.push((
+                offset,
+                context.trim_end().chars().take(MAX_CONTEXT_LEN).collect(),
+            ));
+        }
+        offset += len as u64;
+    }
+    Ok(Scan::Searched(matches))
+}
+
+/// Checks for NUL bytes in the leading bytes, the same heuristic `grep`
+/// uses, without consuming the reader.
+fn is_binary<R: Read>(reader: &mut BufReader<R>) -> Result<bool> {
+    let buf = reader.fill_buf()?;
+    Ok(buf[..buf.len().min(BINARY_SNIFF_LEN)].contains(&0))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::num::NonZeroUsize;
+    use std::path::Path;
+
+    use regex::bytes::Regex;
+
+    use super::ContentSearchOptions;
+    use crate::utils::test_fixture::FixtureBackup;
+
+    #[test]
+    fn it_searches_content() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file(
+            "HomeDomain",
+            "Library/Preferences/a.plist",
+            b"<key>name</key>\n<string>user@example.com</string>\n",
+        );
+        fixture.add_file("HomeDomain", "Library/b.bin", b"\0user@example.com");
+        fixture.add_file("HomeDomain", "Library/c.txt", &[b'x'; 100]);
+        fixture.add_file("HomeDomain", "Media/d.txt", b"user@example.com");
+        let missing_id = fixture.add_file("HomeDomain", "Library/e.txt", b"e");
+        fs::remove_file(fixture.path().join(&missing_id[0..2]).join(&missing_id)).unwrap();
+
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+        let pattern = Regex::new(r"user@\w+\.com").unwrap();
+        let search = |binary, jobs| {
+            let options = ContentSearchOptions {
+                under: Some(Path::new("Library")),
+                max_size: 64,
+                binary,
+                jobs: NonZeroUsize::new(jobs).unwrap(),
+            };
+            let mut matches = vec![];
+            let stats = context
+                .search_content("HomeDomain", &pattern, &options, |m| {
+                    matches.push((m.relative_path.to_owned(), m.offset, m.context));
+                })
+                .unwrap();
+            matches.sort();
+            (matches, stats)
+        };
+
+        let (matches, stats) = search(false, 1);
+        assert_eq!(
+            matches,
+            [(
+                "Library/Preferences/a.plist".to_owned(),
+                16,
+                "<string>user@example.com</string>".to_owned()
+            )]
+        );
+        assert_eq!(
+            (stats.searched, stats.matched),
+            (1, 1),
+            "d.txt is outside `Library`"
+        );
+        assert_eq!(stats.skipped_binary, 1);
+        assert_eq!(stats.skipped_too_large, 1);
+        assert_eq!(stats.skipped_missing, 1);
+
+        for jobs in [1, 4] {
+            let (matches, stats) = search(true, jobs);
+            assert_eq!(matches.len(), 2);
+            assert_eq!(matches[1].0, "Library/b.bin");
+            assert_eq!((stats.searched, stats.skipped_binary), (2, 0));
+            assert_eq!(stats.skipped_missing, 1);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+use anyhow::{Context as AnyhowContext, Result};
+use serde_json::json;
+
+use super::{Context, ProgressEvent};
+use crate::db::{ManifestFile, ManifestFileType};
+use crate::utils::hex;
+
+impl<'p, 'd> Context<'p, 'd> {
+    /// Writes a `<file ID>.json` sidecar with the metadata of each file in
+    /// the domain, laid out with the same bucket strategy as the backup so
+    /// that the sidecars sit next to where the blobs would be.
+    pub fn write_sidecars<F>(&self, domain: &str, dest_dir: &Path, progress_cb: F) -> Result<()>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let mut progress_cb = progress_cb;
+
+        progress_cb(ProgressEvent::Querying);
+        let files: Vec<_> = self
+            .query_files(domain)
+            .context("failed to query files from database")?
+            .into_iter()
+            .filter(|file| file.file_type == ManifestFileType::File)
+            .collect();
+
+        info!("writing {} sidecar(s) of domain `{domain}`", files.len());
+        for (idx, file) in files.iter().enumerate() {
+            let sidecar_path = self
+                .bucket_strategy
+                .blob_path(dest_dir, &file.file_id)
+                .with_extension("json");
+            let dir = sidecar_path.parent().expect("path should have a parent");
+            fs::create_dir_all(dir).with_context(|| {
+                format!("failed to create directory: {}", dir.to_string_lossy())
+            })?;
+
+            let writer = BufWriter::new(File::create(&sidecar_path).with_context(|| {
+                format!("failed to create file: {}", sidecar_path.to_string_lossy())
+            })?);
+            serde_json::to_writer_pretty(writer, &sidecar(domain, file)).with_context(|| {
+                format!("failed to write file: {}", sidecar_path.to_string_lossy())
+            })?;
+            debug!("wrote {}", sidecar_path.to_string_lossy());
+
+            progress_cb(ProgressEvent::Extracting {
+                extracted: idx + 1,
+                total: files.len(),
+                bytes_written: 0,
+                bytes_done: 0,
+                bytes_total: 0,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+pub(super) fn sidecar(domain: &str, file: &ManifestFile) -> serde_json::Value {
+    json!({
+        "fileID": file.file_id,
+        "domain": domain,
+        "relativePath": file.relative_path,
+        "size": file.size,
+        "mode": file.mode,
+        "userID": file.user_id,
+        "groupID": file.group_id,
+        "inode": file.inode,
+        "lastModified": file.last_modified,
+        "lastStatusChange": file.last_status_change,
+        "birth": file.birth,
+        "protectionClass": file.protection_class,
+        "digest": file.digest.as_deref().map(hex::encode),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::bucket::BucketStrategy;
+    use crate::ctx::Context;
+    use crate::utils::test_fixture::FixtureBackup;
+
+    #[test]
+    fn it_writes_sidecars() {
+        let fixture = FixtureBackup::new();
+        let file_id = fixture.add_file("HomeDomain", "Library/a.plist", b"abc");
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let mut manifest = fixture.manifest();
+        let context = Context::new(
+            fixture.path(),
+            &mut manifest,
+            false,
+            BucketStrategy::default(),
+        );
+        context
+            .write_sidecars("HomeDomain", out_dir.path(), |_| {})
+            .unwrap();
+
+        let sidecar_path = out_dir
+            .path()
+            .join(&file_id[0..2])
+            .join(format!("{file_id}.json"));
+        let sidecar: serde_json::Value =
+            serde_json::from_slice(&fs::read(sidecar_path).unwrap()).unwrap();
+        assert_eq!(sidecar["fileID"], file_id.as_str());
+        assert_eq!(sidecar["relativePath"], "Library/a.plist");
+        assert_eq!(sidecar["size"], 3);
+    }
+}
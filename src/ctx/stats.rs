@@ -0,0 +1,37 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context as AnyhowContext, Result};
+
+use super::{Context, ExtractionSize};
+use crate::db::ManifestFileType;
+use crate::utils::protection_class::ProtectionClass;
+
+impl<'p, 'd> Context<'p, 'd> {
+    /// Counts the regular files of the domains by their protection classes,
+    /// only counting the given classes unless the list is empty.
+    pub fn count_by_protection_class(
+        &self,
+        domains: &[String],
+        protection_classes: &[ProtectionClass],
+    ) -> Result<BTreeMap<ProtectionClass, ExtractionSize>> {
+        let mut counts: BTreeMap<_, ExtractionSize> = BTreeMap::new();
+        for domain in domains {
+            let files = self
+                .query_files(domain)
+                .with_context(|| format!("failed to query files of domain `{domain}`"))?;
+            for file in &files {
+                let class = ProtectionClass::of(file);
+                if file.file_type != ManifestFileType::File
+                    || !(protection_classes.is_empty() || protection_classes.contains(&class))
+                {
+                    continue;
+                }
+
+                let size = counts.entry(class).or_default();
+                size.files += 1;
+                size.bytes += file.size.unwrap_or_default();
+            }
+        }
+        Ok(counts)
+    }
+}
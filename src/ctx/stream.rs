@@ -0,0 +1,256 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as AnyhowContext, Result};
+
+use super::parallel::WriteProgress;
+use super::{
+    check_writable, create_dir, create_symlink, free_space, oversized_files_error, paths_not_found,
+    restore_dir_timestamps, select_file, Context, ExtractOptions, ProgressEvent,
+};
+use crate::db::{ManifestFile, ManifestFileType};
+
+/// Counts of the files a streamed extraction writes, found by a first
+/// pass over the rows.
+#[derive(Default)]
+struct StreamPlan {
+    files: usize,
+    bytes: u64,
+    /// Whether any file is prioritized, which takes a pass of its own.
+    prioritized: bool,
+}
+
+impl<'p, 'd> Context<'p, 'd> {
+    /// Extracts a domain without indexing it, reading the manifest rows
+    /// again for each pass instead of loading them: one to plan the
+    /// extraction, one for the prioritized files if any, one for the rest
+    /// and one for the symbolic links and directories. Only the paths
+    /// written so far are kept, so that a later file of the same path
+    /// replaces the earlier one as it would in the index.
+    pub(super) fn stream_domain<F>(
+        &self,
+        domain: &str,
+        dest_dir: &Path,
+        options: &ExtractOptions,
+        progress_cb: F,
+    ) -> Result<()>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let mut progress_cb = progress_cb;
+
+        if options.since_backup.is_some() {
+            return Err(anyhow!(
+                "incremental extractions need an index, `no_index` can't be used"
+            ));
+        }
+
+        progress_cb(ProgressEvent::Querying);
+        check_writable(dest_dir)?;
+        let plan = self.plan_stream(domain, dest_dir, options, &mut progress_cb)?;
+        info!("streaming {} file(s) without an index", plan.files);
+
+        let mut writer = StreamWriter {
+            created_dirs: HashSet::new(),
+            written_paths: HashSet::new(),
+            progress: WriteProgress {
+                extracted: 0,
+                total: plan.files,
+                bytes_written: 0,
+                bytes_done: 0,
+                bytes_total: plan.bytes,
+            },
+        };
+        progress_cb(ProgressEvent::Extracting {
+            extracted: 0,
+            total: plan.files,
+            bytes_written: 0,
+            bytes_done: 0,
+            bytes_total: plan.bytes,
+        });
+        let passes: &[bool] = if plan.prioritized {
+            &[true, false]
+        } else {
+            &[false]
+        };
+        for prioritized in passes {
+            // The same rows come in the same order, so the files are routed
+            // to the same paths as when planning, where they were reported.
+            let mut routed_paths = HashSet::new();
+            self.manifest.for_each_file(domain, |file| {
+                if !select_file(&file, options, &mut |_| {})? {
+                    return Ok(());
+                }
+                let path = self.route_file(&file, options, &mut routed_paths, &mut |_| {})?;
+                if options.prioritizes(&file) != *prioritized {
+                    return Ok(());
+                }
+                writer.write(self, &dest_dir.join(path), &file, options, &mut progress_cb)
+            })?;
+        }
+
+        // Links go last, as they can't take the place of a file.
+        let mut dirs = vec![];
+        self.manifest.for_each_file(domain, |file| {
+            if options.date_layout.is_some() {
+                return Ok(());
+            }
+            match file.file_type {
+                ManifestFileType::Directory if options.preserve_timestamps => {
+                    if let Some(path) = options.dest_entry_path(&file) {
+                        dirs.push((path, file));
+                    }
+                }
+                ManifestFileType::SymbolicLink if options.includes(&file) => {
+                    let Some(target) = &file.target else {
+                        progress_cb(ProgressEvent::Skipped {
+                            relative_path: file.relative_path.clone(),
+                            reason: "symbolic link without target",
+                        });
+                        return Ok(());
+                    };
+                    if let Some(path) = options.dest_entry_path(&file) {
+                        let link_path = dest_dir.join(&path);
+                        create_dir(link_path.parent().expect("path should have a parent"), true)?;
+                        create_symlink(dest_dir, &path, target, &mut progress_cb)?;
+                    }
+                }
+                _ => {}
+            }
+            Ok(())
+        })?;
+        restore_dir_timestamps(
+            dest_dir,
+            dirs.iter().map(|(path, dir)| (path.as_path(), dir)),
+        )
+    }
+
+    /// Goes through the rows once before writing anything, to report the
+    /// skipped and filtered files and fail on the ones that can't be
+    /// extracted, and to check the room on the destination.
+    fn plan_stream<F>(
+        &self,
+        domain: &str,
+        dest_dir: &Path,
+        options: &ExtractOptions,
+        progress_cb: &mut F,
+    ) -> Result<StreamPlan>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let checks_room = self.checks_room(options);
+        let mut plan = StreamPlan::default();
+        let (mut row_count, mut path_found) = (0, false);
+        let (mut matched, mut total) = (0, 0);
+        let (mut required, mut unsized_count) = (0, 0);
+        let mut oversized_files = vec![];
+        let mut routed_paths = HashSet::new();
+        self.manifest.for_each_file(domain, |file| {
+            row_count += 1;
+            if file.file_type != ManifestFileType::Directory && options.selects_path(&file) {
+                path_found = true;
+            }
+            if file.file_type == ManifestFileType::File {
+                matched += options.includes(&file) as usize;
+                total += 1;
+            }
+            if !select_file(&file, options, progress_cb)? {
+                return Ok(());
+            }
+            let size = file.size.unwrap_or_default();
+            if options.max_file_size.is_some_and(|max| size > max) {
+                oversized_files.push((file.relative_path.clone(), size));
+                return Ok(());
+            }
+            let path = self.route_file(&file, options, &mut routed_paths, progress_cb)?;
+            if checks_room {
+                let (file_required, file_unsized) =
+                    self.required_room(dest_dir, [(path.as_ref(), &file)], options);
+                required += file_required;
+                unsized_count += file_unsized;
+            }
+            plan.files += 1;
+            plan.bytes += size;
+            plan.prioritized |= options.prioritizes(&file);
+            Ok(())
+        })?;
+
+        if row_count == 0 {
+            return Err(anyhow!("domain not found: {domain}"));
+        }
+        if !options.paths.is_empty() && !path_found {
+            return Err(paths_not_found(options));
+        }
+        if options.filters() {
+            progress_cb(ProgressEvent::Filtered { matched, total });
+        }
+        if !oversized_files.is_empty() {
+            return Err(oversized_files_error(&oversized_files));
+        }
+        if checks_room {
+            free_space::check_room(dest_dir, required, unsized_count)?;
+        }
+        Ok(plan)
+    }
+}
+
+/// Writes the streamed files, creating their parent directories on demand.
+struct StreamWriter {
+    created_dirs: HashSet<PathBuf>,
+    /// Destination paths written so far.
+    written_paths: HashSet<PathBuf>,
+    progress: WriteProgress,
+}
+
+impl StreamWriter {
+    fn write<F>(
+        &mut self,
+        context: &Context,
+        dest_file_path: &Path,
+        file: &ManifestFile,
+        options: &ExtractOptions,
+        progress_cb: &mut F,
+    ) -> Result<()>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let dir = dest_file_path.parent().expect("path should have a parent");
+        if !self.created_dirs.contains(dir) {
+            create_dir(dir, true)?;
+            self.created_dirs.insert(dir.to_path_buf());
+        }
+
+        if !self.written_paths.insert(dest_file_path.to_path_buf()) {
+            // The index keeps the last file of the same path, replace the
+            // earlier one to produce the same result.
+            fs::remove_file(dest_file_path).with_context(|| {
+                format!(
+                    "failed to replace file: {}",
+                    dest_file_path.to_string_lossy()
+                )
+            })?;
+        }
+
+        if let Some(free_space_guard) = &options.free_space_guard {
+            if self
+                .progress
+                .extracted
+                .is_multiple_of(free_space::CHECK_INTERVAL)
+            {
+                free_space_guard.check(dir, progress_cb)?;
+            }
+        }
+
+        let res = context
+            .write_file(dest_file_path, file, options)
+            .with_context(|| {
+                format!(
+                    "failed to create file: {}",
+                    dest_file_path.to_string_lossy()
+                )
+            });
+        self.progress
+            .record_result(dest_file_path, file, res, options.keep_going, progress_cb)
+    }
+}
@@ -0,0 +1,118 @@
+use anyhow::{Context as AnyhowContext, Result};
+
+use super::Context;
+
+/// Timestamps earlier than 1980-01-01 are treated as bogus, real files
+/// are rarely that old while zeroed or wrapped clocks produce such values.
+const MIN_PLAUSIBLE_TIMESTAMP: i64 = 315_532_800;
+
+#[derive(Debug)]
+pub struct TimestampAnomaly {
+    pub domain: String,
+    pub relative_path: String,
+    pub field: &'static str,
+    pub timestamp: i64,
+    pub kind: TimestampAnomalyKind,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum TimestampAnomalyKind {
+    /// Later than the reference date (usually when the backup was made).
+    InFuture,
+    /// Implausibly old, e.g. the Unix epoch.
+    TooOld,
+}
+
+impl<'p, 'd> Context<'p, 'd> {
+    /// Scans the file timestamps of the given domains and reports the
+    /// ones later than `reference` or implausibly old.
+    pub fn scan_timestamps(
+        &self,
+        domains: &[String],
+        reference: i64,
+    ) -> Result<Vec<TimestampAnomaly>> {
+        let mut anomalies = vec![];
+        for domain in domains {
+            let files = self
+                .query_files(domain)
+                .context("failed to query files from database")?;
+            for file in files {
+                let fields = [
+                    ("LastModified", file.last_modified),
+                    ("LastStatusChange", file.last_status_change),
+                    ("Birth", file.birth),
+                ];
+                for (field, timestamp) in fields {
+                    let Some(timestamp) = timestamp else {
+                        continue;
+                    };
+                    let kind = if timestamp > reference {
+                        TimestampAnomalyKind::InFuture
+                    } else if timestamp < MIN_PLAUSIBLE_TIMESTAMP {
+                        TimestampAnomalyKind::TooOld
+                    } else {
+                        continue;
+                    };
+
+                    anomalies.push(TimestampAnomaly {
+                        domain: domain.clone(),
+                        relative_path: file.relative_path.clone(),
+                        field,
+                        timestamp,
+                        kind,
+                    });
+                }
+            }
+        }
+        Ok(anomalies)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TimestampAnomalyKind;
+    use crate::utils::test_fixture::FixtureBackup;
+
+    #[test]
+    fn it_scans_timestamps() {
+        let fixture = FixtureBackup::new();
+        // The fixture files are born at 1_600_000_000 and modified at
+        // 1_650_000_000.
+        fixture.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+        fixture.add_file("MediaDomain", "Media/a.jpg", b"jpg");
+
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+        let domains = ["HomeDomain".to_owned(), "MediaDomain".to_owned()];
+        assert!(context
+            .scan_timestamps(&domains, 1_700_000_000)
+            .unwrap()
+            .is_empty());
+
+        let anomalies = context.scan_timestamps(&domains, 1_620_000_000).unwrap();
+        let anomalies: Vec<_> = anomalies
+            .iter()
+            .map(|anomaly| {
+                assert_eq!(anomaly.kind, TimestampAnomalyKind::InFuture);
+                assert_eq!(anomaly.timestamp, 1_650_000_000);
+                (
+                    anomaly.domain.as_str(),
+                    anomaly.relative_path.as_str(),
+                    anomaly.field,
+                )
+            })
+            .collect();
+        assert_eq!(
+            anomalies,
+            [
+                ("HomeDomain", "Library/SMS/sms.db", "LastModified"),
+                ("HomeDomain", "Library/SMS/sms.db", "LastStatusChange"),
+                ("MediaDomain", "Media/a.jpg", "LastModified"),
+                ("MediaDomain", "Media/a.jpg", "LastStatusChange"),
+            ]
+        );
+
+        let anomalies = context.scan_timestamps(&domains, 1_500_000_000).unwrap();
+        assert_eq!(anomalies.len(), 6);
+    }
+}
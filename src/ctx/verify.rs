@@ -0,0 +1,151 @@
+use std::fs;
+use std::io;
+
+use anyhow::{Context as AnyhowContext, Result};
+
+use super::{has_valid_file_id, Context};
+use crate::db::ManifestFileType;
+use crate::mbdb;
+
+/// Size of the AES blocks encrypted blobs are padded to.
+const AES_BLOCK_SIZE: u64 = 16;
+
+#[derive(Debug)]
+pub struct BlobProblem {
+    pub domain: String,
+    pub relative_path: String,
+    pub file_id: String,
+    pub kind: BlobProblemKind,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum BlobProblemKind {
+    /// The blob is not in the backup.
+    Missing,
+    /// The blob is not as large as the manifest says, e.g. truncated.
+    SizeMismatch { expected: u64, actual: u64 },
+    /// The file ID is not the SHA-1 of the domain and relative path.
+    Misnamed,
+}
+
+#[derive(Debug, Default)]
+pub struct BlobVerification {
+    pub files_checked: usize,
+    pub problems: Vec<BlobProblem>,
+}
+
+impl<'p, 'd> Context<'p, 'd> {
+    /// Checks that the blob of every regular file of the given domains
+    /// exists and has the size recorded in the manifest, and with
+    /// `check_ids` that the file IDs match their paths.
+    pub fn verify_blobs(&self, domains: &[String], check_ids: bool) -> Result<BlobVerification> {
+        let mut verification = BlobVerification::default();
+        for domain in domains {
+            let files = self
+                .query_files(domain)
+                .context("failed to query files from database")?;
+            for file in files {
+                if file.file_type != ManifestFileType::File {
+                    continue;
+                }
+                verification.files_checked += 1;
+
+                let mut report = |kind| {
+                    verification.problems.push(BlobProblem {
+                        domain: domain.clone(),
+                        relative_path: file.relative_path.clone(),
+                        file_id: file.file_id.clone(),
+                        kind,
+                    })
+                };
+                // A malformed ID doesn't point to any blob.
+                if !has_valid_file_id(&file) {
+                    report(BlobProblemKind::Misnamed);
+                    continue;
+                }
+                if check_ids && file.file_id != mbdb::file_id(domain, &file.relative_path) {
+                    report(BlobProblemKind::Misnamed);
+                }
+
+                let blob_path = self.original_file_path(&file.file_id);
+                let actual = match fs::metadata(&blob_path) {
+                    Ok(metadata) => metadata.len(),
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                        report(BlobProblemKind::Missing);
+                        continue;
+                    }
+                    Err(err) => {
+                        return Err(err).with_context(|| {
+                            format!("failed to read metadata: {}", blob_path.to_string_lossy())
+                        })
+                    }
+                };
+                let Some(mut expected) = file.size else {
+                    continue;
+                };
+                // Encrypted blobs carry PKCS#7 padding, always at least a
+                // byte of it.
+                if file.encryption_key.is_some() {
+                    expected = (expected / AES_BLOCK_SIZE + 1) * AES_BLOCK_SIZE;
+                }
+                if actual != expected {
+                    report(BlobProblemKind::SizeMismatch { expected, actual });
+                }
+            }
+        }
+        Ok(verification)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::BlobProblemKind;
+    use crate::utils::test_fixture::FixtureBackup;
+
+    #[test]
+    fn it_verifies_blobs() {
+        let fixture = FixtureBackup::new();
+        fixture.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+        fixture.add_encrypted_file("HomeDomain", "Library/Notes/notes.db", b"notes");
+        let missing_id = fixture.add_file("HomeDomain", "Library/missing", b"missing");
+        let truncated_id = fixture.add_file("HomeDomain", "Library/truncated", b"truncated");
+        fixture.add_dir("HomeDomain", "Library");
+        fixture.add_malformed_file("HomeDomain", "Library/broken");
+
+        let blob_path = |file_id: &str| fixture.path().join(&file_id[..2]).join(file_id);
+        fs::remove_file(blob_path(&missing_id)).unwrap();
+        fs::write(blob_path(&truncated_id), b"trunc").unwrap();
+
+        let mut manifest = fixture.manifest();
+        let context = fixture.context(&mut manifest);
+        let domains = ["HomeDomain".to_owned()];
+        let verification = context.verify_blobs(&domains, false).unwrap();
+        assert_eq!(verification.files_checked, 5);
+        let mut problems: Vec<_> = verification
+            .problems
+            .iter()
+            .map(|problem| (problem.relative_path.as_str(), problem.kind))
+            .collect();
+        problems.sort_by_key(|(path, _)| *path);
+        assert_eq!(
+            problems,
+            [
+                ("Library/broken", BlobProblemKind::Misnamed),
+                ("Library/missing", BlobProblemKind::Missing),
+                (
+                    "Library/truncated",
+                    BlobProblemKind::SizeMismatch {
+                        expected: 9,
+                        actual: 5
+                    }
+                ),
+            ]
+        );
+
+        // The fixture doesn't derive its file IDs from the paths.
+        let verification = context.verify_blobs(&domains, true).unwrap();
+        assert_eq!(verification.problems.len(), 7);
+    }
+}
@@ -1,15 +1,89 @@
+use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-use anyhow::{Error as AnyhowError, Result};
+use anyhow::{Context, Error as AnyhowError, Result};
 use fallible_iterator::FallibleIterator;
-use rusqlite::Connection as SqliteConnection;
+use globset::GlobBuilder;
+use rusqlite::{Connection as SqliteConnection, Statement};
+use serde::{Deserialize, Serialize};
 
+use crate::mbdb;
+
+pub const MANIFEST_DB_NAME: &str = "Manifest.db";
+pub const MANIFEST_MBDB_NAME: &str = "Manifest.mbdb";
+
+/// Returns the path of the manifest of a backup, which is `Manifest.mbdb`
+/// in backups made before iOS 10.
+pub fn manifest_path(backup_dir: &Path) -> PathBuf {
+    let mbdb_path = backup_dir.join(MANIFEST_MBDB_NAME);
+    let db_path = backup_dir.join(MANIFEST_DB_NAME);
+    if !db_path.exists() && mbdb_path.exists() {
+        return mbdb_path;
+    }
+    db_path
+}
+
+#[derive(Debug)]
 pub struct BackupManifest {
     db_conn: SqliteConnection,
+    /// Whether it was loaded from `Manifest.mbdb`.
+    legacy: bool,
 }
 
 impl BackupManifest {
+    /// Opens the manifest of a backup in either format.
+    pub fn open_backup(backup_dir: &Path) -> Result<Self> {
+        let path = manifest_path(backup_dir);
+        if path.ends_with(MANIFEST_MBDB_NAME) {
+            return Self::open_mbdb(path);
+        }
+        Self::open(path)
+    }
+
+    /// Loads a `Manifest.mbdb` into an in-memory database with the schema
+    /// of `Manifest.db`.
+    pub fn open_mbdb<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let data = fs::read(path.as_ref())
+            .with_context(|| format!("failed to read: {}", path.as_ref().to_string_lossy()))?;
+        let records = mbdb::parse(&data)?;
+
+        let mut db_conn = SqliteConnection::open_in_memory()?;
+        db_conn.execute_batch(
+            "CREATE TABLE Files (fileID TEXT PRIMARY KEY, domain TEXT, \
+             relativePath TEXT, flags INTEGER, file BLOB);",
+        )?;
+        let tx = db_conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO Files (fileID, domain, relativePath, flags, file) \
+                 VALUES (?, ?, ?, ?, ?)",
+            )?;
+            for record in &records {
+                let Some(flags) = record.flags() else {
+                    continue;
+                };
+                stmt.execute(rusqlite::params![
+                    record.file_id(),
+                    record.domain,
+                    record.relative_path,
+                    flags,
+                    record.file_plist()?,
+                ])?;
+            }
+        }
+        tx.commit()?;
+
+        Ok(Self {
+            db_conn,
+            legacy: true,
+        })
+    }
+
     pub fn open<P>(path: P) -> Result<Self>
     where
         P: AsRef<Path>,
@@ -60,7 +134,16 @@ impl BackupManifest {
             return Err(anyhow!("table schema is not compatible"));
         }
 
-        Ok(Self { db_conn })
+        Ok(Self {
+            db_conn,
+            legacy: false,
+        })
+    }
+
+    /// Whether the backup was made before iOS 10, when the blobs were not
+    /// bucketed yet.
+    pub fn is_legacy(&self) -> bool {
+        self.legacy
     }
 
     pub fn query_domains(&self) -> Result<Vec<String>> {
@@ -71,6 +154,70 @@ impl BackupManifest {
         Ok(rows.map(|r| r.get(0)).collect()?)
     }
 
+    /// Counts the entries of each domain.
+    pub fn query_domain_file_counts(&self) -> Result<Vec<(String, usize)>> {
+        let mut stmt = self
+            .db_conn
+            .prepare("SELECT domain, COUNT(*) FROM files GROUP BY domain")?;
+        let rows = stmt.query([])?;
+        Ok(rows.map(|r| Ok((r.get(0)?, r.get(1)?))).collect()?)
+    }
+
+    /// Sums the recorded sizes of the regular files of each domain, the
+    /// domains with only directories and links have a size of 0, as do the
+    /// files with corrupt metadata.
+    pub fn query_domain_sizes(&self) -> Result<Vec<(String, u64)>> {
+        let mut stmt = self
+            .db_conn
+            .prepare("SELECT domain, flags, file, relativePath FROM files")?;
+        let mut rows = stmt.query([])?;
+        let mut sizes: HashMap<String, u64> = HashMap::new();
+        while let Some(row) = rows.next()? {
+            let size = sizes.entry(row.get(0)?).or_default();
+            let flags: u64 = row.get(1)?;
+            if ManifestFileType::try_from(flags) != Ok(ManifestFileType::File) {
+                continue;
+            }
+            // Only the size is needed, the other fields aren't decoded.
+            let file_buf: Vec<u8> = row.get(2)?;
+            let file_plist: plist::Value = match plist::from_bytes(&file_buf) {
+                Ok(file_plist) => file_plist,
+                Err(err) => {
+                    let relative_path: String = row.get(3)?;
+                    warn!("ignored the corrupt metadata of `{relative_path}`: {err}");
+                    continue;
+                }
+            };
+            *size += ArchivedFile::new(&file_plist)
+                .and_then(|f| f.get("Size"))
+                .and_then(plist::Value::as_unsigned_integer)
+                .unwrap_or_default();
+        }
+        Ok(sizes.into_iter().collect())
+    }
+
+    /// Writes a copy of the database that only contains the files of the
+    /// given domains to `path`, which must not exist yet.
+    pub fn export_domains<P>(&self, path: P, domains: &[String]) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let Some(path_str) = path.as_ref().to_str() else {
+            return Err(anyhow!("unsupported path, not UTF-8 compatible"));
+        };
+        self.db_conn.execute("VACUUM INTO ?", [path_str])?;
+
+        let exported_conn = SqliteConnection::open(path)?;
+        let placeholders = vec!["?"; domains.len()].join(", ");
+        exported_conn.execute(
+            &format!("DELETE FROM files WHERE domain NOT IN ({placeholders})"),
+            rusqlite::params_from_iter(domains),
+        )?;
+        exported_conn.execute("VACUUM", [])?;
+
+        Ok(())
+    }
+
     pub fn query_files(&self, domain: &str) -> Result<Vec<ManifestFile>> {
         let mut stmt = self
             .db_conn
@@ -79,31 +226,402 @@ impl BackupManifest {
         rows.map(|r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)))
             .map_err(AnyhowError::from)
             .map(|(file_id, relative_path, flags, file)| {
-                let file_buf: Vec<u8> = file;
-                // TODO: parse metadata from the plist.
-                let _file_plist: plist::Value = plist::from_bytes(&file_buf)?;
-
-                let flags: u64 = flags;
-                Ok(ManifestFile {
-                    file_id,
-                    relative_path,
-                    file_type: TryFrom::try_from(flags)
-                        .map_err(|_| anyhow!("unknown file type: {flags}"))?,
-                })
+                ManifestFile::from_row(file_id, relative_path, flags, file)
             })
             .collect()
     }
+
+    /// Calls `f` with each file of a domain as its row is read, instead of
+    /// loading them all like `query_files`. The rows come in the same order
+    /// on each call.
+    pub fn for_each_file<F>(&self, domain: &str, f: F) -> Result<()>
+    where
+        F: FnMut(ManifestFile) -> Result<()>,
+    {
+        let mut f = f;
+
+        let mut stmt = self
+            .db_conn
+            .prepare("SELECT fileID, relativePath, flags, file FROM files WHERE domain = ?")?;
+        let mut rows = stmt.query([domain])?;
+        while let Some(row) = rows.next()? {
+            f(ManifestFile::from_row(
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+            )?)?;
+        }
+        Ok(())
+    }
+
+    /// Queries the files of a domain like `query_files`, but keeps going
+    /// past the rows whose metadata can't be decoded.
+    pub fn query_file_metadata(&self, domain: &str) -> Result<Vec<FileMetadataRow>> {
+        let mut stmt = self
+            .db_conn
+            .prepare("SELECT fileID, relativePath, flags, file FROM files WHERE domain = ?")?;
+        let mut rows = stmt.query([domain])?;
+        let mut files = vec![];
+        while let Some(row) = rows.next()? {
+            let (file_id, relative_path): (String, String) = (row.get(0)?, row.get(1)?);
+            let (flags, file_buf): (u64, Vec<u8>) = (row.get(2)?, row.get(3)?);
+            // Unlike `query_files`, a corrupt plist is an error here.
+            let file = plist::from_bytes(&file_buf)
+                .map_err(AnyhowError::from)
+                .and_then(|file_plist| {
+                    ManifestFile::from_plist(
+                        file_id.clone(),
+                        relative_path.clone(),
+                        flags,
+                        Some(&file_plist),
+                    )
+                });
+            files.push(FileMetadataRow {
+                file_id,
+                relative_path,
+                file,
+            });
+        }
+        Ok(files)
+    }
+
+    pub fn has_domain(&self, domain: &str) -> Result<bool> {
+        let mut stmt = self
+            .db_conn
+            .prepare("SELECT 1 FROM files WHERE domain = ? LIMIT 1")?;
+        Ok(stmt.exists([domain])?)
+    }
+
+    /// Compares the regular files of a domain with the same domain in an
+    /// older backup, calls `f` with each added, changed or deleted file.
+    ///
+    /// Files are matched by their relative paths and compared by their IDs,
+    /// sizes and modification times. Both sides are streamed in the order
+    /// of their relative paths instead of being loaded up front.
+    pub fn diff_files<F>(&self, old: &BackupManifest, domain: &str, f: F) -> Result<()>
+    where
+        F: FnMut(FileChange) -> Result<()>,
+    {
+        let mut f = f;
+
+        let mut new_stmt = self.db_conn.prepare(SORTED_FILES_QUERY)?;
+        let mut old_stmt = old.db_conn.prepare(SORTED_FILES_QUERY)?;
+        let mut new_files = query_sorted_files(&mut new_stmt, domain)?.peekable();
+        let mut old_files = query_sorted_files(&mut old_stmt, domain)?.peekable();
+
+        loop {
+            // SQLite sorts text by its bytes, just like `str`.
+            let order = match (new_files.peek()?, old_files.peek()?) {
+                (None, None) => return Ok(()),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(new), Some(old)) => new.relative_path.cmp(&old.relative_path),
+            };
+            match order {
+                Ordering::Less => {
+                    let new = new_files.next()?.expect("file should be peeked");
+                    f(FileChange::Added(new))?;
+                }
+                Ordering::Greater => {
+                    let old = old_files.next()?.expect("file should be peeked");
+                    f(FileChange::Deleted(old))?;
+                }
+                Ordering::Equal => {
+                    let new = new_files.next()?.expect("file should be peeked");
+                    let old = old_files.next()?.expect("file should be peeked");
+                    if new.file_id != old.file_id
+                        || new.size != old.size
+                        || new.last_modified != old.last_modified
+                    {
+                        f(FileChange::Changed(new))?;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn query_file(&self, domain: &str, relative_path: &str) -> Result<Option<ManifestFile>> {
+        let mut stmt = self.db_conn.prepare(
+            "SELECT fileID, relativePath, flags, file FROM files \
+             WHERE domain = ? AND relativePath = ?",
+        )?;
+        let mut rows = stmt.query([domain, relative_path])?;
+        let Some(row) = rows.next()? else {
+            return Ok(None);
+        };
+        let file = ManifestFile::from_row(row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)?;
+        Ok(Some(file))
+    }
+
+    /// Looks up a file by its ID, returns its domain along with the file.
+    pub fn query_file_by_id(&self, file_id: &str) -> Result<Option<(String, ManifestFile)>> {
+        let mut stmt = self.db_conn.prepare(
+            "SELECT fileID, relativePath, flags, file, domain FROM files WHERE fileID = ?",
+        )?;
+        let mut rows = stmt.query([file_id])?;
+        let Some(row) = rows.next()? else {
+            return Ok(None);
+        };
+        let file = ManifestFile::from_row(row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)?;
+        Ok(Some((row.get(4)?, file)))
+    }
+
+    /// Looks up the files whose IDs start with the prefix, returns at most
+    /// `limit` of them along with their domains.
+    pub fn query_files_by_id_prefix(
+        &self,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, ManifestFile)>> {
+        let mut stmt = self.db_conn.prepare(
+            "SELECT fileID, relativePath, flags, file, domain FROM files \
+             WHERE substr(fileID, 1, length(?1)) = ?1 LIMIT ?2",
+        )?;
+        let mut rows = stmt.query(rusqlite::params![prefix, limit])?;
+        let mut files = vec![];
+        while let Some(row) = rows.next()? {
+            let file = ManifestFile::from_row(row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)?;
+            files.push((row.get(4)?, file));
+        }
+        Ok(files)
+    }
+
+    /// Finds the entries of all the domains whose relative paths contain
+    /// `pattern`, or match it if it's a glob pattern. Their metadata isn't
+    /// decoded.
+    pub fn search(&self, pattern: &str, ignore_case: bool) -> Result<Vec<PathMatch>> {
+        let to_match = |r: &rusqlite::Row| {
+            Ok(PathMatch {
+                domain: r.get(0)?,
+                relative_path: r.get(1)?,
+                file_id: r.get(2)?,
+            })
+        };
+        if crate::domains::is_pattern(pattern) {
+            let matcher = GlobBuilder::new(pattern)
+                .case_insensitive(ignore_case)
+                .build()
+                .context("invalid glob pattern")?
+                .compile_matcher();
+            let mut stmt = self.db_conn.prepare(
+                "SELECT domain, relativePath, fileID FROM files ORDER BY domain, relativePath",
+            )?;
+            let rows = stmt.query([])?;
+            return Ok(rows
+                .map(to_match)
+                .filter(|m| Ok(matcher.is_match(&m.relative_path)))
+                .collect()?);
+        }
+
+        // `LIKE` only folds the case of ASCII letters.
+        let condition = if ignore_case {
+            "relativePath LIKE '%' || ?1 || '%' ESCAPE '\\'"
+        } else {
+            "instr(relativePath, ?1) > 0"
+        };
+        let pattern = if ignore_case {
+            escape_like(pattern)
+        } else {
+            pattern.to_owned()
+        };
+        let mut stmt = self.db_conn.prepare(&format!(
+            "SELECT domain, relativePath, fileID FROM files WHERE {condition} \
+             ORDER BY domain, relativePath"
+        ))?;
+        let rows = stmt.query([pattern])?;
+        Ok(rows.map(to_match).collect()?)
+    }
+
+    /// Returns the undecoded metadata plist of a file.
+    pub fn query_file_plist(&self, file_id: &str) -> Result<plist::Value> {
+        let file_buf: Vec<u8> =
+            self.db_conn
+                .query_row("SELECT file FROM files WHERE fileID = ?", [file_id], |r| {
+                    r.get(0)
+                })?;
+        Ok(plist::from_bytes(&file_buf)?)
+    }
 }
 
-#[readonly::make]
+const SORTED_FILES_QUERY: &str = "SELECT fileID, relativePath, flags, file FROM files \
+     WHERE domain = ? AND flags = 1 ORDER BY relativePath";
+
+fn query_sorted_files<'s>(
+    stmt: &'s mut Statement,
+    domain: &str,
+) -> Result<impl FallibleIterator<Item = ManifestFile, Error = AnyhowError> + 's> {
+    let rows = stmt.query([domain])?;
+    Ok(rows
+        .map(|r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)))
+        .map_err(AnyhowError::from)
+        .map(|(file_id, relative_path, flags, file)| {
+            ManifestFile::from_row(file_id, relative_path, flags, file)
+        }))
+}
+
+/// A regular file that differs between two backups.
 #[derive(Debug)]
+pub enum FileChange {
+    Added(ManifestFile),
+    Changed(ManifestFile),
+    Deleted(ManifestFile),
+}
+
+#[readonly::make]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct ManifestFile {
     pub file_id: String,
     pub relative_path: String,
     pub file_type: ManifestFileType,
+    /// Logical size of the file, if recorded in the metadata.
+    pub size: Option<u64>,
+    /// Timestamps (in seconds since the Unix epoch) recorded in the metadata.
+    pub last_modified: Option<i64>,
+    pub last_status_change: Option<i64>,
+    pub birth: Option<i64>,
+    /// POSIX mode, including the file type bits.
+    pub mode: Option<u32>,
+    pub user_id: Option<u32>,
+    pub group_id: Option<u32>,
+    pub inode: Option<u64>,
+    pub protection_class: Option<u64>,
+    pub digest: Option<Vec<u8>>,
+    /// Target of a symbolic link.
+    pub target: Option<String>,
+    /// Wrapped key of the blob in encrypted backups, prefixed by its
+    /// protection class.
+    pub encryption_key: Option<Vec<u8>>,
+}
+
+impl ManifestFile {
+    /// Decodes a row, a corrupt metadata plist only leaves the metadata
+    /// out so that it doesn't fail the whole query.
+    fn from_row(
+        file_id: String,
+        relative_path: String,
+        flags: u64,
+        file_buf: Vec<u8>,
+    ) -> Result<Self> {
+        let file_plist = match plist::from_bytes(&file_buf) {
+            Ok(file_plist) => Some(file_plist),
+            Err(err) => {
+                warn!("ignored the corrupt metadata of `{relative_path}`: {err}");
+                None
+            }
+        };
+        Self::from_plist(file_id, relative_path, flags, file_plist.as_ref())
+    }
+
+    fn from_plist(
+        file_id: String,
+        relative_path: String,
+        flags: u64,
+        file_plist: Option<&plist::Value>,
+    ) -> Result<Self> {
+        let archived_file = file_plist.and_then(ArchivedFile::new);
+        let field = |key| archived_file.as_ref().and_then(|f| f.get(key));
+        let int_field = |key| field(key).and_then(plist::Value::as_signed_integer);
+
+        Ok(ManifestFile {
+            file_id,
+            relative_path,
+            file_type: TryFrom::try_from(flags)
+                .map_err(|_| anyhow!("unknown file type: {flags}"))?,
+            size: field("Size").and_then(plist::Value::as_unsigned_integer),
+            last_modified: int_field("LastModified"),
+            last_status_change: int_field("LastStatusChange"),
+            birth: int_field("Birth"),
+            mode: int_field("Mode").map(|m| m as u32),
+            user_id: int_field("UserID").map(|id| id as u32),
+            group_id: int_field("GroupID").map(|id| id as u32),
+            inode: field("InodeNumber").and_then(plist::Value::as_unsigned_integer),
+            protection_class: field("ProtectionClass").and_then(plist::Value::as_unsigned_integer),
+            digest: field("Digest").and_then(archived_data),
+            target: field("Target")
+                .and_then(plist::Value::as_string)
+                .map(str::to_owned),
+            encryption_key: field("EncryptionKey").and_then(archived_data),
+        })
+    }
+}
+
+/// The root `MBFile` object of a keyed archive, or the plain dictionary
+/// of the fields.
+struct ArchivedFile<'a> {
+    objects: &'a [plist::Value],
+    root: &'a plist::Dictionary,
+}
+
+impl<'a> ArchivedFile<'a> {
+    fn new(file_plist: &'a plist::Value) -> Option<Self> {
+        let archive = file_plist.as_dictionary()?;
+        let Some(objects) = archive.get("$objects") else {
+            // Some backups store the fields in a plain dictionary instead.
+            return Some(Self {
+                objects: &[],
+                root: archive,
+            });
+        };
+        let objects = objects.as_array()?;
+        let root_uid = archive
+            .get("$top")?
+            .as_dictionary()?
+            .get("root")?
+            .as_uid()?
+            .get();
+        let root = objects.get(root_uid as usize)?.as_dictionary()?;
+        Some(Self { objects, root })
+    }
+
+    /// Returns the value of a field, following the object references.
+    fn get(&self, key: &str) -> Option<&'a plist::Value> {
+        match self.root.get(key)? {
+            plist::Value::Uid(uid) => self.objects.get(uid.get() as usize),
+            value => Some(value),
+        }
+    }
+}
+
+/// Reads raw bytes, either stored directly or wrapped in an archived
+/// `NSData`/`NSMutableData` object.
+fn archived_data(value: &plist::Value) -> Option<Vec<u8>> {
+    match value {
+        plist::Value::Data(data) => Some(data.clone()),
+        plist::Value::Dictionary(dict) => dict.get("NS.data")?.as_data().map(<[u8]>::to_vec),
+        _ => None,
+    }
+}
+
+/// A row of `BackupManifest::query_file_metadata`.
+#[derive(Debug)]
+pub struct FileMetadataRow {
+    pub file_id: String,
+    pub relative_path: String,
+    /// The decoded metadata, or why it couldn't be decoded.
+    pub file: Result<ManifestFile>,
+}
+
+/// An entry found by `BackupManifest::search`.
+#[derive(Debug)]
+pub struct PathMatch {
+    pub domain: String,
+    pub relative_path: String,
+    pub file_id: String,
+}
+
+fn escape_like(pattern: &str) -> String {
+    let mut escaped = String::with_capacity(pattern.len());
+    for c in pattern.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
 }
 
-#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
 pub enum ManifestFileType {
     File,
     Directory,
@@ -122,3 +640,183 @@ impl TryFrom<u64> for ManifestFileType {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{BackupManifest, ManifestFile, ManifestFileType};
+    use crate::mbdb::test_support::{mbdb, record};
+    use crate::utils::test_fixture::FixtureBackup;
+
+    #[test]
+    fn it_decodes_keyed_archive() {
+        let file_buf = include_bytes!("../tests/fixtures/mbfile-keyed.bplist").to_vec();
+        let file = ManifestFile::from_row(
+            "0123456789abcdef0123456789abcdef01234567".to_owned(),
+            "Library/Caches/link".to_owned(),
+            4,
+            file_buf,
+        )
+        .unwrap();
+
+        assert_eq!(file.file_type, ManifestFileType::SymbolicLink);
+        assert_eq!(file.size, Some(0));
+        assert_eq!(file.mode, Some(0o120755));
+        assert_eq!((file.user_id, file.group_id), (Some(501), Some(501)));
+        assert_eq!(file.inode, Some(98765));
+        assert_eq!(file.protection_class, Some(4));
+        assert_eq!(file.birth, Some(1_600_000_000));
+        assert_eq!(file.last_modified, Some(1_650_000_000));
+        assert_eq!(file.last_status_change, Some(1_650_000_100));
+        assert_eq!(
+            file.target.as_deref(),
+            Some("/private/var/mobile/Library/target")
+        );
+    }
+
+    #[test]
+    fn it_decodes_plain_dictionary() {
+        let file_buf = include_bytes!("../tests/fixtures/mbfile-flat.plist").to_vec();
+        let file = ManifestFile::from_row(
+            "0123456789abcdef0123456789abcdef01234567".to_owned(),
+            "Library/Preferences/a.plist".to_owned(),
+            1,
+            file_buf,
+        )
+        .unwrap();
+
+        assert_eq!(file.file_type, ManifestFileType::File);
+        assert_eq!(file.size, Some(1234));
+        assert_eq!(file.mode, Some(0o100644));
+        assert_eq!(file.protection_class, Some(3));
+        assert_eq!(file.last_modified, Some(1_550_000_000));
+        assert_eq!(file.target, None);
+    }
+
+    #[test]
+    fn it_opens_legacy_manifest() {
+        let backup_dir = tempfile::tempdir().unwrap();
+        let data = mbdb(&[
+            record("HomeDomain", "Library", None),
+            record("HomeDomain", "Library/a.plist", Some(b"abc")),
+            record("MediaDomain", "Media/b.jpg", Some(b"b")),
+        ]);
+        fs::write(backup_dir.path().join("Manifest.mbdb"), data).unwrap();
+
+        let manifest = BackupManifest::open_backup(backup_dir.path()).unwrap();
+        assert!(manifest.is_legacy());
+        assert_eq!(
+            manifest.query_domains().unwrap(),
+            ["HomeDomain", "MediaDomain"]
+        );
+        assert_eq!(
+            manifest.query_domain_file_counts().unwrap(),
+            [("HomeDomain".to_owned(), 2), ("MediaDomain".to_owned(), 1)]
+        );
+
+        let files = manifest.query_files("HomeDomain").unwrap();
+        assert_eq!(files.len(), 2);
+        let file = files
+            .iter()
+            .find(|f| f.relative_path == "Library/a.plist")
+            .unwrap();
+        assert_eq!(file.file_type, ManifestFileType::File);
+        assert_eq!(file.size, Some(3));
+        assert_eq!(file.mode, Some(0o100644));
+        assert_eq!(file.last_modified, Some(1_400_000_000));
+    }
+
+    #[test]
+    fn it_searches_relative_paths() {
+        let backup = FixtureBackup::new();
+        backup.add_file("HomeDomain", "Library/SMS/sms.db", b"a");
+        backup.add_file("HomeDomain", "Library/SMS/sms.db-wal", b"b");
+        backup.add_file("AppDomain-com.example", "Documents/100%_SMS.DB", b"c");
+        let manifest = backup.manifest();
+
+        let paths = |pattern, ignore_case| -> Vec<_> {
+            manifest
+                .search(pattern, ignore_case)
+                .unwrap()
+                .into_iter()
+                .map(|m| format!("{}/{}", m.domain, m.relative_path))
+                .collect()
+        };
+        assert_eq!(
+            paths("sms.db", false),
+            [
+                "HomeDomain/Library/SMS/sms.db",
+                "HomeDomain/Library/SMS/sms.db-wal"
+            ]
+        );
+        assert_eq!(
+            paths("sms.db", true),
+            [
+                "AppDomain-com.example/Documents/100%_SMS.DB",
+                "HomeDomain/Library/SMS/sms.db",
+                "HomeDomain/Library/SMS/sms.db-wal"
+            ]
+        );
+        assert_eq!(paths("*.db", false), ["HomeDomain/Library/SMS/sms.db"]);
+        assert_eq!(paths("0%_", true).len(), 1);
+        assert!(paths("0%_s", false).is_empty());
+        assert!(paths("SMS/%", true).is_empty());
+    }
+
+    #[test]
+    fn it_sums_domain_sizes() {
+        let backup = FixtureBackup::new();
+        backup.add_file("HomeDomain", "Library/a.txt", b"abc");
+        backup.add_file("HomeDomain", "Library/b.txt", b"de");
+        backup.add_dir("HomeDomain", "Library");
+        backup.add_dir("EmptyDomain", "Library");
+        backup.add_symlink("EmptyDomain", "Library/link", "a.txt");
+
+        let corrupt_id = backup.add_file("HomeDomain", "Library/c.txt", b"corrupt");
+        rusqlite::Connection::open(backup.path().join("Manifest.db"))
+            .unwrap()
+            .execute(
+                "UPDATE Files SET file = x'00' WHERE fileID = ?",
+                [&corrupt_id],
+            )
+            .unwrap();
+
+        let mut sizes = backup.manifest().query_domain_sizes().unwrap();
+        sizes.sort();
+        assert_eq!(
+            sizes,
+            [("EmptyDomain".to_owned(), 0), ("HomeDomain".to_owned(), 5)]
+        );
+    }
+
+    #[test]
+    fn it_queries_files_past_corrupt_metadata() {
+        let backup = FixtureBackup::new();
+        let mut corrupt_id = String::new();
+        for idx in 0..1000 {
+            let file_id = backup.add_file("HomeDomain", &format!("Library/{idx}.txt"), b"abc");
+            if idx == 500 {
+                corrupt_id = file_id;
+            }
+        }
+        rusqlite::Connection::open(backup.path().join("Manifest.db"))
+            .unwrap()
+            .execute(
+                "UPDATE Files SET file = x'00' WHERE fileID = ?",
+                [&corrupt_id],
+            )
+            .unwrap();
+
+        let files = backup.manifest().query_files("HomeDomain").unwrap();
+        assert_eq!(files.len(), 1000);
+        for file in files {
+            assert_eq!(file.file_type, ManifestFileType::File);
+            if file.file_id == corrupt_id {
+                assert_eq!((file.size, file.mode), (None, None));
+            } else {
+                assert_eq!(file.size, Some(3));
+            }
+        }
+    }
+}
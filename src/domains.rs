@@ -0,0 +1,293 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use globset::Glob;
+use serde::Serialize;
+
+/// Known on-device roots of the domains whose names are fixed.
+const FIXED_DOMAIN_ROOTS: &[(&str, &str)] = &[
+    ("HomeDomain", "/var/mobile"),
+    ("CameraRollDomain", "/var/mobile"),
+    ("MediaDomain", "/var/mobile"),
+    ("TonesDomain", "/var/mobile"),
+    ("BooksDomain", "/var/mobile/Media/Books"),
+    ("HealthDomain", "/var/mobile/Library"),
+    ("KeychainDomain", "/var/Keychains"),
+    ("RootDomain", "/var/root"),
+    ("SystemPreferencesDomain", "/var/preferences"),
+    ("ManagedPreferencesDomain", "/var/Managed Preferences"),
+    ("WirelessDomain", "/var/wireless"),
+    ("MobileDeviceDomain", "/var/MobileDevice"),
+    ("DatabaseDomain", "/var/db"),
+    ("InstallDomain", "/var/installd"),
+    ("NetworkDomain", "/var/networkd"),
+    ("ProtectedDomain", "/var/protected"),
+];
+
+/// Known on-device roots of the domains named `<prefix>-<identifier>`.
+const CONTAINER_DOMAIN_ROOTS: &[(&str, &str)] = &[
+    ("AppDomain", "/var/mobile/Containers/Data/Application"),
+    ("AppDomainGroup", "/var/mobile/Containers/Shared/AppGroup"),
+    (
+        "AppDomainPlugin",
+        "/var/mobile/Containers/Data/PluginKitPlugin",
+    ),
+    ("SysContainerDomain", "/var/containers/Data/System"),
+    (
+        "SysSharedContainerDomain",
+        "/var/containers/Shared/SystemGroup",
+    ),
+];
+
+/// Returns the directory on the device that the relative paths of the
+/// domain are based on, or `None` if the domain is unknown.
+///
+/// Containers are keyed by random UUIDs on the device, the identifier
+/// from the domain name is used in their place.
+pub fn device_root(domain: &str) -> Option<String> {
+    if let Some((_, root)) = FIXED_DOMAIN_ROOTS.iter().find(|(d, _)| *d == domain) {
+        return Some((*root).to_owned());
+    }
+
+    let (prefix, identifier) = domain.split_once('-')?;
+    let (_, root) = CONTAINER_DOMAIN_ROOTS.iter().find(|(p, _)| *p == prefix)?;
+    Some(format!("{root}/{identifier}"))
+}
+
+/// A domain as listed by `list-domains --format json`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DomainListing {
+    pub name: String,
+    /// Entries of the domain, including directories and links.
+    pub file_count: usize,
+    /// Known on-device root, only with `--with-paths`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root: Option<String>,
+    /// Total size of the regular files, only with `--sizes`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+/// Returns the domains of an app and where they go under the destination,
+/// mirroring the containers on the device: `Container`,
+/// `GroupContainers/<group>` and `PlugIns/<plugin>`.
+///
+/// Groups and plugins are found by name, their identifiers contain the
+/// bundle identifier of the app.
+pub fn app_domains(bundle_id: &str, domains: &[String]) -> Result<Vec<(String, PathBuf)>> {
+    let container = format!("AppDomain-{bundle_id}");
+    if !domains.contains(&container) {
+        let mut app_ids: Vec<_> = domains
+            .iter()
+            .filter_map(|domain| domain.strip_prefix("AppDomain-"))
+            .collect();
+        app_ids.sort_unstable();
+        return Err(anyhow!(
+            "app `{bundle_id}` is not in the backup, the apps are:\n  {}",
+            app_ids.join("\n  ")
+        ));
+    }
+
+    let mut app_domains = vec![(container, PathBuf::from("Container"))];
+    for domain in domains {
+        let (dir, identifier) = if let Some(group) = domain.strip_prefix("AppDomainGroup-") {
+            ("GroupContainers", group)
+        } else if let Some(plugin) = domain.strip_prefix("AppDomainPlugin-") {
+            ("PlugIns", plugin)
+        } else {
+            continue;
+        };
+        let belongs = identifier
+            .match_indices(bundle_id)
+            .any(|(start, _)| is_word_boundary(identifier, start, bundle_id.len()));
+        if belongs {
+            app_domains.push((
+                domain.clone(),
+                Path::new(dir).join(path_component(identifier)),
+            ));
+        }
+    }
+    Ok(app_domains)
+}
+
+/// Whether the identifier has dots or ends around the range, so that
+/// `com.foo.bar` doesn't claim `com.foo.barber`.
+fn is_word_boundary(identifier: &str, start: usize, len: usize) -> bool {
+    let end = start + len;
+    (start == 0 || identifier[..start].ends_with('.'))
+        && (end == identifier.len() || identifier[end..].starts_with('.'))
+}
+
+/// Makes a value fit in a single path component, which also can't be empty
+/// or refer to the current or parent directory.
+pub fn path_component(value: &str) -> String {
+    match value {
+        "" => "_".to_owned(),
+        "." | ".." => value.replace('.', "_"),
+        _ => value.replace(['/', '\\', ':'], "_"),
+    }
+}
+
+/// Whether a domain argument is a glob pattern rather than a domain name.
+pub fn is_pattern(domain: &str) -> bool {
+    domain.contains(['*', '?', '[', '{'])
+}
+
+/// Returns the domains matching a glob pattern, failing with the closest
+/// domains if none does.
+pub fn expand_pattern(pattern: &str, domains: &[String]) -> Result<Vec<String>> {
+    let matcher = Glob::new(pattern)
+        .with_context(|| format!("invalid domain pattern: {pattern}"))?
+        .compile_matcher();
+    let matched: Vec<_> = domains
+        .iter()
+        .filter(|domain| matcher.is_match(domain))
+        .cloned()
+        .collect();
+    if !matched.is_empty() {
+        return Ok(matched);
+    }
+
+    let candidates = close_domains(pattern, domains);
+    if candidates.is_empty() {
+        return Err(anyhow!("no domain matches `{pattern}`"));
+    }
+    Err(anyhow!(
+        "no domain matches `{pattern}`, close ones are:\n  {}",
+        candidates.join("\n  ")
+    ))
+}
+
+/// Ranks the domains by how many words of the pattern they contain,
+/// ignoring case.
+fn close_domains<'a>(pattern: &str, domains: &'a [String]) -> Vec<&'a str> {
+    const MAX_CANDIDATES: usize = 5;
+
+    let words: Vec<_> = pattern
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    let mut scored: Vec<_> = domains
+        .iter()
+        .map(|domain| {
+            let domain_lower = domain.to_lowercase();
+            let score = words
+                .iter()
+                .filter(|word| domain_lower.contains(word.as_str()))
+                .count();
+            (score, domain.as_str())
+        })
+        .filter(|(score, _)| *score > 0)
+        .collect();
+    scored.sort_by(|(a, _), (b, _)| b.cmp(a));
+    scored
+        .into_iter()
+        .take(MAX_CANDIDATES)
+        .map(|(_, domain)| domain)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{app_domains, device_root, expand_pattern, is_pattern, path_component};
+
+    #[test]
+    fn it_knows_device_roots() {
+        assert_eq!(device_root("HomeDomain").as_deref(), Some("/var/mobile"));
+        assert_eq!(
+            device_root("AppDomain-com.foo.bar").as_deref(),
+            Some("/var/mobile/Containers/Data/Application/com.foo.bar")
+        );
+        assert_eq!(
+            device_root("AppDomainGroup-group.com.foo.bar").as_deref(),
+            Some("/var/mobile/Containers/Shared/AppGroup/group.com.foo.bar")
+        );
+        assert_eq!(device_root("UnknownDomain"), None);
+        assert_eq!(device_root("UnknownDomain-com.foo.bar"), None);
+    }
+
+    #[test]
+    fn it_expands_domain_patterns() {
+        let domains: Vec<_> = [
+            "AppDomain-com.foo.bar",
+            "AppDomainGroup-group.com.foo.bar",
+            "AppDomainPlugin-com.foo.bar.extension",
+            "AppDomain-com.foo.baz",
+            "HomeDomain",
+        ]
+        .map(str::to_owned)
+        .into();
+
+        assert!(is_pattern("*com.foo.bar*"));
+        assert!(!is_pattern("AppDomain-com.foo.bar"));
+        assert_eq!(
+            expand_pattern("*com.foo.bar*", &domains).unwrap(),
+            &domains[0..3]
+        );
+
+        let err = expand_pattern("*com.foo.qux*", &domains).unwrap_err();
+        assert!(err.to_string().contains("AppDomain-com.foo.baz"));
+        assert!(!err.to_string().contains("HomeDomain"));
+    }
+
+    #[test]
+    fn it_makes_single_path_components() {
+        assert_eq!(path_component("HomeDomain"), "HomeDomain");
+        assert_eq!(path_component("a/b\\c:d"), "a_b_c_d");
+        assert_eq!(path_component(""), "_");
+        assert_eq!(path_component("."), "_");
+        assert_eq!(path_component(".."), "__");
+        assert_eq!(path_component("..."), "...");
+    }
+
+    #[test]
+    fn it_keeps_app_domains_in_their_directories() {
+        let domains: Vec<_> = [
+            "AppDomain-com.foo.bar",
+            "AppDomainGroup-com.foo.bar./../../x",
+        ]
+        .map(str::to_owned)
+        .into();
+
+        assert_eq!(
+            app_domains("com.foo.bar", &domains).unwrap()[1].1,
+            PathBuf::from("GroupContainers/com.foo.bar._.._.._x")
+        );
+    }
+
+    #[test]
+    fn it_finds_app_domains() {
+        let domains: Vec<_> = [
+            "AppDomain-net.whatsapp.WhatsApp",
+            "AppDomainGroup-group.net.whatsapp.WhatsApp.shared",
+            "AppDomainPlugin-net.whatsapp.WhatsApp.ShareExtension",
+            "AppDomain-net.whatsapp.WhatsAppBusiness",
+            "AppDomainPlugin-net.whatsapp.WhatsAppBusiness.ShareExtension",
+            "HomeDomain",
+        ]
+        .map(str::to_owned)
+        .into();
+
+        assert_eq!(
+            app_domains("net.whatsapp.WhatsApp", &domains).unwrap(),
+            [
+                (domains[0].clone(), PathBuf::from("Container")),
+                (
+                    domains[1].clone(),
+                    PathBuf::from("GroupContainers/group.net.whatsapp.WhatsApp.shared")
+                ),
+                (
+                    domains[2].clone(),
+                    PathBuf::from("PlugIns/net.whatsapp.WhatsApp.ShareExtension")
+                ),
+            ]
+        );
+
+        let err = app_domains("com.example.missing", &domains).unwrap_err();
+        assert!(err.to_string().contains("net.whatsapp.WhatsAppBusiness"));
+    }
+}
@@ -0,0 +1,126 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::utils::timestamp::{format_timestamp, unix_timestamp};
+
+/// A file of the extraction manifest.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestEntry<'a> {
+    /// Path of the extracted file, relative to the destination directory.
+    pub path: &'a str,
+    /// Path of the file in its domain.
+    pub relative_path: &'a str,
+    #[serde(rename = "fileID")]
+    pub file_id: &'a str,
+    pub size: u64,
+}
+
+/// Writes the JSON manifest of an extraction, written by
+/// `--write-manifest`, as the files are extracted instead of holding them
+/// in memory.
+pub struct ExtractManifestWriter {
+    writer: BufWriter<File>,
+    entry_count: usize,
+    /// The first error of adding a file, returned by `finish`.
+    error: Option<anyhow::Error>,
+}
+
+impl ExtractManifestWriter {
+    pub fn create(path: &Path, domain: &str) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("failed to create file: {}", path.to_string_lossy()))?;
+        let mut writer = BufWriter::new(file);
+        let created_at = format_timestamp(unix_timestamp(SystemTime::now()));
+        write!(
+            writer,
+            "{{\n  \"domain\": {},\n  \"createdAt\": {},\n  \"files\": [",
+            serde_json::to_string(domain)?,
+            serde_json::to_string(&created_at)?
+        )
+        .context("failed to write manifest")?;
+
+        Ok(Self {
+            writer,
+            entry_count: 0,
+            error: None,
+        })
+    }
+
+    /// Adds a file, an error is kept until `finish`, so that the extraction
+    /// isn't interrupted.
+    pub fn add(&mut self, entry: &ManifestEntry) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(err) = self.write_entry(entry) {
+            self.error = Some(err);
+        }
+    }
+
+    fn write_entry(&mut self, entry: &ManifestEntry) -> Result<()> {
+        let separator = if self.entry_count == 0 { "" } else { "," };
+        write!(self.writer, "{separator}\n    ")?;
+        serde_json::to_writer(&mut self.writer, entry)?;
+        self.entry_count += 1;
+        Ok(())
+    }
+
+    /// Closes the manifest, which is valid JSON even if the extraction
+    /// stopped early.
+    pub fn finish(mut self) -> Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err.context("failed to write manifest"));
+        }
+        let indent = if self.entry_count == 0 { "" } else { "\n  " };
+        write!(self.writer, "{indent}]\n}}\n").context("failed to write manifest")?;
+        self.writer.flush().context("failed to write manifest")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{ExtractManifestWriter, ManifestEntry};
+
+    #[test]
+    fn it_writes_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("manifest.json");
+
+        for entry_count in [0, 2] {
+            let mut writer = ExtractManifestWriter::create(&path, "HomeDomain").unwrap();
+            for idx in 0..entry_count {
+                writer.add(&ManifestEntry {
+                    path: &format!("{idx}.txt"),
+                    relative_path: &format!("Library/{idx}.txt"),
+                    file_id: "0123456789abcdef0123456789abcdef01234567",
+                    size: idx,
+                });
+            }
+            writer.finish().unwrap();
+
+            let manifest: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+            assert_eq!(manifest["domain"], "HomeDomain");
+            assert!(manifest["createdAt"].is_string());
+            let files = manifest["files"].as_array().unwrap();
+            assert_eq!(files.len(), entry_count as usize);
+            if entry_count > 0 {
+                assert_eq!(files[1]["path"], "1.txt");
+                assert_eq!(files[1]["relativePath"], "Library/1.txt");
+                assert_eq!(
+                    files[1]["fileID"],
+                    "0123456789abcdef0123456789abcdef01234567"
+                );
+                assert_eq!(files[1]["size"], 1);
+            }
+        }
+    }
+}
@@ -1,11 +1,25 @@
 use std::collections::{hash_map, HashMap};
 use std::path::{Component as PathComponent, Path};
 use std::result::Result as StdResult;
+use std::{fmt, mem};
 
 use anyhow::Result;
 
 use crate::utils::string_pool::*;
 
+/// Error of adding an entry whose path is already taken by another one,
+/// retrieved with `anyhow::Error::downcast_ref`.
+#[derive(Debug)]
+pub struct PathTakenError(pub String);
+
+impl fmt::Display for PathTakenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "path `{}` already exists", self.0)
+    }
+}
+
+impl std::error::Error for PathTakenError {}
+
 #[derive(Debug)]
 pub struct FileSystemIndex<'p> {
     entries: HashMap<u64, Entry<'p>>,
@@ -38,46 +52,211 @@ impl<'p> FileSystemIndex<'p> {
     where
         F: FnMut(&str, &str) -> StdResult<(), E>,
     {
-        fn recursively_walk<'p, F, E>(
-            entries: &HashMap<u64, Entry<'p>>,
-            current_entry: &Entry<'p>,
-            current_path: &str,
-            f: &mut F,
-        ) -> StdResult<(), E>
-        where
-            F: FnMut(&str, &str) -> StdResult<(), E>,
-        {
+        let mut f = f;
+        self.walk_leaves(|path, entry_type| match entry_type {
+            EntryType::File { file_id } => f(path, file_id),
+            _ => Ok(()),
+        })
+    }
+
+    /// Walks the symbolic links with their targets.
+    pub fn walk_symlinks<F, E>(&self, f: F) -> StdResult<(), E>
+    where
+        F: FnMut(&str, &str) -> StdResult<(), E>,
+    {
+        let mut f = f;
+        self.walk_leaves(|path, entry_type| match entry_type {
+            EntryType::Symlink { target } => f(path, target),
+            _ => Ok(()),
+        })
+    }
+
+    /// Walks all the entries but directories.
+    fn walk_leaves<F, E>(&self, f: F) -> StdResult<(), E>
+    where
+        F: FnMut(&str, &EntryType<'p>) -> StdResult<(), E>,
+    {
+        let mut f = f;
+
+        // Walk with an explicit stack, deep trees would overflow the call
+        // stack otherwise.
+        let mut pending = vec![(&self.root_entry, String::new())];
+        while let Some((current_entry, current_path)) = pending.pop() {
             match &current_entry.entry_type {
-                EntryType::File { file_id } => f(current_path, file_id),
                 EntryType::Dir { children } => {
                     for child_id in children.values() {
-                        let child_entry = entries
-                            .get(child_id)
-                            .expect("internal state is inconsistent");
-                        let child_path = if current_path.is_empty() {
-                            child_entry.name.to_string()
-                        } else {
-                            format!("{current_path}/{}", child_entry.name)
-                        };
-
-                        recursively_walk(entries, child_entry, &child_path, f)?;
+                        let child_entry = self.entry(*child_id);
+                        let child_path = join_path(&current_path, &child_entry.name);
+                        pending.push((child_entry, child_path));
                     }
+                }
+                leaf => f(&current_path, leaf)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Walks all the directories except the root, parents are visited
+    /// before their children.
+    pub fn walk_dirs<F, E>(&self, f: F) -> StdResult<(), E>
+    where
+        F: FnMut(&str) -> StdResult<(), E>,
+    {
+        let mut f = f;
 
-                    Ok(())
+        let mut pending = vec![(&self.root_entry, String::new())];
+        while let Some((current_entry, current_path)) = pending.pop() {
+            let EntryType::Dir { children } = &current_entry.entry_type else {
+                continue;
+            };
+            for child_id in children.values() {
+                let child_entry = self.entry(*child_id);
+                if !child_entry.is_dir() {
+                    continue;
                 }
+                let child_path = join_path(&current_path, &child_entry.name);
+                f(&child_path)?;
+                pending.push((child_entry, child_path));
             }
         }
 
+        Ok(())
+    }
+
+    /// Walks all the entries except the root in the order of a tree view,
+    /// directories before files and each sorted by name. The entries
+    /// deeper than `max_depth` are left out, `size_of` gives the size of a
+    /// file by its ID.
+    pub fn walk_tree<S, F>(&self, max_depth: Option<usize>, size_of: S, f: F)
+    where
+        S: Fn(&str) -> u64,
+        F: FnMut(&TreeEntry),
+    {
         let mut f = f;
-        recursively_walk(&self.entries, &self.root_entry, "", &mut f)
+        let subtrees = self.measure_subtrees(size_of);
+
+        let mut pending = vec![];
+        let push_children = |pending: &mut Vec<_>, dir: &Entry<'p>, depth: usize| {
+            let EntryType::Dir { children } = &dir.entry_type else {
+                return;
+            };
+            let mut children: Vec<_> = children
+                .values()
+                .map(|id| {
+                    (
+                        !self.entry(*id).is_dir(),
+                        self.entry(*id).name.to_string(),
+                        *id,
+                    )
+                })
+                .collect();
+            children.sort();
+            // Pushed in reverse, so that the first child is visited first.
+            let last_idx = children.len().saturating_sub(1);
+            for (idx, (_, name, id)) in children.into_iter().enumerate().rev() {
+                pending.push((id, name, depth, idx == last_idx));
+            }
+        };
+        push_children(&mut pending, &self.root_entry, 0);
+
+        while let Some((id, name, depth, is_last)) = pending.pop() {
+            let entry = self.entry(id);
+            let subtree = subtrees.get(&id).copied();
+            let collapsed = max_depth.is_some_and(|max_depth| depth + 1 >= max_depth)
+                && subtree.is_some_and(|subtree| subtree.entries() > 0);
+            f(&TreeEntry {
+                name: &name,
+                depth,
+                is_last,
+                subtree,
+                collapsed,
+                target: match &entry.entry_type {
+                    EntryType::Symlink { target } => Some(target),
+                    _ => None,
+                },
+            });
+            if !collapsed {
+                push_children(&mut pending, entry, depth + 1);
+            }
+        }
+    }
+
+    /// Measures the entries under each directory, including the nested
+    /// ones.
+    fn measure_subtrees<S>(&self, size_of: S) -> HashMap<u64, SubtreeSize>
+    where
+        S: Fn(&str) -> u64,
+    {
+        // Children are always created after their parents, so visiting the
+        // directories from the newest measures the children first.
+        let mut dir_ids: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.is_dir())
+            .map(|(id, _)| *id)
+            .collect();
+        dir_ids.sort_unstable_by(|a, b| b.cmp(a));
+
+        let mut subtrees: HashMap<u64, SubtreeSize> = HashMap::with_capacity(dir_ids.len());
+        for id in dir_ids {
+            let EntryType::Dir { children } = &self.entry(id).entry_type else {
+                unreachable!();
+            };
+            let mut subtree = SubtreeSize::default();
+            for child_id in children.values() {
+                match &self.entry(*child_id).entry_type {
+                    EntryType::File { file_id } => {
+                        subtree.files += 1;
+                        subtree.bytes += size_of(file_id);
+                    }
+                    EntryType::Dir { .. } => {
+                        let child = subtrees[child_id];
+                        subtree.files += child.files;
+                        subtree.dirs += child.dirs + 1;
+                        subtree.links += child.links;
+                        subtree.bytes += child.bytes;
+                    }
+                    EntryType::Symlink { .. } => subtree.links += 1,
+                }
+            }
+            subtrees.insert(id, subtree);
+        }
+        subtrees
+    }
+
+    fn entry(&self, id: u64) -> &Entry<'p> {
+        self.entries
+            .get(&id)
+            .expect("internal state is inconsistent")
     }
 
-    pub fn add_file<P>(&mut self, path: P, file_id: String) -> Result<()>
+    /// Adds a file, replacing the file that had the path, whose ID is
+    /// returned. Fails with `PathTakenError` if a directory or a symbolic
+    /// link has the path.
+    pub fn add_file<P>(&mut self, path: P, file_id: String) -> Result<Option<String>>
     where
         P: AsRef<Path>,
     {
+        let replaced = self.add_entry(path.as_ref(), EntryType::new_file(file_id))?;
+        if replaced.is_none() {
+            self.file_count += 1;
+        }
+        Ok(replaced)
+    }
+
+    /// Adds a symbolic link, which can't replace any entry.
+    pub fn add_symlink<P>(&mut self, path: P, target: String) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        self.add_entry(path.as_ref(), EntryType::Symlink { target })?;
+        Ok(())
+    }
+
+    fn add_entry(&mut self, path: &Path, entry_type: EntryType<'p>) -> Result<Option<String>> {
         let mut current_entry = &mut self.root_entry;
-        if let Some(parent) = path.as_ref().parent() {
+        if let Some(parent) = path.parent() {
             // Get the parent path and create all intermediate paths if needed.
             for component in parent.components() {
                 let PathComponent::Normal(component) = component else {
@@ -121,7 +300,6 @@ impl<'p> FileSystemIndex<'p> {
         }
 
         let Some(file_name_str) = path
-            .as_ref()
             .file_name()
             .and_then(|p| p.to_str())
             .map(|s| self.string_pool.intern(s))
@@ -136,6 +314,23 @@ impl<'p> FileSystemIndex<'p> {
             ));
         };
 
+        if let Some(existing_id) = children.get(&file_name_str).copied() {
+            let existing_entry = self
+                .entries
+                .get_mut(&existing_id)
+                .expect("internal state is inconsistent");
+            return match (&mut existing_entry.entry_type, entry_type) {
+                (
+                    EntryType::File { file_id },
+                    EntryType::File {
+                        file_id: new_file_id,
+                    },
+                ) => Ok(Some(mem::replace(file_id, new_file_id))),
+                // Replacing a directory would lose its whole subtree.
+                _ => Err(PathTakenError(path.to_string_lossy().into_owned()).into()),
+            };
+        }
+
         let entry_id = self.next_id;
         self.next_id += 1;
 
@@ -143,13 +338,44 @@ impl<'p> FileSystemIndex<'p> {
 
         let entry = Entry {
             name: file_name_str,
-            entry_type: EntryType::new_file(file_id),
+            entry_type,
         };
         self.entries.insert(entry_id, entry);
 
-        self.file_count += 1;
+        Ok(None)
+    }
+}
 
-        Ok(())
+/// An entry visited by `FileSystemIndex::walk_tree`.
+#[derive(Debug)]
+pub struct TreeEntry<'a> {
+    pub name: &'a str,
+    /// Depth below the root, starting from 0.
+    pub depth: usize,
+    /// Whether it's the last entry of its directory.
+    pub is_last: bool,
+    /// What a directory holds, `None` for a file or a symbolic link.
+    pub subtree: Option<SubtreeSize>,
+    /// Whether the entries of a directory are left out by the maximum
+    /// depth.
+    pub collapsed: bool,
+    /// Target of a symbolic link.
+    pub target: Option<&'a str>,
+}
+
+/// Entries under a directory of the index, including the nested ones.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SubtreeSize {
+    pub files: usize,
+    pub dirs: usize,
+    pub links: usize,
+    /// Total size of the files.
+    pub bytes: u64,
+}
+
+impl SubtreeSize {
+    pub fn entries(&self) -> usize {
+        self.files + self.dirs + self.links
     }
 }
 
@@ -159,6 +385,12 @@ struct Entry<'p> {
     entry_type: EntryType<'p>,
 }
 
+impl<'p> Entry<'p> {
+    fn is_dir(&self) -> bool {
+        matches!(self.entry_type, EntryType::Dir { .. })
+    }
+}
+
 #[derive(Debug)]
 enum EntryType<'p> {
     File {
@@ -167,6 +399,9 @@ enum EntryType<'p> {
     Dir {
         children: HashMap<StringId<'p>, u64>,
     },
+    Symlink {
+        target: String,
+    },
 }
 
 impl<'p> EntryType<'p> {
@@ -181,12 +416,19 @@ impl<'p> EntryType<'p> {
     }
 }
 
+fn join_path(parent: &str, name: &StringId) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{parent}/{name}")
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::assert_matches::assert_matches;
     use std::collections::HashMap;
 
-    use super::FileSystemIndex;
+    use super::{FileSystemIndex, PathTakenError, SubtreeSize};
     use crate::utils::string_pool::StringPool;
 
     #[test]
@@ -199,7 +441,7 @@ mod tests {
         let mut assert_add_file = |path: &str, file_id: &str| {
             let res = index.add_file(path, file_id.to_owned());
             added_files.insert(path.to_owned(), file_id.to_owned());
-            assert_matches!(res, Ok(()));
+            assert!(matches!(res, Ok(None)));
         };
 
         assert_add_file("Library/Cookies/a", "a");
@@ -214,7 +456,188 @@ mod tests {
             }
             Ok(())
         });
-        assert_matches!(res, Ok(()));
+        assert_eq!(res, Ok(()));
         assert_eq!(added_files.len(), 0);
     }
+
+    #[test]
+    fn it_walks_deep_trees() {
+        // A small stack, which a recursive walk would overflow long before
+        // reaching the file.
+        let walker = std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(|| {
+                let string_pool = StringPool::new();
+                let mut index = FileSystemIndex::new(&string_pool);
+                let dir_path = vec!["d"; 10_000].join("/");
+                index
+                    .add_file(format!("{dir_path}/a"), "a".to_owned())
+                    .unwrap();
+                index
+                    .add_file(format!("{dir_path}/b"), "b".to_owned())
+                    .unwrap();
+
+                let mut files = vec![];
+                index
+                    .walk_files(|path, file_id| -> Result<(), ()> {
+                        files.push((path.len(), file_id.to_owned()));
+                        Ok(())
+                    })
+                    .unwrap();
+                files.sort();
+                let file_path_len = dir_path.len() + 2;
+                assert_eq!(
+                    files,
+                    [
+                        (file_path_len, "a".to_owned()),
+                        (file_path_len, "b".to_owned())
+                    ]
+                );
+
+                // The walk stops at the first error.
+                let mut visited = 0;
+                let res = index.walk_files(|_, _| {
+                    visited += 1;
+                    Err("stop")
+                });
+                assert_eq!((res, visited), (Err("stop"), 1));
+
+                let mut dir_count = 0;
+                index
+                    .walk_dirs(|_| -> Result<(), ()> {
+                        dir_count += 1;
+                        Ok(())
+                    })
+                    .unwrap();
+                assert_eq!(dir_count, 10_000);
+            })
+            .unwrap();
+        walker.join().unwrap();
+    }
+
+    #[test]
+    fn it_walks_parent_dirs_first() {
+        let string_pool = StringPool::new();
+        let mut index = FileSystemIndex::new(&string_pool);
+        index.add_file("Library/Cookies/a", "a".to_owned()).unwrap();
+        index.add_file("Library/b", "b".to_owned()).unwrap();
+        index.add_file("c", "c".to_owned()).unwrap();
+
+        let mut dirs = vec![];
+        index
+            .walk_dirs(|path| -> Result<(), ()> {
+                dirs.push(path.to_owned());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(dirs, ["Library", "Library/Cookies"]);
+    }
+
+    #[test]
+    fn it_walks_tree() {
+        let string_pool = StringPool::new();
+        let mut index = FileSystemIndex::new(&string_pool);
+        index.add_file("b", "b".to_owned()).unwrap();
+        index.add_file("Library/Cookies/a", "a".to_owned()).unwrap();
+        index.add_file("Library/Cookies/c", "c".to_owned()).unwrap();
+        index.add_file("Library/z", "z".to_owned()).unwrap();
+        index.add_file("Documents/d", "d".to_owned()).unwrap();
+
+        let mut entries = vec![];
+        index.walk_tree(
+            None,
+            |_| 0,
+            |entry| {
+                entries.push((
+                    entry.name.to_owned(),
+                    entry.depth,
+                    entry.is_last,
+                    entry.subtree.map(|subtree| subtree.files),
+                ));
+            },
+        );
+        let expected = [
+            ("Documents", 0, false, Some(1)),
+            ("d", 1, true, None),
+            ("Library", 0, false, Some(3)),
+            ("Cookies", 1, false, Some(2)),
+            ("a", 2, false, None),
+            ("c", 2, true, None),
+            ("z", 1, true, None),
+            ("b", 0, true, None),
+        ];
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(name, depth, is_last, file_count)| (name.to_owned(), depth, is_last, file_count))
+            .collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn it_collapses_subtrees_below_max_depth() {
+        let string_pool = StringPool::new();
+        let mut index = FileSystemIndex::new(&string_pool);
+        index.add_file("b", "b".to_owned()).unwrap();
+        index.add_file("Library/Caches/a", "a".to_owned()).unwrap();
+        index
+            .add_file("Library/Caches/x/c", "c".to_owned())
+            .unwrap();
+        index
+            .add_symlink("Library/Caches/l", "a".to_owned())
+            .unwrap();
+        index.add_file("Library/z", "z".to_owned()).unwrap();
+
+        let mut entries = vec![];
+        index.walk_tree(
+            Some(2),
+            |file_id| file_id.len() as u64 * 10,
+            |entry| {
+                entries.push((entry.name.to_owned(), entry.collapsed, entry.subtree));
+            },
+        );
+        let caches = SubtreeSize {
+            files: 2,
+            dirs: 1,
+            links: 1,
+            bytes: 20,
+        };
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[1], ("Caches".to_owned(), true, Some(caches)));
+        assert_eq!(caches.entries(), 4);
+        assert_eq!(entries[0].2.unwrap().entries(), 6);
+        assert_eq!(entries[2].0, "z");
+    }
+
+    #[test]
+    fn it_indexes_symlinks() {
+        let string_pool = StringPool::new();
+        let mut index = FileSystemIndex::new(&string_pool);
+        index.add_file("Library/a", "a".to_owned()).unwrap();
+        index
+            .add_symlink("Library/Links/b", "../a".to_owned())
+            .unwrap();
+        assert!(index.add_symlink("Library/a", "b".to_owned()).is_err());
+        assert!(index.add_symlink("Library/Links", "b".to_owned()).is_err());
+        assert!(index
+            .add_file("Library/Links", "c".to_owned())
+            .unwrap_err()
+            .is::<PathTakenError>());
+        assert_eq!(
+            index.add_file("Library/a", "c".to_owned()).unwrap(),
+            Some("a".to_owned())
+        );
+        assert_eq!(index.file_count(), 1);
+
+        let mut symlinks = vec![];
+        index
+            .walk_symlinks(|path, target| -> Result<(), ()> {
+                symlinks.push((path.to_owned(), target.to_owned()));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            symlinks,
+            [("Library/Links/b".to_owned(), "../a".to_owned())]
+        );
+    }
 }
@@ -0,0 +1,200 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use plist::{Dictionary, Value};
+
+use crate::db;
+use crate::utils::timestamp::unix_timestamp;
+
+/// Information about the backup, read from `Info.plist`, `Status.plist`
+/// and `Manifest.plist`. The fields are `None` when the files lack them.
+#[readonly::make]
+#[derive(Default, Debug)]
+pub struct BackupInfo {
+    pub device_name: Option<String>,
+    /// Model identifier of the device, e.g. `iPhone15,2`.
+    pub product_type: Option<String>,
+    /// iOS version of the device, e.g. `16.5`.
+    pub product_version: Option<String>,
+    pub serial_number: Option<String>,
+    /// When the backup was made, in seconds since the Unix epoch.
+    pub last_backup_date: Option<i64>,
+    pub is_encrypted: Option<bool>,
+    /// Whether the last backup finished, backups interrupted midway may
+    /// lack files.
+    pub is_complete: Option<bool>,
+}
+
+/// Reads a plist of the backup as a dictionary, `None` if it's missing or
+/// isn't one.
+fn read_plist(backup_dir: &Path, file_name: &str) -> Result<Option<Dictionary>> {
+    let path = backup_dir.join(file_name);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let value: Value =
+        plist::from_file(&path).with_context(|| format!("failed to parse `{file_name}`"))?;
+    Ok(value.into_dictionary())
+}
+
+/// Reads the backup information, missing plists yield empty fields. Only
+/// a broken `Info.plist` is an error, the others are just warned about,
+/// as few commands need what they add.
+pub fn read_info(backup_dir: &Path) -> Result<BackupInfo> {
+    let info = read_plist(backup_dir, "Info.plist")?.unwrap_or_default();
+    let [status, manifest] = ["Status.plist", "Manifest.plist"].map(|file_name| {
+        read_plist(backup_dir, file_name)
+            .unwrap_or_else(|err| {
+                warn!("{}: {err:#}", backup_dir.to_string_lossy());
+                None
+            })
+            .unwrap_or_default()
+    });
+
+    let string_field = |key| info.get(key).and_then(Value::as_string).map(str::to_owned);
+    let date_field = |dict: &Dictionary, key| {
+        dict.get(key)
+            .and_then(Value::as_date)
+            .map(|d| unix_timestamp(d.into()))
+    };
+    Ok(BackupInfo {
+        device_name: string_field("Device Name"),
+        product_type: string_field("Product Type"),
+        product_version: string_field("Product Version"),
+        serial_number: string_field("Serial Number"),
+        last_backup_date: date_field(&info, "Last Backup Date")
+            .or_else(|| date_field(&status, "Date")),
+        is_encrypted: manifest.get("IsEncrypted").and_then(Value::as_boolean),
+        is_complete: status
+            .get("SnapshotState")
+            .and_then(Value::as_string)
+            .map(|state| state == "finished"),
+    })
+}
+
+/// A backup found by `list_backups`.
+#[derive(Debug)]
+pub struct BackupEntry {
+    pub path: PathBuf,
+    pub info: BackupInfo,
+}
+
+/// Returns where Finder and iTunes keep the backups on this platform, the
+/// first that exists on Windows, where iTunes installed from the Microsoft
+/// Store uses another directory.
+pub fn default_backups_dir() -> Option<PathBuf> {
+    let candidates = if cfg!(target_os = "macos") {
+        vec![env::var_os("HOME")
+            .map(|home| Path::new(&home).join("Library/Application Support/MobileSync/Backup"))]
+    } else if cfg!(windows) {
+        vec![
+            env::var_os("APPDATA")
+                .map(|app_data| Path::new(&app_data).join(r"Apple Computer\MobileSync\Backup")),
+            env::var_os("USERPROFILE")
+                .map(|profile| Path::new(&profile).join(r"Apple\MobileSync\Backup")),
+        ]
+    } else {
+        vec![]
+    };
+    candidates.into_iter().flatten().find(|dir| dir.is_dir())
+}
+
+/// Lists the backups in `backups_dir`, the subdirectories with a manifest,
+/// the most recent first.
+pub fn list_backups(backups_dir: &Path) -> Result<Vec<BackupEntry>> {
+    let mut backups = vec![];
+    let dir_entries = fs::read_dir(backups_dir).with_context(|| {
+        format!(
+            "failed to read directory: {}",
+            backups_dir.to_string_lossy()
+        )
+    })?;
+    for dir_entry in dir_entries {
+        let path = dir_entry?.path();
+        if !path.is_dir() || !db::manifest_path(&path).exists() {
+            continue;
+        }
+        // A broken `Info.plist` shouldn't hide the other backups.
+        let info = read_info(&path).unwrap_or_else(|err| {
+            warn!("{}: {err:#}", path.to_string_lossy());
+            Default::default()
+        });
+        backups.push(BackupEntry { path, info });
+    }
+    backups.sort_by(|a, b| {
+        b.info
+            .last_backup_date
+            .cmp(&a.info.last_backup_date)
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    Ok(backups)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    use plist::{Dictionary, Value};
+
+    use super::{list_backups, read_info};
+
+    #[test]
+    fn it_lists_backups() {
+        let backups_dir = tempfile::tempdir().unwrap();
+        for (udid, name, days) in [("old", "iPhone", 1), ("new", "iPad", 2)] {
+            let backup_dir = backups_dir.path().join(udid);
+            fs::create_dir(&backup_dir).unwrap();
+            fs::write(backup_dir.join("Manifest.db"), b"").unwrap();
+            let mut info = Dictionary::new();
+            info.insert("Device Name".to_owned(), name.into());
+            info.insert("Product Version".to_owned(), "17.1".into());
+            let date = SystemTime::UNIX_EPOCH + Duration::from_secs(days * 86400);
+            info.insert("Last Backup Date".to_owned(), Value::Date(date.into()));
+            plist::to_file_xml(backup_dir.join("Info.plist"), &info).unwrap();
+        }
+        fs::create_dir(backups_dir.path().join("not-a-backup")).unwrap();
+
+        let backups = list_backups(backups_dir.path()).unwrap();
+        let names: Vec<_> = backups
+            .iter()
+            .map(|backup| backup.info.device_name.as_deref().unwrap())
+            .collect();
+        assert_eq!(names, ["iPad", "iPhone"]);
+        assert_eq!(backups[0].path, backups_dir.path().join("new"));
+        assert_eq!(backups[0].info.last_backup_date, Some(2 * 86400));
+    }
+
+    #[test]
+    fn it_reads_info() {
+        let backup_dir = tempfile::tempdir().unwrap();
+        let info = read_info(backup_dir.path()).unwrap();
+        assert_eq!(info.device_name, None);
+        assert_eq!(info.is_complete, None);
+
+        let mut info = Dictionary::new();
+        info.insert("Device Name".to_owned(), "iPhone".into());
+        info.insert("Product Type".to_owned(), "iPhone15,2".into());
+        plist::to_file_xml(backup_dir.path().join("Info.plist"), &info).unwrap();
+        let mut status = Dictionary::new();
+        status.insert("SnapshotState".to_owned(), "finished".into());
+        let date = SystemTime::UNIX_EPOCH + Duration::from_secs(86400);
+        status.insert("Date".to_owned(), Value::Date(date.into()));
+        plist::to_file_binary(backup_dir.path().join("Status.plist"), &status).unwrap();
+        fs::write(backup_dir.path().join("Manifest.plist"), b"broken").unwrap();
+
+        let info = read_info(backup_dir.path()).unwrap();
+        assert_eq!(info.device_name.as_deref(), Some("iPhone"));
+        assert_eq!(info.product_type.as_deref(), Some("iPhone15,2"));
+        assert_eq!(info.product_version, None);
+        assert_eq!(info.serial_number, None);
+        assert_eq!(info.last_backup_date, Some(86400));
+        assert_eq!(info.is_complete, Some(true));
+        assert_eq!(info.is_encrypted, None);
+
+        fs::write(backup_dir.path().join("Info.plist"), b"broken").unwrap();
+        assert!(read_info(backup_dir.path()).is_err());
+    }
+}
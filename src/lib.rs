@@ -0,0 +1,54 @@
+//! Extracts the files of iOS backup archives.
+//!
+//! The types re-exported here make up the stable API: open the manifest
+//! with [`BackupManifest::open`], wrap it in a [`Context`] and extract a
+//! domain with [`Context::extract_file`], following the [`ProgressEvent`]s
+//! it sends. Everything else backs the command line tool and may change
+//! between releases.
+
+#[macro_use]
+extern crate anyhow;
+#[macro_use]
+extern crate log;
+
+mod app;
+mod bucket;
+mod checkpoint;
+mod cli;
+mod crypto;
+mod ctx;
+mod db;
+mod domains;
+mod extract_manifest;
+mod fs_index;
+mod info;
+mod mbdb;
+mod notes;
+mod staging;
+mod store;
+mod summary;
+mod utils;
+mod volume;
+
+pub use bucket::BucketStrategy;
+pub use ctx::{
+    ConflictPolicy, Context, ExtractOptions, ExtractionReport, PrefixMismatchPolicy, ProgressEvent,
+    ReportRecorder,
+};
+pub use db::{BackupManifest, ManifestFile, ManifestFileType};
+pub use fs_index::FileSystemIndex;
+pub use utils::string_pool::StringPool;
+
+/// Runs the command line tool with the arguments of the process, exiting
+/// on failure.
+#[doc(hidden)]
+pub fn cli_main() {
+    let args = cli::parse_args();
+    let res = utils::logger::init(args.verbose, args.quiet, args.log_file.as_deref())
+        .and_then(|()| app::run(args));
+    if let Err(err) = res {
+        let prefix = console::style("error: ").red().bold().to_string();
+        eprintln!("{prefix}{err:?}");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,278 @@
+//! Parser of `Manifest.mbdb`, the manifest of backups made before iOS 10.
+
+use anyhow::{Context, Result};
+use plist::{Dictionary, Value};
+use sha1::{Digest, Sha1};
+
+use crate::utils::hex;
+
+const MAGIC: &[u8] = b"mbdb\x05\x00";
+
+const S_IFMT: u16 = 0o170000;
+const S_IFREG: u16 = 0o100000;
+const S_IFDIR: u16 = 0o040000;
+const S_IFLNK: u16 = 0o120000;
+
+/// A file record of `Manifest.mbdb`.
+#[derive(Debug)]
+pub struct MbdbRecord {
+    pub domain: String,
+    pub relative_path: String,
+    pub target: Option<String>,
+    /// SHA-1 of the contents, only recorded for some files.
+    pub digest: Option<Vec<u8>>,
+    pub mode: u16,
+    pub inode: u64,
+    pub user_id: u32,
+    pub group_id: u32,
+    pub last_modified: u32,
+    pub last_status_change: u32,
+    pub size: u64,
+    pub protection_class: u8,
+}
+
+impl MbdbRecord {
+    pub fn file_id(&self) -> String {
+        file_id(&self.domain, &self.relative_path)
+    }
+
+    /// Returns the file type flags used by `Manifest.db`, or `None` for
+    /// other kinds of files.
+    pub fn flags(&self) -> Option<u64> {
+        match self.mode & S_IFMT {
+            S_IFREG => Some(1),
+            S_IFDIR => Some(2),
+            S_IFLNK => Some(4),
+            _ => None,
+        }
+    }
+
+    /// Builds the fields of the record as a plain dictionary, the form
+    /// the `file` column of `Manifest.db` can also take.
+    pub fn file_plist(&self) -> Result<Vec<u8>> {
+        let mut file = Dictionary::new();
+        file.insert("RelativePath".to_owned(), self.relative_path.clone().into());
+        file.insert("Size".to_owned(), self.size.into());
+        file.insert("Mode".to_owned(), u64::from(self.mode).into());
+        file.insert("UserID".to_owned(), u64::from(self.user_id).into());
+        file.insert("GroupID".to_owned(), u64::from(self.group_id).into());
+        file.insert("InodeNumber".to_owned(), self.inode.into());
+        file.insert(
+            "ProtectionClass".to_owned(),
+            u64::from(self.protection_class).into(),
+        );
+        file.insert(
+            "LastModified".to_owned(),
+            u64::from(self.last_modified).into(),
+        );
+        file.insert(
+            "LastStatusChange".to_owned(),
+            u64::from(self.last_status_change).into(),
+        );
+        if let Some(target) = &self.target {
+            file.insert("Target".to_owned(), target.clone().into());
+        }
+        if let Some(digest) = &self.digest {
+            file.insert("Digest".to_owned(), Value::Data(digest.clone()));
+        }
+
+        let mut buf = Vec::new();
+        plist::to_writer_binary(&mut buf, &Value::Dictionary(file))?;
+        Ok(buf)
+    }
+}
+
+/// Returns the file ID of a file, the SHA-1 of its domain and relative
+/// path, which names its blob.
+pub fn file_id(domain: &str, relative_path: &str) -> String {
+    let key = format!("{domain}-{relative_path}");
+    hex::encode(&Sha1::digest(key.as_bytes()))
+}
+
+/// Parses all the records of a `Manifest.mbdb` file.
+pub fn parse(data: &[u8]) -> Result<Vec<MbdbRecord>> {
+    let Some(data) = data.strip_prefix(MAGIC) else {
+        return Err(anyhow!("not a `Manifest.mbdb` file"));
+    };
+
+    let mut reader = Reader { data, offset: 0 };
+    let mut records = vec![];
+    while !reader.data.is_empty() {
+        let offset = reader.offset + MAGIC.len();
+        let record = reader
+            .read_record()
+            .with_context(|| format!("malformed record at offset {offset}"))?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read_record(&mut self) -> Result<MbdbRecord> {
+        let domain = self.read_string()?.unwrap_or_default();
+        let relative_path = self.read_string()?.unwrap_or_default();
+        let target = self.read_string()?;
+        let digest = self.read_data()?.map(<[u8]>::to_vec);
+        // Wrapped key of encrypted backups, which are not supported.
+        self.read_data()?;
+
+        let mode = self.read_u16()?;
+        let inode = self.read_u64()?;
+        let user_id = self.read_u32()?;
+        let group_id = self.read_u32()?;
+        let last_modified = self.read_u32()?;
+        // Last accessed time, not recorded in `Manifest.db`.
+        self.read_u32()?;
+        let last_status_change = self.read_u32()?;
+        let size = self.read_u64()?;
+        let protection_class = self.read_u8()?;
+        let record = MbdbRecord {
+            domain,
+            relative_path,
+            target,
+            digest,
+            mode,
+            inode,
+            user_id,
+            group_id,
+            last_modified,
+            last_status_change,
+            size,
+            protection_class,
+        };
+
+        let property_count = self.read_u8()?;
+        for _ in 0..property_count {
+            self.read_data()?;
+            self.read_data()?;
+        }
+        Ok(record)
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(anyhow!("unexpected end of data"));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.read_bytes(2)?.try_into()?))
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.read_bytes(4)?.try_into()?))
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.read_bytes(8)?.try_into()?))
+    }
+
+    /// Reads a length-prefixed byte string, `0xffff` marks an absent one.
+    fn read_data(&mut self) -> Result<Option<&'a [u8]>> {
+        match self.read_u16()? {
+            0xffff => Ok(None),
+            len => self.read_bytes(len as usize).map(Some),
+        }
+    }
+
+    fn read_string(&mut self) -> Result<Option<String>> {
+        let Some(bytes) = self.read_data()? else {
+            return Ok(None);
+        };
+        let string = String::from_utf8(bytes.to_vec()).context("string is not UTF-8")?;
+        Ok(Some(string))
+    }
+}
+
+#[cfg(test)]
+pub mod test_support {
+    /// Encodes a record of a regular file, or a directory if `contents` is
+    /// `None`.
+    pub fn record(domain: &str, relative_path: &str, contents: Option<&[u8]>) -> Vec<u8> {
+        fn string(buf: &mut Vec<u8>, value: Option<&[u8]>) {
+            match value {
+                Some(value) => {
+                    buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+                    buf.extend_from_slice(value);
+                }
+                None => buf.extend_from_slice(&[0xff, 0xff]),
+            }
+        }
+
+        let mut buf = vec![];
+        string(&mut buf, Some(domain.as_bytes()));
+        string(&mut buf, Some(relative_path.as_bytes()));
+        string(&mut buf, None);
+        string(&mut buf, contents.map(|_| [0xab; 20].as_slice()));
+        string(&mut buf, None);
+        let mode: u16 = if contents.is_some() {
+            0o100644
+        } else {
+            0o040755
+        };
+        buf.extend_from_slice(&mode.to_be_bytes());
+        buf.extend_from_slice(&42u64.to_be_bytes());
+        buf.extend_from_slice(&501u32.to_be_bytes());
+        buf.extend_from_slice(&501u32.to_be_bytes());
+        for timestamp in [1_400_000_000u32, 1_400_000_100, 1_400_000_200] {
+            buf.extend_from_slice(&timestamp.to_be_bytes());
+        }
+        let size = contents.map_or(0, <[u8]>::len) as u64;
+        buf.extend_from_slice(&size.to_be_bytes());
+        buf.push(4);
+        // A single extended attribute.
+        buf.push(1);
+        string(&mut buf, Some(b"com.apple.test"));
+        string(&mut buf, Some(b"1"));
+        buf
+    }
+
+    /// Encodes a whole `Manifest.mbdb` file.
+    pub fn mbdb(records: &[Vec<u8>]) -> Vec<u8> {
+        [super::MAGIC.to_vec(), records.concat()].concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use super::test_support::{mbdb, record};
+
+    #[test]
+    fn it_parses_records() {
+        let data = mbdb(&[
+            record("HomeDomain", "Library", None),
+            record("HomeDomain", "Library/a.plist", Some(b"abc")),
+        ]);
+        let records = parse(&data).unwrap();
+        assert_eq!(records.len(), 2);
+
+        let file = &records[1];
+        assert_eq!(file.relative_path, "Library/a.plist");
+        assert_eq!(file.flags(), Some(1));
+        assert_eq!(file.size, 3);
+        assert_eq!(file.last_modified, 1_400_000_000);
+        assert_eq!(file.last_status_change, 1_400_000_200);
+        assert_eq!(file.protection_class, 4);
+        assert_eq!(file.digest.as_deref(), Some([0xab; 20].as_slice()));
+        // SHA-1 of `HomeDomain-Library/a.plist`.
+        assert_eq!(file.file_id(), "4bbd785b7005715a279d96ef815826192fb73daf");
+        assert_eq!(records[0].flags(), Some(2));
+
+        assert!(parse(&data[..data.len() - 1]).is_err());
+        assert!(parse(b"garbage").is_err());
+    }
+}
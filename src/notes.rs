@@ -0,0 +1,149 @@
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use fallible_iterator::FallibleIterator;
+use flate2::read::GzDecoder;
+use rusqlite::{Connection as SqliteConnection, OpenFlags};
+
+/// File name of the Notes database in its app group container.
+pub const NOTE_STORE_NAME: &str = "NoteStore.sqlite";
+
+/// The Notes database along with its WAL and shared memory files.
+pub const NOTE_STORE_FILES: &[&str] = &[
+    "NoteStore.sqlite",
+    "NoteStore.sqlite-wal",
+    "NoteStore.sqlite-shm",
+];
+
+/// Field numbers leading to the note text in the `NoteStoreProto` message
+/// (`document` -> `note` -> `note_text`).
+const NOTE_TEXT_PATH: &[u64] = &[2, 3, 2];
+
+/// Decodes the note bodies stored in `db_path` into `<note ID>.txt` files
+/// under `out_dir`, returns the number of decoded notes.
+///
+/// The body format is undocumented and changes between iOS versions, the
+/// notes that fail to decode are skipped with a warning.
+pub fn decode_notes(db_path: &Path, out_dir: &Path) -> Result<usize> {
+    let db_conn = SqliteConnection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("failed to open: {}", db_path.to_string_lossy()))?;
+    let mut stmt = db_conn
+        .prepare(
+            "SELECT ZNOTE, ZDATA FROM ZICNOTEDATA WHERE ZNOTE IS NOT NULL AND ZDATA IS NOT NULL",
+        )
+        .context("unsupported Notes database schema")?;
+    let notes: Vec<(i64, Vec<u8>)> = stmt
+        .query([])?
+        .map(|r| Ok((r.get(0)?, r.get(1)?)))
+        .collect()?;
+
+    fs::create_dir_all(out_dir)
+        .with_context(|| format!("failed to create directory: {}", out_dir.to_string_lossy()))?;
+
+    let mut decoded_count = 0;
+    for (note_id, data) in notes {
+        let text = match decode_note_body(&data) {
+            Ok(text) => text,
+            Err(err) => {
+                warn!("failed to decode note {note_id}: {err:#}");
+                continue;
+            }
+        };
+
+        let note_path = out_dir.join(format!("{note_id}.txt"));
+        fs::write(&note_path, text)
+            .with_context(|| format!("failed to write file: {}", note_path.to_string_lossy()))?;
+        decoded_count += 1;
+    }
+    Ok(decoded_count)
+}
+
+/// Inflates a `ZDATA` blob and extracts the plain text from the protobuf.
+fn decode_note_body(data: &[u8]) -> Result<String> {
+    let mut proto = vec![];
+    GzDecoder::new(data)
+        .read_to_end(&mut proto)
+        .context("failed to inflate note data")?;
+
+    let mut message = proto.as_slice();
+    for field_number in NOTE_TEXT_PATH {
+        message = find_bytes_field(message, *field_number)?
+            .ok_or_else(|| anyhow!("missing protobuf field {field_number}"))?;
+    }
+    Ok(String::from_utf8_lossy(message).into_owned())
+}
+
+/// Returns the first length-delimited field with the given number.
+fn find_bytes_field(mut message: &[u8], field_number: u64) -> Result<Option<&[u8]>> {
+    while !message.is_empty() {
+        let key = read_varint(&mut message)?;
+        let value_len = match key & 0x7 {
+            0 => {
+                read_varint(&mut message)?;
+                continue;
+            }
+            1 => 8,
+            2 => read_varint(&mut message)? as usize,
+            5 => 4,
+            wire_type => return Err(anyhow!("unsupported protobuf wire type {wire_type}")),
+        };
+        if value_len > message.len() {
+            return Err(anyhow!("truncated protobuf message"));
+        }
+
+        let (value, rest) = message.split_at(value_len);
+        if key >> 3 == field_number && key & 0x7 == 2 {
+            return Ok(Some(value));
+        }
+        message = rest;
+    }
+    Ok(None)
+}
+
+fn read_varint(buf: &mut &[u8]) -> Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let Some((&byte, rest)) = buf.split_first() else {
+            return Err(anyhow!("truncated protobuf varint"));
+        };
+        *buf = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(anyhow!("protobuf varint is too long"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use super::decode_note_body;
+
+    fn bytes_field(field_number: u8, value: &[u8]) -> Vec<u8> {
+        let mut field = vec![field_number << 3 | 2, value.len() as u8];
+        field.extend_from_slice(value);
+        field
+    }
+
+    #[test]
+    fn it_decodes_note_body() {
+        let text = bytes_field(2, b"Groceries\nMilk");
+        let note = [vec![0x08, 0x01], text].concat();
+        let document = [vec![0x08, 0x00], bytes_field(3, &note)].concat();
+        let proto = [vec![0x08, 0x00], bytes_field(2, &document)].concat();
+
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&proto).unwrap();
+        let data = encoder.finish().unwrap();
+
+        assert_eq!(decode_note_body(&data).unwrap(), "Groceries\nMilk");
+        assert!(decode_note_body(b"not gzip").is_err());
+    }
+}
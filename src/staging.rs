@@ -0,0 +1,145 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// Directory an atomic extraction writes into, next to the destination so
+/// that it's on the same volume, and renamed to the destination once the
+/// extraction succeeded. It's removed if dropped before then.
+pub struct StagingDir {
+    path: PathBuf,
+    dest_dir: PathBuf,
+    replace: bool,
+    committed: bool,
+}
+
+impl StagingDir {
+    /// Creates the staging directory of `dest_dir`, which must not exist
+    /// unless `replace` is set.
+    pub fn new(dest_dir: &Path, replace: bool) -> Result<Self> {
+        let Some(name) = dest_dir.file_name() else {
+            return Err(anyhow!(
+                "can't extract atomically into {}, name the directory",
+                dest_dir.to_string_lossy()
+            ));
+        };
+        if dest_dir.exists() && !replace {
+            return Err(already_exists(dest_dir));
+        }
+
+        let mut staging_name = name.to_owned();
+        staging_name.push(format!(".tmp-{}", std::process::id()));
+        let path = dest_dir.with_file_name(staging_name);
+        remove_dir(&path)?;
+        fs::create_dir_all(&path)
+            .with_context(|| format!("failed to create directory: {}", path.to_string_lossy()))?;
+        Ok(Self {
+            path,
+            dest_dir: dest_dir.to_owned(),
+            replace,
+            committed: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the extracted files to the destination, replacing it if
+    /// allowed. The replaced directory is only removed once the new one is
+    /// in place.
+    pub fn commit(mut self) -> Result<()> {
+        let mut replaced_path = None;
+        if self.dest_dir.exists() {
+            if !self.replace {
+                return Err(already_exists(&self.dest_dir));
+            }
+            let mut old_name = self.path.file_name().unwrap_or_default().to_owned();
+            old_name.push(".old");
+            let old_path = self.path.with_file_name(old_name);
+            remove_dir(&old_path)?;
+            fs::rename(&self.dest_dir, &old_path).with_context(|| {
+                format!("failed to move aside: {}", self.dest_dir.to_string_lossy())
+            })?;
+            replaced_path = Some(old_path);
+        }
+
+        if let Err(err) = fs::rename(&self.path, &self.dest_dir) {
+            if let Some(old_path) = &replaced_path {
+                let _ = fs::rename(old_path, &self.dest_dir);
+            }
+            return Err(anyhow::Error::new(err).context(format!(
+                "failed to rename {} to {}",
+                self.path.to_string_lossy(),
+                self.dest_dir.to_string_lossy()
+            )));
+        }
+        self.committed = true;
+
+        if let Some(old_path) = replaced_path {
+            remove_dir(&old_path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        if let Err(err) = remove_dir(&self.path) {
+            warn!("{err:#}");
+        }
+    }
+}
+
+fn already_exists(dest_dir: &Path) -> anyhow::Error {
+    anyhow!(
+        "directory already exists: {}, pass `--overwrite-dir` to replace it",
+        dest_dir.to_string_lossy()
+    )
+}
+
+/// Removes a directory and its content if it exists.
+fn remove_dir(path: &Path) -> Result<()> {
+    match fs::remove_dir_all(path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(anyhow::Error::new(err)
+            .context(format!("failed to remove: {}", path.to_string_lossy()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::StagingDir;
+
+    #[test]
+    fn it_commits_or_removes_staged_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest_dir = dir.path().join("out");
+
+        let staging = StagingDir::new(&dest_dir, false).unwrap();
+        assert_eq!(staging.path().parent(), Some(dir.path()));
+        fs::write(staging.path().join("a"), b"a").unwrap();
+        drop(staging);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let staging = StagingDir::new(&dest_dir, false).unwrap();
+        fs::write(staging.path().join("a"), b"a").unwrap();
+        staging.commit().unwrap();
+        assert_eq!(fs::read(dest_dir.join("a")).unwrap(), b"a");
+
+        assert!(StagingDir::new(&dest_dir, false).is_err());
+        let staging = StagingDir::new(&dest_dir, true).unwrap();
+        fs::write(staging.path().join("b"), b"b").unwrap();
+        staging.commit().unwrap();
+        assert!(!dest_dir.join("a").exists());
+        assert_eq!(fs::read(dest_dir.join("b")).unwrap(), b"b");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
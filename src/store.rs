@@ -0,0 +1,191 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use fs2::FileExt;
+use sha2::{Digest, Sha256};
+
+use crate::utils::hex;
+
+const OBJECTS_DIR_NAME: &str = "objects";
+const TMP_DIR_NAME: &str = "tmp";
+const LOCK_FILE_NAME: &str = "lock";
+
+/// A content-addressed store, each blob is kept once as
+/// `objects/<aa>/<SHA-256 of the content>` no matter how many backups or
+/// files refer to it.
+///
+/// The store is locked for as long as it's open, so that two processes
+/// never write to it at the same time.
+#[derive(Debug)]
+pub struct ObjectStore {
+    root: PathBuf,
+    _lock: File,
+}
+
+#[derive(Default, Debug)]
+pub struct VerifyStats {
+    pub verified: usize,
+    pub corrupted: Vec<PathBuf>,
+}
+
+impl ObjectStore {
+    /// Opens the store for writing, creating it if needed.
+    pub fn open(root: &Path) -> Result<Self> {
+        for dir_name in [OBJECTS_DIR_NAME, TMP_DIR_NAME] {
+            let dir = root.join(dir_name);
+            fs::create_dir_all(&dir).with_context(|| {
+                format!("failed to create directory: {}", dir.to_string_lossy())
+            })?;
+        }
+        Self::lock(root, true)
+    }
+
+    /// Opens an existing store for reading.
+    pub fn open_read_only(root: &Path) -> Result<Self> {
+        if !root.join(OBJECTS_DIR_NAME).is_dir() {
+            return Err(anyhow!("not a store: {}", root.to_string_lossy()));
+        }
+        Self::lock(root, false)
+    }
+
+    fn lock(root: &Path, exclusive: bool) -> Result<Self> {
+        let lock_path = root.join(LOCK_FILE_NAME);
+        let lock = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .with_context(|| format!("failed to open: {}", lock_path.to_string_lossy()))?;
+        let res = if exclusive {
+            FileExt::try_lock_exclusive(&lock)
+        } else {
+            FileExt::try_lock_shared(&lock)
+        };
+        res.map_err(|_| {
+            anyhow!(
+                "store is in use by another process: {}",
+                root.to_string_lossy()
+            )
+        })?;
+
+        Ok(Self {
+            root: root.to_owned(),
+            _lock: lock,
+        })
+    }
+
+    /// Adds the content of a file to the store, returns the path of the
+    /// object and the number of bytes copied, which is zero if the store
+    /// already had it.
+    pub fn insert(&self, path: &Path) -> Result<(PathBuf, u64)> {
+        let digest = hash_file(path)?;
+        let object_path = self.object_path(&digest);
+        if object_path.exists() {
+            return Ok((object_path, 0));
+        }
+
+        // Copy to a temporary file first, so that an interrupted copy
+        // never leaves a truncated object behind.
+        let tmp_path = self.root.join(TMP_DIR_NAME).join(&digest);
+        let size = fs::copy(path, &tmp_path)
+            .with_context(|| format!("failed to copy: {}", path.to_string_lossy()))?;
+        let bucket_dir = object_path.parent().expect("path should have a parent");
+        fs::create_dir_all(bucket_dir).with_context(|| {
+            format!(
+                "failed to create directory: {}",
+                bucket_dir.to_string_lossy()
+            )
+        })?;
+        fs::rename(&tmp_path, &object_path)
+            .with_context(|| format!("failed to move: {}", object_path.to_string_lossy()))?;
+        Ok((object_path, size))
+    }
+
+    /// Rehashes every object and reports the ones whose content doesn't
+    /// match their name.
+    pub fn verify(&self) -> Result<VerifyStats> {
+        let mut stats = VerifyStats::default();
+        let objects_dir = self.root.join(OBJECTS_DIR_NAME);
+        for bucket in read_dir(&objects_dir)? {
+            for object_path in read_dir(&bucket)? {
+                let name = object_path.file_name().unwrap_or_default();
+                if hash_file(&object_path)? != name.to_string_lossy() {
+                    stats.corrupted.push(object_path);
+                }
+                stats.verified += 1;
+            }
+        }
+        Ok(stats)
+    }
+
+    fn object_path(&self, digest: &str) -> PathBuf {
+        self.root
+            .join(OBJECTS_DIR_NAME)
+            .join(&digest[0..2])
+            .join(digest)
+    }
+}
+
+/// Returns the hex SHA-256 of the content of a file.
+pub(crate) fn hash_file(path: &Path) -> Result<String> {
+    let mut file =
+        File::open(path).with_context(|| format!("failed to open: {}", path.to_string_lossy()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let len = match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("failed to read: {}", path.to_string_lossy()))
+            }
+        };
+        hasher.update(&buf[..len]);
+    }
+    Ok(hex::encode(&hasher.finalize()))
+}
+
+fn read_dir(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    for entry in fs::read_dir(dir)
+        .with_context(|| format!("failed to read directory: {}", dir.to_string_lossy()))?
+    {
+        paths.push(entry?.path());
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::ObjectStore;
+
+    #[test]
+    fn it_deduplicates_and_verifies_objects() {
+        let src_dir = tempfile::tempdir().unwrap();
+        let a_path = src_dir.path().join("a");
+        let b_path = src_dir.path().join("b");
+        fs::write(&a_path, b"same").unwrap();
+        fs::write(&b_path, b"same").unwrap();
+
+        let store_dir = tempfile::tempdir().unwrap();
+        let store = ObjectStore::open(store_dir.path()).unwrap();
+        let (a_object, a_size) = store.insert(&a_path).unwrap();
+        let (b_object, b_size) = store.insert(&b_path).unwrap();
+        assert_eq!(a_object, b_object);
+        assert_eq!((a_size, b_size), (4, 0));
+
+        assert!(ObjectStore::open(store_dir.path()).is_err());
+
+        fs::write(&a_object, b"tampered").unwrap();
+        let stats = store.verify().unwrap();
+        assert_eq!(stats.verified, 1);
+        assert_eq!(stats.corrupted, [a_object]);
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::ctx::{ExtractionReport, PhaseDurations};
+use crate::volume::Adaptation;
+
+/// Final statistics of a run, written by `--summary-json`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RunSummary {
+    pub status: RunStatus,
+    /// Exit status the process terminates with.
+    pub exit_code: i32,
+    #[serde(flatten)]
+    pub totals: DomainSummary,
+    pub domains: BTreeMap<String, DomainSummary>,
+    pub warnings: Vec<String>,
+    /// Relative paths of the files written as placeholders as their blobs
+    /// are missing.
+    pub placeholders: Vec<String>,
+    /// Seconds spent in each phase.
+    pub phases: PhaseDurations,
+    /// Differences from the older backup in incremental extractions.
+    pub changes: Option<ChangeCounts>,
+    /// Regular files that passed the filters of a filtered extraction.
+    pub filtered: Option<FilterCounts>,
+    /// Changes made to suit the destination file system.
+    pub adaptations: Vec<Adaptation>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum RunStatus {
+    Succeeded,
+    /// The domain has no files to extract.
+    Empty,
+    Failed,
+    Cancelled,
+}
+
+impl RunStatus {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Succeeded | Self::Empty => 0,
+            Self::Failed => 1,
+            // Conventional status of a process terminated by SIGINT.
+            Self::Cancelled => 130,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeCounts {
+    pub added: usize,
+    pub changed: usize,
+    pub deleted: usize,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FilterCounts {
+    pub matched: usize,
+    /// All the regular files of the domain.
+    pub total: usize,
+}
+
+#[derive(Serialize, Default, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DomainSummary {
+    pub files_extracted: usize,
+    /// Placeholders of missing files, included in `files_extracted`.
+    pub files_missing: usize,
+    pub files_skipped: usize,
+    /// Existing files kept by `--skip-existing`, included in
+    /// `files_extracted`.
+    pub files_kept: usize,
+    /// Files left unwritten because the run stopped early.
+    pub files_failed: usize,
+    /// Files left unwritten as their blobs are cloud placeholders, included
+    /// in `files_failed`.
+    pub files_offloaded: usize,
+    pub bytes_written: u64,
+    /// Bytes not copied thanks to `--dedupe`.
+    pub bytes_saved: u64,
+}
+
+impl RunSummary {
+    /// Renders the report of an extraction of `domain`.
+    pub fn new(
+        domain: &str,
+        status: RunStatus,
+        report: &ExtractionReport,
+        error: Option<String>,
+    ) -> Self {
+        let stats = DomainSummary {
+            files_extracted: report.files_extracted,
+            files_missing: report.placeholders.len(),
+            files_skipped: report.files_skipped(),
+            files_kept: report.kept.len(),
+            files_failed: match status {
+                RunStatus::Failed | RunStatus::Cancelled => report.files_unwritten(),
+                RunStatus::Succeeded | RunStatus::Empty => 0,
+            },
+            files_offloaded: report.offloaded.len(),
+            bytes_written: report.bytes_written,
+            bytes_saved: report.bytes_saved,
+        };
+        let warnings = report
+            .skipped
+            .iter()
+            .flat_map(|(reason, paths)| {
+                paths
+                    .iter()
+                    .map(move |path| format!("skipped `{path}`: {reason}"))
+            })
+            .chain(
+                report
+                    .failed
+                    .iter()
+                    .map(|(path, error)| format!("failed `{path}`: {error}")),
+            )
+            .chain(
+                report
+                    .normalized
+                    .iter()
+                    .map(|(path, normalized)| format!("normalized `{path}` to `{normalized}`")),
+            )
+            .collect();
+        Self {
+            status,
+            exit_code: status.exit_code(),
+            totals: stats.clone(),
+            domains: BTreeMap::from([(domain.to_owned(), stats)]),
+            warnings,
+            placeholders: report.placeholders.clone(),
+            phases: report.phases.clone(),
+            changes: report.changes.as_ref().map(|changes| ChangeCounts {
+                added: changes.added,
+                changed: changes.changed,
+                deleted: changes.deleted.len(),
+            }),
+            filtered: report
+                .filtered
+                .map(|(matched, total)| FilterCounts { matched, total }),
+            adaptations: vec![],
+            error,
+        }
+    }
+}
+
+/// Writes the summary to the given path, or to stdout if it's `-`.
+pub fn write_summary(path: &Path, summary: &RunSummary) -> Result<()> {
+    let mut writer: Box<dyn Write> = if path == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(
+            File::create(path)
+                .with_context(|| format!("failed to create file: {}", path.to_string_lossy()))?,
+        )
+    };
+    serde_json::to_writer_pretty(&mut writer, summary).context("failed to write summary")?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RunStatus, RunSummary};
+    use crate::ctx::{ProgressEvent, ReportRecorder};
+
+    #[test]
+    fn it_counts_failed_files() {
+        let mut recorder = ReportRecorder::default();
+        recorder.record(&ProgressEvent::Querying);
+        recorder.record(&ProgressEvent::Skipped {
+            relative_path: "a".to_owned(),
+            reason: "malformed file ID",
+        });
+        recorder.record(&ProgressEvent::Extracting {
+            extracted: 2,
+            total: 5,
+            bytes_written: 42,
+            bytes_done: 42,
+            bytes_total: 100,
+        });
+
+        let report = recorder.report();
+        let summary = RunSummary::new(
+            "HomeDomain",
+            RunStatus::Failed,
+            &report,
+            Some("oops".to_owned()),
+        );
+        assert_eq!(summary.exit_code, 1);
+        assert_eq!(summary.totals.files_extracted, 2);
+        assert_eq!(summary.totals.files_skipped, 1);
+        assert_eq!(summary.totals.files_failed, 3);
+        assert_eq!(summary.domains["HomeDomain"].bytes_written, 42);
+        assert_eq!(summary.warnings, ["skipped `a`: malformed file ID"]);
+    }
+}
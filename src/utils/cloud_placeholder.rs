@@ -0,0 +1,78 @@
+use std::fmt;
+use std::fs::Metadata;
+
+/// Error of writing a file whose blob is a cloud placeholder, retrieved
+/// with `anyhow::Error::downcast_ref`. It only fails the file.
+#[derive(Debug)]
+pub struct CloudPlaceholderError;
+
+impl fmt::Display for CloudPlaceholderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "blob is a cloud placeholder, download the backup folder locally first"
+        )
+    }
+}
+
+impl std::error::Error for CloudPlaceholderError {}
+
+/// Checks whether a blob is a dataless placeholder left by a cloud
+/// storage provider (iCloud Drive, OneDrive, Dropbox, etc.), whose
+/// content has not been downloaded to the local disk.
+pub fn is_cloud_placeholder(metadata: &Metadata, expected_size: Option<u64>) -> bool {
+    if metadata.len() == 0 && expected_size.is_some_and(|s| s > 0) {
+        return true;
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        use std::os::macos::fs::MetadataExt;
+
+        const SF_DATALESS: u32 = 0x40000000;
+        if metadata.st_flags() & SF_DATALESS != 0 {
+            return true;
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+
+        const FILE_ATTRIBUTE_OFFLINE: u32 = 0x1000;
+        const FILE_ATTRIBUTE_RECALL_ON_OPEN: u32 = 0x40000;
+        const FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS: u32 = 0x400000;
+        let attributes = metadata.file_attributes();
+        if attributes
+            & (FILE_ATTRIBUTE_OFFLINE
+                | FILE_ATTRIBUTE_RECALL_ON_OPEN
+                | FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS)
+            != 0
+        {
+            return true;
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::is_cloud_placeholder;
+
+    #[test]
+    fn it_detects_empty_blobs_of_sized_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (empty, full) = (dir.path().join("empty"), dir.path().join("full"));
+        fs::write(&empty, b"").unwrap();
+        fs::write(&full, b"abc").unwrap();
+        let (empty, full) = (fs::metadata(empty).unwrap(), fs::metadata(full).unwrap());
+
+        assert!(is_cloud_placeholder(&empty, Some(3)));
+        assert!(!is_cloud_placeholder(&empty, Some(0)));
+        assert!(!is_cloud_placeholder(&empty, None));
+        assert!(!is_cloud_placeholder(&full, Some(3)));
+    }
+}
@@ -0,0 +1,9 @@
+use std::fmt::Write;
+
+/// Formats bytes as a lowercase hexadecimal string.
+pub fn encode(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{b:02x}");
+        s
+    })
+}
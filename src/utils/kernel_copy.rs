@@ -0,0 +1,87 @@
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+
+/// Most bytes asked of a single `copy_file_range` call, so that a huge
+/// file doesn't block in one call.
+const CHUNK_SIZE: usize = 64 << 20;
+
+/// Copies the rest of `reader` to `writer` from their current offsets with
+/// `copy_file_range`, without going through userspace buffers. Returns the
+/// bytes moved, counted from what each call reports.
+///
+/// The copy stops early, leaving the offsets after the bytes moved, if the
+/// kernel or the file systems can't do it (e.g. across file systems on
+/// older kernels), the rest is for the caller to copy by reading and
+/// writing.
+pub fn copy_file_range(reader: &File, writer: &File) -> io::Result<u64> {
+    let mut copied = 0;
+    loop {
+        let len = unsafe {
+            libc::copy_file_range(
+                reader.as_raw_fd(),
+                ptr::null_mut(),
+                writer.as_raw_fd(),
+                ptr::null_mut(),
+                CHUNK_SIZE,
+                0,
+            )
+        };
+        if len == 0 {
+            return Ok(copied);
+        }
+        if len > 0 {
+            copied += len as u64;
+            continue;
+        }
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::EINTR) => continue,
+            Some(libc::ENOSYS | libc::EXDEV | libc::EOPNOTSUPP | libc::EINVAL) => {
+                return Ok(copied)
+            }
+            _ => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    use super::copy_file_range;
+
+    #[test]
+    fn it_copies_the_rest_of_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let (from, to) = (dir.path().join("from"), dir.path().join("to"));
+        let content: Vec<u8> = (0..300_000u32).map(|i| i as u8).collect();
+        fs::write(&from, &content).unwrap();
+
+        let mut reader = File::open(&from).unwrap();
+        reader.seek(SeekFrom::Start(1000)).unwrap();
+        let mut writer = File::create(&to).unwrap();
+        let copied = copy_file_range(&reader, &writer).unwrap();
+
+        // Whatever wasn't moved by the kernel is left to read.
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(copied + rest.len() as u64, 299_000);
+        writer.write_all(&rest).unwrap();
+        assert_eq!(fs::read(&to).unwrap(), &content[1000..]);
+    }
+
+    #[test]
+    fn it_copies_empty_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (from, to) = (dir.path().join("from"), dir.path().join("to"));
+        fs::write(&from, b"").unwrap();
+
+        let reader = File::open(&from).unwrap();
+        let writer = File::create(&to).unwrap();
+        assert_eq!(copy_file_range(&reader, &writer).unwrap(), 0);
+        assert_eq!(fs::read(&to).unwrap(), b"");
+    }
+}
@@ -0,0 +1,145 @@
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use indicatif::ProgressBar;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::utils::timestamp::{format_timestamp, unix_timestamp};
+
+struct Logger {
+    max_level: LevelFilter,
+    console_level: LevelFilter,
+    /// Gets the debug logs whatever the console level.
+    file: Option<Mutex<LineWriter<File>>>,
+}
+
+/// Bar being drawn, which is hidden while a line is logged so that the two
+/// don't get mixed up.
+static PROGRESS_BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.max_level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        if let Some(file) = &self.file {
+            let now = format_timestamp(unix_timestamp(SystemTime::now()));
+            let mut file = file.lock().unwrap();
+            // Logging must not fail the run.
+            let _ = writeln!(file, "{now} {:<5} {}", record.level(), record.args());
+        }
+        if record.level() > self.console_level {
+            return;
+        }
+
+        let prefix = match record.level() {
+            Level::Error => console::style("error: ").red().bold().to_string(),
+            Level::Warn => console::style("warning: ").yellow().bold().to_string(),
+            Level::Info => String::new(),
+            Level::Debug | Level::Trace => console::style("debug: ").dim().to_string(),
+        };
+        match PROGRESS_BAR.lock().unwrap().as_ref() {
+            Some(progress_bar) => progress_bar.suspend(|| eprintln!("{prefix}{}", record.args())),
+            None => eprintln!("{prefix}{}", record.args()),
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap().flush();
+        }
+    }
+}
+
+impl Logger {
+    fn new(verbosity: u8, quiet: bool, log_file: Option<&Path>) -> Result<Self> {
+        let console_level = match verbosity {
+            _ if quiet => LevelFilter::Error,
+            0 => LevelFilter::Warn,
+            1 => LevelFilter::Info,
+            2 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        };
+        let file = log_file
+            .map(|path| {
+                File::create(path)
+                    .with_context(|| format!("failed to create file: {}", path.to_string_lossy()))
+            })
+            .transpose()?;
+        let max_level = if file.is_some() {
+            console_level.max(LevelFilter::Debug)
+        } else {
+            console_level
+        };
+        Ok(Self {
+            max_level,
+            console_level,
+            file: file.map(|file| Mutex::new(LineWriter::new(file))),
+        })
+    }
+}
+
+/// Installs the logger, `verbosity` is the number of `-v` flags given, and
+/// `quiet` only lets the errors through. The debug logs also go to
+/// `log_file` if given.
+pub fn init(verbosity: u8, quiet: bool, log_file: Option<&Path>) -> Result<()> {
+    let logger = Box::leak(Box::new(Logger::new(verbosity, quiet, log_file)?));
+    log::set_logger(logger).expect("logger should only be installed once");
+    log::set_max_level(logger.max_level);
+    QUIET.store(quiet, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Whether only errors should be printed, besides the output of commands.
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Sets the bar being drawn, `None` once it's gone.
+pub fn set_progress_bar(progress_bar: Option<ProgressBar>) {
+    *PROGRESS_BAR.lock().unwrap() = progress_bar;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use log::{Level, LevelFilter, Log, Record};
+
+    use super::Logger;
+
+    #[test]
+    fn it_writes_debug_logs_to_the_file_when_quiet() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.txt");
+        let logger = Logger::new(0, true, Some(&path)).unwrap();
+        assert_eq!(logger.console_level, LevelFilter::Error);
+        assert_eq!(logger.max_level, LevelFilter::Debug);
+
+        for (level, message) in [(Level::Debug, "debug line"), (Level::Trace, "trace line")] {
+            logger.log(
+                &Record::builder()
+                    .level(level)
+                    .args(format_args!("{message}"))
+                    .build(),
+            );
+        }
+        logger.flush();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains("DEBUG debug line"));
+        assert!(!content.contains("trace line"));
+    }
+}
@@ -1,4 +1,18 @@
+pub mod cloud_placeholder;
+pub mod hex;
+#[cfg(target_os = "linux")]
+pub mod kernel_copy;
+pub mod logger;
+pub mod mode;
 mod perf_timer;
+pub mod portable_name;
+pub mod protection_class;
+pub mod reflink;
+pub mod relative_path;
+pub mod size;
 pub mod string_pool;
+#[cfg(test)]
+pub mod test_fixture;
+pub mod timestamp;
 
 pub use perf_timer::PerfTimer;
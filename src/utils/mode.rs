@@ -0,0 +1,44 @@
+/// Formats a POSIX mode like `ls -l` does, e.g. `-rw-r--r--`.
+pub fn format_mode(mode: u32) -> String {
+    let file_type = match mode & 0o170000 {
+        0o100000 => '-',
+        0o040000 => 'd',
+        0o120000 => 'l',
+        0o020000 => 'c',
+        0o060000 => 'b',
+        0o010000 => 'p',
+        0o140000 => 's',
+        _ => '?',
+    };
+
+    let mut formatted = String::from(file_type);
+    for (shift, special_bit, special_char) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')]
+    {
+        let bits = (mode >> shift) & 0o7;
+        formatted.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        formatted.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        formatted.push(match (bits & 0o1 != 0, mode & special_bit != 0) {
+            (true, true) => special_char,
+            (false, true) => special_char.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_mode;
+
+    #[test]
+    fn it_formats_modes() {
+        assert_eq!(format_mode(0o100644), "-rw-r--r--");
+        assert_eq!(format_mode(0o040755), "drwxr-xr-x");
+        assert_eq!(format_mode(0o120777), "lrwxrwxrwx");
+        assert_eq!(format_mode(0o104755), "-rwsr-xr-x");
+        assert_eq!(format_mode(0o041777), "drwxrwxrwt");
+        assert_eq!(format_mode(0o102644), "-rw-r-Sr--");
+        assert_eq!(format_mode(0o000000), "?---------");
+    }
+}
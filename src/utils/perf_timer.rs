@@ -8,8 +8,12 @@ impl PerfTimer {
     }
 
     pub fn finish(self) {
+        if super::logger::is_quiet() {
+            return;
+        }
         let msg = format!("finished in {}ms", self.0.elapsed().as_millis());
-        println!("\n{}", console::style(msg).dim());
+        // Stdout is left to the output of the commands.
+        eprintln!("\n{}", console::style(msg).for_stderr().dim());
     }
 }
 
@@ -0,0 +1,54 @@
+use std::path::{Component, Path, PathBuf};
+
+/// Characters FAT and exFAT (as well as Windows) reject in file names.
+const RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*', '\\'];
+
+/// Replaces the characters that are invalid in file names on FAT, exFAT
+/// and Windows with `_`, including the trailing dots and spaces they drop.
+pub fn sanitize(path: &Path) -> PathBuf {
+    path.components()
+        .map(|component| match component {
+            Component::Normal(name) => sanitize_name(&name.to_string_lossy()),
+            other => other.as_os_str().to_string_lossy().into_owned(),
+        })
+        .collect()
+}
+
+fn sanitize_name(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || RESERVED_CHARS.contains(&c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    let kept_len = sanitized.trim_end_matches(['.', ' ']).len();
+    let trailing_len = sanitized.len() - kept_len;
+    sanitized.truncate(kept_len);
+    sanitized.push_str(&"_".repeat(trailing_len));
+    sanitized
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::sanitize;
+
+    #[test]
+    fn it_sanitizes_each_component() {
+        assert_eq!(
+            sanitize(Path::new("Notes: 2023/a?b.txt")),
+            Path::new("Notes_ 2023/a_b.txt")
+        );
+        assert_eq!(sanitize(Path::new("dir./name. ")), Path::new("dir_/name__"));
+        assert_eq!(
+            sanitize(Path::new("plain/name.db")),
+            Path::new("plain/name.db")
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use std::fmt;
+
+use crate::db::ManifestFile;
+
+/// Data protection classes of files, by number, `NSFileProtection` name
+/// and command line name.
+const CLASSES: &[(u64, &str, &str)] = &[
+    (1, "NSFileProtectionComplete", "complete"),
+    (
+        2,
+        "NSFileProtectionCompleteUnlessOpen",
+        "complete-unless-open",
+    ),
+    (
+        3,
+        "NSFileProtectionCompleteUntilFirstUserAuthentication",
+        "complete-until-first-user-authentication",
+    ),
+    (4, "NSFileProtectionNone", "none"),
+];
+
+/// Data protection class of a file, `None` if the metadata doesn't have it.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub struct ProtectionClass(pub Option<u64>);
+
+impl ProtectionClass {
+    pub fn of(file: &ManifestFile) -> Self {
+        Self(file.protection_class)
+    }
+
+    pub fn name(self) -> Option<&'static str> {
+        let class = self.0?;
+        CLASSES
+            .iter()
+            .find(|(number, ..)| *number == class)
+            .map(|(_, name, _)| *name)
+    }
+}
+
+impl fmt::Display for ProtectionClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.0, self.name()) {
+            (Some(class), Some(name)) => write!(f, "{class} ({name})"),
+            (Some(class), None) => write!(f, "{class}"),
+            (None, _) => f.write_str("unknown"),
+        }
+    }
+}
+
+/// Parses a protection class given by number, by name (with or without
+/// the `NSFileProtection` prefix) or as `unknown`.
+pub fn parse_protection_class(s: &str) -> Result<ProtectionClass, String> {
+    if s.eq_ignore_ascii_case("unknown") {
+        return Ok(ProtectionClass(None));
+    }
+    if let Ok(class) = s.parse() {
+        return Ok(ProtectionClass(Some(class)));
+    }
+
+    let normalized = s.to_ascii_lowercase().replace(['-', '_'], "");
+    CLASSES
+        .iter()
+        .find(|(_, name, cli_name)| {
+            normalized == name.to_ascii_lowercase() || normalized == cli_name.replace('-', "")
+        })
+        .map(|(class, ..)| ProtectionClass(Some(*class)))
+        .ok_or_else(|| format!("unknown protection class: `{s}`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_protection_class, ProtectionClass};
+
+    #[test]
+    fn it_parses_protection_classes() {
+        for s in ["1", "complete", "NSFileProtectionComplete"] {
+            assert_eq!(parse_protection_class(s), Ok(ProtectionClass(Some(1))));
+        }
+        assert_eq!(
+            parse_protection_class("complete-until-first-user-authentication"),
+            Ok(ProtectionClass(Some(3)))
+        );
+        assert_eq!(parse_protection_class("unknown"), Ok(ProtectionClass(None)));
+        assert!(parse_protection_class("secret").is_err());
+
+        assert_eq!(
+            ProtectionClass(Some(4)).to_string(),
+            "4 (NSFileProtectionNone)"
+        );
+        assert_eq!(ProtectionClass(None).to_string(), "unknown");
+    }
+}
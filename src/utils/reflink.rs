@@ -0,0 +1,76 @@
+use std::io;
+use std::path::Path;
+
+/// Creates `to` as a clone of `from`, sharing its blocks until either is
+/// modified, on file systems that support it (APFS, Btrfs, XFS). Returns
+/// the size of the file, or `None` if it can't be cloned there, in which
+/// case nothing is left at `to`, which must not exist.
+#[cfg(target_os = "macos")]
+pub fn clone_file(from: &Path, to: &Path) -> io::Result<Option<u64>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_from = CString::new(from.as_os_str().as_bytes())?;
+    let c_to = CString::new(to.as_os_str().as_bytes())?;
+    if unsafe { libc::clonefile(c_from.as_ptr(), c_to.as_ptr(), 0) } != 0 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::ENOTSUP | libc::EXDEV) => Ok(None),
+            _ => Err(err),
+        };
+    }
+    Ok(Some(std::fs::metadata(to)?.len()))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn clone_file(from: &Path, to: &Path) -> io::Result<Option<u64>> {
+    use std::fs::{self, File};
+    use std::os::unix::io::AsRawFd;
+
+    // `_IOW(0x94, 9, int)`, not exposed by every version of libc.
+    const FICLONE: libc::c_ulong = 0x40049409;
+
+    let reader = File::open(from)?;
+    let metadata = reader.metadata()?;
+    let writer = File::create(to)?;
+    if unsafe { libc::ioctl(writer.as_raw_fd(), FICLONE as _, reader.as_raw_fd()) } != 0 {
+        let err = io::Error::last_os_error();
+        drop(writer);
+        fs::remove_file(to)?;
+        return match err.raw_os_error() {
+            Some(libc::EOPNOTSUPP | libc::EXDEV | libc::EINVAL | libc::ENOTTY) => Ok(None),
+            _ => Err(err),
+        };
+    }
+    writer.set_permissions(metadata.permissions())?;
+    Ok(Some(metadata.len()))
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "android")))]
+pub fn clone_file(_from: &Path, _to: &Path) -> io::Result<Option<u64>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::clone_file;
+
+    #[test]
+    fn it_clones_or_leaves_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("from");
+        let to = dir.path().join("to");
+        fs::write(&from, b"abc").unwrap();
+
+        // The temporary directory may be on any file system.
+        match clone_file(&from, &to).unwrap() {
+            Some(size) => {
+                assert_eq!(size, 3);
+                assert_eq!(fs::read(&to).unwrap(), b"abc");
+            }
+            None => assert!(!to.exists()),
+        }
+    }
+}
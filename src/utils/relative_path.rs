@@ -0,0 +1,47 @@
+/// Cleans up the benign oddities some apps leave in relative paths
+/// (`./` segments, doubled and trailing slashes), returns `None` if the
+/// path is already clean.
+///
+/// Paths that could escape the destination directory are rejected.
+pub fn normalize(relative_path: &str) -> anyhow::Result<Option<String>> {
+    if relative_path.starts_with('/') {
+        return Err(anyhow!("absolute path: {relative_path}"));
+    }
+
+    let mut components = vec![];
+    for component in relative_path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return Err(anyhow!("parent directory in path: {relative_path}")),
+            _ => components.push(component),
+        }
+    }
+    if components.is_empty() {
+        return Err(anyhow!("empty path: {relative_path}"));
+    }
+
+    let normalized = components.join("/");
+    Ok(Some(normalized).filter(|n| n != relative_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize;
+
+    #[test]
+    fn it_normalizes_odd_paths() {
+        assert_eq!(normalize("Library/a.db").unwrap(), None);
+        for (odd_path, clean_path) in [
+            ("./Library/a.db", "Library/a.db"),
+            ("Library/./a.db", "Library/a.db"),
+            ("Library//a.db", "Library/a.db"),
+            ("Library/Caches/", "Library/Caches"),
+            ("./Library//./a.db/", "Library/a.db"),
+        ] {
+            assert_eq!(normalize(odd_path).unwrap().as_deref(), Some(clean_path));
+        }
+        for bad_path in ["../a.db", "Library/../../a.db", "/etc/passwd", "./", ""] {
+            assert!(normalize(bad_path).is_err(), "{bad_path}");
+        }
+    }
+}
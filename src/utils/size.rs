@@ -0,0 +1,35 @@
+/// Parses a size like `512`, `64K`, `1.5G` or `2TiB`, using binary units.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split_at = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split_at);
+    let number: f64 = number.parse().map_err(|_| format!("invalid size: `{s}`"))?;
+
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(format!("unknown size unit: `{unit}`")),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_size;
+
+    #[test]
+    fn it_parses_sizes() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("64K"), Ok(64 << 10));
+        assert_eq!(parse_size("1.5G"), Ok(3 << 29));
+        assert_eq!(parse_size("2TiB"), Ok(2 << 40));
+        assert_eq!(parse_size(" 10 mb "), Ok(10 << 20));
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("G").is_err());
+    }
+}
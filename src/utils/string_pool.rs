@@ -2,6 +2,7 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt::{Debug, Display};
 use std::hash::{Hash, Hasher};
+use std::ptr;
 
 #[derive(Default, Debug)]
 pub struct StringPool {
@@ -19,7 +20,7 @@ impl StringPool {
         Self::default()
     }
 
-    pub fn intern(&self, s: &str) -> StringId {
+    pub fn intern(&self, s: &str) -> StringId<'_> {
         let mut inner_mut = self.inner.borrow_mut();
         if let Some(idx) = inner_mut.idx_map.get(s).cloned() {
             return StringId { pool: self, idx };
@@ -32,10 +33,6 @@ impl StringPool {
 
         StringId { pool: self, idx }
     }
-
-    fn unchecked_get(&self, idx: usize) -> String {
-        self.inner.borrow().pool[idx].clone()
-    }
 }
 
 pub struct StringId<'p> {
@@ -52,15 +49,21 @@ impl<'p> Clone for StringId<'p> {
     }
 }
 
+// A pool interns each string once, so the indices of the IDs from the
+// same pool can stand for their strings.
 impl<'p> Hash for StringId<'p> {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.to_string().hash(state)
+        self.idx.hash(state)
     }
 }
 
 impl<'p> PartialEq for StringId<'p> {
     fn eq(&self, other: &Self) -> bool {
-        self.to_string() == other.to_string()
+        debug_assert!(
+            ptr::eq(self.pool, other.pool),
+            "comparing IDs from different pools"
+        );
+        self.idx == other.idx
     }
 }
 
@@ -76,6 +79,35 @@ impl<'p> Debug for StringId<'p> {
 
 impl<'p> Display for StringId<'p> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        <String as Display>::fmt(&self.pool.unchecked_get(self.idx), f)
+        <str as Display>::fmt(&self.pool.inner.borrow().pool[self.idx], f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::StringPool;
+
+    // The IDs only hash their indices, the interior mutability of the pool
+    // doesn't change them.
+    #[allow(clippy::mutable_key_type)]
+    #[test]
+    fn it_compares_ids_without_reading_strings() {
+        let pool = StringPool::new();
+        let names: Vec<_> = (0..5000).map(|i| format!("file{i}")).collect();
+        let mut map = HashMap::new();
+        for (i, name) in names.iter().enumerate() {
+            map.insert(pool.intern(name), i);
+        }
+        let ids: Vec<_> = names.iter().map(|name| pool.intern(name)).collect();
+
+        // Hashing and comparing the IDs would panic if they read the
+        // strings while the pool is borrowed.
+        let _guard = pool.inner.borrow_mut();
+        for (i, id) in ids.iter().enumerate() {
+            assert_eq!(map.get(id), Some(&i));
+        }
+        assert_ne!(ids[0], ids[1]);
     }
 }
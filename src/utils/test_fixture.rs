@@ -0,0 +1,222 @@
+use std::cell::Cell;
+use std::fs;
+use std::path::Path;
+
+use plist::{Dictionary, Uid, Value};
+use rusqlite::Connection as SqliteConnection;
+use tempfile::TempDir;
+
+use crate::bucket::BucketStrategy;
+use crate::crypto::test_support;
+use crate::ctx::Context;
+use crate::db::BackupManifest;
+
+/// A minimal unencrypted backup archive living in a temporary directory.
+pub struct FixtureBackup {
+    dir: TempDir,
+    next_id: Cell<u64>,
+}
+
+impl FixtureBackup {
+    pub fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let db_conn = SqliteConnection::open(dir.path().join("Manifest.db")).unwrap();
+        db_conn
+            .execute_batch(
+                "CREATE TABLE Files (fileID TEXT PRIMARY KEY, domain TEXT, \
+                 relativePath TEXT, flags INTEGER, file BLOB);",
+            )
+            .unwrap();
+
+        Self {
+            dir,
+            next_id: Cell::new(1),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn manifest(&self) -> BackupManifest {
+        BackupManifest::open(self.path().join("Manifest.db")).unwrap()
+    }
+
+    /// Opens a copy-mode context on the backup, borrowing `manifest`.
+    pub fn context<'a>(&'a self, manifest: &'a mut BackupManifest) -> Context<'a, 'a> {
+        Context::new(self.path(), manifest, true, BucketStrategy::default())
+    }
+
+    /// Adds a regular file and its blob, returns the file ID.
+    pub fn add_file(&self, domain: &str, relative_path: &str, contents: &[u8]) -> String {
+        let file_id = self.next_file_id();
+        let bucket_dir = self.path().join(&file_id[0..2]);
+        fs::create_dir_all(&bucket_dir).unwrap();
+        fs::write(bucket_dir.join(&file_id), contents).unwrap();
+
+        self.insert_row(
+            &file_id,
+            domain,
+            relative_path,
+            1,
+            file_plist(relative_path, contents.len() as u64, 0o100644, vec![]),
+        );
+        file_id
+    }
+
+    /// Adds a regular file with its blob encrypted as in encrypted backups,
+    /// returns the file ID.
+    pub fn add_encrypted_file(&self, domain: &str, relative_path: &str, contents: &[u8]) -> String {
+        let key = [9; 32];
+        let file_id = self.next_file_id();
+        let bucket_dir = self.path().join(&file_id[0..2]);
+        fs::create_dir_all(&bucket_dir).unwrap();
+        fs::write(
+            bucket_dir.join(&file_id),
+            test_support::encrypt(&key, contents),
+        )
+        .unwrap();
+
+        self.insert_row(
+            &file_id,
+            domain,
+            relative_path,
+            1,
+            file_plist(
+                relative_path,
+                contents.len() as u64,
+                0o100644,
+                vec![(
+                    "EncryptionKey",
+                    archived_data(test_support::wrap_key(3, &key)),
+                )],
+            ),
+        );
+        file_id
+    }
+
+    /// Adds a directory, returns its file ID.
+    pub fn add_dir(&self, domain: &str, relative_path: &str) -> String {
+        let file_id = self.next_file_id();
+        self.insert_row(
+            &file_id,
+            domain,
+            relative_path,
+            2,
+            file_plist(relative_path, 0, 0o040755, vec![]),
+        );
+        file_id
+    }
+
+    /// Adds a symbolic link, returns its file ID.
+    pub fn add_symlink(&self, domain: &str, relative_path: &str, target: &str) -> String {
+        let file_id = self.next_file_id();
+        self.insert_row(
+            &file_id,
+            domain,
+            relative_path,
+            4,
+            file_plist(relative_path, 0, 0o120755, vec![("Target", target.into())]),
+        );
+        file_id
+    }
+
+    /// Adds a file whose row has a malformed file ID.
+    pub fn add_malformed_file(&self, domain: &str, relative_path: &str) {
+        self.insert_row(
+            "not-a-file-id",
+            domain,
+            relative_path,
+            1,
+            file_plist(relative_path, 0, 0o100644, vec![]),
+        );
+    }
+
+    fn next_file_id(&self) -> String {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        format!("{id:040x}")
+    }
+
+    fn insert_row(
+        &self,
+        file_id: &str,
+        domain: &str,
+        relative_path: &str,
+        flags: u64,
+        file: Vec<u8>,
+    ) {
+        let db_conn = SqliteConnection::open(self.path().join("Manifest.db")).unwrap();
+        db_conn
+            .execute(
+                "INSERT INTO Files (fileID, domain, relativePath, flags, file) \
+                 VALUES (?, ?, ?, ?, ?)",
+                rusqlite::params![file_id, domain, relative_path, flags, file],
+            )
+            .unwrap();
+    }
+}
+
+/// Wraps raw bytes like an archived `NSMutableData`.
+fn archived_data(data: Vec<u8>) -> Value {
+    let mut object = Dictionary::new();
+    object.insert("NS.data".to_owned(), Value::Data(data));
+    Value::Dictionary(object)
+}
+
+/// Builds an `NSKeyedArchiver` encoded `MBFile`, as stored in the `file`
+/// column of the manifest.
+fn file_plist(
+    relative_path: &str,
+    size: u64,
+    mode: u64,
+    extra_fields: Vec<(&str, Value)>,
+) -> Vec<u8> {
+    let mut file = Dictionary::new();
+    file.insert("$class".to_owned(), Value::Uid(Uid::new(3)));
+    file.insert("RelativePath".to_owned(), Value::Uid(Uid::new(2)));
+    file.insert("Size".to_owned(), size.into());
+    file.insert("Mode".to_owned(), mode.into());
+    file.insert("UserID".to_owned(), 501.into());
+    file.insert("GroupID".to_owned(), 501.into());
+    file.insert("InodeNumber".to_owned(), 1234.into());
+    file.insert("ProtectionClass".to_owned(), 3.into());
+    file.insert("Flags".to_owned(), 0.into());
+    file.insert("Birth".to_owned(), 1_600_000_000.into());
+    file.insert("LastModified".to_owned(), 1_650_000_000.into());
+    file.insert("LastStatusChange".to_owned(), 1_650_000_000.into());
+
+    let mut class = Dictionary::new();
+    class.insert("$classname".to_owned(), "MBFile".into());
+    class.insert(
+        "$classes".to_owned(),
+        Value::Array(vec!["MBFile".into(), "NSObject".into()]),
+    );
+
+    let mut top = Dictionary::new();
+    top.insert("root".to_owned(), Value::Uid(Uid::new(1)));
+
+    let mut archive = Dictionary::new();
+    archive.insert("$version".to_owned(), 100000.into());
+    archive.insert("$archiver".to_owned(), "NSKeyedArchiver".into());
+    archive.insert("$top".to_owned(), Value::Dictionary(top));
+    let mut objects = vec![
+        "$null".into(),
+        Value::Dictionary(file),
+        relative_path.into(),
+        Value::Dictionary(class),
+    ];
+    // Extra fields are archived as objects of their own.
+    for (key, value) in extra_fields {
+        let uid = Uid::new(objects.len() as u64);
+        objects.push(value);
+        if let Some(Value::Dictionary(file)) = objects.get_mut(1) {
+            file.insert(key.to_owned(), Value::Uid(uid));
+        }
+    }
+    archive.insert("$objects".to_owned(), Value::Array(objects));
+
+    let mut buf = Vec::new();
+    plist::to_writer_binary(&mut buf, &Value::Dictionary(archive)).unwrap();
+    buf
+}
@@ -0,0 +1,72 @@
+use std::borrow::Cow;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Converts a `SystemTime` to seconds since the Unix epoch.
+pub fn unix_timestamp(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(err) => -(err.duration().as_secs() as i64),
+    }
+}
+
+/// Converts seconds since the Unix epoch to a `SystemTime`.
+pub fn system_time(timestamp: i64) -> SystemTime {
+    let duration = Duration::from_secs(timestamp.unsigned_abs());
+    if timestamp >= 0 {
+        UNIX_EPOCH + duration
+    } else {
+        UNIX_EPOCH - duration
+    }
+}
+
+/// Formats seconds since the Unix epoch as an RFC 3339 date in UTC.
+pub fn format_timestamp(timestamp: i64) -> String {
+    plist::Date::from(system_time(timestamp)).to_xml_format()
+}
+
+/// Parses a date as seconds since the Unix epoch, either absolute like
+/// `2024-05-01` or `2024-05-01T12:00:00+08:00` (UTC unless given), or
+/// relative to now like `30d`, `12h`, `2w` or `45m`.
+pub fn parse_date(s: &str) -> Result<i64, String> {
+    let s = s.trim();
+    if let Some(unit) = s.chars().last().filter(|c| c.is_ascii_alphabetic()) {
+        if let Ok(amount) = s[..s.len() - 1].parse::<i64>() {
+            let seconds = match unit {
+                'm' => 60,
+                'h' => 60 * 60,
+                'd' => 24 * 60 * 60,
+                'w' => 7 * 24 * 60 * 60,
+                _ => return Err(format!("unknown time unit `{unit}` in `{s}`")),
+            };
+            return amount
+                .checked_mul(seconds)
+                .and_then(|ago| unix_timestamp(SystemTime::now()).checked_sub(ago))
+                .ok_or_else(|| format!("time too far from now: `{s}`"));
+        }
+    }
+
+    let rfc3339 = if s.contains('T') {
+        Cow::Borrowed(s)
+    } else {
+        Cow::Owned(format!("{s}T00:00:00Z"))
+    };
+    let date = plist::Date::from_xml_format(&rfc3339)
+        .map_err(|_| format!("invalid date: `{s}`, expected e.g. `2024-05-01` or `30d`"))?;
+    Ok(unix_timestamp(date.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_date;
+
+    #[test]
+    fn it_parses_dates() {
+        assert_eq!(parse_date("2022-04-15"), Ok(1_649_980_800));
+        assert_eq!(parse_date("2022-04-15T08:00:00+08:00"), Ok(1_649_980_800));
+        assert!(parse_date("1d").unwrap() < parse_date("1h").unwrap());
+        assert!(parse_date("2022-13-01").is_err());
+        assert!(parse_date("3y").is_err());
+        assert!(parse_date("9223372036854775807w").is_err());
+        assert!(parse_date("-9223372036854775807m").is_err());
+    }
+}
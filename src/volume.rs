@@ -0,0 +1,109 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// Largest file a FAT32 volume can hold.
+pub const FAT32_MAX_FILE_SIZE: u64 = u32::MAX as u64;
+
+/// File systems that need extraction to be adapted.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum VolumeKind {
+    Fat32,
+    ExFat,
+    Other,
+}
+
+impl VolumeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Fat32 => "FAT32",
+            Self::ExFat => "exFAT",
+            Self::Other => "other",
+        }
+    }
+
+    /// Returns the adaptations needed to extract to this file system.
+    pub fn adaptations(self) -> &'static [Adaptation] {
+        match self {
+            Self::Fat32 => &[
+                Adaptation::ForceCopy,
+                Adaptation::SizeCheck,
+                Adaptation::PortableNames,
+            ],
+            Self::ExFat => &[Adaptation::ForceCopy, Adaptation::PortableNames],
+            Self::Other => &[],
+        }
+    }
+}
+
+/// Changes made to an extraction to suit the destination file system.
+#[derive(ValueEnum, Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum Adaptation {
+    /// Copy the files, the file system has no symbolic links.
+    ForceCopy,
+    /// Fail before writing anything if a file exceeds 4 GB.
+    SizeCheck,
+    /// Replace the characters the file system rejects in file names.
+    PortableNames,
+}
+
+/// Detects the file system of the volume `path` is (or will be) on.
+pub fn detect(path: &Path) -> Result<VolumeKind> {
+    // The destination may not exist yet, look at its closest ancestor.
+    let existing = path
+        .ancestors()
+        .find(|p| p.exists())
+        .unwrap_or(Path::new("."));
+    detect_existing(existing)
+        .with_context(|| format!("failed to query file system: {}", path.to_string_lossy()))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn detect_existing(path: &Path) -> Result<VolumeKind> {
+    const MSDOS_SUPER_MAGIC: u32 = 0x4d44;
+    const EXFAT_SUPER_MAGIC: u32 = 0x2011bab0;
+
+    let stat = statfs(path)?;
+    // VFAT doesn't tell FAT12/16 apart from FAT32, which share the limits.
+    Ok(match stat.f_type as u32 {
+        MSDOS_SUPER_MAGIC => VolumeKind::Fat32,
+        EXFAT_SUPER_MAGIC => VolumeKind::ExFat,
+        _ => VolumeKind::Other,
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn detect_existing(path: &Path) -> Result<VolumeKind> {
+    use std::ffi::CStr;
+
+    let stat = statfs(path)?;
+    let type_name = unsafe { CStr::from_ptr(stat.f_fstypename.as_ptr()) };
+    Ok(match type_name.to_bytes() {
+        b"msdos" => VolumeKind::Fat32,
+        b"exfat" => VolumeKind::ExFat,
+        _ => VolumeKind::Other,
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+fn detect_existing(_path: &Path) -> Result<VolumeKind> {
+    Ok(VolumeKind::Other)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+fn statfs(path: &Path) -> Result<libc::statfs> {
+    use std::ffi::CString;
+    use std::io;
+    use std::mem::MaybeUninit;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = MaybeUninit::<libc::statfs>::uninit();
+    if unsafe { libc::statfs(c_path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(unsafe { stat.assume_init() })
+}
@@ -0,0 +1,311 @@
+use std::cell::Cell;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use plist::{Dictionary, Uid, Value};
+use rusqlite::Connection as SqliteConnection;
+use tempfile::TempDir;
+
+/// A minimal unencrypted backup archive living in a temporary directory.
+struct Backup {
+    dir: TempDir,
+    next_id: Cell<u64>,
+}
+
+impl Backup {
+    fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let db_conn = SqliteConnection::open(dir.path().join("Manifest.db")).unwrap();
+        db_conn
+            .execute_batch(
+                "CREATE TABLE Files (fileID TEXT PRIMARY KEY, domain TEXT, \
+                 relativePath TEXT, flags INTEGER, file BLOB);",
+            )
+            .unwrap();
+
+        Self {
+            dir,
+            next_id: Cell::new(1),
+        }
+    }
+
+    fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Adds a regular file and its blob, returns the file ID.
+    fn add_file(&self, domain: &str, relative_path: &str, contents: &[u8]) -> String {
+        let file_id = self.next_file_id();
+        let bucket_dir = self.path().join(&file_id[0..2]);
+        fs::create_dir_all(&bucket_dir).unwrap();
+        fs::write(bucket_dir.join(&file_id), contents).unwrap();
+
+        self.insert_row(
+            &file_id,
+            domain,
+            relative_path,
+            1,
+            file_plist(relative_path, contents.len() as u64, 0o100644),
+        );
+        file_id
+    }
+
+    /// Adds a directory, returns its file ID.
+    fn add_dir(&self, domain: &str, relative_path: &str) -> String {
+        let file_id = self.next_file_id();
+        self.insert_row(
+            &file_id,
+            domain,
+            relative_path,
+            2,
+            file_plist(relative_path, 0, 0o040755),
+        );
+        file_id
+    }
+
+    fn next_file_id(&self) -> String {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        format!("{id:040x}")
+    }
+
+    fn insert_row(
+        &self,
+        file_id: &str,
+        domain: &str,
+        relative_path: &str,
+        flags: u64,
+        file: Vec<u8>,
+    ) {
+        let db_conn = SqliteConnection::open(self.path().join("Manifest.db")).unwrap();
+        db_conn
+            .execute(
+                "INSERT INTO Files (fileID, domain, relativePath, flags, file) \
+                 VALUES (?, ?, ?, ?, ?)",
+                rusqlite::params![file_id, domain, relative_path, flags, file],
+            )
+            .unwrap();
+    }
+}
+
+/// Builds an `NSKeyedArchiver` encoded `MBFile`, as stored in the `file`
+/// column of the manifest.
+fn file_plist(relative_path: &str, size: u64, mode: u64) -> Vec<u8> {
+    let mut file = Dictionary::new();
+    file.insert("$class".to_owned(), Value::Uid(Uid::new(3)));
+    file.insert("RelativePath".to_owned(), Value::Uid(Uid::new(2)));
+    file.insert("Size".to_owned(), size.into());
+    file.insert("Mode".to_owned(), mode.into());
+    file.insert("UserID".to_owned(), 501.into());
+    file.insert("GroupID".to_owned(), 501.into());
+    file.insert("InodeNumber".to_owned(), 1234.into());
+    file.insert("ProtectionClass".to_owned(), 3.into());
+    file.insert("Flags".to_owned(), 0.into());
+    file.insert("Birth".to_owned(), 1_600_000_000.into());
+    file.insert("LastModified".to_owned(), 1_650_000_000.into());
+    file.insert("LastStatusChange".to_owned(), 1_650_000_000.into());
+
+    let mut class = Dictionary::new();
+    class.insert("$classname".to_owned(), "MBFile".into());
+    class.insert(
+        "$classes".to_owned(),
+        Value::Array(vec!["MBFile".into(), "NSObject".into()]),
+    );
+
+    let mut top = Dictionary::new();
+    top.insert("root".to_owned(), Value::Uid(Uid::new(1)));
+
+    let mut archive = Dictionary::new();
+    archive.insert("$version".to_owned(), 100000.into());
+    archive.insert("$archiver".to_owned(), "NSKeyedArchiver".into());
+    archive.insert("$top".to_owned(), Value::Dictionary(top));
+    archive.insert(
+        "$objects".to_owned(),
+        Value::Array(vec![
+            "$null".into(),
+            Value::Dictionary(file),
+            relative_path.into(),
+            Value::Dictionary(class),
+        ]),
+    );
+
+    let mut buf = Vec::new();
+    plist::to_writer_binary(&mut buf, &Value::Dictionary(archive)).unwrap();
+    buf
+}
+
+/// Runs the binary, panicking unless it succeeds.
+fn run(args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_ibackupextractor"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+#[test]
+fn it_runs_each_subcommand() {
+    let backup = Backup::new();
+    backup.add_dir("HomeDomain", "Library");
+    backup.add_file("HomeDomain", "Library/SMS/sms.db", b"sms");
+    let file_id = backup.add_file("HomeDomain", "Media/a.txt", b"hello");
+    backup.add_file("AppDomain-com.example.app", "Documents/b.txt", b"app");
+
+    let backup_dir = backup.path().to_str().unwrap();
+    let out_dir = tempfile::tempdir().unwrap();
+    let out = |name: &str| out_dir.path().join(name).to_str().unwrap().to_owned();
+    let (extracted, extracted_manifest, atomic, app, blob, archive, tar_archive, store) = (
+        out("extracted"),
+        out("extracted.json"),
+        out("atomic"),
+        out("app"),
+        out("blob"),
+        out("archive.zip"),
+        out("domain.tar.gz"),
+        out("store"),
+    );
+    let commands: &[&[&str]] = &[
+        &["list-domains", backup_dir, "--sizes"],
+        &["list-domains", backup_dir, "--format", "json", "--sizes"],
+        &["list-files", backup_dir, "-d", "HomeDomain"],
+        &[
+            "list-files",
+            backup_dir,
+            "-d",
+            "HomeDomain",
+            "--max-depth",
+            "1",
+        ],
+        &[
+            "list-files",
+            backup_dir,
+            "-d",
+            "HomeDomain",
+            "--format",
+            "ndjson",
+        ],
+        &[
+            "list-files",
+            backup_dir,
+            "-d",
+            "HomeDomain",
+            "--sort",
+            "size",
+            "--reverse",
+            "--limit",
+            "2",
+        ],
+        &["tree", backup_dir, "-d", "HomeDomain"],
+        &["tree", backup_dir, "-d", "HomeDomain", "--max-depth", "1"],
+        &[
+            "extract",
+            backup_dir,
+            "HomeDomain",
+            "-o",
+            &extracted,
+            "-c",
+            "--write-manifest",
+            &extracted_manifest,
+            "--metadata",
+            "ndjson",
+        ],
+        &["extract", backup_dir, "-o", &extracted, "--store", &store],
+        &[
+            "extract",
+            backup_dir,
+            "HomeDomain",
+            "-o",
+            &atomic,
+            "-c",
+            "--atomic",
+        ],
+        &[
+            "extract",
+            backup_dir,
+            "HomeDomain",
+            "--archive",
+            &tar_archive,
+        ],
+        &["info", backup_dir],
+        &["stat", backup_dir, "-d", "HomeDomain", "Media/a.txt"],
+        &["cat", backup_dir, "-d", "HomeDomain", "Media/a.txt"],
+        &["show-blob", backup_dir, "-d", "HomeDomain", "Media/a.txt"],
+        &["whois", backup_dir, &file_id],
+        &[
+            "extract-app",
+            backup_dir,
+            &app,
+            "--bundle-id",
+            "com.example.app",
+        ],
+        &["extract-id", backup_dir, &file_id, &blob, "-c"],
+        &[
+            "search",
+            backup_dir,
+            "--content",
+            "hel+o",
+            "-d",
+            "HomeDomain",
+        ],
+        &["search", backup_dir, "--name", "*.TXT", "--ignore-case"],
+        &["check-timestamps", backup_dir],
+        &["verify", backup_dir],
+        &["stats", backup_dir, "--by-protection-class"],
+        &["pack", backup_dir, "-o", &archive],
+        &["store", "verify", &store],
+    ];
+
+    for command in commands {
+        run(command);
+    }
+    assert_eq!(
+        fs::read_to_string(out_dir.path().join("extracted/Media/a.txt")).unwrap(),
+        "hello"
+    );
+    assert!(out_dir
+        .path()
+        .join("app/Container/Documents/b.txt")
+        .exists());
+    assert!(out_dir.path().join("atomic/Media/a.txt").exists());
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&extracted_manifest).unwrap()).unwrap();
+    assert_eq!(manifest["files"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn it_prints_listings_to_stdout() {
+    let backup = Backup::new();
+    backup.add_dir("HomeDomain", "Media");
+    backup.add_file("HomeDomain", "Media/a.txt", b"hello");
+    backup.add_file("HomeDomain", "Media/b.txt", b"hi");
+    let backup_dir = backup.path().to_str().unwrap();
+
+    let output = run(&["cat", backup_dir, "-d", "HomeDomain", "Media/a.txt"]);
+    assert_eq!(output.stdout, b"hello");
+
+    let output = run(&[
+        "list-files",
+        backup_dir,
+        "-d",
+        "HomeDomain",
+        "--format",
+        "ndjson",
+        "--sort",
+        "size",
+    ]);
+    let paths: Vec<String> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let entry: serde_json::Value = serde_json::from_str(line).unwrap();
+            entry["path"].as_str().unwrap().to_owned()
+        })
+        .collect();
+    assert_eq!(paths, ["Media", "Media/b.txt", "Media/a.txt"]);
+}